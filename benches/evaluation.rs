@@ -0,0 +1,86 @@
+use cel_rs::compiler::compile;
+use cel_rs::interpreter::{evaluate_with, Activation};
+use cel_rs::model::{Expression, Literal};
+use cel_rs::parsers::parse;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const RULES: &[(&str, &str)] = &[
+    (
+        "access",
+        r#"method == "GET" && ["admin", "editor", "viewer"].contains(role) && size < 1024 * 1024"#,
+    ),
+    (
+        "routing",
+        r#"headers["x-tenant"] == "acme" && (retries < 3 ? priority > 2 : priority > 5) || path.len() > 64"#,
+    ),
+    (
+        "quota",
+        r#"used + requested <= limits[tier] && (tier == "free" ? requested <= 10 : requested <= 1000)"#,
+    ),
+];
+
+fn activation() -> Activation {
+    let mut activation = Activation::new();
+    let string = |s: &str| Literal::String(String::from(s));
+    activation.insert(String::from("method"), string("GET"));
+    activation.insert(String::from("role"), string("viewer"));
+    activation.insert(String::from("size"), Literal::I64(4096));
+    activation.insert(
+        String::from("headers"),
+        Literal::Map(vec![
+            (
+                Expression::from(string("accept")),
+                Expression::from(string("*/*")),
+            ),
+            (
+                Expression::from(string("x-tenant")),
+                Expression::from(string("acme")),
+            ),
+        ]),
+    );
+    activation.insert(String::from("retries"), Literal::I64(1));
+    activation.insert(String::from("priority"), Literal::I64(4));
+    activation.insert(String::from("path"), string("/v1/tenants/acme/objects"));
+    activation.insert(String::from("used"), Literal::I64(120));
+    activation.insert(String::from("requested"), Literal::I64(8));
+    activation.insert(String::from("tier"), string("free"));
+    activation.insert(
+        String::from("limits"),
+        Literal::Map(vec![
+            (
+                Expression::from(string("free")),
+                Expression::from(Literal::I64(200)),
+            ),
+            (
+                Expression::from(string("pro")),
+                Expression::from(Literal::I64(20000)),
+            ),
+        ]),
+    );
+    activation
+}
+
+fn bench_evaluation(c: &mut Criterion) {
+    let activation = activation();
+    let mut group = c.benchmark_group("evaluation");
+    for (name, source) in RULES {
+        let expr = parse(source).unwrap();
+        let program = compile(&expr);
+        assert_eq!(
+            program.evaluate(&activation),
+            evaluate_with(expr.clone(), &activation)
+        );
+        // The interpreter consumes its input, so every evaluation pays for a
+        // copy of the tree; that is part of the cost the VM avoids.
+        group.bench_with_input(BenchmarkId::new("interpreter", name), &expr, |b, expr| {
+            b.iter(|| evaluate_with(expr.clone(), &activation))
+        });
+        group.bench_with_input(BenchmarkId::new("vm", name), &program, |b, program| {
+            b.iter(|| program.evaluate(&activation))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_evaluation);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate cel_rs;
+
+use cel_rs::parsers::{parse_with_options, ParserOptions};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        if let Ok(e) = cel_rs::parse(s) {
+            let unparsed = cel_rs::unparse(&e);
+            // Escaping can make string literals longer than the source was.
+            let options = ParserOptions {
+                max_code_points: usize::MAX,
+                ..ParserOptions::default()
+            };
+            assert_eq!(parse_with_options(&unparsed, &options), Ok(e), "{}", unparsed);
+        }
+    }
+});
@@ -0,0 +1,248 @@
+//! Finds the variables, field paths and functions an expression refers to,
+//! e.g. so that a caller can fetch only the attributes a rule reads before
+//! evaluating it.
+//!
+//! Every identifier is taken to refer to a variable, except for the variable
+//! bound by a macro from an extension library within the macro's body, such
+//! as the `x` in `list.sortBy(x, x.name)`. Without the functions at hand, any
+//! call of the form `receiver.name(x, body)` is taken to be a macro call.
+
+use crate::checker::CheckedExpression;
+use crate::model::{walk_expression, walk_method, ExprKind, Expression, MethodName, Visitor};
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct References {
+    /// The names of the variables referred to, e.g. `request`.
+    pub variables: BTreeSet<String>,
+    /// The longest chain of field selections from each variable reference,
+    /// e.g. `request.auth.claims.email`. A variable that is not selected from
+    /// is a path by itself.
+    pub paths: BTreeSet<String>,
+    /// The functions called: their CEL names, such as `_+_` and `len`, for a
+    /// parsed expression, and the ids of the overloads they resolved to, such
+    /// as `add_int64`, for a checked one.
+    pub functions: BTreeSet<String>,
+}
+
+/// The references made by a parsed expression.
+pub fn references(expr: &Expression) -> References {
+    let mut collector = Collector {
+        references: References::default(),
+        checked: None,
+        bound: Vec::new(),
+    };
+    collector.visit_expression(expr);
+    collector.references
+}
+
+/// The references made by a checked expression, with the functions narrowed
+/// down to the overloads the checker found for each call.
+pub fn checked_references(checked: &CheckedExpression) -> References {
+    let mut collector = Collector {
+        references: References::default(),
+        checked: Some(checked),
+        bound: Vec::new(),
+    };
+    collector.visit_expression(&checked.expr);
+    collector.references
+}
+
+struct Collector<'a> {
+    references: References,
+    checked: Option<&'a CheckedExpression>,
+    /// The variables bound by the macros enclosing the node being visited.
+    bound: Vec<&'a str>,
+}
+
+impl<'a> Visitor<'a> for Collector<'a> {
+    fn visit_expression(&mut self, expr: &'a Expression) {
+        if let Some((name, _)) = path(expr) {
+            if self.bound.contains(&name) {
+                return;
+            }
+            self.references.variables.insert(String::from(name));
+            self.references.paths.insert(qualified_name(expr).unwrap());
+            return;
+        }
+        match self.checked {
+            Some(checked) => {
+                let ids = checked.overloads_of(expr.id).iter().cloned();
+                self.references.functions.extend(ids);
+            }
+            None => {
+                if let Some(function) = function(expr) {
+                    self.references.functions.insert(function);
+                }
+            }
+        }
+        walk_expression(self, expr);
+    }
+
+    fn visit_method(
+        &mut self,
+        _expr: &'a Expression,
+        receiver: &'a Expression,
+        name: &'a MethodName,
+        args: &'a [Expression],
+    ) {
+        match macro_arguments(name, args) {
+            Some((var, body)) => {
+                self.visit_expression(receiver);
+                self.bound.push(var);
+                self.visit_expression(body);
+                self.bound.pop();
+            }
+            None => walk_method(self, receiver, args),
+        }
+    }
+}
+
+/// Splits a chain of field selections from a variable into the variable and
+/// the fields, innermost last.
+pub(crate) fn path(expr: &Expression) -> Option<(&str, Vec<&str>)> {
+    let mut fields = Vec::new();
+    let mut expr = expr;
+    loop {
+        match &expr.kind {
+            ExprKind::Select(e, field) => {
+                fields.push(field.as_str());
+                expr = e;
+            }
+            ExprKind::Ident(name) => return Some((name, fields)),
+            _ => return None,
+        }
+    }
+}
+
+/// The dotted name of a chain of field selections from a variable, e.g.
+/// `request.auth`, which may also be the namespace of a global function.
+pub(crate) fn qualified_name(expr: &Expression) -> Option<String> {
+    let (name, fields) = path(expr)?;
+    let mut qualified = String::from(name);
+    for field in fields.iter().rev() {
+        qualified.push('.');
+        qualified.push_str(field);
+    }
+    Some(qualified)
+}
+
+/// The variable and body of a call that has the shape of a macro call,
+/// `receiver.name(x, body)`.
+pub(crate) fn macro_arguments<'e>(
+    name: &MethodName,
+    args: &'e [Expression],
+) -> Option<(&'e str, &'e Expression)> {
+    match (name, args) {
+        (MethodName::Extension(_), [var, body]) => match &var.kind {
+            ExprKind::Ident(var) => Some((var, body)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The CEL name of the function `expr` calls, if it is a call.
+fn function(expr: &Expression) -> Option<String> {
+    let name = match &expr.kind {
+        ExprKind::Conditional(..) => "_?_:_",
+        ExprKind::Or(..) => "_||_",
+        ExprKind::And(..) => "_&&_",
+        ExprKind::Eq(..) => "_==_",
+        ExprKind::Neq(..) => "_!=_",
+        ExprKind::Lt(..) => "_<_",
+        ExprKind::Lte(..) => "_<=_",
+        ExprKind::Gte(..) => "_>=_",
+        ExprKind::Gt(..) => "_>_",
+        ExprKind::Add(..) => "_+_",
+        ExprKind::Sub(..) => "_-_",
+        ExprKind::Mul(..) => "_*_",
+        ExprKind::Div(..) => "_/_",
+        ExprKind::Mod(..) => "_%_",
+        ExprKind::Neg(..) => "-_",
+        ExprKind::Not(..) => "!_",
+        ExprKind::Index(..) => "_[_]",
+        ExprKind::OptionalIndex(..) => "_[?_]",
+        ExprKind::OptionalSelect(..) => "_?._",
+        ExprKind::Method(_, name, _) => return Some(name.to_string()),
+        ExprKind::Call(function, _) => return Some(function.clone()),
+        ExprKind::Select(..)
+        | ExprKind::OptionalElement(_)
+        | ExprKind::Ident(_)
+        | ExprKind::Lit(_) => return None,
+    };
+    Some(String::from(name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{checked_references, references, References};
+    use crate::checker::{check, Declarations, Type};
+    use crate::parsers::parse;
+
+    fn set(items: &[&str]) -> std::collections::BTreeSet<String> {
+        items.iter().map(|s| String::from(*s)).collect()
+    }
+
+    #[test]
+    fn parsed() {
+        let expr = parse("request.auth.claims.email == x && request.size.len() > 0").unwrap();
+        assert_eq!(
+            references(&expr),
+            References {
+                variables: set(&["request", "x"]),
+                paths: set(&["request.auth.claims.email", "request.size", "x"]),
+                functions: set(&["_&&_", "_==_", "_>_", "len"]),
+            }
+        );
+    }
+
+    #[test]
+    fn paths_stop_at_other_operations() {
+        let expr = parse("m['k'].a + (n).b.c + {'a': y}.a").unwrap();
+        let refs = references(&expr);
+        assert_eq!(refs.variables, set(&["m", "n", "y"]));
+        assert_eq!(refs.paths, set(&["m", "n.b.c", "y"]));
+        assert_eq!(refs.functions, set(&["_+_", "_[_]"]));
+    }
+
+    #[test]
+    fn macro_variables_are_bound_in_the_body_only() {
+        let expr = parse("items.sortBy(it, it.name)").unwrap();
+        let refs = references(&expr);
+        assert_eq!(refs.variables, set(&["items"]));
+        assert_eq!(refs.paths, set(&["items"]));
+        assert_eq!(refs.functions, set(&["sortBy"]));
+
+        let expr = parse("it.a + items.sortBy(it, it.b + n.c)[0]").unwrap();
+        let refs = references(&expr);
+        assert_eq!(refs.variables, set(&["it", "items", "n"]));
+        assert_eq!(refs.paths, set(&["it.a", "items", "n.c"]));
+
+        let mut decls = Declarations::standard();
+        crate::ext::lists::declare(&mut decls);
+        decls.add_variable("items", Type::list(Type::map(Type::String, Type::Int)));
+        let expr = parse("items.sortBy(it, it.n)").unwrap();
+        let refs = checked_references(&check(expr, &decls).unwrap());
+        assert_eq!(refs.variables, set(&["items"]));
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        let claims = Type::map(Type::String, Type::String);
+        decls.add_variable("request", Type::map(Type::String, claims));
+        decls.add_variable("n", Type::Int);
+        let expr = parse("request.claims.email == 'a' || n + 1 > 2").unwrap();
+        let checked = check(expr, &decls).unwrap();
+        assert_eq!(
+            checked_references(&checked),
+            References {
+                variables: set(&["n", "request"]),
+                paths: set(&["n", "request.claims.email"]),
+                functions: set(&["add_int64", "equals_string", "greater_int64", "logical_or"]),
+            }
+        );
+    }
+}
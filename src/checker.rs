@@ -0,0 +1,1019 @@
+use crate::analysis::{macro_arguments, qualified_name};
+use crate::model::{ExprKind, Expression, Literal, Location, MethodName, SourceInfo};
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Type {
+    Null,
+    Bool,
+    Int,
+    Uint,
+    Double,
+    String,
+    Bytes,
+    List(Box<Type>),
+    Map(Box<Type>, Box<Type>),
+    Dyn,
+    /// A type parameter in an overload signature, e.g. the `A` in `list(A)`.
+    Param(String),
+    /// A type defined outside of CEL, by its name, e.g. `net.IP`.
+    Opaque(String),
+    /// A value that may be absent, e.g. the result of `m.?field`.
+    Optional(Box<Type>),
+    /// The type of an expression that failed to check.
+    Error,
+}
+
+impl Type {
+    pub fn list(elem: Type) -> Type {
+        Type::List(Box::new(elem))
+    }
+
+    pub fn map(key: Type, value: Type) -> Type {
+        Type::Map(Box::new(key), Box::new(value))
+    }
+
+    pub fn param(name: &str) -> Type {
+        Type::Param(String::from(name))
+    }
+
+    pub fn opaque(name: &str) -> Type {
+        Type::Opaque(String::from(name))
+    }
+
+    pub fn optional(value: Type) -> Type {
+        Type::Optional(Box::new(value))
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Null => write!(f, "null_type"),
+            Type::Bool => write!(f, "bool"),
+            Type::Int => write!(f, "int"),
+            Type::Uint => write!(f, "uint"),
+            Type::Double => write!(f, "double"),
+            Type::String => write!(f, "string"),
+            Type::Bytes => write!(f, "bytes"),
+            Type::List(elem) => write!(f, "list({})", elem),
+            Type::Map(key, value) => write!(f, "map({}, {})", key, value),
+            Type::Dyn => write!(f, "dyn"),
+            Type::Param(name) | Type::Opaque(name) => write!(f, "{}", name),
+            Type::Optional(value) => write!(f, "optional_type({})", value),
+            Type::Error => write!(f, "*error*"),
+        }
+    }
+}
+
+/// One signature of a function. For member overloads the receiver is the
+/// first parameter.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Overload {
+    pub id: String,
+    pub member: bool,
+    pub params: Vec<Type>,
+    pub result: Type,
+}
+
+impl Overload {
+    pub fn global(id: &str, params: Vec<Type>, result: Type) -> Overload {
+        Overload {
+            id: String::from(id),
+            member: false,
+            params,
+            result,
+        }
+    }
+
+    pub fn member(id: &str, params: Vec<Type>, result: Type) -> Overload {
+        Overload {
+            id: String::from(id),
+            member: true,
+            params,
+            result,
+        }
+    }
+}
+
+/// A constraint on the argument types of every call of a function, beyond
+/// what its overloads express, e.g. that the arguments of a variadic function
+/// are all numbers.
+pub type ArgumentCheck = fn(&[Type]) -> bool;
+
+/// The variables and functions an expression may refer to. Operators are
+/// declared under their CEL names, e.g. `_+_` and `-_`.
+#[derive(Debug, Clone, Default)]
+pub struct Declarations {
+    variables: HashMap<String, Type>,
+    functions: HashMap<String, Vec<Overload>>,
+    macros: HashSet<String>,
+    argument_checks: HashMap<String, ArgumentCheck>,
+}
+
+impl Declarations {
+    /// Declarations for every operator and method the interpreter supports.
+    pub fn standard() -> Declarations {
+        let mut decls = Declarations::default();
+        let comparable = [
+            ("int64", Type::Int),
+            ("uint64", Type::Uint),
+            ("string", Type::String),
+            ("bytes", Type::Bytes),
+        ];
+        for (function, prefix) in &[
+            ("_==_", "equals"),
+            ("_!=_", "not_equals"),
+            ("_<_", "less"),
+            ("_<=_", "less_equals"),
+            ("_>_", "greater"),
+            ("_>=_", "greater_equals"),
+        ] {
+            for (suffix, t) in &comparable {
+                decls.add_overload(
+                    function,
+                    Overload::global(
+                        &format!("{}_{}", prefix, suffix),
+                        vec![t.clone(), t.clone()],
+                        Type::Bool,
+                    ),
+                );
+            }
+        }
+        for (function, id, t) in &[
+            ("_+_", "add_int64", Type::Int),
+            ("_+_", "add_uint64", Type::Uint),
+            ("_+_", "add_string", Type::String),
+            ("_+_", "add_list", Type::list(Type::param("A"))),
+            ("_-_", "subtract_int64", Type::Int),
+            ("_-_", "subtract_uint64", Type::Uint),
+            ("_*_", "multiply_int64", Type::Int),
+            ("_*_", "multiply_uint64", Type::Uint),
+            ("_*_", "multiply_double", Type::Double),
+            ("_/_", "divide_int64", Type::Int),
+            ("_/_", "divide_uint64", Type::Uint),
+            ("_/_", "divide_double", Type::Double),
+            ("_%_", "modulo_int64", Type::Int),
+            ("_%_", "modulo_uint64", Type::Uint),
+        ] {
+            decls.add_overload(
+                function,
+                Overload::global(id, vec![t.clone(), t.clone()], t.clone()),
+            );
+        }
+        decls.add_overload(
+            "-_",
+            Overload::global("negate_int64", vec![Type::Int], Type::Int),
+        );
+        decls.add_overload(
+            "_&&_",
+            Overload::global("logical_and", vec![Type::Bool, Type::Bool], Type::Bool),
+        );
+        decls.add_overload(
+            "_||_",
+            Overload::global("logical_or", vec![Type::Bool, Type::Bool], Type::Bool),
+        );
+        decls.add_overload(
+            "_?_:_",
+            Overload::global(
+                "conditional",
+                vec![Type::Bool, Type::param("A"), Type::param("A")],
+                Type::param("A"),
+            ),
+        );
+        decls.add_overload(
+            "!_",
+            Overload::global("logical_not", vec![Type::Bool], Type::Bool),
+        );
+        decls.add_overload(
+            "_[_]",
+            Overload::global(
+                "index_list",
+                vec![Type::list(Type::param("A")), Type::Int],
+                Type::param("A"),
+            ),
+        );
+        decls.add_overload(
+            "_[_]",
+            Overload::global(
+                "index_map",
+                vec![
+                    Type::map(Type::param("K"), Type::param("V")),
+                    Type::param("K"),
+                ],
+                Type::param("V"),
+            ),
+        );
+        for (suffix, t) in [
+            ("string", Type::String),
+            ("bytes", Type::Bytes),
+            ("list", Type::list(Type::param("A"))),
+            ("map", Type::map(Type::param("K"), Type::param("V"))),
+        ] {
+            decls.add_overload(
+                "len",
+                Overload::member(&format!("len_{}", suffix), vec![t], Type::Int),
+            );
+        }
+        decls.add_overload(
+            "pow",
+            Overload::member("pow_int64", vec![Type::Int, Type::Int], Type::Int),
+        );
+        decls.add_overload(
+            "pow",
+            Overload::member("pow_double", vec![Type::Double, Type::Double], Type::Double),
+        );
+        decls.add_overload(
+            "pow",
+            Overload::member(
+                "pow_double_int64",
+                vec![Type::Double, Type::Int],
+                Type::Double,
+            ),
+        );
+        decls.add_overload(
+            "contains",
+            Overload::member(
+                "contains_list",
+                vec![Type::list(Type::param("A")), Type::param("A")],
+                Type::Bool,
+            ),
+        );
+        decls.add_optionals();
+        decls
+    }
+
+    /// The functions of optional values, and the operators that accept them.
+    fn add_optionals(&mut self) {
+        let a = Type::optional(Type::param("A"));
+        for (function, id) in &[("_==_", "equals_optional"), ("_!=_", "not_equals_optional")] {
+            self.add_overload(
+                function,
+                Overload::global(id, vec![a.clone(), a.clone()], Type::Bool),
+            );
+        }
+        for (function, id) in &[
+            ("optional.of", "optional_of"),
+            ("optional.ofNonZeroValue", "optional_ofNonZeroValue"),
+        ] {
+            self.add_overload(
+                function,
+                Overload::global(id, vec![Type::param("A")], a.clone()),
+            );
+        }
+        self.add_overload(
+            "optional.none",
+            Overload::global("optional_none", vec![], a.clone()),
+        );
+        self.add_overload(
+            "hasValue",
+            Overload::member("optional_hasValue", vec![a.clone()], Type::Bool),
+        );
+        self.add_overload(
+            "value",
+            Overload::member("optional_value", vec![a.clone()], Type::param("A")),
+        );
+        self.add_overload(
+            "or",
+            Overload::member(
+                "optional_or_optional",
+                vec![a.clone(), a.clone()],
+                a.clone(),
+            ),
+        );
+        self.add_overload(
+            "orValue",
+            Overload::member(
+                "optional_orValue_value",
+                vec![a.clone(), Type::param("A")],
+                Type::param("A"),
+            ),
+        );
+        let list = Type::list(Type::param("A"));
+        let map = Type::map(Type::param("K"), Type::param("V"));
+        let v = Type::optional(Type::param("V"));
+        for (function, id, params, result) in [
+            (
+                "_[?_]",
+                "list_optindex_optional_int",
+                vec![list.clone(), Type::Int],
+                a.clone(),
+            ),
+            (
+                "_[?_]",
+                "map_optindex_optional_value",
+                vec![map.clone(), Type::param("K")],
+                v.clone(),
+            ),
+            (
+                "_[?_]",
+                "optional_list_optindex_optional_int",
+                vec![Type::optional(list.clone()), Type::Int],
+                a.clone(),
+            ),
+            (
+                "_[?_]",
+                "optional_map_optindex_optional_value",
+                vec![Type::optional(map.clone()), Type::param("K")],
+                v.clone(),
+            ),
+            (
+                "_[_]",
+                "optional_list_index_int",
+                vec![Type::optional(list), Type::Int],
+                a,
+            ),
+            (
+                "_[_]",
+                "optional_map_index_value",
+                vec![Type::optional(map), Type::param("K")],
+                v,
+            ),
+        ] {
+            self.add_overload(function, Overload::global(id, params, result));
+        }
+    }
+
+    pub fn add_variable(&mut self, name: &str, t: Type) {
+        self.variables.insert(String::from(name), t);
+    }
+
+    pub fn add_overload(&mut self, function: &str, overload: Overload) {
+        self.functions
+            .entry(String::from(function))
+            .or_default()
+            .push(overload);
+    }
+
+    /// Restricts calls of `function` to arguments whose types pass `check`.
+    /// A call that fails it matches none of the function's overloads.
+    pub fn add_argument_check(&mut self, function: &str, check: ArgumentCheck) {
+        self.argument_checks.insert(String::from(function), check);
+    }
+
+    /// Declares that the member function `function` is a macro, called as
+    /// `receiver.function(x, body)` where `body` may refer to the variable
+    /// `x`, which ranges over the elements of the receiver. Its overloads
+    /// take the receiver, `x` and `body` as parameters.
+    pub fn add_macro(&mut self, function: &str, overloads: Vec<Overload>) {
+        self.macros.insert(String::from(function));
+        for overload in overloads {
+            self.add_overload(function, overload);
+        }
+    }
+}
+
+/// An expression together with the inferred type of each of its nodes.
+/// `types` is keyed by node id, as is `overloads`, which holds the ids of the
+/// overloads each call may resolve to and has no entry for nodes that are not
+/// calls. The ids of the nodes must be unique, as they are in a parsed tree.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CheckedExpression {
+    pub expr: Expression,
+    pub types: HashMap<u64, Type>,
+    pub overloads: HashMap<u64, Vec<String>>,
+}
+
+impl CheckedExpression {
+    pub fn result_type(&self) -> &Type {
+        &self.types[&self.expr.id]
+    }
+
+    /// The type of node `id`.
+    pub fn type_of(&self, id: u64) -> Option<&Type> {
+        self.types.get(&id)
+    }
+
+    /// The ids of the overloads that the call `id` may resolve to, which is
+    /// empty if `id` is not a call.
+    pub fn overloads_of(&self, id: u64) -> &[String] {
+        self.overloads.get(&id).map_or(&[], |ids| &ids[..])
+    }
+}
+
+/// A type error, and the id of the node it was found at.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CheckError {
+    pub id: u64,
+    pub message: String,
+    /// Where the node is in the source, if known.
+    pub location: Option<Location>,
+}
+
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "{} at {}", self.message, location),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+/// Checks `expr` against `decls`, returning every error found, in the order
+/// of the nodes they were found at.
+pub fn check(expr: Expression, decls: &Declarations) -> Result<CheckedExpression, Vec<CheckError>> {
+    let mut checker = Checker {
+        decls,
+        scopes: Vec::new(),
+        types: HashMap::new(),
+        overloads: HashMap::new(),
+        errors: Vec::new(),
+    };
+    checker.check(&expr);
+    if checker.errors.is_empty() {
+        Ok(CheckedExpression {
+            expr,
+            types: checker.types,
+            overloads: checker.overloads,
+        })
+    } else {
+        Err(checker.errors)
+    }
+}
+
+/// Like `check`, but gives each error the location of its node in the
+/// source, e.g. "undeclared reference to 'x' at 1:5".
+pub fn check_with_source_info(
+    expr: Expression,
+    decls: &Declarations,
+    source_info: &SourceInfo,
+) -> Result<CheckedExpression, Vec<CheckError>> {
+    check(expr, decls).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|error| CheckError {
+                location: source_info.location(error.id),
+                ..error
+            })
+            .collect()
+    })
+}
+
+struct Checker<'a> {
+    decls: &'a Declarations,
+    /// The variables bound by the macros enclosing the node being checked,
+    /// innermost last.
+    scopes: Vec<(String, Type)>,
+    types: HashMap<u64, Type>,
+    overloads: HashMap<u64, Vec<String>>,
+    errors: Vec<CheckError>,
+}
+
+impl<'a> Checker<'a> {
+    fn check(&mut self, expr: &Expression) -> Type {
+        let id = expr.id;
+        let t = match &expr.kind {
+            ExprKind::Conditional(cond, a, b) => self.call(id, "_?_:_", false, &[cond, a, b]),
+            ExprKind::Or(a, b) => self.call(id, "_||_", false, &[a, b]),
+            ExprKind::And(a, b) => self.call(id, "_&&_", false, &[a, b]),
+            ExprKind::Eq(a, b) => self.call(id, "_==_", false, &[a, b]),
+            ExprKind::Neq(a, b) => self.call(id, "_!=_", false, &[a, b]),
+            ExprKind::Lt(a, b) => self.call(id, "_<_", false, &[a, b]),
+            ExprKind::Lte(a, b) => self.call(id, "_<=_", false, &[a, b]),
+            ExprKind::Gte(a, b) => self.call(id, "_>=_", false, &[a, b]),
+            ExprKind::Gt(a, b) => self.call(id, "_>_", false, &[a, b]),
+            ExprKind::Add(a, b) => self.call(id, "_+_", false, &[a, b]),
+            ExprKind::Sub(a, b) => self.call(id, "_-_", false, &[a, b]),
+            ExprKind::Mul(a, b) => self.call(id, "_*_", false, &[a, b]),
+            ExprKind::Div(a, b) => self.call(id, "_/_", false, &[a, b]),
+            ExprKind::Mod(a, b) => self.call(id, "_%_", false, &[a, b]),
+            ExprKind::Neg(a) => self.call(id, "-_", false, &[a]),
+            ExprKind::Not(a) => self.call(id, "!_", false, &[a]),
+            ExprKind::Index(a, b) => self.call(id, "_[_]", false, &[a, b]),
+            ExprKind::OptionalIndex(a, b) => self.call(id, "_[?_]", false, &[a, b]),
+            ExprKind::Select(e, field) => {
+                let operand = self.check(e);
+                self.select(expr.id, operand, field)
+            }
+            ExprKind::OptionalSelect(e, field) => {
+                let operand = self.check(e);
+                match self.select(expr.id, operand, field) {
+                    t @ Type::Optional(_) | t @ Type::Error => t,
+                    t => Type::optional(t),
+                }
+            }
+            ExprKind::OptionalElement(e) => match self.check(e) {
+                Type::Optional(t) => *t,
+                t @ Type::Dyn | t @ Type::Error => t,
+                t => {
+                    self.error(
+                        expr.id,
+                        format!("expected an optional value but found '{}'", t),
+                    );
+                    Type::Error
+                }
+            },
+            ExprKind::Method(e, name, args) => match self.namespaced(e, name) {
+                Some(function) => {
+                    // The receiver only names the function's namespace, so
+                    // its nodes have no type of their own.
+                    let mut node = e.as_ref();
+                    loop {
+                        self.types.insert(node.id, Type::Dyn);
+                        match &node.kind {
+                            ExprKind::Select(operand, _) => node = operand,
+                            _ => break,
+                        }
+                    }
+                    let operands: Vec<&Expression> = args.iter().collect();
+                    self.call(id, &function, false, &operands)
+                }
+                None => match macro_arguments(name, args) {
+                    Some((var, body)) if self.decls.macros.contains(&name.to_string()) => {
+                        self.bind(id, &name.to_string(), e, args[0].id, var, body)
+                    }
+                    _ => {
+                        let mut operands = vec![e.as_ref()];
+                        operands.extend(args.iter());
+                        self.call(id, &name.to_string(), true, &operands)
+                    }
+                },
+            },
+            ExprKind::Ident(name) => match self
+                .scopes
+                .iter()
+                .rev()
+                .find(|(var, _)| var == name)
+                .map(|(_, t)| t)
+                .or_else(|| self.decls.variables.get(name))
+            {
+                Some(t) => t.clone(),
+                None => {
+                    self.error(expr.id, format!("undeclared reference to '{}'", name));
+                    Type::Error
+                }
+            },
+            ExprKind::Call(function, args) => {
+                let operands: Vec<&Expression> = args.iter().collect();
+                self.call(id, function, false, &operands)
+            }
+            ExprKind::Lit(literal) => self.check_literal(literal),
+        };
+        self.types.insert(id, t.clone());
+        t
+    }
+
+    fn error(&mut self, id: u64, message: String) {
+        self.errors.push(CheckError {
+            id,
+            message,
+            location: None,
+        });
+    }
+
+    fn check_literal(&mut self, literal: &Literal) -> Type {
+        match literal {
+            Literal::I64(_) => Type::Int,
+            Literal::U64(_) => Type::Uint,
+            Literal::F64(_) => Type::Double,
+            Literal::Bool(_) => Type::Bool,
+            Literal::String(_) => Type::String,
+            Literal::Bytes(_) => Type::Bytes,
+            Literal::Null => Type::Null,
+            Literal::Opaque(value) => Type::Opaque(String::from(value.type_name())),
+            Literal::Optional(None) => Type::optional(Type::Dyn),
+            Literal::Optional(Some(value)) => Type::optional(self.check_literal(value)),
+            Literal::List(xs) => {
+                let elems: Vec<Type> = xs.iter().map(|x| self.check(x)).collect();
+                Type::list(join(&elems))
+            }
+            Literal::Map(entries) => {
+                let mut keys = Vec::new();
+                let mut values = Vec::new();
+                for (k, v) in entries {
+                    keys.push(self.check(k));
+                    values.push(self.check(v));
+                }
+                Type::map(join(&keys), join(&values))
+            }
+        }
+    }
+
+    /// The type of selecting `field` from a value of type `operand`. Selecting
+    /// from an optional is presence-tested, so the result is optional too.
+    fn select(&mut self, id: u64, operand: Type, field: &str) -> Type {
+        match operand {
+            Type::Map(key, value) if matches!(*key, Type::String | Type::Dyn | Type::Param(_)) => {
+                *value
+            }
+            Type::Optional(operand) => match self.select(id, *operand, field) {
+                t @ Type::Optional(_) | t @ Type::Error => t,
+                t => Type::optional(t),
+            },
+            Type::Dyn => Type::Dyn,
+            Type::Error => Type::Error,
+            t => {
+                self.error(
+                    id,
+                    format!(
+                        "type '{}' does not support field selection of '{}'",
+                        t, field
+                    ),
+                );
+                Type::Error
+            }
+        }
+    }
+
+    /// The name of the global function that the method call `receiver.name`
+    /// refers to, if `receiver` is a path that qualifies a declared function,
+    /// as in `strings.quote(s)`.
+    fn namespaced(&self, receiver: &Expression, name: &MethodName) -> Option<String> {
+        let function = format!("{}.{}", qualified_name(receiver)?, name);
+        let overloads = self.decls.functions.get(&function)?;
+        if overloads.iter().any(|o| !o.member) {
+            Some(function)
+        } else {
+            None
+        }
+    }
+
+    /// Checks the macro call `receiver.function(var, body)`, with `var` bound
+    /// to the type of the receiver's elements while checking `body`. `var_id`
+    /// is the id of the node that names `var`.
+    fn bind(
+        &mut self,
+        id: u64,
+        function: &str,
+        receiver: &Expression,
+        var_id: u64,
+        var: &str,
+        body: &Expression,
+    ) -> Type {
+        let receiver = self.check(receiver);
+        let element = match &receiver {
+            Type::List(t) | Type::Map(t, _) => (**t).clone(),
+            _ => Type::Dyn,
+        };
+        self.types.insert(var_id, element.clone());
+        self.scopes.push((String::from(var), element.clone()));
+        let body = self.check(body);
+        self.scopes.pop();
+        self.resolve(id, function, true, vec![receiver, element, body])
+    }
+
+    fn call(&mut self, id: u64, function: &str, member: bool, operands: &[&Expression]) -> Type {
+        let args: Vec<Type> = operands.iter().map(|e| self.check(e)).collect();
+        self.resolve(id, function, member, args)
+    }
+
+    /// The result type of calling `function` with arguments of types `args`.
+    fn resolve(&mut self, id: u64, function: &str, member: bool, args: Vec<Type>) -> Type {
+        if args.contains(&Type::Error) {
+            return Type::Error;
+        }
+        let overloads = match self.decls.functions.get(function) {
+            Some(overloads) => overloads,
+            None => {
+                self.error(id, format!("undeclared reference to '{}'", function));
+                return Type::Error;
+            }
+        };
+        let accepted = match self.decls.argument_checks.get(function) {
+            Some(check) => check(&args),
+            None => true,
+        };
+        let mut results = Vec::new();
+        for overload in overloads {
+            if !accepted || overload.member != member || overload.params.len() != args.len() {
+                continue;
+            }
+            let mut bindings = HashMap::new();
+            if overload
+                .params
+                .iter()
+                .zip(args.iter())
+                .all(|(param, arg)| is_assignable(param, arg, &mut bindings))
+            {
+                results.push(substitute(&overload.result, &bindings));
+                self.overloads
+                    .entry(id)
+                    .or_default()
+                    .push(overload.id.clone());
+            }
+        }
+        if results.is_empty() {
+            let signature = if member {
+                format!("{}.({})", args[0], format_types(&args[1..]))
+            } else {
+                format!("({})", format_types(&args))
+            };
+            self.error(
+                id,
+                format!(
+                    "found no matching overload for '{}' applied to '{}'",
+                    function, signature
+                ),
+            );
+            return Type::Error;
+        }
+        join(&results)
+    }
+}
+
+/// The most specific type that describes all of `types`.
+fn join(types: &[Type]) -> Type {
+    match types.split_first() {
+        Some((first, rest)) if rest.iter().all(|t| t == first) => first.clone(),
+        _ => Type::Dyn,
+    }
+}
+
+fn is_assignable(param: &Type, arg: &Type, bindings: &mut HashMap<String, Type>) -> bool {
+    match (param, arg) {
+        (Type::Param(name), _) => match bindings.get(name).cloned() {
+            Some(bound) => is_assignable(&bound, arg, bindings),
+            None => {
+                bindings.insert(name.clone(), arg.clone());
+                true
+            }
+        },
+        (Type::Dyn, _) | (_, Type::Dyn) | (Type::Error, _) | (_, Type::Error) => true,
+        (Type::List(a), Type::List(b)) | (Type::Optional(a), Type::Optional(b)) => {
+            is_assignable(a, b, bindings)
+        }
+        (Type::Map(ka, va), Type::Map(kb, vb)) => {
+            is_assignable(ka, kb, bindings) && is_assignable(va, vb, bindings)
+        }
+        _ => param == arg,
+    }
+}
+
+fn substitute(t: &Type, bindings: &HashMap<String, Type>) -> Type {
+    match t {
+        Type::Param(name) => bindings.get(name).cloned().unwrap_or(Type::Dyn),
+        Type::List(elem) => Type::list(substitute(elem, bindings)),
+        Type::Optional(value) => Type::optional(substitute(value, bindings)),
+        Type::Map(key, value) => Type::map(substitute(key, bindings), substitute(value, bindings)),
+        other => other.clone(),
+    }
+}
+
+fn format_types(types: &[Type]) -> String {
+    types
+        .iter()
+        .map(|t| t.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, check_with_source_info, Declarations, Overload, Type};
+    use crate::model::ExprKind;
+    use crate::parsers::{parse, parse_with_source_info, ParserOptions};
+
+    use std::collections::HashMap;
+
+    fn type_of(input: &str, decls: &Declarations) -> Result<Type, String> {
+        check(parse(input).unwrap(), decls)
+            .map(|c| c.result_type().clone())
+            .map_err(|errors| {
+                errors
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+    }
+
+    #[test]
+    fn literals() {
+        let decls = Declarations::standard();
+        assert_eq!(type_of("1", &decls), Ok(Type::Int));
+        assert_eq!(type_of("1.5", &decls), Ok(Type::Double));
+        assert_eq!(type_of("1u + 2u", &decls), Ok(Type::Uint));
+        assert!(type_of("1u + 2", &decls).is_err());
+        assert_eq!(type_of("'a'", &decls), Ok(Type::String));
+        assert_eq!(type_of("b'a'", &decls), Ok(Type::Bytes));
+        assert_eq!(type_of("[1, 2]", &decls), Ok(Type::list(Type::Int)));
+        assert_eq!(type_of("[1, 'a']", &decls), Ok(Type::list(Type::Dyn)));
+        assert_eq!(
+            type_of("{'a': 1}", &decls),
+            Ok(Type::map(Type::String, Type::Int))
+        );
+        assert_eq!(type_of("{}", &decls), Ok(Type::map(Type::Dyn, Type::Dyn)));
+    }
+
+    #[test]
+    fn operators() {
+        let decls = Declarations::standard();
+        assert_eq!(type_of("1 + 2 * 3", &decls), Ok(Type::Int));
+        assert_eq!(type_of("'a' + 'b' == 'ab'", &decls), Ok(Type::Bool));
+        assert_eq!(type_of("!(1 < 2)", &decls), Ok(Type::Bool));
+        assert_eq!(type_of("2.0.pow(2)", &decls), Ok(Type::Double));
+        assert_eq!(type_of("1 < 2 && 2 < 3 || false", &decls), Ok(Type::Bool));
+        assert_eq!(type_of("true ? 'a' : 'b'", &decls), Ok(Type::String));
+        assert_eq!(
+            type_of("true ? 'a' : 1", &decls),
+            Err(String::from(
+                "found no matching overload for '_?_:_' applied to '(bool, string, int)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn no_matching_overload() {
+        let decls = Declarations::standard();
+        assert_eq!(
+            type_of(r#" "asdf" + 5 "#, &decls),
+            Err(String::from(
+                "found no matching overload for '_+_' applied to '(string, int)'"
+            ))
+        );
+        assert_eq!(
+            type_of(r#" "asdf".pow(2) "#, &decls),
+            Err(String::from(
+                "found no matching overload for 'pow' applied to 'string.(int)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn errors_do_not_cascade() {
+        let decls = Declarations::standard();
+        assert_eq!(
+            type_of(r#" ("a" - "b") + x "#, &decls),
+            Err(String::from(
+                "found no matching overload for '_-_' applied to '(string, string)'\n\
+                 undeclared reference to 'x'"
+            ))
+        );
+    }
+
+    #[test]
+    fn errors_name_their_node() {
+        let expr = parse("1 + (2 - 'a') * x").unwrap();
+        let (sub, x) = match &expr.kind {
+            ExprKind::Add(_, mul) => match &mul.kind {
+                ExprKind::Mul(sub, x) => (sub.id, x.id),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let errors = check(expr, &Declarations::standard()).unwrap_err();
+        assert_eq!(
+            errors.iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![sub, x]
+        );
+    }
+
+    #[test]
+    fn errors_report_their_location() {
+        let input = "1 +\n  ('a' - x)";
+        let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+        let errors = check_with_source_info(expr, &Declarations::standard(), &info).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(messages, vec!["undeclared reference to 'x' at 2:10"]);
+    }
+
+    #[test]
+    fn variables() {
+        let mut decls = Declarations::standard();
+        decls.add_variable("x", Type::Int);
+        decls.add_variable("names", Type::list(Type::String));
+        assert_eq!(type_of("x + 1", &decls), Ok(Type::Int));
+        assert_eq!(type_of("names[x]", &decls), Ok(Type::String));
+        assert_eq!(type_of("names.contains('a')", &decls), Ok(Type::Bool));
+        assert_eq!(
+            type_of("names.contains(x)", &decls),
+            Err(String::from(
+                "found no matching overload for 'contains' applied to 'list(string).(int)'"
+            ))
+        );
+        assert_eq!(
+            type_of("y", &decls),
+            Err(String::from("undeclared reference to 'y'"))
+        );
+    }
+
+    #[test]
+    fn parameterized_maps() {
+        let mut decls = Declarations::standard();
+        decls.add_variable("m", Type::map(Type::String, Type::list(Type::Int)));
+        assert_eq!(type_of("m['a']", &decls), Ok(Type::list(Type::Int)));
+        assert_eq!(type_of("m['a'][0] + 1", &decls), Ok(Type::Int));
+        assert_eq!(type_of("m.len()", &decls), Ok(Type::Int));
+        assert_eq!(type_of("m.a[0]", &decls), Ok(Type::Int));
+        assert_eq!(
+            type_of("{1: 'a'}.a", &decls),
+            Err(String::from(
+                "type 'map(int, string)' does not support field selection of 'a'"
+            ))
+        );
+        assert_eq!(
+            type_of("m[0]", &decls),
+            Err(String::from(
+                "found no matching overload for '_[_]' applied to '(map(string, list(int)), int)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn dyn_is_assignable_to_anything() {
+        let mut decls = Declarations::standard();
+        decls.add_variable("d", Type::Dyn);
+        assert_eq!(type_of("d + 1", &decls), Ok(Type::Int));
+        assert_eq!(type_of("d[0]", &decls), Ok(Type::Dyn));
+        assert_eq!(type_of("d.a.b", &decls), Ok(Type::Dyn));
+        assert_eq!(type_of("d + d", &decls), Ok(Type::Dyn));
+    }
+
+    #[test]
+    fn custom_overloads() {
+        let mut decls = Declarations::standard();
+        decls.add_overload(
+            "_+_",
+            Overload::global("add_double", vec![Type::Double, Type::Double], Type::Double),
+        );
+        assert_eq!(type_of("1.0 + 2.0", &decls), Ok(Type::Double));
+        assert_eq!(
+            type_of("1 + 2", &Declarations::default()),
+            Err(String::from("undeclared reference to '_+_'"))
+        );
+    }
+
+    #[test]
+    fn optionals() {
+        let mut decls = Declarations::standard();
+        decls.add_variable("m", Type::map(Type::String, Type::Int));
+        decls.add_variable("xs", Type::list(Type::String));
+        let optional_int = Ok(Type::optional(Type::Int));
+        assert_eq!(type_of("m.?a", &decls), optional_int);
+        assert_eq!(type_of("m[?'a']", &decls), optional_int);
+        assert_eq!(type_of("optional.of(m).?a", &decls), optional_int);
+        assert_eq!(type_of("optional.of(m)['a']", &decls), optional_int);
+        assert_eq!(type_of("xs[?0].orValue('')", &decls), Ok(Type::String));
+        assert_eq!(type_of("m.?a.hasValue()", &decls), Ok(Type::Bool));
+        assert_eq!(type_of("m.?a == optional.none()", &decls), Ok(Type::Bool));
+        assert_eq!(type_of("[?m.?a, 1]", &decls), Ok(Type::list(Type::Int)));
+        assert_eq!(
+            type_of("{?'k': xs[?0]}", &decls),
+            Ok(Type::map(Type::String, Type::String))
+        );
+        assert_eq!(
+            type_of("[?m.a]", &decls),
+            Err(String::from("expected an optional value but found 'int'"))
+        );
+        assert_eq!(
+            type_of("m.?a.orValue('')", &decls),
+            Err(String::from(
+                "found no matching overload for 'orValue' applied to 'optional_type(int).(string)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn annotates_every_node() {
+        // Ids are assigned in post-order: 1, 2, [1, 2], 0, [..][0], 'a', len,
+        // and the addition.
+        let checked = check(
+            parse("[1, 2][0] + 'a'.len()").unwrap(),
+            &Declarations::standard(),
+        )
+        .unwrap();
+        let types: HashMap<u64, Type> = vec![
+            (1, Type::Int),
+            (2, Type::Int),
+            (3, Type::list(Type::Int)),
+            (4, Type::Int),
+            (5, Type::Int),
+            (6, Type::String),
+            (7, Type::Int),
+            (8, Type::Int),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(checked.types, types);
+        let overloads: HashMap<u64, Vec<String>> = vec![
+            (5, vec![String::from("index_list")]),
+            (7, vec![String::from("len_string")]),
+            (8, vec![String::from("add_int64")]),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(checked.overloads, overloads);
+        assert_eq!(checked.overloads_of(3), &[] as &[String]);
+    }
+
+    #[test]
+    fn annotates_macro_variables() {
+        let mut decls = Declarations::standard();
+        decls.add_macro(
+            "all",
+            vec![Overload::member(
+                "all_list",
+                vec![Type::list(Type::param("A")), Type::param("A"), Type::Bool],
+                Type::Bool,
+            )],
+        );
+        // The variable `x` is the third node built, after `'a'` and `['a']`.
+        let checked = check(parse("['a'].all(x, x == 'a')").unwrap(), &decls).unwrap();
+        assert_eq!(checked.type_of(3), Some(&Type::String));
+        assert_eq!(checked.result_type(), &Type::Bool);
+    }
+}
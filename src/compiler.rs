@@ -0,0 +1,309 @@
+use crate::analysis::{macro_arguments, qualified_name};
+use crate::interpreter::Functions;
+use crate::model::{ExprKind, Expression, Literal, MethodName};
+use crate::optimizer::is_constant;
+
+/// A builtin operation, resolved at compile time so that the VM can dispatch
+/// on it directly.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Function {
+    Neg,
+    Not,
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gte,
+    Gt,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Index,
+    /// Indexes a list or map, producing an optional.
+    OptionalIndex,
+    /// Selects the given field of a map.
+    Select(String),
+    /// Selects the given field of a map, producing an optional.
+    OptionalSelect(String),
+    And,
+    Or,
+    /// A method call with the given number of arguments, not counting the
+    /// receiver.
+    Method(MethodName, usize),
+    /// A call of the global function with the given name and number of
+    /// arguments.
+    Global(String, usize),
+    /// A method call of the form `receiver.name(x, body)`, which expands the
+    /// macro `name` if one is registered. Otherwise the two arguments are
+    /// passed to the method as usual.
+    Macro(MethodName, String, Box<Expression>),
+    /// A method call on a variable or field path, which
+    /// calls the global function with the qualified name instead, e.g.
+    /// `strings.quote`, if one is registered. Otherwise it makes the given
+    /// method or macro call.
+    Qualified(String, Box<Function>),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Instruction {
+    /// Pushes `constants[i]`.
+    Const(usize),
+    /// Pushes the value of the variable `names[i]`.
+    Load(usize),
+    /// Pops the operands of a function and pushes its result.
+    Call(Function),
+    /// Pops one value per element of `collections[i]` and pushes a list.
+    List(usize),
+    /// Pops a key and a value per entry of `collections[i]` and pushes a map.
+    Map(usize),
+    /// Jumps if the top of the stack is `false`, leaving it in place.
+    JumpIfFalse(usize),
+    /// Jumps if the top of the stack is `true`, leaving it in place.
+    JumpIfTrue(usize),
+    /// Pops a condition and falls through if it is `true` or jumps to
+    /// `otherwise` if it is `false`. Anything else is pushed back as an error
+    /// and jumps to `end`.
+    Branch {
+        otherwise: usize,
+        end: usize,
+    },
+    Jump(usize),
+}
+
+/// A compiled expression. Evaluating it never recurses on the native stack
+/// except to evaluate list elements and map entries that failed when the
+/// collection was built.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    pub(crate) instructions: Vec<Instruction>,
+    pub(crate) constants: Vec<Literal>,
+    pub(crate) names: Vec<String>,
+    /// The source of each list element or map value, kept so that elements
+    /// which fail to evaluate can be stored lazily, as the interpreter does.
+    pub(crate) collections: Vec<Vec<Expression>>,
+}
+
+pub fn compile(expr: &Expression) -> Program {
+    let mut program = Program {
+        instructions: Vec::new(),
+        constants: Vec::new(),
+        names: Vec::new(),
+        collections: Vec::new(),
+    };
+    program.emit_expression(expr);
+    program
+}
+
+/// Compiles `expr` for evaluation with `functions`, after running the
+/// preparations that they come with, such as compiling constant patterns.
+pub fn compile_with_functions(expr: &Expression, functions: &Functions) -> Result<Program, String> {
+    let mut expr = expr.clone();
+    functions.prepare(&mut expr)?;
+    Ok(compile(&expr))
+}
+
+impl Program {
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.instructions.push(instruction);
+        self.instructions.len() - 1
+    }
+
+    fn emit_expression(&mut self, expr: &Expression) {
+        match &expr.kind {
+            ExprKind::Conditional(cond, a, b) => {
+                self.emit_expression(cond);
+                let branch = self.emit(Instruction::Branch {
+                    otherwise: 0,
+                    end: 0,
+                });
+                self.emit_expression(a);
+                let jump = self.emit(Instruction::Jump(0));
+                let otherwise = self.instructions.len();
+                self.emit_expression(b);
+                let end = self.instructions.len();
+                self.instructions[branch] = Instruction::Branch { otherwise, end };
+                self.instructions[jump] = Instruction::Jump(end);
+            }
+            ExprKind::Or(a, b) => {
+                self.emit_expression(a);
+                let jump = self.emit(Instruction::JumpIfTrue(0));
+                self.emit_expression(b);
+                self.emit(Instruction::Call(Function::Or));
+                self.instructions[jump] = Instruction::JumpIfTrue(self.instructions.len());
+            }
+            ExprKind::And(a, b) => {
+                self.emit_expression(a);
+                let jump = self.emit(Instruction::JumpIfFalse(0));
+                self.emit_expression(b);
+                self.emit(Instruction::Call(Function::And));
+                self.instructions[jump] = Instruction::JumpIfFalse(self.instructions.len());
+            }
+            ExprKind::Eq(a, b) => self.emit_call(Function::Eq, &[a, b]),
+            ExprKind::Neq(a, b) => self.emit_call(Function::Neq, &[a, b]),
+            ExprKind::Lt(a, b) => self.emit_call(Function::Lt, &[a, b]),
+            ExprKind::Lte(a, b) => self.emit_call(Function::Lte, &[a, b]),
+            ExprKind::Gte(a, b) => self.emit_call(Function::Gte, &[a, b]),
+            ExprKind::Gt(a, b) => self.emit_call(Function::Gt, &[a, b]),
+            ExprKind::Add(a, b) => self.emit_call(Function::Add, &[a, b]),
+            ExprKind::Sub(a, b) => self.emit_call(Function::Sub, &[a, b]),
+            ExprKind::Mul(a, b) => self.emit_call(Function::Mul, &[a, b]),
+            ExprKind::Div(a, b) => self.emit_call(Function::Div, &[a, b]),
+            ExprKind::Mod(a, b) => self.emit_call(Function::Mod, &[a, b]),
+            ExprKind::Neg(a) => self.emit_call(Function::Neg, &[a]),
+            ExprKind::Not(a) => self.emit_call(Function::Not, &[a]),
+            ExprKind::Index(a, b) => self.emit_call(Function::Index, &[a, b]),
+            ExprKind::OptionalIndex(a, b) => self.emit_call(Function::OptionalIndex, &[a, b]),
+            ExprKind::Select(e, field) => self.emit_call(Function::Select(field.clone()), &[e]),
+            ExprKind::OptionalSelect(e, field) => {
+                self.emit_call(Function::OptionalSelect(field.clone()), &[e])
+            }
+            // The list or map it is an element of unwraps the optional.
+            ExprKind::OptionalElement(e) => self.emit_expression(e),
+            ExprKind::Method(e, name, args) => {
+                let mut operands = vec![e.as_ref()];
+                operands.extend(args.iter());
+                let mut function = match macro_arguments(name, args) {
+                    Some((var, body)) => {
+                        Function::Macro(name.clone(), String::from(var), Box::new(body.clone()))
+                    }
+                    None => Function::Method(name.clone(), args.len()),
+                };
+                if let Some(receiver) = qualified_name(e) {
+                    let qualified = format!("{}.{}", receiver, name);
+                    function = Function::Qualified(qualified, Box::new(function));
+                }
+                self.emit_call(function, &operands);
+            }
+            ExprKind::Call(function, args) => {
+                let operands: Vec<&Expression> = args.iter().collect();
+                self.emit_call(Function::Global(function.clone(), args.len()), &operands);
+            }
+            ExprKind::Ident(name) => {
+                let i = match self.names.iter().position(|n| n == name) {
+                    Some(i) => i,
+                    None => {
+                        self.names.push(name.clone());
+                        self.names.len() - 1
+                    }
+                };
+                self.emit(Instruction::Load(i));
+            }
+            ExprKind::Lit(literal) if is_constant(expr) => {
+                self.constants.push(literal.clone());
+                let i = self.constants.len() - 1;
+                self.emit(Instruction::Const(i));
+            }
+            ExprKind::Lit(Literal::List(xs)) => {
+                for x in xs {
+                    self.emit_expression(x);
+                }
+                self.collections.push(xs.clone());
+                let i = self.collections.len() - 1;
+                self.emit(Instruction::List(i));
+            }
+            ExprKind::Lit(Literal::Map(entries)) => {
+                for (k, v) in entries {
+                    self.emit_expression(k);
+                    self.emit_expression(v);
+                }
+                self.collections
+                    .push(entries.iter().map(|(_, v)| v.clone()).collect());
+                let i = self.collections.len() - 1;
+                self.emit(Instruction::Map(i));
+            }
+            ExprKind::Lit(_) => unreachable!(),
+        }
+    }
+
+    fn emit_call(&mut self, function: Function, operands: &[&Expression]) {
+        for operand in operands {
+            self.emit_expression(operand);
+        }
+        self.emit(Instruction::Call(function));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, Function, Instruction};
+    use crate::model::MethodName;
+    use crate::parsers::parse;
+
+    #[test]
+    fn operators() {
+        let program = compile(&parse("x + 1 * x").unwrap());
+        assert_eq!(
+            program.instructions(),
+            &[
+                Instruction::Load(0),
+                Instruction::Const(0),
+                Instruction::Load(0),
+                Instruction::Call(Function::Mul),
+                Instruction::Call(Function::Add),
+            ]
+        );
+        assert_eq!(program.names, vec![String::from("x")]);
+    }
+
+    #[test]
+    fn short_circuit() {
+        let program = compile(&parse("x && y || z").unwrap());
+        assert_eq!(
+            program.instructions(),
+            &[
+                Instruction::Load(0),
+                Instruction::JumpIfFalse(4),
+                Instruction::Load(1),
+                Instruction::Call(Function::And),
+                Instruction::JumpIfTrue(7),
+                Instruction::Load(2),
+                Instruction::Call(Function::Or),
+            ]
+        );
+    }
+
+    #[test]
+    fn conditional() {
+        let program = compile(&parse("x ? 1 : 2").unwrap());
+        assert_eq!(
+            program.instructions(),
+            &[
+                Instruction::Load(0),
+                Instruction::Branch {
+                    otherwise: 4,
+                    end: 5
+                },
+                Instruction::Const(0),
+                Instruction::Jump(5),
+                Instruction::Const(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn collections() {
+        let program = compile(&parse("[1, 2].contains(x) || {x: 1}.len() == 1").unwrap());
+        assert_eq!(
+            program.instructions(),
+            &[
+                Instruction::Const(0),
+                Instruction::Load(0),
+                Instruction::Call(Function::Method(MethodName::Contains, 1)),
+                Instruction::JumpIfTrue(11),
+                Instruction::Load(0),
+                Instruction::Const(1),
+                Instruction::Map(0),
+                Instruction::Call(Function::Method(MethodName::Len, 0)),
+                Instruction::Const(2),
+                Instruction::Call(Function::Eq),
+                Instruction::Call(Function::Or),
+            ]
+        );
+    }
+}
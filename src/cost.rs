@@ -0,0 +1,382 @@
+use crate::analysis::macro_arguments;
+use crate::checker::{CheckedExpression, Type};
+use crate::model::{ExprKind, Expression, Literal};
+
+use std::collections::HashMap;
+
+/// The size of a value for the purpose of costing the operations applied to
+/// it: the length of strings, bytes, lists and maps, and 0 for scalars.
+pub(crate) fn size(value: &Literal) -> u64 {
+    match value {
+        Literal::String(s) => s.len() as u64,
+        Literal::Bytes(b) => b.len() as u64,
+        Literal::List(xs) => xs.len() as u64,
+        Literal::Map(entries) => entries.len() as u64,
+        _ => 0,
+    }
+}
+
+/// Bounds on the cost of evaluating an expression, in the units charged by
+/// evaluation against an `EvalOptions::budget`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CostEstimate {
+    pub min: u64,
+    pub max: u64,
+}
+
+/// Bounds on the size of a value, as defined by `cost::size`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SizeEstimate {
+    pub min: u64,
+    pub max: u64,
+}
+
+impl SizeEstimate {
+    pub fn exactly(size: u64) -> SizeEstimate {
+        SizeEstimate {
+            min: size,
+            max: size,
+        }
+    }
+
+    pub fn unknown() -> SizeEstimate {
+        SizeEstimate {
+            min: 0,
+            max: u64::MAX,
+        }
+    }
+
+    fn add(self, other: SizeEstimate) -> SizeEstimate {
+        SizeEstimate {
+            min: self.min.saturating_add(other.min),
+            max: self.max.saturating_add(other.max),
+        }
+    }
+}
+
+impl CostEstimate {
+    fn add(self, other: CostEstimate) -> CostEstimate {
+        CostEstimate {
+            min: self.min.saturating_add(other.min),
+            max: self.max.saturating_add(other.max),
+        }
+    }
+
+    fn charge(self, size: SizeEstimate) -> CostEstimate {
+        CostEstimate {
+            min: self.min.saturating_add(size.min),
+            max: self.max.saturating_add(size.max),
+        }
+    }
+}
+
+/// Computes the minimum and maximum cost of evaluating a checked expression.
+///
+/// `sizes` bounds the size of variables. Variables of a string, bytes, list,
+/// map or dynamic type without a bound are assumed to be arbitrarily large,
+/// so the maximum cost of anything that depends on them is `u64::MAX`.
+pub fn estimate_cost(
+    checked: &CheckedExpression,
+    sizes: &HashMap<String, SizeEstimate>,
+) -> CostEstimate {
+    let estimator = Estimator { checked, sizes };
+    estimator.estimate(&checked.expr).0
+}
+
+struct Estimator<'a> {
+    checked: &'a CheckedExpression,
+    sizes: &'a HashMap<String, SizeEstimate>,
+}
+
+impl<'a> Estimator<'a> {
+    /// Estimates the cost of `expr` and the size of its value.
+    fn estimate(&self, expr: &Expression) -> (CostEstimate, SizeEstimate) {
+        let t = self.checked.type_of(expr.id).unwrap_or(&Type::Dyn);
+        let node = CostEstimate { min: 1, max: 1 };
+        match &expr.kind {
+            ExprKind::Lit(Literal::List(xs)) => {
+                let mut cost = node;
+                for x in xs {
+                    cost = cost.add(self.estimate(x).0);
+                }
+                (cost, collection_size(xs.iter()))
+            }
+            ExprKind::Lit(Literal::Map(entries)) => {
+                let mut cost = node;
+                for (k, v) in entries {
+                    cost = cost.add(self.estimate(k).0).add(self.estimate(v).0);
+                }
+                (cost, collection_size(entries.iter().map(|(_, v)| v)))
+            }
+            ExprKind::Lit(literal) => (node, SizeEstimate::exactly(size(literal))),
+            ExprKind::Ident(name) => {
+                let size = match self.sizes.get(name) {
+                    Some(size) => *size,
+                    None => result_size(t, SizeEstimate::unknown()),
+                };
+                (node, size)
+            }
+            ExprKind::Conditional(cond, a, b) => {
+                let (cond, _) = self.estimate(cond);
+                let (a, a_size) = self.estimate(a);
+                let (b, b_size) = self.estimate(b);
+                let cost = CostEstimate {
+                    min: a.min.min(b.min),
+                    max: a.max.max(b.max),
+                };
+                let size = SizeEstimate {
+                    min: a_size.min.min(b_size.min),
+                    max: a_size.max.max(b_size.max),
+                };
+                (node.add(cond).add(cost), size)
+            }
+            ExprKind::Or(a, b) | ExprKind::And(a, b) => {
+                let (a, _) = self.estimate(a);
+                let (b, _) = self.estimate(b);
+                let cost = CostEstimate {
+                    min: a.min,
+                    max: a.max.saturating_add(b.max),
+                };
+                (node.add(cost), SizeEstimate::exactly(0))
+            }
+            ExprKind::Eq(a, b)
+            | ExprKind::Neq(a, b)
+            | ExprKind::Lt(a, b)
+            | ExprKind::Lte(a, b)
+            | ExprKind::Gte(a, b)
+            | ExprKind::Gt(a, b)
+            | ExprKind::Add(a, b)
+            | ExprKind::Sub(a, b)
+            | ExprKind::Mul(a, b)
+            | ExprKind::Div(a, b)
+            | ExprKind::Mod(a, b)
+            | ExprKind::Index(a, b)
+            | ExprKind::OptionalIndex(a, b) => {
+                let (a, a_size) = self.estimate(a);
+                let (b, b_size) = self.estimate(b);
+                let operand_sizes = a_size.add(b_size);
+                let size = match expr.kind {
+                    ExprKind::Add(_, _) => result_size(t, operand_sizes),
+                    _ => result_size(t, SizeEstimate::unknown()),
+                };
+                (node.add(a).add(b).charge(operand_sizes), size)
+            }
+            ExprKind::Select(e, _) | ExprKind::OptionalSelect(e, _) => {
+                let (e, e_size) = self.estimate(e);
+                let size = result_size(t, SizeEstimate::unknown());
+                (node.add(e).charge(e_size), size)
+            }
+            ExprKind::OptionalElement(e) => {
+                let (e, e_size) = self.estimate(e);
+                (node.add(e), e_size)
+            }
+            ExprKind::Neg(a) | ExprKind::Not(a) => {
+                let (a, a_size) = self.estimate(a);
+                (node.add(a).charge(a_size), SizeEstimate::exactly(0))
+            }
+            ExprKind::Method(e, name, args) => {
+                if let Some((_, body)) = macro_arguments(name, args) {
+                    // The body is evaluated once for each element of the
+                    // receiver, and the variable it binds is never evaluated.
+                    let (e, e_size) = self.estimate(e);
+                    let (body, _) = self.estimate(body);
+                    let cost = CostEstimate {
+                        min: body.min.saturating_mul(e_size.min),
+                        max: body.max.saturating_mul(e_size.max),
+                    };
+                    let size = result_size(t, SizeEstimate::unknown());
+                    return (node.add(e).add(cost), size);
+                }
+                let (mut cost, mut operand_sizes) = self.estimate(e);
+                for arg in args {
+                    let (arg, arg_size) = self.estimate(arg);
+                    cost = cost.add(arg);
+                    operand_sizes = operand_sizes.add(arg_size);
+                }
+                let size = result_size(t, SizeEstimate::unknown());
+                (node.add(cost).charge(operand_sizes), size)
+            }
+            ExprKind::Call(_, args) => {
+                let mut cost = node;
+                let mut operand_sizes = SizeEstimate::exactly(0);
+                for arg in args {
+                    let (arg, arg_size) = self.estimate(arg);
+                    cost = cost.add(arg);
+                    operand_sizes = operand_sizes.add(arg_size);
+                }
+                let size = result_size(t, SizeEstimate::unknown());
+                (cost.charge(operand_sizes), size)
+            }
+        }
+    }
+}
+
+/// The size of a list or map with the given elements or values, those of
+/// which that are optional, `?x`, possibly being left out.
+fn collection_size<'e>(elements: impl Iterator<Item = &'e Expression>) -> SizeEstimate {
+    let mut size = SizeEstimate::exactly(0);
+    for e in elements {
+        size.max += 1;
+        if !matches!(e.kind, ExprKind::OptionalElement(_)) {
+            size.min += 1;
+        }
+    }
+    size
+}
+
+/// The size of a value of type `t`, given an estimate that applies if `t`
+/// is not a scalar.
+fn result_size(t: &Type, estimate: SizeEstimate) -> SizeEstimate {
+    match t {
+        Type::Null | Type::Bool | Type::Int | Type::Double => SizeEstimate::exactly(0),
+        _ => estimate,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{estimate_cost, CostEstimate, SizeEstimate};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{
+        evaluate_with_options, Activation, EvalError, EvalOptions, Functions,
+    };
+    use crate::model::{Expression, Literal};
+    use crate::parsers::parse;
+
+    use std::collections::HashMap;
+
+    fn declarations() -> Declarations {
+        let mut decls = Declarations::standard();
+        decls.add_variable("x", Type::Int);
+        decls.add_variable("s", Type::String);
+        decls.add_variable("xs", Type::list(Type::Int));
+        decls
+    }
+
+    fn estimate(input: &str, sizes: &HashMap<String, SizeEstimate>) -> CostEstimate {
+        let checked = check(parse(input).unwrap(), &declarations()).unwrap();
+        estimate_cost(&checked, sizes)
+    }
+
+    #[test]
+    fn constant_expressions() {
+        let sizes = HashMap::new();
+        assert_eq!(estimate("1", &sizes), CostEstimate { min: 1, max: 1 });
+        assert_eq!(estimate("1 + 2", &sizes), CostEstimate { min: 3, max: 3 });
+        assert_eq!(
+            estimate("'ab' + 'cd' == 'abcd'", &sizes),
+            CostEstimate { min: 17, max: 17 }
+        );
+        assert_eq!(
+            estimate("[1, 2, 3].contains(x)", &sizes),
+            CostEstimate { min: 9, max: 9 }
+        );
+    }
+
+    #[test]
+    fn short_circuiting() {
+        let sizes = HashMap::new();
+        assert_eq!(
+            estimate("x < 1 && x + 1 < 3", &sizes),
+            CostEstimate { min: 4, max: 9 }
+        );
+        assert_eq!(
+            estimate("x < 1 ? 1 : x * x * x", &sizes),
+            CostEstimate { min: 5, max: 9 }
+        );
+    }
+
+    #[test]
+    fn unbounded_variables() {
+        let sizes = HashMap::new();
+        assert_eq!(estimate("x * x", &sizes), CostEstimate { min: 3, max: 3 });
+        assert_eq!(
+            estimate("s + s", &sizes),
+            CostEstimate {
+                min: 3,
+                max: u64::MAX
+            }
+        );
+    }
+
+    #[test]
+    fn declared_sizes() {
+        let mut sizes = HashMap::new();
+        sizes.insert(String::from("s"), SizeEstimate { min: 0, max: 100 });
+        sizes.insert(String::from("xs"), SizeEstimate::exactly(10));
+        assert_eq!(
+            estimate("(s + s).len()", &sizes),
+            CostEstimate { min: 4, max: 404 }
+        );
+        assert_eq!(
+            estimate("xs.contains(x)", &sizes),
+            CostEstimate { min: 13, max: 13 }
+        );
+    }
+
+    #[test]
+    fn macro_bodies_scale_with_the_receiver() {
+        let mut decls = declarations();
+        crate::ext::lists::declare(&mut decls);
+        let mut sizes = HashMap::new();
+        sizes.insert(String::from("xs"), SizeEstimate { min: 2, max: 10 });
+        let checked = check(parse("xs.sortBy(v, v + 1)").unwrap(), &decls).unwrap();
+        let cost = estimate_cost(&checked, &sizes);
+        assert_eq!(cost, CostEstimate { min: 8, max: 32 });
+
+        let mut functions = Functions::new();
+        crate::ext::lists::register(&mut functions);
+        let mut activation = Activation::new();
+        let xs = (0..10).map(|x| Expression::from(Literal::I64(x))).collect();
+        activation.insert(String::from("xs"), Literal::List(xs));
+        for (budget, ok) in [(cost.max, true), (cost.max - 1, false)] {
+            let options = EvalOptions {
+                functions: &functions,
+                budget,
+                ..EvalOptions::default()
+            };
+            let result = evaluate_with_options(checked.expr.clone(), &activation, options);
+            assert_eq!(result.is_ok(), ok, "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn estimate_bounds_runtime_cost() {
+        let mut sizes = HashMap::new();
+        sizes.insert(String::from("s"), SizeEstimate::exactly(50));
+        let mut activation = Activation::new();
+        activation.insert(String::from("x"), Literal::I64(3));
+        activation.insert(String::from("s"), Literal::String("x".repeat(50)));
+        activation.insert(
+            String::from("xs"),
+            Literal::List(vec![Expression::from(Literal::I64(1))]),
+        );
+        let evaluate_with_budget = |input: &str, budget| {
+            let options = EvalOptions {
+                budget,
+                ..EvalOptions::default()
+            };
+            evaluate_with_options(parse(input).unwrap(), &activation, options)
+        };
+        for input in &[
+            "s + s + s == s",
+            "x > 2 ? (s + 'a').len() : xs.len()",
+            "[s, s][x - 3] < 'b' || xs.contains(x)",
+        ] {
+            let cost = estimate(input, &sizes);
+            assert!(
+                evaluate_with_budget(input, cost.max).is_ok(),
+                "{} exceeded {:?}",
+                input,
+                cost
+            );
+            assert_eq!(
+                evaluate_with_budget(input, cost.min - 1),
+                Err(EvalError::CostLimitExceeded(cost.min - 1)),
+                "{} within {:?}",
+                input,
+                cost
+            );
+        }
+    }
+}
@@ -0,0 +1,109 @@
+//! Optional libraries of functions, after cel-go's `ext` package.
+//!
+//! None of them is available by default. Each library has a `register`
+//! function, which adds its implementations to the `Functions` passed to
+//! evaluation, and a `declare` function, which adds their signatures to the
+//! checker's `Declarations`.
+//!
+//! `encoders` and `regex` need the features of the same names.
+
+#[cfg(feature = "encoders")]
+pub mod encoders;
+pub mod lists;
+pub mod math;
+pub mod network;
+pub mod quantity;
+#[cfg(feature = "regex")]
+pub mod regex;
+pub mod semver;
+pub mod sets;
+pub mod strings;
+pub mod url;
+
+use crate::interpreter::evaluate;
+use crate::model::{Expression, Literal};
+
+use std::cmp::Ordering;
+
+/// The values of the elements of a list.
+pub(crate) fn elements(xs: Vec<Expression>) -> Result<Vec<Literal>, String> {
+    xs.into_iter().map(evaluate).collect()
+}
+
+/// Orders two numbers, of the same type or not, by their exact values, with
+/// NaN after all of them so that this is a total order. `None` unless both
+/// are numbers.
+pub(crate) fn compare_numbers(a: &Literal, b: &Literal) -> Option<Ordering> {
+    Some(match (a, b) {
+        (Literal::F64(a), Literal::F64(b)) => compare_doubles(*a, *b),
+        (Literal::F64(a), b) => compare_integer_double(integer(b)?, *a).reverse(),
+        (a, Literal::F64(b)) => compare_integer_double(integer(a)?, *b),
+        (a, b) => integer(a)?.cmp(&integer(b)?),
+    })
+}
+
+/// The value of an int or a uint.
+fn integer(value: &Literal) -> Option<i128> {
+    match value {
+        Literal::I64(x) => Some(i128::from(*x)),
+        Literal::U64(x) => Some(i128::from(*x)),
+        _ => None,
+    }
+}
+
+fn compare_doubles(a: f64, b: f64) -> Ordering {
+    a.partial_cmp(&b)
+        .unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+/// Orders an int or uint and a double without rounding the former, which a
+/// conversion to double would for magnitudes above 2^53.
+fn compare_integer_double(a: i128, b: f64) -> Ordering {
+    // 2^64 and -2^63, the doubles beyond every uint and every int.
+    const MAX: f64 = 18_446_744_073_709_551_616.0;
+    const MIN: f64 = -9_223_372_036_854_775_808.0;
+    if b.is_nan() || b >= MAX {
+        return Ordering::Less;
+    }
+    if b < MIN {
+        return Ordering::Greater;
+    }
+    let whole = b.trunc();
+    a.cmp(&(whole as i128))
+        .then_with(|| compare_doubles(0.0, b - whole))
+}
+
+/// The CEL name of the type of `value`, for error messages.
+pub(crate) fn type_name(value: &Literal) -> &'static str {
+    match value {
+        Literal::I64(_) => "int",
+        Literal::U64(_) => "uint",
+        Literal::F64(_) => "double",
+        Literal::Bool(_) => "bool",
+        Literal::String(_) => "string",
+        Literal::Bytes(_) => "bytes",
+        Literal::List(_) => "list",
+        Literal::Map(_) => "map",
+        Literal::Null => "null_type",
+        Literal::Optional(_) => "optional_type",
+        Literal::Opaque(value) => value.type_name(),
+    }
+}
+
+/// The error for a call of `function` with arguments it has no overload for.
+pub(crate) fn no_overload(function: &str, args: &[Literal]) -> String {
+    let types: Vec<&str> = args.iter().map(type_name).collect();
+    format!(
+        "found no matching overload for '{}' applied to '({})'",
+        function,
+        types.join(", ")
+    )
+}
+
+/// `string`, for the opaque values of every library that declares it.
+pub(crate) fn string(args: Vec<Literal>) -> Result<Literal, String> {
+    match args.as_slice() {
+        [Literal::Opaque(value)] => Ok(Literal::String(value.to_string())),
+        _ => Err(no_overload("string", &args)),
+    }
+}
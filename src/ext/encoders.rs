@@ -0,0 +1,171 @@
+//! Encoding functions for bytes, after cel-go's `ext.Encoders`:
+//! `base64.encode`, `base64.decode`, `hex.encode` and `hex.decode`.
+//!
+//! `base64.decode` accepts input with or without padding.
+
+use super::no_overload;
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::Functions;
+use crate::model::Literal;
+
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+
+type Result<T> = std::result::Result<T, String>;
+
+/// The standard alphabet, decoding whether or not the input is padded.
+const LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("base64.encode", base64_encode);
+    functions.add_global("base64.decode", base64_decode);
+    functions.add_global("hex.encode", hex_encode);
+    functions.add_global("hex.decode", hex_decode);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    for (function, id, param, result) in [
+        (
+            "base64.encode",
+            "base64_encode_bytes",
+            Type::Bytes,
+            Type::String,
+        ),
+        (
+            "base64.decode",
+            "base64_decode_string",
+            Type::String,
+            Type::Bytes,
+        ),
+        ("hex.encode", "hex_encode_bytes", Type::Bytes, Type::String),
+        ("hex.decode", "hex_decode_string", Type::String, Type::Bytes),
+    ] {
+        decls.add_overload(function, Overload::global(id, vec![param], result));
+    }
+}
+
+fn base64_encode(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::Bytes(b)] => Ok(Literal::String(STANDARD.encode(b))),
+        _ => Err(no_overload("base64.encode", &args)),
+    }
+}
+
+fn base64_decode(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => LENIENT
+            .decode(s)
+            .map(Literal::Bytes)
+            .map_err(|e| format!("invalid base64 '{}': {}", s, e)),
+        _ => Err(no_overload("base64.decode", &args)),
+    }
+}
+
+fn hex_encode(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::Bytes(b)] => Ok(Literal::String(
+            b.iter().map(|b| format!("{:02x}", b)).collect(),
+        )),
+        _ => Err(no_overload("hex.encode", &args)),
+    }
+}
+
+/// Decodes pairs of hex digits of either case.
+fn hex_decode(args: Vec<Literal>) -> Result<Literal> {
+    let s = match args.as_slice() {
+        [Literal::String(s)] => s,
+        _ => return Err(no_overload("hex.decode", &args)),
+    };
+    let invalid = |reason: &str| format!("invalid hex '{}': {}", s, reason);
+    if s.len() % 2 != 0 {
+        return Err(invalid("odd length"));
+    }
+    let digit = |c: u8| match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(invalid("invalid digit")),
+    };
+    let mut bytes = Vec::with_capacity(s.len() / 2);
+    for pair in s.as_bytes().chunks(2) {
+        bytes.push(digit(pair[0])? << 4 | digit(pair[1])?);
+    }
+    Ok(Literal::Bytes(bytes))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::Literal;
+    use crate::parsers::parse;
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        evaluate_with_functions(parse(input).unwrap(), &Activation::new(), &functions)
+    }
+
+    fn string(s: &str) -> Literal {
+        Literal::String(String::from(s))
+    }
+
+    fn bytes(b: &[u8]) -> Literal {
+        Literal::Bytes(b.to_vec())
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(eval("base64.encode(b'hello')"), Ok(string("aGVsbG8=")));
+        assert_eq!(eval("base64.encode(b'')"), Ok(string("")));
+        assert_eq!(eval("base64.decode('aGVsbG8=')"), Ok(bytes(b"hello")));
+        assert_eq!(eval("base64.decode('aGVsbG8')"), Ok(bytes(b"hello")));
+        assert_eq!(
+            eval("base64.decode(base64.encode(b'\\xff\\x00'))"),
+            Ok(bytes(&[0xff, 0]))
+        );
+        assert!(eval("base64.decode('not base64!')").is_err());
+        assert!(eval("base64.decode('aGVsbG8==x')").is_err());
+        assert_eq!(
+            eval("base64.encode('hello')"),
+            Err(String::from(
+                "found no matching overload for 'base64.encode' applied to '(string)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn hex() {
+        assert_eq!(eval("hex.encode(b'\\x00\\xabz')"), Ok(string("00ab7a")));
+        assert_eq!(eval("hex.decode('00AB7a')"), Ok(bytes(&[0, 0xab, 0x7a])));
+        assert_eq!(
+            eval("hex.decode('abc')"),
+            Err(String::from("invalid hex 'abc': odd length"))
+        );
+        assert_eq!(
+            eval("hex.decode('zz')"),
+            Err(String::from("invalid hex 'zz': invalid digit"))
+        );
+        assert_eq!(
+            eval("hex.decode('+1')"),
+            Err(String::from("invalid hex '+1': invalid digit"))
+        );
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        let type_of =
+            |input: &str| check(parse(input).unwrap(), &decls).map(|c| c.result_type().clone());
+        assert_eq!(type_of("base64.decode('YQ==')"), Ok(Type::Bytes));
+        assert_eq!(type_of("hex.encode(b'a') == 'a'"), Ok(Type::Bool));
+        assert!(type_of("hex.decode(b'a')").is_err());
+    }
+}
@@ -0,0 +1,655 @@
+//! List functions, after cel-go's `ext.Lists`: `lists.range`, `slice`,
+//! `flatten`, `sort`, `sortBy`, `distinct`, `reverse`, `first`, `last`,
+//! `indexOf` and `lastIndexOf`.
+//!
+//! As with `contains`, a function that can answer without the value of an
+//! element that fails to evaluate does so: `indexOf` finds a match before it,
+//! and `slice`, `flatten` and `reverse` keep it as an element of the result.
+
+use super::sets::key;
+use super::{compare_numbers, elements, no_overload, type_name};
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::{evaluate, EvalResult, Functions};
+use crate::model::{Expression, Literal};
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+type Result<T> = std::result::Result<T, String>;
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("lists.range", range);
+    functions.add_member("slice", "list", slice);
+    functions.add_member("flatten", "list", flatten);
+    functions.add_member("sort", "list", sort);
+    functions.add_macro("sortBy", sort_by);
+    functions.add_member("distinct", "list", distinct);
+    functions.add_member("reverse", "list", reverse);
+    functions.add_member("first", "list", first);
+    functions.add_member("last", "list", last);
+    functions.add_member("indexOf", "list", index_of);
+    functions.add_member("lastIndexOf", "list", last_index_of);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    let a = || Type::param("A");
+    let list = || Type::list(Type::param("A"));
+    decls.add_overload(
+        "lists.range",
+        Overload::global("lists_range", vec![Type::Int], Type::list(Type::Int)),
+    );
+    for (function, id, params, result) in [
+        (
+            "slice",
+            "list_slice",
+            vec![list(), Type::Int, Type::Int],
+            list(),
+        ),
+        ("flatten", "list_flatten", vec![Type::list(list())], list()),
+        (
+            "flatten",
+            "list_flatten_int",
+            vec![Type::list(Type::Dyn), Type::Int],
+            Type::list(Type::Dyn),
+        ),
+        ("sort", "list_sort", vec![list()], list()),
+        ("distinct", "list_distinct", vec![list()], list()),
+        ("reverse", "list_reverse", vec![list()], list()),
+        ("first", "list_first", vec![list()], Type::optional(a())),
+        ("last", "list_last", vec![list()], Type::optional(a())),
+        ("indexOf", "list_index_of", vec![list(), a()], Type::Int),
+        (
+            "lastIndexOf",
+            "list_last_index_of",
+            vec![list(), a()],
+            Type::Int,
+        ),
+    ] {
+        decls.add_overload(function, Overload::member(id, params, result));
+    }
+    decls.add_macro(
+        "sortBy",
+        vec![Overload::member(
+            "list_sort_by",
+            vec![list(), a(), Type::Dyn],
+            list(),
+        )],
+    );
+}
+
+fn list(xs: Vec<Literal>) -> Literal {
+    Literal::List(xs.into_iter().map(Expression::from).collect())
+}
+
+/// The largest list `lists.range` builds. The cost of a call is charged for
+/// its arguments, not its result, so the size of the result has to be
+/// bounded by other means.
+const MAX_RANGE: i64 = 100_000;
+
+/// `lists.range(n)`: the ints from 0 up to, but not including, `n`.
+fn range(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::I64(n)] if *n > MAX_RANGE => Err(format!(
+            "lists.range({}) exceeds the maximum of {} elements",
+            n, MAX_RANGE
+        )),
+        [Literal::I64(n)] => Ok(list((0..*n).map(Literal::I64).collect())),
+        _ => Err(no_overload("lists.range", &args)),
+    }
+}
+
+/// `xs.slice(start, end)`: the elements from `start` up to, but not
+/// including, `end`.
+fn slice(args: Vec<Literal>) -> Result<Literal> {
+    let (xs, start, end) = match args.as_slice() {
+        [Literal::List(xs), Literal::I64(start), Literal::I64(end)] => (xs, *start, *end),
+        _ => return Err(no_overload("slice", &args)),
+    };
+    if start < 0 || end < 0 {
+        return Err(format!(
+            "cannot slice({}, {}), negative indexes not supported",
+            start, end
+        ));
+    }
+    if start > end {
+        return Err(format!(
+            "cannot slice({}, {}), start index must be less than or equal to end index",
+            start, end
+        ));
+    }
+    if end as u64 > xs.len() as u64 {
+        return Err(format!(
+            "cannot slice({}, {}), list is length {}",
+            start,
+            end,
+            xs.len()
+        ));
+    }
+    Ok(Literal::List(xs[start as usize..end as usize].to_vec()))
+}
+
+/// `xs.flatten()` flattens one level of nested lists, and `xs.flatten(n)` up
+/// to `n` levels.
+fn flatten(args: Vec<Literal>) -> Result<Literal> {
+    let (xs, depth) = match args.as_slice() {
+        [Literal::List(xs)] => (xs, 1),
+        [Literal::List(xs), Literal::I64(depth)] => (xs, *depth),
+        _ => return Err(no_overload("flatten", &args)),
+    };
+    if depth < 0 {
+        return Err(String::from("level must be non-negative"));
+    }
+    Ok(Literal::List(flattened(xs.clone(), depth)))
+}
+
+fn flattened(xs: Vec<Expression>, depth: i64) -> Vec<Expression> {
+    if depth == 0 {
+        return xs;
+    }
+    let mut flat = Vec::with_capacity(xs.len());
+    for x in xs {
+        match evaluate(x.clone()) {
+            Ok(Literal::List(ys)) => flat.extend(flattened(ys, depth - 1)),
+            _ => flat.push(x),
+        }
+    }
+    flat
+}
+
+/// Orders two elements of a list being sorted, which must both be numbers
+/// or both be of the same orderable type. Numbers are ordered by their exact
+/// values, with NaN after all of them, so that this is a total order.
+fn compare(a: &Literal, b: &Literal) -> Result<Ordering> {
+    if let Some(ordering) = compare_numbers(a, b) {
+        return Ok(ordering);
+    }
+    match (a, b) {
+        (Literal::String(a), Literal::String(b)) => Ok(a.cmp(b)),
+        (Literal::Bytes(a), Literal::Bytes(b)) => Ok(a.cmp(b)),
+        (Literal::Bool(a), Literal::Bool(b)) => Ok(a.cmp(b)),
+        _ if type_name(a) == type_name(b) => Err(format!(
+            "list elements of type {} are not orderable",
+            type_name(a)
+        )),
+        _ => Err(format!(
+            "cannot sort a list of mixed types: {} and {}",
+            type_name(a),
+            type_name(b)
+        )),
+    }
+}
+
+/// Sorts `xs` stably by their `keys`, which must all be comparable.
+fn sort_by_keys(xs: Vec<Literal>, keys: Vec<Literal>) -> Result<Literal> {
+    for pair in keys.windows(2) {
+        compare(&pair[0], &pair[1])?;
+    }
+    if let [only] = keys.as_slice() {
+        compare(only, only)?;
+    }
+    let mut pairs: Vec<(Literal, Literal)> = keys.into_iter().zip(xs).collect();
+    // Comparable types are either all numbers or all of a single type, so
+    // once every neighbour is comparable, so is every pair.
+    pairs.sort_by(|(a, _), (b, _)| compare(a, b).unwrap_or(Ordering::Equal));
+    Ok(list(pairs.into_iter().map(|(_, x)| x).collect()))
+}
+
+fn sort(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::List(xs)] => {
+            let xs = elements(xs.clone())?;
+            sort_by_keys(xs.clone(), xs)
+        }
+        _ => Err(no_overload("sort", &args)),
+    }
+}
+
+/// `xs.sortBy(x, key)`: the elements of `xs` sorted by the value of `key`
+/// with `x` bound to each of them.
+fn sort_by(receiver: Literal, key: &mut dyn FnMut(Literal) -> EvalResult) -> EvalResult {
+    let xs = match receiver {
+        Literal::List(xs) => elements(xs)?,
+        other => return Err(no_overload("sortBy", &[other])),
+    };
+    let keys = xs
+        .iter()
+        .map(|x| key(x.clone()))
+        .collect::<Result<Vec<_>>>()?;
+    sort_by_keys(xs, keys)
+}
+
+/// `xs.distinct()`: the elements of `xs` without repetitions, in the order
+/// of their first occurrence. Elements repeat under heterogeneous equality,
+/// as in `ext::sets`, so `[1, 1.0].distinct()` is `[1]`.
+fn distinct(args: Vec<Literal>) -> Result<Literal> {
+    let xs = match args.as_slice() {
+        [Literal::List(xs)] => elements(xs.clone())?,
+        _ => return Err(no_overload("distinct", &args)),
+    };
+    let mut seen = HashSet::with_capacity(xs.len());
+    let mut unique: Vec<Literal> = Vec::with_capacity(xs.len());
+    for x in xs {
+        // Values without a key, like NaN, equal nothing, not even themselves.
+        let new = match key(x.clone())? {
+            Some(k) => seen.insert(k),
+            None => true,
+        };
+        if new {
+            unique.push(x);
+        }
+    }
+    Ok(list(unique))
+}
+
+fn reverse(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::List(xs)] => Ok(Literal::List(xs.iter().rev().cloned().collect())),
+        _ => Err(no_overload("reverse", &args)),
+    }
+}
+
+/// `xs.first()`, as an optional that is empty for an empty list.
+fn first(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::List(xs)] => optional(xs.first()),
+        _ => Err(no_overload("first", &args)),
+    }
+}
+
+/// `xs.last()`, as an optional that is empty for an empty list.
+fn last(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::List(xs)] => optional(xs.last()),
+        _ => Err(no_overload("last", &args)),
+    }
+}
+
+fn optional(x: Option<&Expression>) -> Result<Literal> {
+    let value = match x {
+        Some(x) => Some(Box::new(evaluate(x.clone())?)),
+        None => None,
+    };
+    Ok(Literal::Optional(value))
+}
+
+/// The position of the first of `positions` at which `xs` holds `needle`,
+/// or -1. An element that fails to evaluate is an error unless the needle is
+/// found before it.
+fn position(
+    xs: &[Expression],
+    needle: &Literal,
+    positions: impl Iterator<Item = usize>,
+) -> Result<Literal> {
+    for i in positions {
+        if evaluate(xs[i].clone())? == *needle {
+            return Ok(Literal::I64(i as i64));
+        }
+    }
+    Ok(Literal::I64(-1))
+}
+
+fn index_of(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::List(xs), needle] => position(xs, needle, 0..xs.len()),
+        _ => Err(no_overload("indexOf", &args)),
+    }
+}
+
+fn last_index_of(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::List(xs), needle] => position(xs, needle, (0..xs.len()).rev()),
+        _ => Err(no_overload("lastIndexOf", &args)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compare, declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::compiler::compile;
+    use crate::interpreter::{
+        evaluate_with_functions, evaluate_with_options, explain, Activation, EvalError,
+        EvalOptions, EvalState, Functions,
+    };
+    use crate::model::{Expression, Literal};
+
+    use crate::parsers::{parse, parse_with_source_info, ParserOptions};
+    use crate::partial::{evaluate_partial, residual, PartialActivation, PartialValue};
+    use std::cmp::Ordering;
+
+    fn functions() -> Functions {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        functions
+    }
+
+    fn activation() -> Activation {
+        let mut activation = Activation::new();
+        let people = parse("[{'name': 'b', 'age': 30}, {'name': 'a', 'age': 20}]").unwrap();
+        activation.insert(String::from("people"), crate::evaluate(people).unwrap());
+        activation
+    }
+
+    /// Evaluates `input` with both the interpreter and the VM, which must
+    /// agree.
+    fn eval(input: &str) -> Result<Literal, String> {
+        let expr = parse(input).unwrap();
+        let functions = functions();
+        let activation = activation();
+        let result = evaluate_with_functions(expr.clone(), &activation, &functions);
+        let compiled = compile(&expr).evaluate_with_functions(&activation, &functions);
+        assert_eq!(result, compiled, "{}", input);
+        result
+    }
+
+    fn ints(xs: &[i64]) -> Literal {
+        Literal::List(
+            xs.iter()
+                .map(|x| Expression::from(Literal::I64(*x)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn range_and_slice() {
+        assert_eq!(eval("lists.range(4)"), Ok(ints(&[0, 1, 2, 3])));
+        assert_eq!(eval("lists.range(0)"), Ok(ints(&[])));
+        assert_eq!(
+            eval("lists.range(9223372036854775807)"),
+            Err(String::from(
+                "lists.range(9223372036854775807) exceeds the maximum of 100000 elements"
+            ))
+        );
+        assert_eq!(eval("[1, 2, 3, 4].slice(1, 3)"), Ok(ints(&[2, 3])));
+        assert_eq!(eval("[1, 2].slice(2, 2)"), Ok(ints(&[])));
+        assert_eq!(
+            eval("[1, 2].slice(1, 3)"),
+            Err(String::from("cannot slice(1, 3), list is length 2"))
+        );
+        assert_eq!(
+            eval("[1, 2].slice(2, 1)"),
+            Err(String::from(
+                "cannot slice(2, 1), start index must be less than or equal to end index"
+            ))
+        );
+    }
+
+    #[test]
+    fn flatten() {
+        assert_eq!(
+            eval("[1, [2, [3]], [4]].flatten()").unwrap(),
+            eval("[1, 2, [3], 4]").unwrap()
+        );
+        assert_eq!(
+            eval("[1, [2, [3]], [4]].flatten(2)"),
+            Ok(ints(&[1, 2, 3, 4]))
+        );
+        assert_eq!(
+            eval("[[1], 2].flatten(0)").unwrap(),
+            eval("[[1], 2]").unwrap()
+        );
+        assert_eq!(
+            eval("[1].flatten(0 - 1)"),
+            Err(String::from("level must be non-negative"))
+        );
+    }
+
+    #[test]
+    fn concatenation() {
+        assert_eq!(eval("[1, 2] + [3]"), Ok(ints(&[1, 2, 3])));
+        assert_eq!(eval("([3] + [1, 2]).sort()"), Ok(ints(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn sort() {
+        assert_eq!(eval("[3, 1, 2].sort()"), Ok(ints(&[1, 2, 3])));
+        assert_eq!(
+            eval("['b', 'c', 'a'].sort()").unwrap(),
+            eval("['a', 'b', 'c']").unwrap()
+        );
+        assert_eq!(eval("[2, 1.5].sort()[0]"), Ok(Literal::F64(1.5)));
+        assert_eq!(
+            eval("[1, 'a'].sort()"),
+            Err(String::from(
+                "cannot sort a list of mixed types: int and string"
+            ))
+        );
+        assert_eq!(
+            eval("[{}, {}].sort()"),
+            Err(String::from("list elements of type map are not orderable"))
+        );
+    }
+
+    #[test]
+    fn sort_mixed_numbers() {
+        assert_eq!(
+            eval("[9007199254740993, 9007199254740992.0, 9007199254740992].sort()"),
+            Ok(Literal::List(vec![
+                Literal::F64(9007199254740992.0).into(),
+                Literal::I64(9007199254740992).into(),
+                Literal::I64(9007199254740993).into(),
+            ]))
+        );
+        assert_eq!(
+            eval("[9223372036854775808.0, 9223372036854775807, 0.5, 0].sort()"),
+            Ok(Literal::List(vec![
+                Literal::I64(0).into(),
+                Literal::F64(0.5).into(),
+                Literal::I64(i64::MAX).into(),
+                Literal::F64(9223372036854775808.0).into(),
+            ]))
+        );
+        let (int, nan) = (Literal::I64(i64::MAX), Literal::F64(f64::NAN));
+        assert_eq!(compare(&int, &nan), Ok(Ordering::Less));
+        assert_eq!(compare(&nan, &nan), Ok(Ordering::Equal));
+        assert_eq!(
+            compare(&Literal::F64(-0.0), &Literal::I64(0)),
+            Ok(Ordering::Equal)
+        );
+    }
+
+    #[test]
+    fn sort_by() {
+        assert_eq!(
+            eval("people.sortBy(p, p.age)[0].name"),
+            Ok(Literal::String(String::from("a")))
+        );
+        assert_eq!(
+            eval("people.sortBy(p, p.name)[1].age"),
+            Ok(Literal::I64(30))
+        );
+        assert_eq!(eval("[3, 1, 2].sortBy(x, 0 - x)"), Ok(ints(&[3, 2, 1])));
+        assert_eq!(
+            eval("[1, 2].sortBy(x, x == 1 ? 'a' : 1)"),
+            Err(String::from(
+                "cannot sort a list of mixed types: string and int"
+            ))
+        );
+    }
+
+    #[test]
+    fn sort_by_keys_are_evaluated_like_the_rest_of_the_expression() {
+        assert_eq!(
+            eval("[[2, 1], [1]].sortBy(x, x.sortBy(x, 0 - x)[0])[0].len()"),
+            Ok(Literal::I64(1))
+        );
+        let functions = functions();
+        let activation = activation();
+        let input = "people.sortBy(p, p.age / (p.age - 20))";
+        let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+        let mut state = EvalState::default();
+        let options = EvalOptions {
+            functions: &functions,
+            source_info: Some(&info),
+            state: Some(&mut state),
+            ..EvalOptions::default()
+        };
+        assert_eq!(
+            evaluate_with_options(expr.clone(), &activation, options),
+            Err(EvalError::Failed(String::from("divide by zero at 1:24")))
+        );
+        assert!(explain(&expr, &state).contains("p.age - 20 => 0"));
+        let options = EvalOptions {
+            functions: &functions,
+            budget: 10,
+            ..EvalOptions::default()
+        };
+        let expr = parse("[1, 2, 3].sortBy(x, x + x + x + x)").unwrap();
+        assert_eq!(
+            evaluate_with_options(expr, &activation, options),
+            Err(EvalError::CostLimitExceeded(10))
+        );
+    }
+
+    #[test]
+    fn distinct_and_reverse() {
+        assert_eq!(eval("[1, 2, 1, 3, 2].distinct()"), Ok(ints(&[1, 2, 3])));
+        assert_eq!(
+            eval("[1, 1.0, 2.0, 2, 2.5].distinct()"),
+            eval("[1, 2.0, 2.5]")
+        );
+        assert_eq!(
+            eval("[[1], [1.0], {'a': 1}, {'a': 1.0}].distinct()"),
+            eval("[[1], {'a': 1}]")
+        );
+        assert_eq!(eval("[1, 2, 3].reverse()"), Ok(ints(&[3, 2, 1])));
+    }
+
+    #[test]
+    fn members_dispatch_on_the_receiver_type() {
+        let mut functions = functions();
+        crate::ext::strings::register(&mut functions);
+        let eval = |input: &str| {
+            evaluate_with_functions(parse(input).unwrap(), &Activation::new(), &functions)
+        };
+        assert_eq!(eval("[1, 2].reverse()"), Ok(ints(&[2, 1])));
+        assert_eq!(
+            eval("'ab'.reverse()"),
+            Ok(Literal::String(String::from("ba")))
+        );
+        assert_eq!(
+            eval("1.reverse()"),
+            Err(String::from(
+                "found no matching overload for 'reverse' applied to '(int)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn first_and_last() {
+        assert_eq!(eval("[1, 2, 3].first().value()"), Ok(Literal::I64(1)));
+        assert_eq!(eval("[1, 2, 3].last().value()"), Ok(Literal::I64(3)));
+        assert_eq!(eval("lists.range(0).first()"), Ok(Literal::Optional(None)));
+        assert_eq!(
+            eval("lists.range(0).last().orValue(7)"),
+            Ok(Literal::I64(7))
+        );
+    }
+
+    #[test]
+    fn index_of() {
+        assert_eq!(eval("[1, 2, 1].indexOf(1)"), Ok(Literal::I64(0)));
+        assert_eq!(eval("[1, 2, 1].lastIndexOf(1)"), Ok(Literal::I64(2)));
+        assert_eq!(eval("[1, 2].indexOf(3)"), Ok(Literal::I64(-1)));
+        assert_eq!(eval("[1, 1 / 0].indexOf(1)"), Ok(Literal::I64(0)));
+        assert_eq!(
+            eval("[1, 1 / 0].indexOf(2)"),
+            Err(String::from("divide by zero"))
+        );
+    }
+
+    #[test]
+    fn errors_inside_elements_are_kept() {
+        assert_eq!(eval("[1 / 0, 2].reverse()[0]"), Ok(Literal::I64(2)));
+        assert_eq!(eval("[[1], 1 / 0].flatten()[0]"), Ok(Literal::I64(1)));
+        assert_eq!(eval("[1 / 0, 2].slice(1, 2)"), Ok(ints(&[2])));
+        assert_eq!(
+            eval("[1 / 0, 2].sort()"),
+            Err(String::from("divide by zero"))
+        );
+        // An element that failed inside a macro keeps its error rather than
+        // being evaluated again without the macro's variable.
+        for input in [
+            "[0].sortBy(x, [1 / x][0])",
+            "[0].sortBy(x, {'a': 1 / x}.a)",
+            "[0].sortBy(x, [1 / x].contains(2))",
+            "[0].sortBy(x, [1 / x, 2].sort()[0])",
+        ]
+        .iter()
+        {
+            assert_eq!(
+                eval(input),
+                Err(String::from("divide by zero")),
+                "{}",
+                input
+            );
+        }
+        // Nor with a variable of the activation in its place.
+        let mut activation = activation();
+        activation.insert(String::from("x"), Literal::I64(1));
+        let expr = parse("[0].sortBy(x, [1 / x][0])").unwrap();
+        assert_eq!(
+            evaluate_with_functions(expr, &activation, &functions()),
+            Err(String::from("divide by zero"))
+        );
+    }
+
+    #[test]
+    fn partial_evaluation() {
+        let mut activation = PartialActivation::new(activation());
+        activation.functions = functions();
+        activation.add_unknown("xs").unwrap();
+        let expr = parse("people.sortBy(p, p.age)[0].name == 'a'").unwrap();
+        assert_eq!(residual(expr, &activation), Ok(parse("true").unwrap()));
+        let expr = parse("xs.sortBy(x, x)").unwrap();
+        assert_eq!(residual(expr.clone(), &activation), Ok(expr));
+
+        let req = parse("{'n': 1, 'auth': {'k': 2}}").unwrap();
+        let req = crate::evaluate(req).unwrap();
+        activation.values.insert(String::from("req"), req);
+        activation.add_unknown("req.auth").unwrap();
+        let expr = parse("[3, 1].sortBy(v, v + req.n)").unwrap();
+        assert_eq!(residual(expr, &activation), Ok(parse("[1, 3]").unwrap()));
+        let expr = parse("[3, 1].sortBy(v, v + req.auth.k)").unwrap();
+        match evaluate_partial(expr.clone(), &activation) {
+            Ok(PartialValue::Unknown(unknown)) => {
+                assert_eq!(unknown.residual, expr);
+                assert_eq!(
+                    unknown.attributes.into_iter().collect::<Vec<_>>(),
+                    vec![String::from("req.auth.k")]
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("xs", Type::list(Type::Int));
+        decls.add_variable("people", Type::list(Type::map(Type::String, Type::Int)));
+        let type_of =
+            |input: &str| check(parse(input).unwrap(), &decls).map(|c| c.result_type().clone());
+        assert_eq!(type_of("xs + [1]"), Ok(Type::list(Type::Int)));
+        assert_eq!(
+            type_of("lists.range(3).slice(0, 1)"),
+            Ok(Type::list(Type::Int))
+        );
+        assert_eq!(type_of("[[1], [2]].flatten()"), Ok(Type::list(Type::Int)));
+        assert_eq!(
+            type_of("xs.sort().reverse().distinct()"),
+            Ok(Type::list(Type::Int))
+        );
+        assert_eq!(type_of("xs.first()"), Ok(Type::optional(Type::Int)));
+        assert_eq!(type_of("xs.last().orValue(0)"), Ok(Type::Int));
+        assert_eq!(type_of("xs.indexOf(1)"), Ok(Type::Int));
+        assert_eq!(
+            type_of("people.sortBy(p, p.age)"),
+            Ok(Type::list(Type::map(Type::String, Type::Int)))
+        );
+        assert!(type_of("people.sortBy(p, q.age)").is_err());
+        assert!(type_of("xs.indexOf('a')").is_err());
+        assert!(type_of("xs + ['a']").is_err());
+    }
+}
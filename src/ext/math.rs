@@ -0,0 +1,452 @@
+//! Math functions, after cel-go's `ext.Math`: `math.greatest`, `math.least`,
+//! `math.abs`, `math.ceil`, `math.floor`, `math.round`, `math.trunc`,
+//! `math.sign`, `math.isInf`, `math.isNaN`, `math.isFinite` and the bit
+//! operations `math.bitAnd`, `math.bitOr`, `math.bitXor`, `math.bitNot`,
+//! `math.bitShiftLeft` and `math.bitShiftRight`, which take ints or uints.
+
+use super::{compare_numbers, elements, no_overload};
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::Functions;
+use crate::model::Literal;
+
+use std::cmp::Ordering;
+
+type Result<T> = std::result::Result<T, String>;
+
+/// The largest number of separate arguments to `math.greatest` and
+/// `math.least` the checker accepts. A list may be passed instead.
+const MAX_ARGS: usize = 8;
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("math.greatest", greatest);
+    functions.add_global("math.least", least);
+    functions.add_global("math.abs", abs);
+    functions.add_global("math.ceil", ceil);
+    functions.add_global("math.floor", floor);
+    functions.add_global("math.round", round);
+    functions.add_global("math.trunc", trunc);
+    functions.add_global("math.sign", sign);
+    functions.add_global("math.isInf", is_inf);
+    functions.add_global("math.isNaN", is_nan);
+    functions.add_global("math.isFinite", is_finite);
+    functions.add_global("math.bitAnd", bit_and);
+    functions.add_global("math.bitOr", bit_or);
+    functions.add_global("math.bitXor", bit_xor);
+    functions.add_global("math.bitNot", bit_not);
+    functions.add_global("math.bitShiftLeft", bit_shift_left);
+    functions.add_global("math.bitShiftRight", bit_shift_right);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    let numbers = [
+        ("int", Type::Int),
+        ("uint", Type::Uint),
+        ("double", Type::Double),
+    ];
+    for (function, prefix) in [
+        ("math.greatest", "math_greatest"),
+        ("math.least", "math_least"),
+    ] {
+        for (suffix, t) in &numbers {
+            let id = format!("{}_{}", prefix, suffix);
+            decls.add_overload(function, Overload::global(&id, vec![t.clone()], t.clone()));
+            let id = format!("{}_list_{}", prefix, suffix);
+            decls.add_overload(
+                function,
+                Overload::global(&id, vec![Type::list(t.clone())], t.clone()),
+            );
+            for (other_suffix, other) in &numbers {
+                let result = if t == other { t.clone() } else { Type::Dyn };
+                let id = format!("{}_{}_{}", prefix, suffix, other_suffix);
+                decls.add_overload(
+                    function,
+                    Overload::global(&id, vec![t.clone(), other.clone()], result),
+                );
+            }
+        }
+        for arity in 3..=MAX_ARGS {
+            let id = format!("{}_{}_args", prefix, arity);
+            decls.add_overload(
+                function,
+                Overload::global(&id, vec![Type::Dyn; arity], Type::Dyn),
+            );
+        }
+        decls.add_argument_check(function, numbers_only);
+    }
+    for (function, prefix) in [("math.abs", "math_abs"), ("math.sign", "math_sign")] {
+        for (suffix, t) in &numbers {
+            let id = format!("{}_{}", prefix, suffix);
+            decls.add_overload(function, Overload::global(&id, vec![t.clone()], t.clone()));
+        }
+    }
+    for (function, id) in [
+        ("math.ceil", "math_ceil_double"),
+        ("math.floor", "math_floor_double"),
+        ("math.round", "math_round_double"),
+        ("math.trunc", "math_trunc_double"),
+    ] {
+        decls.add_overload(
+            function,
+            Overload::global(id, vec![Type::Double], Type::Double),
+        );
+    }
+    for (function, id) in [
+        ("math.isInf", "math_isInf_double"),
+        ("math.isNaN", "math_isNaN_double"),
+        ("math.isFinite", "math_isFinite_double"),
+    ] {
+        decls.add_overload(
+            function,
+            Overload::global(id, vec![Type::Double], Type::Bool),
+        );
+    }
+    for (suffix, t) in [("int", Type::Int), ("uint", Type::Uint)] {
+        for (function, prefix) in [
+            ("math.bitAnd", "math_bitAnd"),
+            ("math.bitOr", "math_bitOr"),
+            ("math.bitXor", "math_bitXor"),
+        ] {
+            let id = format!("{}_{}_{}", prefix, suffix, suffix);
+            decls.add_overload(
+                function,
+                Overload::global(&id, vec![t.clone(), t.clone()], t.clone()),
+            );
+        }
+        for (function, prefix) in [
+            ("math.bitShiftLeft", "math_bitShiftLeft"),
+            ("math.bitShiftRight", "math_bitShiftRight"),
+        ] {
+            let id = format!("{}_{}_int", prefix, suffix);
+            decls.add_overload(
+                function,
+                Overload::global(&id, vec![t.clone(), Type::Int], t.clone()),
+            );
+        }
+        let id = format!("math_bitNot_{}", suffix);
+        decls.add_overload("math.bitNot", Overload::global(&id, vec![t.clone()], t));
+    }
+}
+
+/// Whether the arguments of a call of `math.greatest` or `math.least` may
+/// all be numbers. The overloads for more than two arguments take `dyn`,
+/// which leaves that to this check.
+fn numbers_only(args: &[Type]) -> bool {
+    args.len() <= 2
+        || args
+            .iter()
+            .all(|t| matches!(t, Type::Int | Type::Uint | Type::Double | Type::Dyn))
+}
+
+/// Orders two numbers by value, whatever their types.
+/// NaN is unordered, so it is never chosen over another number.
+fn compare(a: &Literal, b: &Literal) -> Option<Ordering> {
+    match (a, b) {
+        (Literal::F64(x), _) | (_, Literal::F64(x)) if x.is_nan() => None,
+        _ => compare_numbers(a, b),
+    }
+}
+
+/// The numbers `math.greatest` and `math.least` choose from: either their
+/// arguments or the elements of a single list argument.
+fn candidates(function: &str, args: Vec<Literal>) -> Result<Vec<Literal>> {
+    let candidates = match args.as_slice() {
+        [Literal::List(xs)] => elements(xs.clone())?,
+        _ => args,
+    };
+    if candidates.is_empty() {
+        return Err(format!("{}() requires at least one argument", function));
+    }
+    if !candidates
+        .iter()
+        .all(|x| matches!(x, Literal::I64(_) | Literal::U64(_) | Literal::F64(_)))
+    {
+        return Err(no_overload(function, &candidates));
+    }
+    Ok(candidates)
+}
+
+/// The first of the candidates that no other is `ordering` to.
+fn extreme(function: &str, args: Vec<Literal>, ordering: Ordering) -> Result<Literal> {
+    let mut candidates = candidates(function, args)?.into_iter();
+    let mut best = candidates.next().unwrap();
+    for x in candidates {
+        if compare(&x, &best) == Some(ordering) {
+            best = x;
+        }
+    }
+    Ok(best)
+}
+
+fn greatest(args: Vec<Literal>) -> Result<Literal> {
+    extreme("math.greatest", args, Ordering::Greater)
+}
+
+fn least(args: Vec<Literal>) -> Result<Literal> {
+    extreme("math.least", args, Ordering::Less)
+}
+
+fn abs(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::I64(x)] => x
+            .checked_abs()
+            .map(Literal::I64)
+            .ok_or_else(|| String::from("integer overflow")),
+        [Literal::U64(x)] => Ok(Literal::U64(*x)),
+        [Literal::F64(x)] => Ok(Literal::F64(x.abs())),
+        _ => Err(no_overload("math.abs", &args)),
+    }
+}
+
+fn sign(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::I64(x)] => Ok(Literal::I64(x.signum())),
+        [Literal::U64(x)] => Ok(Literal::U64(u64::from(*x != 0))),
+        [Literal::F64(x)] if x.is_nan() || *x == 0.0 => Ok(Literal::F64(*x)),
+        [Literal::F64(x)] => Ok(Literal::F64(x.signum())),
+        _ => Err(no_overload("math.sign", &args)),
+    }
+}
+
+/// Applies `f` to the single double argument of `function`.
+fn double<T>(function: &str, args: Vec<Literal>, f: fn(f64) -> T) -> Result<T> {
+    match args.as_slice() {
+        [Literal::F64(x)] => Ok(f(*x)),
+        _ => Err(no_overload(function, &args)),
+    }
+}
+
+fn ceil(args: Vec<Literal>) -> Result<Literal> {
+    double("math.ceil", args, f64::ceil).map(Literal::F64)
+}
+
+fn floor(args: Vec<Literal>) -> Result<Literal> {
+    double("math.floor", args, f64::floor).map(Literal::F64)
+}
+
+/// Rounds half away from zero.
+fn round(args: Vec<Literal>) -> Result<Literal> {
+    double("math.round", args, f64::round).map(Literal::F64)
+}
+
+fn trunc(args: Vec<Literal>) -> Result<Literal> {
+    double("math.trunc", args, f64::trunc).map(Literal::F64)
+}
+
+fn is_inf(args: Vec<Literal>) -> Result<Literal> {
+    double("math.isInf", args, f64::is_infinite).map(Literal::Bool)
+}
+
+fn is_nan(args: Vec<Literal>) -> Result<Literal> {
+    double("math.isNaN", args, f64::is_nan).map(Literal::Bool)
+}
+
+fn is_finite(args: Vec<Literal>) -> Result<Literal> {
+    double("math.isFinite", args, f64::is_finite).map(Literal::Bool)
+}
+
+/// Applies `f` to the bits of the two int or two uint arguments of
+/// `function`.
+fn bits(function: &str, args: Vec<Literal>, f: fn(u64, u64) -> u64) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::I64(a), Literal::I64(b)] => Ok(Literal::I64(f(*a as u64, *b as u64) as i64)),
+        [Literal::U64(a), Literal::U64(b)] => Ok(Literal::U64(f(*a, *b))),
+        _ => Err(no_overload(function, &args)),
+    }
+}
+
+fn bit_and(args: Vec<Literal>) -> Result<Literal> {
+    bits("math.bitAnd", args, |a, b| a & b)
+}
+
+fn bit_or(args: Vec<Literal>) -> Result<Literal> {
+    bits("math.bitOr", args, |a, b| a | b)
+}
+
+fn bit_xor(args: Vec<Literal>) -> Result<Literal> {
+    bits("math.bitXor", args, |a, b| a ^ b)
+}
+
+fn bit_not(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::I64(x)] => Ok(Literal::I64(!x)),
+        [Literal::U64(x)] => Ok(Literal::U64(!x)),
+        _ => Err(no_overload("math.bitNot", &args)),
+    }
+}
+
+/// Shifts the bits of the int or uint first argument of `function` by the
+/// offset its second argument gives, with `shift`. They are all shifted out
+/// once the offset reaches 64.
+fn shift(function: &str, args: Vec<Literal>, shift: fn(u64, u32) -> u64) -> Result<Literal> {
+    let (x, offset) = match args.as_slice() {
+        [Literal::I64(x), Literal::I64(offset)] => (*x as u64, *offset),
+        [Literal::U64(x), Literal::I64(offset)] => (*x, *offset),
+        _ => return Err(no_overload(function, &args)),
+    };
+    if offset < 0 {
+        return Err(format!("{}() negative offset: {}", function, offset));
+    }
+    let shifted = if offset >= 64 {
+        0
+    } else {
+        shift(x, offset as u32)
+    };
+    Ok(match args[0] {
+        Literal::U64(_) => Literal::U64(shifted),
+        _ => Literal::I64(shifted as i64),
+    })
+}
+
+fn bit_shift_left(args: Vec<Literal>) -> Result<Literal> {
+    shift("math.bitShiftLeft", args, |x, n| x << n)
+}
+
+/// Shifts logically, so the sign bit of an int is not extended.
+fn bit_shift_right(args: Vec<Literal>) -> Result<Literal> {
+    shift("math.bitShiftRight", args, |x, n| x >> n)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::Literal;
+    use crate::parsers::parse;
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        let mut activation = Activation::new();
+        activation.insert(String::from("inf"), Literal::F64(f64::INFINITY));
+        activation.insert(String::from("nan"), Literal::F64(f64::NAN));
+        activation.insert(String::from("neg"), Literal::F64(-2.5));
+        evaluate_with_functions(parse(input).unwrap(), &activation, &functions)
+    }
+
+    #[test]
+    fn greatest_and_least() {
+        assert_eq!(eval("math.greatest(1)"), Ok(Literal::I64(1)));
+        assert_eq!(eval("math.greatest(1, 3, 2)"), Ok(Literal::I64(3)));
+        assert_eq!(eval("math.greatest(1, 2.5)"), Ok(Literal::F64(2.5)));
+        assert_eq!(eval("math.greatest([1.5, 4, 2])"), Ok(Literal::I64(4)));
+        assert_eq!(eval("math.least(3, 1.5, 2)"), Ok(Literal::F64(1.5)));
+        assert_eq!(eval("math.least([3, 1, 2])"), Ok(Literal::I64(1)));
+        assert_eq!(
+            eval("math.least(1, 'a')"),
+            Err(String::from(
+                "found no matching overload for 'math.least' applied to '(int, string)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn abs_and_sign() {
+        assert_eq!(eval("math.abs(0 - 5)"), Ok(Literal::I64(5)));
+        assert_eq!(eval("math.abs(neg)"), Ok(Literal::F64(2.5)));
+        assert_eq!(
+            eval("math.abs(0 - 9223372036854775807 - 1)"),
+            Err(String::from("integer overflow"))
+        );
+        assert_eq!(eval("math.sign(0 - 5)"), Ok(Literal::I64(-1)));
+        assert_eq!(eval("math.sign(0)"), Ok(Literal::I64(0)));
+        assert_eq!(eval("math.sign(2.5)"), Ok(Literal::F64(1.0)));
+        assert_eq!(eval("math.sign(0.0)"), Ok(Literal::F64(0.0)));
+        assert_eq!(eval("math.sign(neg)"), Ok(Literal::F64(-1.0)));
+    }
+
+    #[test]
+    fn rounding() {
+        assert_eq!(eval("math.ceil(1.2)"), Ok(Literal::F64(2.0)));
+        assert_eq!(eval("math.floor(1.8)"), Ok(Literal::F64(1.0)));
+        assert_eq!(eval("math.round(2.5)"), Ok(Literal::F64(3.0)));
+        assert_eq!(eval("math.round(neg)"), Ok(Literal::F64(-3.0)));
+        assert_eq!(eval("math.trunc(neg)"), Ok(Literal::F64(-2.0)));
+        assert!(eval("math.ceil(1)").is_err());
+    }
+
+    #[test]
+    fn classification() {
+        assert_eq!(eval("math.isInf(inf)"), Ok(Literal::Bool(true)));
+        assert_eq!(eval("math.isNaN(nan)"), Ok(Literal::Bool(true)));
+        assert_eq!(eval("math.isFinite(inf)"), Ok(Literal::Bool(false)));
+        assert_eq!(eval("math.isFinite(1.0)"), Ok(Literal::Bool(true)));
+    }
+
+    #[test]
+    fn bit_operations() {
+        assert_eq!(eval("math.bitAnd(12, 10)"), Ok(Literal::I64(8)));
+        assert_eq!(eval("math.bitOr(12, 10)"), Ok(Literal::I64(14)));
+        assert_eq!(eval("math.bitXor(12, 10)"), Ok(Literal::I64(6)));
+        assert_eq!(eval("math.bitNot(0)"), Ok(Literal::I64(-1)));
+        assert_eq!(eval("math.bitShiftLeft(1, 4)"), Ok(Literal::I64(16)));
+        assert_eq!(eval("math.bitShiftLeft(1, 64)"), Ok(Literal::I64(0)));
+        assert_eq!(eval("math.bitShiftRight(16, 4)"), Ok(Literal::I64(1)));
+        assert_eq!(eval("math.bitShiftRight(0 - 1, 60)"), Ok(Literal::I64(15)));
+        assert_eq!(
+            eval("math.bitShiftLeft(1, 0 - 1)"),
+            Err(String::from("math.bitShiftLeft() negative offset: -1"))
+        );
+    }
+
+    #[test]
+    fn uint_bit_operations() {
+        assert_eq!(eval("math.bitAnd(12u, 10u)"), Ok(Literal::U64(8)));
+        assert_eq!(eval("math.bitOr(12u, 10u)"), Ok(Literal::U64(14)));
+        assert_eq!(eval("math.bitXor(12u, 10u)"), Ok(Literal::U64(6)));
+        assert_eq!(eval("math.bitNot(0u)"), Ok(Literal::U64(u64::MAX)));
+        assert_eq!(eval("math.bitShiftLeft(1u, 63)"), Ok(Literal::U64(1 << 63)));
+        assert_eq!(
+            eval("math.bitShiftRight(18446744073709551615u, 60)"),
+            Ok(Literal::U64(15))
+        );
+        assert_eq!(eval("math.bitShiftRight(1u, 64)"), Ok(Literal::U64(0)));
+        assert_eq!(
+            eval("math.bitAnd(1u, 1)"),
+            Err(String::from(
+                "found no matching overload for 'math.bitAnd' applied to '(uint, int)'"
+            ))
+        );
+        assert_eq!(
+            eval("math.greatest(18446744073709551615u, 9223372036854775807, 1.5)"),
+            Ok(Literal::U64(u64::MAX))
+        );
+        assert_eq!(
+            eval("math.least([9007199254740993, 9007199254740992.0])"),
+            Ok(Literal::F64(9007199254740992.0))
+        );
+        assert_eq!(eval("math.abs(3u)"), Ok(Literal::U64(3)));
+        assert_eq!(eval("math.sign(3u)"), Ok(Literal::U64(1)));
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("x", Type::Double);
+        let type_of =
+            |input: &str| check(parse(input).unwrap(), &decls).map(|c| c.result_type().clone());
+        assert_eq!(type_of("math.greatest(1, 2)"), Ok(Type::Int));
+        assert_eq!(type_of("math.greatest(1, x)"), Ok(Type::Dyn));
+        assert_eq!(type_of("math.least([x, 1.0])"), Ok(Type::Double));
+        assert_eq!(type_of("math.least([x, 1])"), Ok(Type::Dyn));
+        assert_eq!(type_of("math.least(1, 2, 3)"), Ok(Type::Dyn));
+        assert_eq!(type_of("math.round(x)"), Ok(Type::Double));
+        assert_eq!(type_of("math.isNaN(x)"), Ok(Type::Bool));
+        assert_eq!(type_of("math.bitShiftLeft(1, 2)"), Ok(Type::Int));
+        assert!(type_of("math.abs('a')").is_err());
+        assert!(type_of("math.bitAnd(x, 1)").is_err());
+        assert_eq!(type_of("math.bitNot(1u)"), Ok(Type::Uint));
+        assert_eq!(type_of("math.bitShiftRight(1u, 2)"), Ok(Type::Uint));
+        assert!(type_of("math.bitOr(1u, 2)").is_err());
+        assert_eq!(type_of("math.greatest(1u, 2u)"), Ok(Type::Uint));
+        assert_eq!(type_of("math.greatest(1u, 2, 3.0)"), Ok(Type::Dyn));
+        assert_eq!(type_of("math.greatest(1, x, 2)"), Ok(Type::Dyn));
+        assert!(type_of("math.greatest(1, 2, 'c')").is_err());
+        let errors = type_of("math.greatest('a', 'b', 'c')").unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "found no matching overload for 'math.greatest' applied to '(string, string, string)'"
+        );
+    }
+}
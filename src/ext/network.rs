@@ -0,0 +1,631 @@
+//! IP address and CIDR range types, after the Kubernetes CEL library `net`:
+//! `ip('10.0.0.1')` and `cidr('10.0.0.0/8')` create values of the opaque
+//! types `net.IP` and `net.CIDR`, and `isIP` and `isCIDR` test whether a
+//! string would be accepted by them. Both IPv4 and IPv6 are supported.
+//!
+//! An IP has the methods `family`, `isUnspecified`, `isLoopback`,
+//! `isLinkLocalMulticast`, `isLinkLocalUnicast` and `isGlobalUnicast`, and a
+//! CIDR has `containsIP`, `containsCIDR`, `ip`, `masked` and `prefixLength`.
+//! `string` converts either back to a string, and values of the same type
+//! can be compared with `==` and `!=`.
+//!
+//! As in Kubernetes, IPv4-mapped IPv6 addresses such as `::ffff:10.0.0.1`
+//! and addresses with zones are rejected, and a CIDR need not be masked:
+//! `cidr('10.0.0.1/8')` keeps its address, which `masked` clears.
+
+use super::{no_overload, string};
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::Functions;
+use crate::model::{Literal, Opaque, OpaqueValue};
+
+use std::any::Any;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+type Result<T> = std::result::Result<T, String>;
+
+pub const IP_TYPE: &str = "net.IP";
+pub const CIDR_TYPE: &str = "net.CIDR";
+
+/// An IP address, as created by `ip`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Ip(pub IpAddr);
+
+/// An address and a prefix length, as created by `cidr`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Cidr {
+    pub ip: IpAddr,
+    pub prefix_length: u8,
+}
+
+impl fmt::Display for Ip {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.ip, self.prefix_length)
+    }
+}
+
+impl OpaqueValue for Ip {
+    fn type_name(&self) -> &'static str {
+        IP_TYPE
+    }
+
+    fn equals(&self, other: &dyn OpaqueValue) -> bool {
+        other.as_any().downcast_ref::<Ip>() == Some(self)
+    }
+
+    fn constructor(&self) -> (&'static str, Vec<Literal>) {
+        ("ip", vec![Literal::String(self.to_string())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl OpaqueValue for Cidr {
+    fn type_name(&self) -> &'static str {
+        CIDR_TYPE
+    }
+
+    fn equals(&self, other: &dyn OpaqueValue) -> bool {
+        other.as_any().downcast_ref::<Cidr>() == Some(self)
+    }
+
+    fn constructor(&self) -> (&'static str, Vec<Literal>) {
+        ("cidr", vec![Literal::String(self.to_string())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+impl Cidr {
+    /// Whether `ip` is in the range, which it cannot be if it is of the other
+    /// family.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.ip, ip) {
+            (IpAddr::V4(a), IpAddr::V4(b)) => {
+                let mask = mask(self.prefix_length, 32) as u32;
+                u32::from(a) & mask == u32::from(b) & mask
+            }
+            (IpAddr::V6(a), IpAddr::V6(b)) => {
+                let mask = mask(self.prefix_length, 128);
+                u128::from(a) & mask == u128::from(b) & mask
+            }
+            _ => false,
+        }
+    }
+
+    /// The range with the bits of its address past the prefix cleared.
+    pub fn masked(&self) -> Cidr {
+        let ip = match self.ip {
+            IpAddr::V4(a) => {
+                let mask = mask(self.prefix_length, 32) as u32;
+                IpAddr::V4(Ipv4Addr::from(u32::from(a) & mask))
+            }
+            IpAddr::V6(a) => IpAddr::V6(Ipv6Addr::from(
+                u128::from(a) & mask(self.prefix_length, 128),
+            )),
+        };
+        Cidr {
+            ip,
+            prefix_length: self.prefix_length,
+        }
+    }
+}
+
+/// The mask of the first `prefix_length` of the low `bits` bits.
+fn mask(prefix_length: u8, bits: u32) -> u128 {
+    let ones = u128::MAX
+        .checked_shl(128 - prefix_length as u32)
+        .unwrap_or(0);
+    ones >> (128 - bits)
+}
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("ip", ip);
+    functions.add_global("cidr", cidr);
+    functions.add_global("isIP", is_ip);
+    functions.add_global("isCIDR", is_cidr);
+    functions.add_global("string", string);
+    functions.add_member("family", IP_TYPE, family);
+    functions.add_member("isUnspecified", IP_TYPE, is_unspecified);
+    functions.add_member("isLoopback", IP_TYPE, is_loopback);
+    functions.add_member("isLinkLocalMulticast", IP_TYPE, is_link_local_multicast);
+    functions.add_member("isLinkLocalUnicast", IP_TYPE, is_link_local_unicast);
+    functions.add_member("isGlobalUnicast", IP_TYPE, is_global_unicast);
+    functions.add_member("containsIP", CIDR_TYPE, contains_ip);
+    functions.add_member("containsCIDR", CIDR_TYPE, contains_cidr);
+    functions.add_member("ip", CIDR_TYPE, cidr_ip);
+    functions.add_member("masked", CIDR_TYPE, masked);
+    functions.add_member("prefixLength", CIDR_TYPE, prefix_length);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    let ip_type = Type::opaque(IP_TYPE);
+    let cidr_type = Type::opaque(CIDR_TYPE);
+    for (function, id, param, result) in [
+        ("ip", "string_to_ip", Type::String, ip_type.clone()),
+        ("cidr", "string_to_cidr", Type::String, cidr_type.clone()),
+        ("isIP", "is_ip", Type::String, Type::Bool),
+        ("isCIDR", "is_cidr", Type::String, Type::Bool),
+        ("string", "ip_to_string", ip_type.clone(), Type::String),
+        ("string", "cidr_to_string", cidr_type.clone(), Type::String),
+    ] {
+        decls.add_overload(function, Overload::global(id, vec![param], result));
+    }
+    for (function, id, params, result) in [
+        ("family", "ip_family", vec![ip_type.clone()], Type::Int),
+        (
+            "isUnspecified",
+            "ip_is_unspecified",
+            vec![ip_type.clone()],
+            Type::Bool,
+        ),
+        (
+            "isLoopback",
+            "ip_is_loopback",
+            vec![ip_type.clone()],
+            Type::Bool,
+        ),
+        (
+            "isLinkLocalMulticast",
+            "ip_is_link_local_multicast",
+            vec![ip_type.clone()],
+            Type::Bool,
+        ),
+        (
+            "isLinkLocalUnicast",
+            "ip_is_link_local_unicast",
+            vec![ip_type.clone()],
+            Type::Bool,
+        ),
+        (
+            "isGlobalUnicast",
+            "ip_is_global_unicast",
+            vec![ip_type.clone()],
+            Type::Bool,
+        ),
+        (
+            "containsIP",
+            "cidr_contains_ip_ip",
+            vec![cidr_type.clone(), ip_type.clone()],
+            Type::Bool,
+        ),
+        (
+            "containsIP",
+            "cidr_contains_ip_string",
+            vec![cidr_type.clone(), Type::String],
+            Type::Bool,
+        ),
+        (
+            "containsCIDR",
+            "cidr_contains_cidr_cidr",
+            vec![cidr_type.clone(), cidr_type.clone()],
+            Type::Bool,
+        ),
+        (
+            "containsCIDR",
+            "cidr_contains_cidr_string",
+            vec![cidr_type.clone(), Type::String],
+            Type::Bool,
+        ),
+        ("ip", "cidr_ip", vec![cidr_type.clone()], ip_type.clone()),
+        (
+            "masked",
+            "cidr_masked",
+            vec![cidr_type.clone()],
+            cidr_type.clone(),
+        ),
+        (
+            "prefixLength",
+            "cidr_prefix_length",
+            vec![cidr_type.clone()],
+            Type::Int,
+        ),
+    ] {
+        decls.add_overload(function, Overload::member(id, params, result));
+    }
+    for (suffix, t) in [("ip", ip_type), ("cidr", cidr_type)] {
+        for (function, prefix) in [("_==_", "equals"), ("_!=_", "not_equals")] {
+            let id = format!("{}_{}", prefix, suffix);
+            let params = vec![t.clone(), t.clone()];
+            decls.add_overload(function, Overload::global(&id, params, Type::Bool));
+        }
+    }
+}
+
+/// Parses an address the way `ip` does.
+pub fn parse_ip(s: &str) -> Result<IpAddr> {
+    let ip: IpAddr = s
+        .parse()
+        .map_err(|_| format!("IP address '{}' is invalid", s))?;
+    if let IpAddr::V6(v6) = ip {
+        if v6.to_ipv4_mapped().is_some() {
+            return Err(format!("IPv4-mapped IPv6 address '{}' is not allowed", s));
+        }
+    }
+    Ok(ip)
+}
+
+/// Parses a range the way `cidr` does: an address, a slash and a prefix
+/// length in decimal without leading zeros.
+pub fn parse_cidr(s: &str) -> Result<Cidr> {
+    let invalid = || format!("network address '{}' is invalid", s);
+    let (ip, prefix_length) = s.split_once('/').ok_or_else(invalid)?;
+    let ip = parse_ip(ip).map_err(|_| invalid())?;
+    let digits = prefix_length.bytes().all(|b| b.is_ascii_digit());
+    if !digits
+        || prefix_length.is_empty()
+        || prefix_length.len() > 1 && prefix_length.starts_with('0')
+    {
+        return Err(invalid());
+    }
+    let max = if ip.is_ipv4() { 32 } else { 128 };
+    match prefix_length.parse::<u8>() {
+        Ok(prefix_length) if prefix_length <= max => Ok(Cidr { ip, prefix_length }),
+        _ => Err(invalid()),
+    }
+}
+
+fn ip(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Opaque(Opaque::new(Ip(parse_ip(s)?)))),
+        _ => Err(no_overload("ip", &args)),
+    }
+}
+
+fn cidr(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Opaque(Opaque::new(parse_cidr(s)?))),
+        _ => Err(no_overload("cidr", &args)),
+    }
+}
+
+fn is_ip(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Bool(parse_ip(s).is_ok())),
+        _ => Err(no_overload("isIP", &args)),
+    }
+}
+
+fn is_cidr(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Bool(parse_cidr(s).is_ok())),
+        _ => Err(no_overload("isCIDR", &args)),
+    }
+}
+
+/// The address a method is called on, and its other arguments.
+fn receiver_ip<'a>(function: &str, args: &'a [Literal]) -> Result<(IpAddr, &'a [Literal])> {
+    match args.split_first() {
+        Some((Literal::Opaque(value), rest)) => match value.downcast_ref::<Ip>() {
+            Some(ip) => Ok((ip.0, rest)),
+            None => Err(no_overload(function, args)),
+        },
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+/// The range a method is called on, and its other arguments.
+fn receiver_cidr<'a>(function: &str, args: &'a [Literal]) -> Result<(Cidr, &'a [Literal])> {
+    match args.split_first() {
+        Some((Literal::Opaque(value), rest)) => match value.downcast_ref::<Cidr>() {
+            Some(cidr) => Ok((*cidr, rest)),
+            None => Err(no_overload(function, args)),
+        },
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+/// Applies a test of an address that takes no arguments.
+fn test_ip(function: &str, args: &[Literal], test: fn(IpAddr) -> bool) -> Result<Literal> {
+    match receiver_ip(function, args)? {
+        (ip, []) => Ok(Literal::Bool(test(ip))),
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+fn family(args: Vec<Literal>) -> Result<Literal> {
+    match receiver_ip("family", &args)? {
+        (IpAddr::V4(_), []) => Ok(Literal::I64(4)),
+        (IpAddr::V6(_), []) => Ok(Literal::I64(6)),
+        _ => Err(no_overload("family", &args)),
+    }
+}
+
+fn is_unspecified(args: Vec<Literal>) -> Result<Literal> {
+    test_ip("isUnspecified", &args, |ip| ip.is_unspecified())
+}
+
+fn is_loopback(args: Vec<Literal>) -> Result<Literal> {
+    test_ip("isLoopback", &args, |ip| ip.is_loopback())
+}
+
+/// 224.0.0.0/24 and ff02::/16, and the other link-local scopes of IPv6.
+fn link_local_multicast(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(a) => a.octets()[..3] == [224, 0, 0],
+        IpAddr::V6(a) => a.octets()[0] == 0xff && a.octets()[1] & 0x0f == 0x02,
+    }
+}
+
+/// 169.254.0.0/16 and fe80::/10.
+fn link_local_unicast(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(a) => a.is_link_local(),
+        IpAddr::V6(a) => a.segments()[0] & 0xffc0 == 0xfe80,
+    }
+}
+
+fn is_link_local_multicast(args: Vec<Literal>) -> Result<Literal> {
+    test_ip("isLinkLocalMulticast", &args, link_local_multicast)
+}
+
+fn is_link_local_unicast(args: Vec<Literal>) -> Result<Literal> {
+    test_ip("isLinkLocalUnicast", &args, link_local_unicast)
+}
+
+/// As in Go, any unicast address that is not unspecified, loopback or link
+/// local, private addresses included.
+fn is_global_unicast(args: Vec<Literal>) -> Result<Literal> {
+    test_ip("isGlobalUnicast", &args, |ip| {
+        !ip.is_unspecified()
+            && !ip.is_loopback()
+            && !ip.is_multicast()
+            && !link_local_unicast(ip)
+            && ip != IpAddr::V4(Ipv4Addr::BROADCAST)
+    })
+}
+
+fn contains_ip(args: Vec<Literal>) -> Result<Literal> {
+    let (cidr, ip) = match receiver_cidr("containsIP", &args)? {
+        (cidr, [Literal::String(s)]) => (cidr, parse_ip(s)?),
+        (cidr, [Literal::Opaque(value)]) => match value.downcast_ref::<Ip>() {
+            Some(ip) => (cidr, ip.0),
+            None => return Err(no_overload("containsIP", &args)),
+        },
+        _ => return Err(no_overload("containsIP", &args)),
+    };
+    Ok(Literal::Bool(cidr.contains(ip)))
+}
+
+/// Whether the receiver contains every address of the other range.
+fn contains_cidr(args: Vec<Literal>) -> Result<Literal> {
+    let (cidr, other) = match receiver_cidr("containsCIDR", &args)? {
+        (cidr, [Literal::String(s)]) => (cidr, parse_cidr(s)?),
+        (cidr, [Literal::Opaque(value)]) => match value.downcast_ref::<Cidr>() {
+            Some(other) => (cidr, *other),
+            None => return Err(no_overload("containsCIDR", &args)),
+        },
+        _ => return Err(no_overload("containsCIDR", &args)),
+    };
+    let contains = other.prefix_length >= cidr.prefix_length && cidr.contains(other.ip);
+    Ok(Literal::Bool(contains))
+}
+
+fn cidr_ip(args: Vec<Literal>) -> Result<Literal> {
+    match receiver_cidr("ip", &args)? {
+        (cidr, []) => Ok(Literal::Opaque(Opaque::new(Ip(cidr.ip)))),
+        _ => Err(no_overload("ip", &args)),
+    }
+}
+
+fn masked(args: Vec<Literal>) -> Result<Literal> {
+    match receiver_cidr("masked", &args)? {
+        (cidr, []) => Ok(Literal::Opaque(Opaque::new(cidr.masked()))),
+        _ => Err(no_overload("masked", &args)),
+    }
+}
+
+fn prefix_length(args: Vec<Literal>) -> Result<Literal> {
+    match receiver_cidr("prefixLength", &args)? {
+        (cidr, []) => Ok(Literal::I64(cidr.prefix_length as i64)),
+        _ => Err(no_overload("prefixLength", &args)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::Literal;
+    use crate::parsers::parse;
+    use crate::unparser::unparse;
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        evaluate_with_functions(parse(input).unwrap(), &Activation::new(), &functions)
+    }
+
+    fn string(s: &str) -> Literal {
+        Literal::String(String::from(s))
+    }
+
+    #[test]
+    fn ips() {
+        assert_eq!(eval("string(ip('10.0.0.1'))"), Ok(string("10.0.0.1")));
+        assert_eq!(
+            eval("string(ip('2001:DB8:0:0::1'))"),
+            Ok(string("2001:db8::1"))
+        );
+        assert_eq!(eval("ip('10.0.0.1').family()"), Ok(Literal::I64(4)));
+        assert_eq!(eval("ip('::1').family()"), Ok(Literal::I64(6)));
+        assert_eq!(
+            eval("ip('10.0.0.1') == ip('10.0.0.1')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(eval("ip('::1') == ip('0:0::1')"), Ok(Literal::Bool(true)));
+        assert_eq!(
+            eval("ip('10.0.0.1') != ip('10.0.0.2')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(eval("isIP('10.0.0.256')"), Ok(Literal::Bool(false)));
+        assert_eq!(eval("isIP('fe80::1')"), Ok(Literal::Bool(true)));
+        assert_eq!(
+            eval("ip('010.0.0.1')"),
+            Err(String::from("IP address '010.0.0.1' is invalid"))
+        );
+        assert_eq!(
+            eval("ip('::ffff:10.0.0.1')"),
+            Err(String::from(
+                "IPv4-mapped IPv6 address '::ffff:10.0.0.1' is not allowed"
+            ))
+        );
+        assert!(eval("ip('fe80::1%eth0')").is_err());
+    }
+
+    #[test]
+    fn classification() {
+        let cases = [
+            ("0.0.0.0", "isUnspecified"),
+            ("::", "isUnspecified"),
+            ("127.0.0.1", "isLoopback"),
+            ("::1", "isLoopback"),
+            ("224.0.0.251", "isLinkLocalMulticast"),
+            ("ff02::1", "isLinkLocalMulticast"),
+            ("169.254.1.1", "isLinkLocalUnicast"),
+            ("fe80::1", "isLinkLocalUnicast"),
+            ("10.0.0.1", "isGlobalUnicast"),
+            ("8.8.8.8", "isGlobalUnicast"),
+            ("2001:db8::1", "isGlobalUnicast"),
+        ];
+        for (address, method) in cases {
+            let expected: Vec<_> = cases.iter().map(|(_, other)| *other == method).collect();
+            let actual: Vec<_> = cases
+                .iter()
+                .map(|(_, other)| {
+                    eval(&format!("ip('{}').{}()", address, other)) == Ok(Literal::Bool(true))
+                })
+                .collect();
+            assert_eq!(actual, expected, "{}", address);
+        }
+        assert_eq!(
+            eval("ip('255.255.255.255').isGlobalUnicast()"),
+            Ok(Literal::Bool(false))
+        );
+    }
+
+    #[test]
+    fn cidrs() {
+        assert_eq!(
+            eval("cidr('10.0.0.0/8').containsIP(ip('10.1.2.3'))"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("cidr('10.0.0.0/8').containsIP('11.0.0.1')"),
+            Ok(Literal::Bool(false))
+        );
+        assert_eq!(
+            eval("cidr('10.0.0.0/8').containsIP('::1')"),
+            Ok(Literal::Bool(false))
+        );
+        assert_eq!(
+            eval("cidr('::/0').containsIP('2001:db8::1')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("cidr('0.0.0.0/0').containsIP('1.2.3.4')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("cidr('2001:db8::/32').containsIP(ip('2001:db9::1'))"),
+            Ok(Literal::Bool(false))
+        );
+        assert_eq!(
+            eval("cidr('10.0.0.0/8').containsCIDR(cidr('10.1.0.0/16'))"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("cidr('10.1.0.0/16').containsCIDR('10.0.0.0/8')"),
+            Ok(Literal::Bool(false))
+        );
+        assert_eq!(
+            eval("cidr('10.0.0.1/8').prefixLength()"),
+            Ok(Literal::I64(8))
+        );
+        assert_eq!(
+            eval("string(cidr('10.0.0.1/8').ip())"),
+            Ok(string("10.0.0.1"))
+        );
+        assert_eq!(
+            eval("string(cidr('10.1.2.3/12').masked())"),
+            Ok(string("10.0.0.0/12"))
+        );
+        assert_eq!(
+            eval("string(cidr('2001:db8:ffff::1/33').masked())"),
+            Ok(string("2001:db8:8000::/33"))
+        );
+        assert_eq!(
+            eval("cidr('10.0.0.1/8') == cidr('10.0.0.1/8')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("cidr('10.0.0.1/8') == cidr('10.0.0.0/8')"),
+            Ok(Literal::Bool(false))
+        );
+        assert_eq!(eval("isCIDR('10.0.0.0/33')"), Ok(Literal::Bool(false)));
+        assert_eq!(eval("isCIDR('10.0.0.0/08')"), Ok(Literal::Bool(false)));
+        assert_eq!(eval("isCIDR('::/128')"), Ok(Literal::Bool(true)));
+        assert_eq!(
+            eval("cidr('10.0.0.0')"),
+            Err(String::from("network address '10.0.0.0' is invalid"))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            eval("ip(1)"),
+            Err(String::from(
+                "found no matching overload for 'ip' applied to '(int)'"
+            ))
+        );
+        assert_eq!(
+            eval("cidr('10.0.0.0/8').family()"),
+            Err(String::from(
+                "found no matching overload for 'family' applied to '(net.CIDR)'"
+            ))
+        );
+        assert_eq!(
+            eval("ip('10.0.0.1') == cidr('10.0.0.0/8')"),
+            Ok(Literal::Bool(false))
+        );
+        assert!(eval("ip('10.0.0.1') == '10.0.0.1'").is_err());
+    }
+
+    #[test]
+    fn unparsing() {
+        let value = eval("cidr('10.0.0.1/8').masked()").unwrap();
+        let expr = crate::model::Expression::new(crate::model::ExprKind::Lit(value));
+        assert_eq!(unparse(&expr), "cidr(\"10.0.0.0/8\")");
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("client", Type::String);
+        let expr =
+            parse("cidr('10.0.0.0/8').containsIP(ip(client)) && ip(client).family() == 4").unwrap();
+        assert!(check(expr, &decls).is_ok());
+        let expr = parse("cidr('10.0.0.0/8').masked()").unwrap();
+        assert_eq!(
+            *check(expr, &decls).unwrap().result_type(),
+            Type::opaque("net.CIDR")
+        );
+        let expr = parse("ip(client) == cidr(client)").unwrap();
+        assert!(check(expr, &decls).is_err());
+        let expr = parse("ip(client).prefixLength()").unwrap();
+        assert!(check(expr, &decls).is_err());
+    }
+}
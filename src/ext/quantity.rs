@@ -0,0 +1,825 @@
+//! Resource quantities, after the Kubernetes CEL library `quantity`:
+//! `quantity('1.5Gi')` creates a value of the opaque type
+//! `kubernetes.Quantity`, and `isQuantity` tests whether a string would be
+//! accepted by it.
+//!
+//! A quantity has the methods `sign`, `isInteger`, `asInteger`,
+//! `asApproximateFloat`, `add` and `sub` (of a quantity or an int),
+//! `compareTo`, `isLessThan` and `isGreaterThan`. Quantities are compared by
+//! value, also with `==`, `<` and the other relational operators, and
+//! `string` gives their canonical form.
+//!
+//! Parsing and the canonical form follow Kubernetes: a quantity keeps the
+//! format it was written in, decimal (`1.5k`), binary (`1Ki`) or exponent
+//! (`1e3`), so `string(quantity('1.5Gi'))` is `1536Mi` and
+//! `string(quantity('1.5'))` is `1500m`. Amounts too precise to be held
+//! exactly are rounded up to the next nano, and binary ones are capped at
+//! 2^63 - 1.
+
+use super::{no_overload, string};
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::Functions;
+use crate::model::{Literal, Opaque, OpaqueValue};
+
+use std::any::Any;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+type Result<T> = std::result::Result<T, String>;
+
+pub const QUANTITY_TYPE: &str = "kubernetes.Quantity";
+
+const FORMAT_WRONG: &str = "quantities must match the regular expression \
+                            '^([+-]?[0-9.]+)([eEinumkKMGTP]*[-+]?[0-9]*)$'";
+const SUFFIX_WRONG: &str = "unable to parse quantity's suffix";
+const OVERFLOW: &str = "quantity overflow";
+
+/// The largest binary quantity, 2^63 - 1.
+const MAX_BINARY: &str = "9223372036854775807";
+
+/// The notation a quantity is written in, which its canonical form keeps.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Format {
+    /// Powers of ten with SI suffixes, e.g. `1.5k`.
+    DecimalSI,
+    /// Powers of 1024 with IEC suffixes, e.g. `1Ki`.
+    BinarySI,
+    /// Powers of ten as exponents, e.g. `1.5e3`.
+    DecimalExponent,
+}
+
+/// A quantity, as created by `quantity`: `value` times ten to the `scale`.
+#[derive(Debug, Clone, Copy)]
+pub struct Quantity {
+    value: i128,
+    scale: i64,
+    pub format: Format,
+}
+
+impl Quantity {
+    pub fn parse(s: &str) -> Result<Quantity> {
+        parse(s).map_err(String::from)
+    }
+
+    /// The quantity of the integer `value`, in exponent format.
+    pub fn from_i64(value: i64) -> Quantity {
+        Quantity {
+            value: i128::from(value),
+            scale: 0,
+            format: Format::DecimalExponent,
+        }
+    }
+
+    pub fn sign(&self) -> i64 {
+        self.value.signum() as i64
+    }
+
+    /// The value, if it is an integer that fits in an `i64`.
+    pub fn as_i64(&self) -> Option<i64> {
+        self.integer().and_then(|value| i64::try_from(value).ok())
+    }
+
+    pub fn as_f64(&self) -> f64 {
+        if self.scale < 0 {
+            self.value as f64 / 10f64.powi(-self.scale as i32)
+        } else {
+            self.value as f64 * 10f64.powi(self.scale.min(i64::from(i32::MAX)) as i32)
+        }
+    }
+
+    /// The sum of the quantities, in the format of this one unless it is
+    /// zero.
+    pub fn checked_add(&self, other: &Quantity) -> Option<Quantity> {
+        let scale = self.scale.min(other.scale);
+        let a = rescale(self.value, self.scale - scale)?;
+        let b = rescale(other.value, other.scale - scale)?;
+        Some(Quantity {
+            value: a.checked_add(b)?,
+            scale,
+            format: if self.value == 0 {
+                other.format
+            } else {
+                self.format
+            },
+        })
+    }
+
+    pub fn checked_sub(&self, other: &Quantity) -> Option<Quantity> {
+        let negated = Quantity {
+            value: other.value.checked_neg()?,
+            ..*other
+        };
+        self.checked_add(&negated)
+    }
+
+    /// The value, if it is an integer that fits in an `i128`.
+    fn integer(&self) -> Option<i128> {
+        if self.value == 0 {
+            Some(0)
+        } else if self.scale >= 0 {
+            rescale(self.value, self.scale)
+        } else {
+            let divisor = pow10(-self.scale)?;
+            if self.value % divisor == 0 {
+                Some(self.value / divisor)
+            } else {
+                None
+            }
+        }
+    }
+
+    /// The canonical form of the quantity written in `format`.
+    fn canonical(&self, format: Format) -> String {
+        if self.value == 0 {
+            return String::from("0");
+        }
+        if format == Format::BinarySI {
+            if let Some(s) = self.binary() {
+                return s;
+            }
+        }
+        // The shortest mantissa, then an exponent that is a multiple of three.
+        let mut mantissa = self.value;
+        let mut exponent = self.scale;
+        while mantissa % 10 == 0 {
+            mantissa /= 10;
+            exponent += 1;
+        }
+        let rounded = exponent.div_euclid(3) * 3;
+        let digits = format!("{}{}", mantissa, "0".repeat((exponent - rounded) as usize));
+        match decimal_suffix(rounded) {
+            Some(suffix) if format != Format::DecimalExponent => format!("{}{}", digits, suffix),
+            _ if rounded == 0 => digits,
+            _ => format!("{}e{}", digits, rounded),
+        }
+    }
+
+    /// The binary canonical form, unless the quantity is a fraction or
+    /// between -1024 and 1024, which are written in decimal instead.
+    fn binary(&self) -> Option<String> {
+        let mut value = self.integer().filter(|value| value.abs() >= 1024)?;
+        let mut exponent = 0;
+        while exponent < BINARY_SUFFIXES.len() - 1 && value % 1024 == 0 {
+            value /= 1024;
+            exponent += 1;
+        }
+        Some(format!("{}{}", value, BINARY_SUFFIXES[exponent]))
+    }
+}
+
+const BINARY_SUFFIXES: [&str; 7] = ["", "Ki", "Mi", "Gi", "Ti", "Pi", "Ei"];
+
+fn decimal_suffix(exponent: i64) -> Option<&'static str> {
+    match exponent {
+        -9 => Some("n"),
+        -6 => Some("u"),
+        -3 => Some("m"),
+        0 => Some(""),
+        3 => Some("k"),
+        6 => Some("M"),
+        9 => Some("G"),
+        12 => Some("T"),
+        15 => Some("P"),
+        18 => Some("E"),
+        _ => None,
+    }
+}
+
+fn pow10(exponent: i64) -> Option<i128> {
+    10i128.checked_pow(u32::try_from(exponent).ok()?)
+}
+
+/// `value` times ten to the `exponent`, unless that overflows.
+fn rescale(value: i128, exponent: i64) -> Option<i128> {
+    if value == 0 {
+        Some(0)
+    } else {
+        value.checked_mul(pow10(exponent)?)
+    }
+}
+
+/// Compares quantities by value, whatever their formats.
+impl Ord for Quantity {
+    fn cmp(&self, other: &Quantity) -> Ordering {
+        if self.scale < other.scale {
+            return other.cmp(self).reverse();
+        }
+        // Whenever this value can't be brought to the finer scale of the
+        // other one, it is the larger in magnitude.
+        match rescale(self.value, self.scale - other.scale) {
+            Some(value) => value.cmp(&other.value),
+            None => self.value.cmp(&0),
+        }
+    }
+}
+
+impl PartialOrd for Quantity {
+    fn partial_cmp(&self, other: &Quantity) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Quantity {
+    fn eq(&self, other: &Quantity) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Quantity {}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.canonical(self.format))
+    }
+}
+
+impl OpaqueValue for Quantity {
+    fn type_name(&self) -> &'static str {
+        QUANTITY_TYPE
+    }
+
+    fn equals(&self, other: &dyn OpaqueValue) -> bool {
+        other.as_any().downcast_ref::<Quantity>() == Some(self)
+    }
+
+    /// Always in decimal, so that equal quantities written in different
+    /// formats, like `1Ki` and `1024`, have the same constructor.
+    fn constructor(&self) -> (&'static str, Vec<Literal>) {
+        let canonical = self.canonical(Format::DecimalSI);
+        ("quantity", vec![Literal::String(canonical)])
+    }
+
+    fn compare(&self, other: &dyn OpaqueValue) -> Option<Ordering> {
+        other
+            .as_any()
+            .downcast_ref::<Quantity>()
+            .map(|other| self.cmp(other))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Parses a quantity as Kubernetes' `ParseQuantity` does. Amounts that fit
+/// in an `i64`, at a scale no finer than nanos, are kept exactly and others
+/// are rounded.
+fn parse(s: &str) -> std::result::Result<Quantity, &'static str> {
+    if s.is_empty() {
+        return Err(FORMAT_WRONG);
+    }
+    let (positive, num, denom, suffix) = split(s)?;
+    let (base, exponent, format) = interpret(suffix).ok_or(SUFFIX_WRONG)?;
+    let places = denom.len() as i64;
+    let precision = if base == 10 {
+        18 - (num.len() + denom.len()) as i64
+    } else if denom.is_empty() {
+        // 2^10 has about three decimal digits of precision.
+        14 - num.len() as i64 - exponent * 3 / 10
+    } else {
+        -1
+    };
+    let scale = if base == 10 { exponent } else { 0 } - places;
+    if precision >= 0 && scale >= -9 {
+        let value: i64 = format!("{}{}", num, denom).parse().unwrap();
+        let mantissa = if base == 10 { 1 } else { 1 << exponent };
+        if let Some(value) = value.checked_mul(mantissa) {
+            return Ok(Quantity {
+                value: i128::from(if positive { value } else { -value }),
+                scale,
+                format,
+            });
+        }
+    }
+    let digits = format!("{}{}", num, denom);
+    if base == 10 {
+        round(positive, digits, exponent - places, format)
+    } else {
+        round(positive, multiply(&digits, 1 << exponent), -places, format)
+    }
+}
+
+/// Splits a quantity into its sign, whole digits, fractional digits and
+/// suffix, as Kubernetes' `parseQuantityString` does. Leading zeros are
+/// dropped, and a number with no whole digits, like `.5`, has the whole
+/// digits `0`.
+fn split(s: &str) -> std::result::Result<(bool, &str, &str, &str), &'static str> {
+    let bytes = s.as_bytes();
+    let digits_from = |start: usize| {
+        start
+            + bytes[start..]
+                .iter()
+                .take_while(|b| b.is_ascii_digit())
+                .count()
+    };
+    let positive = bytes[0] != b'-';
+    let mut pos = if bytes[0] == b'-' || bytes[0] == b'+' {
+        1
+    } else {
+        0
+    };
+    pos += bytes[pos..].iter().take_while(|&&b| b == b'0').count();
+    if pos == bytes.len() {
+        return Ok((positive, "0", "", ""));
+    }
+    let end = digits_from(pos);
+    let num = if end == pos { "0" } else { &s[pos..end] };
+    pos = end;
+    let mut denom = "";
+    if pos < bytes.len() && bytes[pos] == b'.' {
+        let end = digits_from(pos + 1);
+        denom = &s[pos + 1..end];
+        pos = end;
+    }
+    let start = pos;
+    pos += bytes[pos..]
+        .iter()
+        .take_while(|b| b"eEinumkKMGTP".contains(b))
+        .count();
+    if pos < bytes.len() && (bytes[pos] == b'-' || bytes[pos] == b'+') {
+        pos += 1;
+    }
+    if digits_from(pos) != bytes.len() {
+        return Err(FORMAT_WRONG);
+    }
+    Ok((positive, num, denom, &s[start..]))
+}
+
+/// The base, exponent and format of a suffix.
+fn interpret(suffix: &str) -> Option<(u32, i64, Format)> {
+    let decimal = |exponent| Some((10, exponent, Format::DecimalSI));
+    let binary = |exponent| Some((2, exponent, Format::BinarySI));
+    match suffix {
+        "n" => decimal(-9),
+        "u" => decimal(-6),
+        "m" => decimal(-3),
+        "" => decimal(0),
+        "k" => decimal(3),
+        "M" => decimal(6),
+        "G" => decimal(9),
+        "T" => decimal(12),
+        "P" => decimal(15),
+        "E" => decimal(18),
+        "Ki" => binary(10),
+        "Mi" => binary(20),
+        "Gi" => binary(30),
+        "Ti" => binary(40),
+        "Pi" => binary(50),
+        "Ei" => binary(60),
+        _ if suffix.len() > 1 && suffix.starts_with(['e', 'E']) => {
+            let exponent: i32 = suffix[1..].parse().ok()?;
+            Some((10, i64::from(exponent), Format::DecimalExponent))
+        }
+        _ => None,
+    }
+}
+
+/// The decimal digits of `digits` times `factor`.
+fn multiply(digits: &str, factor: u64) -> String {
+    let mut product = Vec::with_capacity(digits.len() + 20);
+    let mut carry = 0u128;
+    for digit in digits.bytes().rev() {
+        let n = u128::from(digit - b'0') * u128::from(factor) + carry;
+        product.push(b'0' + (n % 10) as u8);
+        carry = n / 10;
+    }
+    while carry > 0 {
+        product.push(b'0' + (carry % 10) as u8);
+        carry /= 10;
+    }
+    product.reverse();
+    String::from_utf8(product).unwrap()
+}
+
+/// The quantity of `digits` times ten to the `exponent`, rounded up to the
+/// next nano and, if binary, capped at `MAX_BINARY`.
+fn round(
+    positive: bool,
+    digits: String,
+    mut exponent: i64,
+    format: Format,
+) -> std::result::Result<Quantity, &'static str> {
+    let mut digits = digits.trim_start_matches('0').to_owned();
+    while digits.ends_with('0') {
+        digits.pop();
+        exponent += 1;
+    }
+    if digits.is_empty() {
+        return Ok(Quantity {
+            value: 0,
+            scale: 0,
+            format,
+        });
+    }
+    if exponent < -9 {
+        // Any digits dropped are not all zero, so round up.
+        let kept = digits.len() as i64 + exponent + 9;
+        digits = if kept > 0 {
+            increment(&digits[..kept as usize])
+        } else {
+            String::from("1")
+        };
+        exponent = -9;
+    }
+    // The number of digits before the decimal point.
+    let magnitude = digits.len() as i64 + exponent;
+    let mut format = format;
+    if format == Format::BinarySI && exceeds(&digits, magnitude, MAX_BINARY) {
+        digits = String::from(MAX_BINARY);
+        exponent = 0;
+    } else if format == Format::BinarySI && magnitude <= 0 {
+        format = Format::DecimalSI;
+    }
+    let value: i128 = digits.parse().map_err(|_| OVERFLOW)?;
+    Ok(Quantity {
+        value: if positive { value } else { -value },
+        scale: exponent,
+        format,
+    })
+}
+
+/// The decimal digits of `digits` plus one.
+fn increment(digits: &str) -> String {
+    let mut bytes = digits.as_bytes().to_vec();
+    for byte in bytes.iter_mut().rev() {
+        if *byte == b'9' {
+            *byte = b'0';
+        } else {
+            *byte += 1;
+            return String::from_utf8(bytes).unwrap();
+        }
+    }
+    format!("1{}", String::from_utf8(bytes).unwrap())
+}
+
+/// Whether `digits` with `magnitude` digits before the decimal point are
+/// more than the integer `limit`.
+fn exceeds(digits: &str, magnitude: i64, limit: &str) -> bool {
+    match magnitude.cmp(&(limit.len() as i64)) {
+        Ordering::Less => false,
+        Ordering::Greater => true,
+        Ordering::Equal => digits > limit,
+    }
+}
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("quantity", quantity);
+    functions.add_global("isQuantity", is_quantity);
+    functions.add_global("string", string);
+    functions.add_member("sign", QUANTITY_TYPE, sign);
+    functions.add_member("isInteger", QUANTITY_TYPE, is_integer);
+    functions.add_member("asInteger", QUANTITY_TYPE, as_integer);
+    functions.add_member("asApproximateFloat", QUANTITY_TYPE, as_approximate_float);
+    functions.add_member("add", QUANTITY_TYPE, add);
+    functions.add_member("sub", QUANTITY_TYPE, sub);
+    functions.add_member("compareTo", QUANTITY_TYPE, compare_to);
+    functions.add_member("isLessThan", QUANTITY_TYPE, is_less_than);
+    functions.add_member("isGreaterThan", QUANTITY_TYPE, is_greater_than);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    let quantity_type = Type::opaque(QUANTITY_TYPE);
+    decls.add_overload(
+        "quantity",
+        Overload::global(
+            "string_to_quantity",
+            vec![Type::String],
+            quantity_type.clone(),
+        ),
+    );
+    decls.add_overload(
+        "isQuantity",
+        Overload::global("is_quantity_string", vec![Type::String], Type::Bool),
+    );
+    decls.add_overload(
+        "string",
+        Overload::global(
+            "quantity_to_string",
+            vec![quantity_type.clone()],
+            Type::String,
+        ),
+    );
+    let this = || vec![quantity_type.clone()];
+    let both = || vec![quantity_type.clone(), quantity_type.clone()];
+    let with_int = || vec![quantity_type.clone(), Type::Int];
+    for (function, id, params, result) in [
+        ("sign", "quantity_sign", this(), Type::Int),
+        ("isInteger", "quantity_is_integer", this(), Type::Bool),
+        ("asInteger", "quantity_get_integer", this(), Type::Int),
+        (
+            "asApproximateFloat",
+            "quantity_get_float",
+            this(),
+            Type::Double,
+        ),
+        ("add", "quantity_add", both(), quantity_type.clone()),
+        ("add", "quantity_add_int", with_int(), quantity_type.clone()),
+        ("sub", "quantity_sub", both(), quantity_type.clone()),
+        ("sub", "quantity_sub_int", with_int(), quantity_type.clone()),
+        ("compareTo", "quantity_compare_to", both(), Type::Int),
+        ("isLessThan", "quantity_is_less_than", both(), Type::Bool),
+        (
+            "isGreaterThan",
+            "quantity_is_greater_than",
+            both(),
+            Type::Bool,
+        ),
+    ] {
+        decls.add_overload(function, Overload::member(id, params, result));
+    }
+    for (function, prefix) in [
+        ("_==_", "equals"),
+        ("_!=_", "not_equals"),
+        ("_<_", "less"),
+        ("_<=_", "less_equals"),
+        ("_>_", "greater"),
+        ("_>=_", "greater_equals"),
+    ] {
+        let id = format!("{}_quantity", prefix);
+        decls.add_overload(function, Overload::global(&id, both(), Type::Bool));
+    }
+}
+
+fn quantity(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Opaque(Opaque::new(Quantity::parse(s)?))),
+        _ => Err(no_overload("quantity", &args)),
+    }
+}
+
+fn is_quantity(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Bool(Quantity::parse(s).is_ok())),
+        _ => Err(no_overload("isQuantity", &args)),
+    }
+}
+
+/// The quantity a method is called on, and its other arguments.
+fn receiver<'a>(function: &str, args: &'a [Literal]) -> Result<(&'a Quantity, &'a [Literal])> {
+    match args.split_first() {
+        Some((Literal::Opaque(value), rest)) => match value.downcast_ref::<Quantity>() {
+            Some(q) => Ok((q, rest)),
+            None => Err(no_overload(function, args)),
+        },
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+/// The argument of a method taking a quantity or, if `ints` is set, an int.
+fn operand(function: &str, args: &[Literal], ints: bool) -> Result<(Quantity, Quantity)> {
+    match receiver(function, args)? {
+        (q, [Literal::Opaque(value)]) => match value.downcast_ref::<Quantity>() {
+            Some(other) => Ok((*q, *other)),
+            None => Err(no_overload(function, args)),
+        },
+        (q, [Literal::I64(n)]) if ints => Ok((*q, Quantity::from_i64(*n))),
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+/// Applies `f` to the quantity a method without arguments is called on.
+fn accessor(
+    function: &str,
+    args: &[Literal],
+    f: fn(&Quantity) -> Result<Literal>,
+) -> Result<Literal> {
+    match receiver(function, args)? {
+        (q, []) => f(q),
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+fn sign(args: Vec<Literal>) -> Result<Literal> {
+    accessor("sign", &args, |q| Ok(Literal::I64(q.sign())))
+}
+
+fn is_integer(args: Vec<Literal>) -> Result<Literal> {
+    accessor("isInteger", &args, |q| {
+        Ok(Literal::Bool(q.as_i64().is_some()))
+    })
+}
+
+fn as_integer(args: Vec<Literal>) -> Result<Literal> {
+    accessor("asInteger", &args, |q| {
+        q.as_i64()
+            .map(Literal::I64)
+            .ok_or_else(|| String::from("cannot convert value to integer"))
+    })
+}
+
+fn as_approximate_float(args: Vec<Literal>) -> Result<Literal> {
+    accessor("asApproximateFloat", &args, |q| {
+        Ok(Literal::F64(q.as_f64()))
+    })
+}
+
+fn add(args: Vec<Literal>) -> Result<Literal> {
+    let (a, b) = operand("add", &args, true)?;
+    let sum = a.checked_add(&b).ok_or(OVERFLOW)?;
+    Ok(Literal::Opaque(Opaque::new(sum)))
+}
+
+fn sub(args: Vec<Literal>) -> Result<Literal> {
+    let (a, b) = operand("sub", &args, true)?;
+    let difference = a.checked_sub(&b).ok_or(OVERFLOW)?;
+    Ok(Literal::Opaque(Opaque::new(difference)))
+}
+
+fn compare_to(args: Vec<Literal>) -> Result<Literal> {
+    let (a, b) = operand("compareTo", &args, false)?;
+    Ok(Literal::I64(a.cmp(&b) as i64))
+}
+
+fn is_less_than(args: Vec<Literal>) -> Result<Literal> {
+    let (a, b) = operand("isLessThan", &args, false)?;
+    Ok(Literal::Bool(a < b))
+}
+
+fn is_greater_than(args: Vec<Literal>) -> Result<Literal> {
+    let (a, b) = operand("isGreaterThan", &args, false)?;
+    Ok(Literal::Bool(a > b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register, Quantity};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::{Literal, Opaque};
+    use crate::parsers::parse;
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        evaluate_with_functions(parse(input).unwrap(), &Activation::new(), &functions)
+    }
+
+    fn string(s: &str) -> Literal {
+        Literal::String(String::from(s))
+    }
+
+    #[test]
+    fn canonical() {
+        for (input, canonical) in [
+            ("0", "0"),
+            ("-0.0Ki", "0"),
+            ("1.5", "1500m"),
+            ("0.1", "100m"),
+            ("1000", "1k"),
+            ("1024", "1024"),
+            ("+12M", "12M"),
+            ("1.5Gi", "1536Mi"),
+            ("0.5Gi", "512Mi"),
+            ("2048Ki", "2Mi"),
+            ("1023Ki", "1023Ki"),
+            ("1.1Ki", "1126400m"),
+            ("-1.5Gi", "-1536Mi"),
+            ("0.5Ki", "512"),
+            ("1e3", "1e3"),
+            ("1.5e3", "1500"),
+            ("12E6", "12e6"),
+            ("100e-3", "100e-3"),
+            ("1.", "1"),
+            (".5", "500m"),
+            ("0.1n", "1n"),
+            ("-3.001n", "-4n"),
+            ("1.23456789012345678901", "1234567891n"),
+            ("12345678901234567890", "12345678901234567890"),
+            ("10000000000000000000000", "10e21"),
+            ("9Ei", "9223372036854775807"),
+        ] {
+            assert_eq!(
+                eval(&format!("string(quantity('{}'))", input)),
+                Ok(string(canonical)),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn validation() {
+        for valid in ["1", "-1m", "1.G", "5Ei", "1e-3", "1E+6", "0000.5"] {
+            assert_eq!(
+                eval(&format!("isQuantity('{}')", valid)),
+                Ok(Literal::Bool(true)),
+                "{}",
+                valid
+            );
+        }
+        for invalid in ["", "1x", "1KiB", "1ki", "1e", "1.5.5", " 1", "1e3.5"] {
+            assert_eq!(
+                eval(&format!("isQuantity('{}')", invalid)),
+                Ok(Literal::Bool(false)),
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(
+            eval("quantity('1x')"),
+            Err(String::from(
+                "quantities must match the regular expression \
+                 '^([+-]?[0-9.]+)([eEinumkKMGTP]*[-+]?[0-9]*)$'"
+            ))
+        );
+        assert_eq!(
+            eval("quantity('1ki')"),
+            Err(String::from("unable to parse quantity's suffix"))
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let t = Ok(Literal::Bool(true));
+        assert_eq!(eval("quantity('500m') < quantity('1')"), t);
+        assert_eq!(eval("quantity('500m').isLessThan(quantity('1'))"), t);
+        assert_eq!(eval("quantity('1Gi').isGreaterThan(quantity('1G'))"), t);
+        assert_eq!(eval("quantity('1Ki') == quantity('1024')"), t);
+        assert_eq!(eval("quantity('1e3') == quantity('1k')"), t);
+        assert_eq!(eval("quantity('1E') > quantity('999P')"), t);
+        assert_eq!(eval("quantity('1e100') > quantity('1Ei')"), t);
+        assert_eq!(eval("quantity('-1e100') <= quantity('-1n')"), t);
+        assert_eq!(eval("quantity('1m') != quantity('1')"), t);
+        assert_eq!(
+            eval("quantity('2').compareTo(quantity('1500m'))"),
+            Ok(Literal::I64(1))
+        );
+        assert_eq!(
+            eval("quantity('1.5').compareTo(quantity('1500m'))"),
+            Ok(Literal::I64(0))
+        );
+        assert_eq!(
+            eval("quantity('1').isLessThan(1)"),
+            Err(String::from(
+                "found no matching overload for 'isLessThan' applied to \
+                 '(kubernetes.Quantity, int)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(
+            eval("string(quantity('1Gi').add(quantity('512Mi')))"),
+            Ok(string("1536Mi"))
+        );
+        assert_eq!(eval("string(quantity('1k').add(1))"), Ok(string("1001")));
+        assert_eq!(
+            eval("string(quantity('0').add(quantity('1Ki')))"),
+            Ok(string("1Ki"))
+        );
+        assert_eq!(
+            eval("string(quantity('1').sub(quantity('1500m')))"),
+            Ok(string("-500m"))
+        );
+        assert_eq!(eval("string(quantity('2Ki').sub(1024))"), Ok(string("1Ki")));
+    }
+
+    #[test]
+    fn conversions() {
+        assert_eq!(eval("quantity('-5m').sign()"), Ok(Literal::I64(-1)));
+        assert_eq!(eval("quantity('0').sign()"), Ok(Literal::I64(0)));
+        assert_eq!(eval("quantity('1Ki').asInteger()"), Ok(Literal::I64(1024)));
+        assert_eq!(
+            eval("quantity('1500m').isInteger()"),
+            Ok(Literal::Bool(false))
+        );
+        assert_eq!(eval("quantity('1E').isInteger()"), Ok(Literal::Bool(true)));
+        assert_eq!(
+            eval("quantity('10E').isInteger()"),
+            Ok(Literal::Bool(false))
+        );
+        assert_eq!(
+            eval("quantity('1500m').asInteger()"),
+            Err(String::from("cannot convert value to integer"))
+        );
+        assert_eq!(
+            eval("quantity('1500m').asApproximateFloat()"),
+            Ok(Literal::F64(1.5))
+        );
+    }
+
+    #[test]
+    fn constructors() {
+        let constructor = |s| Opaque::new(Quantity::parse(s).unwrap()).constructor();
+        assert_eq!(constructor("1Ki"), ("quantity", vec![string("1024")]));
+        assert_eq!(constructor("1Ki"), constructor("1024"));
+        assert_eq!(constructor("1.5e3"), ("quantity", vec![string("1500")]));
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("request", Type::String);
+        let expr = parse(
+            "isQuantity(request) && quantity(request).add(1) <= quantity('2Gi') && \
+             quantity(request).sign() > 0",
+        )
+        .unwrap();
+        assert!(check(expr, &decls).is_ok());
+        let expr = parse("quantity(request) < 1").unwrap();
+        assert!(check(expr, &decls).is_err());
+    }
+}
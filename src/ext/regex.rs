@@ -0,0 +1,407 @@
+//! Regular expression functions, after cel-go's `ext.Regex`:
+//! `regex.extract`, `regex.extractAll` and `regex.replace`.
+//!
+//! Patterns use the syntax of the `regex` crate, which matches in time linear
+//! in the length of the input whatever the pattern. Patterns written as
+//! constants are compiled once, before evaluation starts: by
+//! `compiler::compile_with_functions`, which keeps them in the `Program`, or
+//! by the interpreter's `evaluate_with_functions` and `evaluate_with_options`.
+//! Either fails for an invalid one. Any other pattern is compiled on every
+//! call.
+
+use super::no_overload;
+use crate::analysis::qualified_name;
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::Functions;
+use crate::model::{
+    walk_expression_mut, ExprKind, Expression, Literal, Opaque, OpaqueValue, VisitorMut,
+};
+
+use regex::Regex;
+
+use std::any::Any;
+use std::borrow::Cow;
+use std::fmt;
+
+type Result<T> = std::result::Result<T, String>;
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("regex.extract", extract);
+    functions.add_global("regex.extractAll", extract_all);
+    functions.add_global("regex.replace", replace);
+    functions.add_preparation(prepare);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    decls.add_overload(
+        "regex.extract",
+        Overload::global(
+            "regex_extract_string_string",
+            vec![Type::String, Type::String],
+            Type::optional(Type::String),
+        ),
+    );
+    decls.add_overload(
+        "regex.extractAll",
+        Overload::global(
+            "regex_extractAll_string_string",
+            vec![Type::String, Type::String],
+            Type::list(Type::String),
+        ),
+    );
+    decls.add_overload(
+        "regex.replace",
+        Overload::global(
+            "regex_replace_string_string_string",
+            vec![Type::String, Type::String, Type::String],
+            Type::String,
+        ),
+    );
+    decls.add_overload(
+        "regex.replace",
+        Overload::global(
+            "regex_replace_string_string_string_int",
+            vec![Type::String, Type::String, Type::String, Type::Int],
+            Type::String,
+        ),
+    );
+}
+
+fn compile(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|_| format!("invalid regular expression '{}'", pattern))
+}
+
+/// A constant pattern compiled ahead of evaluation, which takes the place of
+/// the string it was written as. It is of its own type, `regex`, which only
+/// the functions of this library accept, and is written back out as that
+/// string.
+#[derive(Debug)]
+struct Pattern(Regex);
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0.as_str())
+    }
+}
+
+impl OpaqueValue for Pattern {
+    fn type_name(&self) -> &'static str {
+        "regex"
+    }
+
+    fn equals(&self, other: &dyn OpaqueValue) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Pattern>()
+            .is_some_and(|other| self.0.as_str() == other.0.as_str())
+    }
+
+    fn constructor(&self) -> (&'static str, Vec<Literal>) {
+        (
+            "string",
+            vec![Literal::String(String::from(self.0.as_str()))],
+        )
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The regular expression that the argument `pattern` stands for, if it is
+/// one: either a pattern compiled ahead of evaluation or a string, which is
+/// compiled now.
+fn regex(pattern: &Literal) -> Option<Result<Cow<'_, Regex>>> {
+    match pattern {
+        Literal::String(pattern) => Some(compile(pattern).map(Cow::Owned)),
+        Literal::Opaque(value) => value
+            .downcast_ref::<Pattern>()
+            .map(|pattern| Ok(Cow::Borrowed(&pattern.0))),
+        _ => None,
+    }
+}
+
+/// Compiles the constant patterns passed to the functions of this library
+/// in `expr`, replacing them with their compiled form, and fails for the
+/// first one that is invalid.
+pub fn prepare(expr: &mut Expression) -> Result<()> {
+    let mut patterns = Patterns(Ok(()));
+    patterns.visit_expression_mut(expr);
+    patterns.0
+}
+
+/// Compiles the constant patterns of calls to this library.
+struct Patterns(Result<()>);
+
+impl VisitorMut for Patterns {
+    fn visit_expression_mut(&mut self, expr: &mut Expression) {
+        walk_expression_mut(self, expr);
+        let pattern = match &mut expr.kind {
+            ExprKind::Method(receiver, name, args)
+                if qualified_name(receiver).as_deref() == Some("regex")
+                    && matches!(
+                        name.to_string().as_str(),
+                        "extract" | "extractAll" | "replace"
+                    ) =>
+            {
+                match args.get_mut(1) {
+                    Some(pattern) => pattern,
+                    None => return,
+                }
+            }
+            _ => return,
+        };
+        if let ExprKind::Lit(Literal::String(source)) = &pattern.kind {
+            match compile(source) {
+                Ok(regex) => {
+                    pattern.kind = ExprKind::Lit(Literal::Opaque(Opaque::new(Pattern(regex))))
+                }
+                Err(e) if self.0.is_ok() => self.0 = Err(e),
+                Err(_) => {}
+            }
+        }
+    }
+}
+
+/// The capture group whose text `extract` and `extractAll` return: the
+/// only one, or the whole match if there is none.
+fn group(regex: &Regex) -> Result<usize> {
+    match regex.captures_len() - 1 {
+        0 => Ok(0),
+        1 => Ok(1),
+        _ => Err(format!(
+            "regular expression has more than one capturing group: '{}'",
+            regex.as_str()
+        )),
+    }
+}
+
+/// `regex.extract(s, re)`: the text of the first match of `re` in `s`, or of
+/// its capture group if it has one, as an optional that is empty if there is
+/// no match.
+fn extract(args: Vec<Literal>) -> Result<Literal> {
+    let (s, regex) = match args.as_slice() {
+        [Literal::String(s), pattern] => (s, regex(pattern)),
+        _ => return Err(no_overload("regex.extract", &args)),
+    };
+    let regex = regex.ok_or_else(|| no_overload("regex.extract", &args))??;
+    let group = group(&regex)?;
+    let found = regex.captures(s).and_then(|c| c.get(group));
+    let found = found.map(|m| Box::new(Literal::String(String::from(m.as_str()))));
+    Ok(Literal::Optional(found))
+}
+
+/// `regex.extractAll(s, re)`: the texts `regex.extract` would return for
+/// each of the matches of `re` in `s`.
+fn extract_all(args: Vec<Literal>) -> Result<Literal> {
+    let (s, regex) = match args.as_slice() {
+        [Literal::String(s), pattern] => (s, regex(pattern)),
+        _ => return Err(no_overload("regex.extractAll", &args)),
+    };
+    let regex = regex.ok_or_else(|| no_overload("regex.extractAll", &args))??;
+    let group = group(&regex)?;
+    let found = regex
+        .captures_iter(s)
+        .filter_map(|c| c.get(group))
+        .map(|m| Literal::String(String::from(m.as_str())).into())
+        .collect();
+    Ok(Literal::List(found))
+}
+
+/// `regex.replace(s, re, replacement)` replaces every match of `re` in `s`,
+/// and `regex.replace(s, re, replacement, n)` the first `n`, or all of them
+/// if `n` is negative. In the replacement `\1` stands for the text of the
+/// first capture group, and `\\` for a backslash.
+fn replace(args: Vec<Literal>) -> Result<Literal> {
+    let (s, regex, replacement, n) = match args.as_slice() {
+        [Literal::String(s), pattern, Literal::String(replacement)] => {
+            (s, regex(pattern), replacement, -1)
+        }
+        [Literal::String(s), pattern, Literal::String(replacement), Literal::I64(n)] => {
+            (s, regex(pattern), replacement, *n)
+        }
+        _ => return Err(no_overload("regex.replace", &args)),
+    };
+    let regex = regex.ok_or_else(|| no_overload("regex.replace", &args))??;
+    let replacement = expansion(replacement, regex.captures_len() - 1)?;
+    let limit = if n < 0 { 0 } else { n as usize };
+    if n == 0 {
+        return Ok(Literal::String(s.clone()));
+    }
+    let replaced = regex.replacen(s, limit, replacement.as_str());
+    Ok(Literal::String(replaced.into_owned()))
+}
+
+/// Translates a replacement with `\1`-style group references into the
+/// `${1}` syntax of the `regex` crate.
+fn expansion(replacement: &str, groups: usize) -> Result<String> {
+    let mut expanded = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('\\') => expanded.push('\\'),
+                Some(d) if d.is_ascii_digit() => {
+                    let group = d.to_digit(10).unwrap() as usize;
+                    if group > groups {
+                        return Err(format!(
+                            "replacement refers to group {}, but the regular expression has {}",
+                            group, groups
+                        ));
+                    }
+                    expanded.push_str(&format!("${{{}}}", group));
+                }
+                _ => return Err(format!("invalid replacement string: '{}'", replacement)),
+            },
+            '$' => expanded.push_str("$$"),
+            c => expanded.push(c),
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::compiler::compile_with_functions;
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::{Expression, Literal};
+    use crate::parsers::parse;
+
+    fn functions() -> Functions {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        functions
+    }
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut activation = Activation::new();
+        activation.insert(
+            String::from("path"),
+            Literal::String(String::from("/tenants/acme/users/42")),
+        );
+        evaluate_with_functions(parse(input).unwrap(), &activation, &functions())
+    }
+
+    fn string(s: &str) -> Literal {
+        Literal::String(String::from(s))
+    }
+
+    #[test]
+    fn extract() {
+        assert_eq!(
+            eval("regex.extract(path, '/tenants/([^/]+)').value()"),
+            Ok(string("acme"))
+        );
+        assert_eq!(
+            eval("regex.extract(path, '[0-9]+')"),
+            Ok(Literal::Optional(Some(Box::new(string("42")))))
+        );
+        assert_eq!(
+            eval("regex.extract(path, '/groups/(.*)')"),
+            Ok(Literal::Optional(None))
+        );
+        assert_eq!(
+            eval("regex.extract(path, '(a)(c)')"),
+            Err(String::from(
+                "regular expression has more than one capturing group: '(a)(c)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn extract_all() {
+        let all = eval("regex.extractAll('a1 b22 c333', '[a-z]([0-9]+)')");
+        let expected: Vec<Expression> = ["1", "22", "333"]
+            .iter()
+            .map(|s| string(s).into())
+            .collect();
+        assert_eq!(all, Ok(Literal::List(expected)));
+        assert_eq!(
+            eval("regex.extractAll('abc', '[0-9]')"),
+            Ok(Literal::List(vec![]))
+        );
+    }
+
+    #[test]
+    fn replace() {
+        assert_eq!(
+            eval(r"regex.replace('abc-def', '([a-z]+)-([a-z]+)', '\\2-\\1')"),
+            Ok(string("def-abc"))
+        );
+        assert_eq!(eval("regex.replace('aaa', 'a', 'b', 2)"), Ok(string("bba")));
+        assert_eq!(eval("regex.replace('aaa', 'a', 'b', 0)"), Ok(string("aaa")));
+        assert_eq!(
+            eval("regex.replace('aaa', 'a', 'b', 0 - 1)"),
+            Ok(string("bbb"))
+        );
+        assert_eq!(
+            eval(r"regex.replace('a', 'a', '$1\\\\')"),
+            Ok(string("$1\\"))
+        );
+        assert_eq!(
+            eval(r"regex.replace('a', '(a)', '\\2')"),
+            Err(String::from(
+                "replacement refers to group 2, but the regular expression has 1"
+            ))
+        );
+        assert!(eval(r"regex.replace('a', 'a', '\\x')").is_err());
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert_eq!(
+            eval("regex.extract(path, '(')"),
+            Err(String::from("invalid regular expression '('"))
+        );
+        let functions = functions();
+        let invalid = parse("regex.extract(path, 'a(').hasValue()").unwrap();
+        assert_eq!(
+            compile_with_functions(&invalid, &functions).err(),
+            Some(String::from("invalid regular expression 'a('"))
+        );
+        let unevaluated = parse("true || regex.extract(path, '(').hasValue()").unwrap();
+        assert_eq!(
+            compile_with_functions(&unevaluated, &functions).err(),
+            Some(String::from("invalid regular expression '('"))
+        );
+        assert_eq!(
+            evaluate_with_functions(unevaluated, &Activation::new(), &functions),
+            Err(String::from("invalid regular expression '('"))
+        );
+        let valid = parse(r"regex.replace(path, '/users/([0-9]+)', '/u/\\1')").unwrap();
+        let program = compile_with_functions(&valid, &functions).unwrap();
+        assert!(program.constants.iter().any(
+            |constant| matches!(constant, Literal::Opaque(value) if value.type_name() == "regex")
+        ));
+        let mut activation = Activation::new();
+        activation.insert(String::from("path"), string("/tenants/t/users/42"));
+        assert_eq!(
+            program.evaluate_with_functions(&activation, &functions),
+            Ok(string("/tenants/t/u/42"))
+        );
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("path", Type::String);
+        let type_of =
+            |input: &str| check(parse(input).unwrap(), &decls).map(|c| c.result_type().clone());
+        assert_eq!(
+            type_of("regex.extract(path, '[0-9]+').orValue('')"),
+            Ok(Type::String)
+        );
+        assert_eq!(
+            type_of("regex.extractAll(path, '[0-9]+')"),
+            Ok(Type::list(Type::String))
+        );
+        assert_eq!(
+            type_of("regex.replace(path, 'a', 'b', 1)"),
+            Ok(Type::String)
+        );
+        assert!(type_of("regex.extract(path, 1)").is_err());
+    }
+}
@@ -0,0 +1,485 @@
+//! Semantic versions, after the Kubernetes CEL library `semver`:
+//! `semver('1.2.3')` creates a value of the opaque type `kubernetes.Semver`,
+//! and `isSemver` tests whether a string would be accepted by it.
+//!
+//! A version has the methods `major`, `minor`, `patch`, `compareTo`,
+//! `isLessThan` and `isGreaterThan`, and versions can be compared with `==`,
+//! `<` and the other relational operators.
+//!
+//! Versions are parsed strictly, as by Kubernetes: `v1.2` and `1.02.3` are
+//! rejected. Build metadata, as in `1.2.3+build.5`, is accepted but, as the
+//! specification requires, ignored when comparing versions, so it is not kept.
+
+use super::no_overload;
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::Functions;
+use crate::model::{Literal, Opaque, OpaqueValue};
+
+use std::any::Any;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt;
+
+type Result<T> = std::result::Result<T, String>;
+
+pub const SEMVER_TYPE: &str = "kubernetes.Semver";
+
+/// A version, as created by `semver`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Semver {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pub pre_release: Vec<Identifier>,
+}
+
+/// An identifier of a pre-release, e.g. the `alpha` and `1` of `alpha.1`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Identifier {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+impl Semver {
+    pub fn parse(s: &str) -> Result<Semver> {
+        parse(s).map_err(|e| format!("Semver parse error during conversion from string: {}", e))
+    }
+}
+
+/// Orders versions by precedence, under which a pre-release comes before
+/// the release it leads up to.
+impl Ord for Semver {
+    fn cmp(&self, other: &Semver) -> Ordering {
+        let core =
+            (self.major, self.minor, self.patch).cmp(&(other.major, other.minor, other.patch));
+        core.then_with(
+            || match (self.pre_release.is_empty(), other.pre_release.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => self.pre_release.cmp(&other.pre_release),
+            },
+        )
+    }
+}
+
+impl PartialOrd for Semver {
+    fn partial_cmp(&self, other: &Semver) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Numeric identifiers come before alphanumeric ones.
+impl Ord for Identifier {
+    fn cmp(&self, other: &Identifier) -> Ordering {
+        match (self, other) {
+            (Identifier::Numeric(a), Identifier::Numeric(b)) => a.cmp(b),
+            (Identifier::Numeric(_), Identifier::Alphanumeric(_)) => Ordering::Less,
+            (Identifier::Alphanumeric(_), Identifier::Numeric(_)) => Ordering::Greater,
+            (Identifier::Alphanumeric(a), Identifier::Alphanumeric(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Identifier) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Semver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        for (i, identifier) in self.pre_release.iter().enumerate() {
+            write!(f, "{}", if i == 0 { '-' } else { '.' })?;
+            match identifier {
+                Identifier::Numeric(n) => write!(f, "{}", n)?,
+                Identifier::Alphanumeric(s) => write!(f, "{}", s)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OpaqueValue for Semver {
+    fn type_name(&self) -> &'static str {
+        SEMVER_TYPE
+    }
+
+    fn equals(&self, other: &dyn OpaqueValue) -> bool {
+        other.as_any().downcast_ref::<Semver>() == Some(self)
+    }
+
+    fn constructor(&self) -> (&'static str, Vec<Literal>) {
+        ("semver", vec![Literal::String(self.to_string())])
+    }
+
+    fn compare(&self, other: &dyn OpaqueValue) -> Option<Ordering> {
+        other
+            .as_any()
+            .downcast_ref::<Semver>()
+            .map(|other| self.cmp(other))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn parse(s: &str) -> Result<Semver> {
+    if s.is_empty() {
+        return Err(String::from("version string empty"));
+    }
+    let (version, build) = match s.split_once('+') {
+        Some((version, build)) => (version, Some(build)),
+        None => (s, None),
+    };
+    if let Some(build) = build {
+        for identifier in build.split('.') {
+            if identifier.is_empty() || !is_alphanumeric(identifier) {
+                return Err(format!("invalid build metadata {:?}", build));
+            }
+        }
+    }
+    let (core, pre_release) = match version.split_once('-') {
+        Some((core, pre_release)) => (core, Some(pre_release)),
+        None => (version, None),
+    };
+    let parts: Vec<&str> = core.split('.').collect();
+    let (major, minor, patch) = match parts.as_slice() {
+        [major, minor, patch] => (major, minor, patch),
+        _ => return Err(String::from("no major, minor and patch version found")),
+    };
+    let pre_release = match pre_release {
+        Some(pre_release) => pre_release
+            .split('.')
+            .map(parse_identifier)
+            .collect::<Result<_>>()?,
+        None => Vec::new(),
+    };
+    Ok(Semver {
+        major: parse_number("major", major)?,
+        minor: parse_number("minor", minor)?,
+        patch: parse_number("patch", patch)?,
+        pre_release,
+    })
+}
+
+/// Whether `s` consists of ASCII letters, digits and hyphens.
+fn is_alphanumeric(s: &str) -> bool {
+    s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+fn is_numeric(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn parse_number(part: &str, s: &str) -> Result<u64> {
+    if !is_numeric(s) {
+        return Err(format!(
+            "invalid character(s) found in {} number {:?}",
+            part, s
+        ));
+    }
+    if s.len() > 1 && s.starts_with('0') {
+        return Err(format!(
+            "{} number must not contain leading zeroes {:?}",
+            part, s
+        ));
+    }
+    s.parse()
+        .map_err(|_| format!("{} number {:?} is too large", part, s))
+}
+
+fn parse_identifier(s: &str) -> Result<Identifier> {
+    if s.is_empty() {
+        return Err(String::from("pre-release identifier is empty"));
+    }
+    if !is_alphanumeric(s) {
+        return Err(format!("invalid character(s) found in pre-release {:?}", s));
+    }
+    if !is_numeric(s) {
+        return Ok(Identifier::Alphanumeric(String::from(s)));
+    }
+    if s.len() > 1 && s.starts_with('0') {
+        return Err(format!(
+            "pre-release number must not contain leading zeroes {:?}",
+            s
+        ));
+    }
+    s.parse()
+        .map(Identifier::Numeric)
+        .map_err(|_| format!("pre-release number {:?} is too large", s))
+}
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("semver", semver);
+    functions.add_global("isSemver", is_semver);
+    functions.add_member("major", SEMVER_TYPE, major);
+    functions.add_member("minor", SEMVER_TYPE, minor);
+    functions.add_member("patch", SEMVER_TYPE, patch);
+    functions.add_member("compareTo", SEMVER_TYPE, compare_to);
+    functions.add_member("isLessThan", SEMVER_TYPE, is_less_than);
+    functions.add_member("isGreaterThan", SEMVER_TYPE, is_greater_than);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    let semver_type = Type::opaque(SEMVER_TYPE);
+    decls.add_overload(
+        "semver",
+        Overload::global("string_to_semver", vec![Type::String], semver_type.clone()),
+    );
+    decls.add_overload(
+        "isSemver",
+        Overload::global("is_semver_string", vec![Type::String], Type::Bool),
+    );
+    let this = || vec![semver_type.clone()];
+    let both = || vec![semver_type.clone(), semver_type.clone()];
+    for (function, id, params, result) in [
+        ("major", "semver_major", this(), Type::Int),
+        ("minor", "semver_minor", this(), Type::Int),
+        ("patch", "semver_patch", this(), Type::Int),
+        ("compareTo", "semver_compare_to", both(), Type::Int),
+        ("isLessThan", "semver_is_less_than", both(), Type::Bool),
+        (
+            "isGreaterThan",
+            "semver_is_greater_than",
+            both(),
+            Type::Bool,
+        ),
+    ] {
+        decls.add_overload(function, Overload::member(id, params, result));
+    }
+    for (function, prefix) in [
+        ("_==_", "equals"),
+        ("_!=_", "not_equals"),
+        ("_<_", "less"),
+        ("_<=_", "less_equals"),
+        ("_>_", "greater"),
+        ("_>=_", "greater_equals"),
+    ] {
+        let id = format!("{}_semver", prefix);
+        decls.add_overload(function, Overload::global(&id, both(), Type::Bool));
+    }
+}
+
+fn semver(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Opaque(Opaque::new(Semver::parse(s)?))),
+        _ => Err(no_overload("semver", &args)),
+    }
+}
+
+fn is_semver(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Bool(Semver::parse(s).is_ok())),
+        _ => Err(no_overload("isSemver", &args)),
+    }
+}
+
+/// The versions a method is called on and with.
+fn versions<'a>(function: &str, args: &'a [Literal]) -> Result<Vec<&'a Semver>> {
+    args.iter()
+        .map(|arg| match arg {
+            Literal::Opaque(value) => value.downcast_ref::<Semver>(),
+            _ => None,
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(|| no_overload(function, args))
+}
+
+/// Applies an accessor of a number to the version a method is called on.
+fn number(function: &str, args: &[Literal], accessor: fn(&Semver) -> u64) -> Result<Literal> {
+    match versions(function, args)?.as_slice() {
+        [version] => i64::try_from(accessor(version))
+            .map(Literal::I64)
+            .map_err(|_| String::from("integer overflow")),
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+/// Compares the version a method is called on with its argument.
+fn compare(function: &str, args: &[Literal]) -> Result<Ordering> {
+    match versions(function, args)?.as_slice() {
+        [a, b] => Ok(a.cmp(b)),
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+fn major(args: Vec<Literal>) -> Result<Literal> {
+    number("major", &args, |version| version.major)
+}
+
+fn minor(args: Vec<Literal>) -> Result<Literal> {
+    number("minor", &args, |version| version.minor)
+}
+
+fn patch(args: Vec<Literal>) -> Result<Literal> {
+    number("patch", &args, |version| version.patch)
+}
+
+fn compare_to(args: Vec<Literal>) -> Result<Literal> {
+    Ok(Literal::I64(compare("compareTo", &args)? as i64))
+}
+
+fn is_less_than(args: Vec<Literal>) -> Result<Literal> {
+    Ok(Literal::Bool(compare("isLessThan", &args)?.is_lt()))
+}
+
+fn is_greater_than(args: Vec<Literal>) -> Result<Literal> {
+    Ok(Literal::Bool(compare("isGreaterThan", &args)?.is_gt()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::Literal;
+    use crate::parsers::parse;
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        evaluate_with_functions(parse(input).unwrap(), &Activation::new(), &functions)
+    }
+
+    #[test]
+    fn components() {
+        assert_eq!(eval("semver('1.2.3').major()"), Ok(Literal::I64(1)));
+        assert_eq!(eval("semver('1.2.3').minor()"), Ok(Literal::I64(2)));
+        assert_eq!(
+            eval("semver('1.2.3-rc.1+build.5').patch()"),
+            Ok(Literal::I64(3))
+        );
+        assert_eq!(
+            eval("semver('9223372036854775808.0.0').major()"),
+            Err(String::from("integer overflow"))
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.2.0",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for (i, a) in ordered.iter().enumerate() {
+            for (j, b) in ordered.iter().enumerate() {
+                let compare = format!("semver('{}').compareTo(semver('{}'))", a, b);
+                assert_eq!(
+                    eval(&compare),
+                    Ok(Literal::I64(i.cmp(&j) as i64)),
+                    "{}",
+                    compare
+                );
+                let less = format!("semver('{}').isLessThan(semver('{}'))", a, b);
+                assert_eq!(eval(&less), Ok(Literal::Bool(i < j)), "{}", less);
+                let greater = format!("semver('{}').isGreaterThan(semver('{}'))", a, b);
+                assert_eq!(eval(&greater), Ok(Literal::Bool(i > j)), "{}", greater);
+            }
+        }
+    }
+
+    #[test]
+    fn equality() {
+        assert_eq!(
+            eval("semver('1.2.3') == semver('1.2.3')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("semver('1.2.3+a') == semver('1.2.3+b')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("semver('1.2.3-a') != semver('1.2.3')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("semver('1.2.3-a') < semver('1.2.3')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("semver('1.10.0') >= semver('1.9.0')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("semver('1.2.3') < '1.2.4'"),
+            Err(String::from("invalid types"))
+        );
+    }
+
+    #[test]
+    fn validation() {
+        for valid in [
+            "0.0.0",
+            "1.2.3-0",
+            "1.2.3-x-y.0a",
+            "1.2.3+001",
+            "10.20.30-rc.1+b",
+        ] {
+            assert_eq!(
+                eval(&format!("isSemver('{}')", valid)),
+                Ok(Literal::Bool(true)),
+                "{}",
+                valid
+            );
+        }
+        for invalid in [
+            "",
+            "1",
+            "1.2",
+            "v1.2.3",
+            "1.2.3.4",
+            "01.2.3",
+            "1.2.3-01",
+            "1.2.3-",
+            "1.2.3-a..b",
+            "1.2.3+",
+            "1.2.3+a_b",
+            "1.2.x",
+            "1.2.-3",
+        ] {
+            assert_eq!(
+                eval(&format!("isSemver('{}')", invalid)),
+                Ok(Literal::Bool(false)),
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(
+            eval("semver('1.02.3')"),
+            Err(String::from(
+                "Semver parse error during conversion from string: \
+                 minor number must not contain leading zeroes \"02\""
+            ))
+        );
+        assert_eq!(
+            eval("semver(1)"),
+            Err(String::from(
+                "found no matching overload for 'semver' applied to '(int)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("v", Type::String);
+        let expr = parse("isSemver(v) && semver(v).isLessThan(semver('1.25.0'))").unwrap();
+        assert!(check(expr, &decls).is_ok());
+        let expr = parse("semver(v).isLessThan('1.25.0')").unwrap();
+        assert!(check(expr, &decls).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Set functions over lists, after cel-go's `ext.Sets`: `sets.contains`,
+//! `sets.equivalent` and `sets.intersects`.
+//!
+//! Elements are compared with CEL's heterogeneous equality, under which
+//! `1 == 1.0` and values of different types are simply unequal. Each list is
+//! hashed once, so a call takes time linear in the lengths of the lists.
+
+use super::{elements, no_overload};
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::{evaluate, Functions};
+use crate::model::{Expression, Literal};
+
+use std::collections::HashSet;
+use std::convert::TryFrom;
+
+type Result<T> = std::result::Result<T, String>;
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("sets.contains", contains);
+    functions.add_global("sets.equivalent", equivalent);
+    functions.add_global("sets.intersects", intersects);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    let list = || Type::list(Type::param("A"));
+    for (function, id) in [
+        ("sets.contains", "list_sets_contains_list"),
+        ("sets.equivalent", "list_sets_equivalent_list"),
+        ("sets.intersects", "list_sets_intersects_list"),
+    ] {
+        decls.add_overload(
+            function,
+            Overload::global(id, vec![list(), list()], Type::Bool),
+        );
+    }
+}
+
+/// A value in a form that can be hashed, such that two values are equal
+/// under heterogeneous equality exactly when their keys are equal.
+#[derive(Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub(crate) enum Key {
+    Null,
+    Bool(bool),
+    /// An int, or a uint or a double with an integral value that fits in one.
+    Int(i64),
+    /// A uint above the largest int, or a double with such a value.
+    Uint(u64),
+    /// Any other double, by its bits.
+    Double(u64),
+    String(String),
+    Bytes(Vec<u8>),
+    List(Vec<Key>),
+    /// The entries of a map, sorted by key.
+    Map(Vec<(Key, Key)>),
+    /// A value of a type defined outside of CEL, by the function and
+    /// arguments that create it.
+    Opaque(&'static str, Vec<Key>),
+    Optional(Option<Box<Key>>),
+}
+
+/// The key of `value`, or `None` if it is not equal to any value, as for
+/// NaN and lists that contain it.
+pub(crate) fn key(value: Literal) -> Result<Option<Key>> {
+    Ok(Some(match value {
+        Literal::Null => Key::Null,
+        Literal::Bool(b) => Key::Bool(b),
+        Literal::I64(x) => Key::Int(x),
+        Literal::U64(x) => match i64::try_from(x) {
+            Ok(x) => Key::Int(x),
+            Err(_) => Key::Uint(x),
+        },
+        Literal::F64(x) if x.is_nan() => return Ok(None),
+        Literal::F64(x) if x.fract() == 0.0 && x >= i64::MIN as f64 && x < i64::MAX as f64 => {
+            Key::Int(x as i64)
+        }
+        Literal::F64(x) if x.fract() == 0.0 && x >= 0.0 && x < u64::MAX as f64 => {
+            Key::Uint(x as u64)
+        }
+        Literal::F64(x) => Key::Double(x.to_bits()),
+        Literal::String(s) => Key::String(s),
+        Literal::Bytes(b) => Key::Bytes(b),
+        Literal::List(xs) => {
+            let mut keys = Vec::with_capacity(xs.len());
+            for x in elements(xs)? {
+                match key(x)? {
+                    Some(k) => keys.push(k),
+                    None => return Ok(None),
+                }
+            }
+            Key::List(keys)
+        }
+        Literal::Map(entries) => {
+            let mut keys = Vec::with_capacity(entries.len());
+            for (k, v) in entries {
+                match (key(evaluate(k)?)?, key(evaluate(v)?)?) {
+                    (Some(k), Some(v)) => keys.push((k, v)),
+                    _ => return Ok(None),
+                }
+            }
+            keys.sort();
+            Key::Map(keys)
+        }
+        Literal::Opaque(value) => {
+            let (function, args) = value.constructor();
+            let mut keys = Vec::with_capacity(args.len());
+            for arg in args {
+                match key(arg)? {
+                    Some(k) => keys.push(k),
+                    None => return Ok(None),
+                }
+            }
+            Key::Opaque(function, keys)
+        }
+        Literal::Optional(None) => Key::Optional(None),
+        Literal::Optional(Some(value)) => match key(*value)? {
+            Some(k) => Key::Optional(Some(Box::new(k))),
+            None => return Ok(None),
+        },
+    }))
+}
+
+/// The keys of the elements of `xs`, along with the first failure to
+/// evaluate one, which only matters if no other element answers the
+/// question.
+fn keys(xs: &[Expression]) -> (HashSet<Key>, Option<String>) {
+    let mut keys = HashSet::with_capacity(xs.len());
+    let mut err = None;
+    for x in xs {
+        match evaluate(x.clone()).and_then(key) {
+            Ok(Some(k)) => {
+                keys.insert(k);
+            }
+            Ok(None) => {}
+            Err(e) => {
+                err.get_or_insert(e);
+            }
+        }
+    }
+    (keys, err)
+}
+
+/// Whether every element of `needles` is in `haystack`.
+fn contains_all(haystack: &[Expression], needles: &[Expression]) -> Result<bool> {
+    let (keys, err) = keys(haystack);
+    for needle in needles {
+        let found = match key(evaluate(needle.clone())?)? {
+            Some(k) => keys.contains(&k),
+            None => false,
+        };
+        if !found {
+            return match err {
+                Some(e) => Err(e),
+                None => Ok(false),
+            };
+        }
+    }
+    Ok(true)
+}
+
+/// `sets.contains(xs, ys)`: whether every element of `ys` is in `xs`.
+fn contains(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::List(xs), Literal::List(ys)] => contains_all(xs, ys).map(Literal::Bool),
+        _ => Err(no_overload("sets.contains", &args)),
+    }
+}
+
+/// `sets.equivalent(xs, ys)`: whether `xs` and `ys` have the same elements,
+/// ignoring repetitions.
+fn equivalent(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::List(xs), Literal::List(ys)] => Ok(Literal::Bool(
+            contains_all(xs, ys)? && contains_all(ys, xs)?,
+        )),
+        _ => Err(no_overload("sets.equivalent", &args)),
+    }
+}
+
+/// `sets.intersects(xs, ys)`: whether `xs` and `ys` have an element in
+/// common.
+fn intersects(args: Vec<Literal>) -> Result<Literal> {
+    let (xs, ys) = match args.as_slice() {
+        [Literal::List(xs), Literal::List(ys)] => (xs, ys),
+        _ => return Err(no_overload("sets.intersects", &args)),
+    };
+    let (keys, mut err) = keys(xs);
+    for y in ys {
+        match evaluate(y.clone()).and_then(key) {
+            Ok(Some(k)) if keys.contains(&k) => return Ok(Literal::Bool(true)),
+            Ok(_) => {}
+            Err(e) => {
+                err.get_or_insert(e);
+            }
+        }
+    }
+    match err {
+        Some(e) => Err(e),
+        None => Ok(Literal::Bool(false)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::Literal;
+    use crate::parsers::parse;
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        let mut activation = Activation::new();
+        activation.insert(String::from("nan"), Literal::F64(f64::NAN));
+        evaluate_with_functions(parse(input).unwrap(), &activation, &functions)
+    }
+
+    fn assert_eval(input: &str, expected: bool) {
+        assert_eq!(eval(input), Ok(Literal::Bool(expected)), "{}", input);
+    }
+
+    #[test]
+    fn contains() {
+        assert_eval("sets.contains([1, 2, 3], [3, 1])", true);
+        assert_eval("sets.contains([1, 2], [2, 2, 2])", true);
+        assert_eval("sets.contains([1, 2], [3])", false);
+        assert_eval("sets.contains(['admin', 'dev'], ['dev'])", true);
+        assert_eval("sets.contains([1], [1.0])", true);
+        assert_eval("sets.contains([1.5], [1])", false);
+        assert_eval("sets.contains([1], ['1'])", false);
+        assert_eval("sets.contains([[1, 2]], [[1.0, 2]])", true);
+        assert_eval(
+            "sets.contains([{'a': 1, 'b': 2}], [{'b': 2, 'a': 1}])",
+            true,
+        );
+        assert_eval("sets.contains([nan], [nan])", false);
+    }
+
+    #[test]
+    fn equivalent() {
+        assert_eval("sets.equivalent([1, 2, 2], [2, 1])", true);
+        assert_eval("sets.equivalent([1, 2], [1])", false);
+        assert_eval("sets.equivalent([1, 2.0], [2, 1.0])", true);
+    }
+
+    #[test]
+    fn intersects() {
+        assert_eval(
+            "sets.intersects(['read', 'write'], ['write', 'admin'])",
+            true,
+        );
+        assert_eval("sets.intersects([1, 2], [3, 4])", false);
+        assert_eval("sets.intersects([1], [1.0])", true);
+    }
+
+    #[test]
+    fn errors_inside_elements() {
+        assert_eval("sets.contains([1 / 0, 2], [2])", true);
+        assert_eq!(
+            eval("sets.contains([1 / 0, 2], [3])"),
+            Err(String::from("divide by zero"))
+        );
+        assert_eval("sets.intersects([1 / 0, 2], [2])", true);
+        assert_eq!(
+            eval("sets.intersects([1, 2], [1 / 0])"),
+            Err(String::from("divide by zero"))
+        );
+        assert_eq!(
+            eval("sets.contains([1], 1)"),
+            Err(String::from(
+                "found no matching overload for 'sets.contains' applied to '(list, int)'"
+            ))
+        );
+    }
+
+    #[test]
+    fn large_lists() {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        let xs = Literal::List((0..50_000).map(|i| Literal::I64(i).into()).collect());
+        let mut activation = Activation::new();
+        activation.insert(String::from("xs"), xs);
+        let expr = parse("sets.equivalent(xs, xs)").unwrap();
+        assert_eq!(
+            evaluate_with_functions(expr, &activation, &functions),
+            Ok(Literal::Bool(true))
+        );
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("roles", Type::list(Type::String));
+        let type_of =
+            |input: &str| check(parse(input).unwrap(), &decls).map(|c| c.result_type().clone());
+        assert_eq!(type_of("sets.contains(roles, ['admin'])"), Ok(Type::Bool));
+        assert_eq!(type_of("sets.intersects(roles, roles)"), Ok(Type::Bool));
+        assert!(type_of("sets.equivalent(roles, [1])").is_err());
+    }
+}
@@ -0,0 +1,724 @@
+//! String functions, after cel-go's `ext.Strings`: `charAt`, `indexOf`,
+//! `lastIndexOf`, `lowerAscii`, `upperAscii`, `replace`, `split`,
+//! `substring`, `trim`, `join`, `reverse`, `format` and `strings.quote`.
+//!
+//! Indices count Unicode code points, and an index outside the string is an
+//! error.
+
+use super::{elements, no_overload, type_name};
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::{evaluate, Functions};
+use crate::model::{Expression, Literal};
+
+type Result<T> = std::result::Result<T, String>;
+
+pub fn register(functions: &mut Functions) {
+    functions.add_member("charAt", "string", char_at);
+    functions.add_member("indexOf", "string", index_of);
+    functions.add_member("lastIndexOf", "string", last_index_of);
+    functions.add_member("lowerAscii", "string", lower_ascii);
+    functions.add_member("upperAscii", "string", upper_ascii);
+    functions.add_member("replace", "string", replace);
+    functions.add_member("split", "string", split);
+    functions.add_member("substring", "string", substring);
+    functions.add_member("trim", "string", trim);
+    functions.add_member("join", "list", join);
+    functions.add_member("reverse", "string", reverse);
+    functions.add_member("format", "string", format);
+    functions.add_global("strings.quote", quote);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    let strings = Type::list(Type::String);
+    for (function, id, params, result) in [
+        (
+            "charAt",
+            "string_char_at_int",
+            vec![Type::String, Type::Int],
+            Type::String,
+        ),
+        (
+            "indexOf",
+            "string_index_of_string",
+            vec![Type::String, Type::String],
+            Type::Int,
+        ),
+        (
+            "indexOf",
+            "string_index_of_string_int",
+            vec![Type::String, Type::String, Type::Int],
+            Type::Int,
+        ),
+        (
+            "lastIndexOf",
+            "string_last_index_of_string",
+            vec![Type::String, Type::String],
+            Type::Int,
+        ),
+        (
+            "lastIndexOf",
+            "string_last_index_of_string_int",
+            vec![Type::String, Type::String, Type::Int],
+            Type::Int,
+        ),
+        (
+            "lowerAscii",
+            "string_lower_ascii",
+            vec![Type::String],
+            Type::String,
+        ),
+        (
+            "upperAscii",
+            "string_upper_ascii",
+            vec![Type::String],
+            Type::String,
+        ),
+        (
+            "replace",
+            "string_replace_string_string",
+            vec![Type::String, Type::String, Type::String],
+            Type::String,
+        ),
+        (
+            "replace",
+            "string_replace_string_string_int",
+            vec![Type::String, Type::String, Type::String, Type::Int],
+            Type::String,
+        ),
+        (
+            "split",
+            "string_split_string",
+            vec![Type::String, Type::String],
+            strings.clone(),
+        ),
+        (
+            "split",
+            "string_split_string_int",
+            vec![Type::String, Type::String, Type::Int],
+            strings.clone(),
+        ),
+        (
+            "substring",
+            "string_substring_int",
+            vec![Type::String, Type::Int],
+            Type::String,
+        ),
+        (
+            "substring",
+            "string_substring_int_int",
+            vec![Type::String, Type::Int, Type::Int],
+            Type::String,
+        ),
+        ("trim", "string_trim", vec![Type::String], Type::String),
+        ("join", "list_join", vec![strings.clone()], Type::String),
+        (
+            "join",
+            "list_join_string",
+            vec![strings, Type::String],
+            Type::String,
+        ),
+        (
+            "reverse",
+            "string_reverse",
+            vec![Type::String],
+            Type::String,
+        ),
+        (
+            "format",
+            "string_format",
+            vec![Type::String, Type::list(Type::Dyn)],
+            Type::String,
+        ),
+    ] {
+        decls.add_overload(function, Overload::member(id, params, result));
+    }
+    decls.add_overload(
+        "strings.quote",
+        Overload::global("strings_quote", vec![Type::String], Type::String),
+    );
+}
+
+/// Checks that `i` is a position in a string of `len` code points, which
+/// may be its end.
+fn position(i: i64, len: usize) -> Result<usize> {
+    if i < 0 || i as u64 > len as u64 {
+        return Err(format!("index out of range: {}", i));
+    }
+    Ok(i as usize)
+}
+
+fn string(s: String) -> Literal {
+    Literal::String(s)
+}
+
+fn char_at(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s), Literal::I64(i)] => {
+            let chars: Vec<char> = s.chars().collect();
+            let i = position(*i, chars.len())?;
+            let c = chars.get(i).map(char::to_string).unwrap_or_default();
+            Ok(string(c))
+        }
+        _ => Err(no_overload("charAt", &args)),
+    }
+}
+
+fn index_of(args: Vec<Literal>) -> Result<Literal> {
+    let (s, sub, offset) = match args.as_slice() {
+        [Literal::String(s), Literal::String(sub)] => (s, sub, 0),
+        [Literal::String(s), Literal::String(sub), Literal::I64(offset)] => (s, sub, *offset),
+        _ => return Err(no_overload("indexOf", &args)),
+    };
+    let s: Vec<char> = s.chars().collect();
+    let sub: Vec<char> = sub.chars().collect();
+    let offset = position(offset, s.len())?;
+    let found = (offset..=s.len())
+        .take_while(|i| i + sub.len() <= s.len())
+        .find(|&i| s[i..i + sub.len()] == sub[..]);
+    Ok(Literal::I64(found.map_or(-1, |i| i as i64)))
+}
+
+fn last_index_of(args: Vec<Literal>) -> Result<Literal> {
+    let (s, sub, offset) = match args.as_slice() {
+        [Literal::String(s), Literal::String(sub)] => (s, sub, None),
+        [Literal::String(s), Literal::String(sub), Literal::I64(offset)] => (s, sub, Some(*offset)),
+        _ => return Err(no_overload("lastIndexOf", &args)),
+    };
+    let s: Vec<char> = s.chars().collect();
+    let sub: Vec<char> = sub.chars().collect();
+    let offset = match offset {
+        Some(offset) => position(offset, s.len())?,
+        None => s.len(),
+    };
+    let found = (0..=offset)
+        .rev()
+        .filter(|i| i + sub.len() <= s.len())
+        .find(|&i| s[i..i + sub.len()] == sub[..]);
+    Ok(Literal::I64(found.map_or(-1, |i| i as i64)))
+}
+
+fn lower_ascii(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(string(s.to_ascii_lowercase())),
+        _ => Err(no_overload("lowerAscii", &args)),
+    }
+}
+
+fn upper_ascii(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(string(s.to_ascii_uppercase())),
+        _ => Err(no_overload("upperAscii", &args)),
+    }
+}
+
+/// `s.replace(old, new)`, or `s.replace(old, new, n)` to replace only the
+/// first `n` occurrences, or all of them if `n` is negative.
+fn replace(args: Vec<Literal>) -> Result<Literal> {
+    let (s, old, new, n) = match args.as_slice() {
+        [Literal::String(s), Literal::String(old), Literal::String(new)] => (s, old, new, -1),
+        [Literal::String(s), Literal::String(old), Literal::String(new), Literal::I64(n)] => {
+            (s, old, new, *n)
+        }
+        _ => return Err(no_overload("replace", &args)),
+    };
+    if n < 0 {
+        Ok(string(s.replace(old.as_str(), new)))
+    } else {
+        Ok(string(s.replacen(old.as_str(), new, n as usize)))
+    }
+}
+
+/// `s.split(sep)`, or `s.split(sep, n)` to split into at most `n` parts, the
+/// last of which is the rest of the string. An empty separator splits the
+/// string into code points.
+fn split(args: Vec<Literal>) -> Result<Literal> {
+    let (s, sep, n) = match args.as_slice() {
+        [Literal::String(s), Literal::String(sep)] => (s, sep, -1),
+        [Literal::String(s), Literal::String(sep), Literal::I64(n)] => (s, sep, *n),
+        _ => return Err(no_overload("split", &args)),
+    };
+    let parts: Vec<String> = if n == 0 {
+        Vec::new()
+    } else if sep.is_empty() {
+        let chars: Vec<char> = s.chars().collect();
+        let n = if n < 0 {
+            chars.len()
+        } else {
+            chars.len().min(n as usize)
+        };
+        let mut parts: Vec<String> = chars.iter().take(n).map(char::to_string).collect();
+        if let Some(last) = parts.last_mut() {
+            *last = chars[n - 1..].iter().collect();
+        }
+        parts
+    } else if n < 0 {
+        s.split(sep.as_str()).map(String::from).collect()
+    } else {
+        s.splitn(n as usize, sep.as_str())
+            .map(String::from)
+            .collect()
+    };
+    Ok(Literal::List(
+        parts
+            .into_iter()
+            .map(|part| Expression::from(string(part)))
+            .collect(),
+    ))
+}
+
+fn substring(args: Vec<Literal>) -> Result<Literal> {
+    let (s, start, end) = match args.as_slice() {
+        [Literal::String(s), Literal::I64(start)] => (s, *start, None),
+        [Literal::String(s), Literal::I64(start), Literal::I64(end)] => (s, *start, Some(*end)),
+        _ => return Err(no_overload("substring", &args)),
+    };
+    let chars: Vec<char> = s.chars().collect();
+    let start = position(start, chars.len())?;
+    let end = match end {
+        Some(end) => position(end, chars.len())?,
+        None => chars.len(),
+    };
+    if start > end {
+        return Err(format!(
+            "invalid substring range. start: {}, end: {}",
+            start, end
+        ));
+    }
+    Ok(string(chars[start..end].iter().collect()))
+}
+
+fn trim(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(string(String::from(s.trim()))),
+        _ => Err(no_overload("trim", &args)),
+    }
+}
+
+fn join(args: Vec<Literal>) -> Result<Literal> {
+    let (xs, sep) = match args.as_slice() {
+        [Literal::List(xs)] => (xs, ""),
+        [Literal::List(xs), Literal::String(sep)] => (xs, sep.as_str()),
+        _ => return Err(no_overload("join", &args)),
+    };
+    let mut parts = Vec::with_capacity(xs.len());
+    for x in elements(xs.clone())? {
+        match x {
+            Literal::String(s) => parts.push(s),
+            other => {
+                return Err(format!(
+                    "join: list element is not a string: {}",
+                    type_name(&other)
+                ))
+            }
+        }
+    }
+    Ok(string(parts.join(sep)))
+}
+
+fn reverse(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(string(s.chars().rev().collect())),
+        _ => Err(no_overload("reverse", &args)),
+    }
+}
+
+/// `strings.quote(s)`: `s` as a double-quoted string literal.
+fn quote(args: Vec<Literal>) -> Result<Literal> {
+    let s = match args.as_slice() {
+        [Literal::String(s)] => s,
+        _ => return Err(no_overload("strings.quote", &args)),
+    };
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\x07' => quoted.push_str("\\a"),
+            '\x08' => quoted.push_str("\\b"),
+            '\x0C' => quoted.push_str("\\f"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            '\x0B' => quoted.push_str("\\v"),
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Ok(string(quoted))
+}
+
+/// The largest precision a formatting clause may ask for, far beyond the 17
+/// significant digits of a double, so that `%.70000f` can't make `format`
+/// build an arbitrarily long string.
+const MAX_PRECISION: usize = 100;
+
+/// `template.format([args])`, which substitutes each `%` clause of the
+/// template with the next argument: `%s` for any value, `%d` for an int,
+/// `%f` and `%e` for a number, optionally with a precision of at most
+/// `MAX_PRECISION` as in `%.2f`, `%x` and `%X` for an int, string or bytes in
+/// hex, `%b` and `%o` for an int in binary and octal, and `%%` for a literal
+/// `%`.
+fn format(args: Vec<Literal>) -> Result<Literal> {
+    let (template, xs) = match args.as_slice() {
+        [Literal::String(template), Literal::List(xs)] => (template, xs),
+        _ => return Err(no_overload("format", &args)),
+    };
+    let values = elements(xs.clone())?;
+    let mut values = values.iter();
+    let mut formatted = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            formatted.push(c);
+            continue;
+        }
+        let mut precision = None;
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let mut digits = String::new();
+            while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                digits.push(d);
+            }
+            precision = match digits.parse() {
+                Ok(p) if p <= MAX_PRECISION => Some(p),
+                _ => {
+                    return Err(format!(
+                        "invalid precision '{}' in formatting clause, expected at most {}",
+                        digits, MAX_PRECISION
+                    ))
+                }
+            };
+        }
+        let verb = chars
+            .next()
+            .ok_or_else(|| String::from("unterminated formatting clause"))?;
+        if verb == '%' {
+            formatted.push('%');
+            continue;
+        }
+        let value = values
+            .next()
+            .ok_or_else(|| format!("too few arguments for the formatting clause '%{}'", verb))?;
+        formatted.push_str(&clause(verb, precision, value)?);
+    }
+    Ok(string(formatted))
+}
+
+fn clause(verb: char, precision: Option<usize>, value: &Literal) -> Result<String> {
+    let precision = precision.unwrap_or(6);
+    match (verb, value) {
+        ('s', value) => display(value),
+        ('d', Literal::I64(x)) => Ok(x.to_string()),
+        ('d', Literal::U64(x)) => Ok(x.to_string()),
+        ('f', Literal::F64(x)) => Ok(fixed(*x, precision)),
+        ('f', Literal::I64(x)) => Ok(fixed(*x as f64, precision)),
+        ('e', Literal::F64(x)) => Ok(scientific(*x, precision)),
+        ('e', Literal::I64(x)) => Ok(scientific(*x as f64, precision)),
+        ('x', Literal::I64(x)) => Ok(radix(*x, |m| format!("{:x}", m))),
+        ('X', Literal::I64(x)) => Ok(radix(*x, |m| format!("{:X}", m))),
+        ('x', Literal::String(s)) => Ok(hex(s.as_bytes(), false)),
+        ('X', Literal::String(s)) => Ok(hex(s.as_bytes(), true)),
+        ('x', Literal::Bytes(b)) => Ok(hex(b, false)),
+        ('X', Literal::Bytes(b)) => Ok(hex(b, true)),
+        ('b', Literal::I64(x)) => Ok(radix(*x, |m| format!("{:b}", m))),
+        ('o', Literal::I64(x)) => Ok(radix(*x, |m| format!("{:o}", m))),
+        ('d' | 'f' | 'e' | 'x' | 'X' | 'b' | 'o', value) => Err(format!(
+            "formatting clause '%{}' does not support {}",
+            verb,
+            type_name(value)
+        )),
+        (verb, _) => Err(format!("unrecognized formatting clause '%{}'", verb)),
+    }
+}
+
+/// The `%s` form of a value: strings and bytes as their text, and lists and
+/// maps with their elements in that form.
+fn display(value: &Literal) -> Result<String> {
+    Ok(match value {
+        Literal::String(s) => s.clone(),
+        Literal::Bytes(b) => String::from_utf8_lossy(b).into_owned(),
+        Literal::I64(x) => x.to_string(),
+        Literal::U64(x) => x.to_string(),
+        Literal::F64(x) if !x.is_finite() => non_finite(*x),
+        Literal::F64(x) => x.to_string(),
+        Literal::Bool(x) => x.to_string(),
+        Literal::Null => String::from("null"),
+        Literal::Opaque(value) => value.to_string(),
+        Literal::Optional(None) => String::from("optional.none()"),
+        Literal::Optional(Some(value)) => format!("optional.of({})", display(value)?),
+        Literal::List(xs) => {
+            let xs = elements(xs.clone())?;
+            let parts = xs.iter().map(display).collect::<Result<Vec<_>>>()?;
+            format!("[{}]", parts.join(", "))
+        }
+        Literal::Map(entries) => {
+            let mut parts = Vec::with_capacity(entries.len());
+            for (k, v) in entries {
+                let k = display(&evaluate(k.clone())?)?;
+                let v = display(&evaluate(v.clone())?)?;
+                parts.push(format!("{}: {}", k, v));
+            }
+            format!("{{{}}}", parts.join(", "))
+        }
+    })
+}
+
+fn non_finite(x: f64) -> String {
+    String::from(if x.is_nan() {
+        "NaN"
+    } else if x > 0.0 {
+        "Infinity"
+    } else {
+        "-Infinity"
+    })
+}
+
+fn fixed(x: f64, precision: usize) -> String {
+    if !x.is_finite() {
+        return non_finite(x);
+    }
+    format!("{:.*}", precision, x)
+}
+
+/// `x` in scientific notation with a signed exponent of at least two digits,
+/// e.g. `1.050000e+03`.
+fn scientific(x: f64, precision: usize) -> String {
+    if !x.is_finite() {
+        return non_finite(x);
+    }
+    let formatted = format!("{:.*e}", precision, x);
+    let (mantissa, exponent) = formatted.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// Formats the magnitude of `x` with `digits`, preceded by its sign.
+fn radix(x: i64, digits: impl Fn(u64) -> String) -> String {
+    if x < 0 {
+        format!("-{}", digits(x.unsigned_abs()))
+    } else {
+        digits(x as u64)
+    }
+}
+
+fn hex(bytes: &[u8], upper: bool) -> String {
+    bytes
+        .iter()
+        .map(|b| {
+            if upper {
+                format!("{:02X}", b)
+            } else {
+                format!("{:02x}", b)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::{Expression, Literal};
+    use crate::parsers::parse;
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        evaluate_with_functions(parse(input).unwrap(), &Activation::new(), &functions)
+    }
+
+    fn string(s: &str) -> Literal {
+        Literal::String(String::from(s))
+    }
+
+    fn strings(items: &[&str]) -> Literal {
+        Literal::List(items.iter().map(|s| Expression::from(string(s))).collect())
+    }
+
+    #[test]
+    fn char_at() {
+        assert_eq!(eval("'héllo'.charAt(1)"), Ok(string("é")));
+        assert_eq!(eval("'héllo'.charAt(5)"), Ok(string("")));
+        assert_eq!(
+            eval("'héllo'.charAt(6)"),
+            Err(String::from("index out of range: 6"))
+        );
+        assert_eq!(
+            eval("'héllo'.charAt(0 - 1)"),
+            Err(String::from("index out of range: -1"))
+        );
+    }
+
+    #[test]
+    fn index_of() {
+        assert_eq!(eval("'héllo héllo'.indexOf('llo')"), Ok(Literal::I64(2)));
+        assert_eq!(eval("'héllo héllo'.indexOf('llo', 3)"), Ok(Literal::I64(8)));
+        assert_eq!(eval("'héllo'.indexOf('')"), Ok(Literal::I64(0)));
+        assert_eq!(eval("'héllo'.indexOf('x')"), Ok(Literal::I64(-1)));
+        assert_eq!(eval("'ab'.indexOf('abc')"), Ok(Literal::I64(-1)));
+        assert!(eval("'héllo'.indexOf('l', 6)").is_err());
+        assert_eq!(
+            eval("'héllo héllo'.lastIndexOf('llo')"),
+            Ok(Literal::I64(8))
+        );
+        assert_eq!(
+            eval("'héllo héllo'.lastIndexOf('llo', 7)"),
+            Ok(Literal::I64(2))
+        );
+        assert_eq!(eval("'héllo'.lastIndexOf('')"), Ok(Literal::I64(5)));
+        assert_eq!(eval("'héllo'.lastIndexOf('x')"), Ok(Literal::I64(-1)));
+    }
+
+    #[test]
+    fn case_trim_and_reverse() {
+        assert_eq!(eval("'TacoCÉl'.lowerAscii()"), Ok(string("tacocÉl")));
+        assert_eq!(eval("'TacoCél'.upperAscii()"), Ok(string("TACOCéL")));
+        assert_eq!(eval("' \\t trim\\n '.trim()"), Ok(string("trim")));
+        assert_eq!(eval("'héllo'.reverse()"), Ok(string("olléh")));
+    }
+
+    #[test]
+    fn replace() {
+        assert_eq!(eval("'a.b.c'.replace('.', '/')"), Ok(string("a/b/c")));
+        assert_eq!(eval("'a.b.c'.replace('.', '/', 1)"), Ok(string("a/b.c")));
+        assert_eq!(
+            eval("'a.b.c'.replace('.', '/', 0 - 1)"),
+            Ok(string("a/b/c"))
+        );
+        assert_eq!(eval("'ab'.replace('', '-')"), Ok(string("-a-b-")));
+    }
+
+    #[test]
+    fn split_and_join() {
+        assert_eq!(eval("'a,b,c'.split(',')"), Ok(strings(&["a", "b", "c"])));
+        assert_eq!(eval("'a,b,c'.split(',', 2)"), Ok(strings(&["a", "b,c"])));
+        assert_eq!(eval("'a,b,c'.split(',', 0)"), Ok(strings(&[])));
+        assert_eq!(eval("'héy'.split('')"), Ok(strings(&["h", "é", "y"])));
+        assert_eq!(eval("'héy'.split('', 2)"), Ok(strings(&["h", "éy"])));
+        assert_eq!(eval("['a', 'b'].join()"), Ok(string("ab")));
+        assert_eq!(eval("['a', 'b'].join(', ')"), Ok(string("a, b")));
+        assert_eq!(
+            eval("['a', 1].join()"),
+            Err(String::from("join: list element is not a string: int"))
+        );
+        assert_eq!(eval("'a-b'.split('-').join('+')"), Ok(string("a+b")));
+    }
+
+    #[test]
+    fn substring() {
+        assert_eq!(eval("'héllo'.substring(1)"), Ok(string("éllo")));
+        assert_eq!(eval("'héllo'.substring(1, 3)"), Ok(string("él")));
+        assert_eq!(eval("'héllo'.substring(5)"), Ok(string("")));
+        assert_eq!(
+            eval("'héllo'.substring(6)"),
+            Err(String::from("index out of range: 6"))
+        );
+        assert_eq!(
+            eval("'héllo'.substring(3, 1)"),
+            Err(String::from("invalid substring range. start: 3, end: 1"))
+        );
+    }
+
+    #[test]
+    fn quote() {
+        assert_eq!(
+            eval(r#" strings.quote('say \"hi\"\n') "#),
+            Ok(string(r#""say \"hi\"\n""#))
+        );
+        assert_eq!(eval(r#" strings.quote('\\') "#), Ok(string(r#""\\""#)));
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(
+            eval("'%s has %d items costing %.2f'.format(['cart', 3, 9.5])"),
+            Ok(string("cart has 3 items costing 9.50"))
+        );
+        assert_eq!(eval("'%f'.format([1])"), Ok(string("1.000000")));
+        assert_eq!(eval("'%e'.format([1050.0])"), Ok(string("1.050000e+03")));
+        assert_eq!(eval("'%.1e'.format([0.05])"), Ok(string("5.0e-02")));
+        assert_eq!(
+            eval("'%x %X %x'.format([255, 0 - 255, 'hi'])"),
+            Ok(string("ff -FF 6869"))
+        );
+        assert_eq!(eval("'%b %o'.format([5, 8])"), Ok(string("101 10")));
+        assert_eq!(eval("'100%%'.format([1])"), Ok(string("100%")));
+        assert_eq!(
+            eval("'%s'.format([['a', 1, true]])"),
+            Ok(string("[a, 1, true]"))
+        );
+        assert_eq!(eval("'%s'.format([{'a': 1.5}])"), Ok(string("{a: 1.5}")));
+        assert_eq!(
+            eval("'%d'.format(['a'])"),
+            Err(String::from(
+                "formatting clause '%d' does not support string"
+            ))
+        );
+        assert_eq!(
+            eval("'%.70000f'.format([1.0])"),
+            Err(String::from(
+                "invalid precision '70000' in formatting clause, expected at most 100"
+            ))
+        );
+        assert_eq!(
+            eval("'%.99999999999999999999999e'.format([1.0])"),
+            Err(String::from(
+                "invalid precision '99999999999999999999999' in formatting clause, \
+                 expected at most 100"
+            ))
+        );
+        assert_eq!(
+            eval("'%.f'.format([1.0])"),
+            Err(String::from(
+                "invalid precision '' in formatting clause, expected at most 100"
+            ))
+        );
+        assert_eq!(
+            eval("'%.100f'.format([0.5])"),
+            Ok(string(&format!("0.5{}", "0".repeat(99))))
+        );
+        assert_eq!(
+            eval("'%q'.format([1])"),
+            Err(String::from("unrecognized formatting clause '%q'"))
+        );
+        assert_eq!(
+            eval("'%s %s'.format([1])"),
+            Err(String::from(
+                "too few arguments for the formatting clause '%s'"
+            ))
+        );
+    }
+
+    #[test]
+    fn unregistered() {
+        assert_eq!(
+            crate::evaluate(parse("'a'.upperAscii()").unwrap()),
+            Err(String::from("undeclared reference to 'upperAscii'"))
+        );
+        assert_eq!(
+            crate::evaluate(parse("strings.quote('a')").unwrap()),
+            Err(String::from("undeclared reference to 'strings'"))
+        );
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("s", Type::String);
+        let type_of =
+            |input: &str| check(parse(input).unwrap(), &decls).map(|c| c.result_type().clone());
+        assert_eq!(type_of("s.split(',')"), Ok(Type::list(Type::String)));
+        assert_eq!(
+            type_of("s.split(',').join('-').indexOf('a', 1)"),
+            Ok(Type::Int)
+        );
+        assert_eq!(type_of("strings.quote(s)"), Ok(Type::String));
+        assert_eq!(type_of("'%d'.format([1, 'a'])"), Ok(Type::String));
+        assert!(type_of("s.charAt('a')").is_err());
+        assert!(check(parse("s.trim()").unwrap(), &Declarations::standard()).is_err());
+    }
+}
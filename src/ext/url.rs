@@ -0,0 +1,660 @@
+//! URLs, after the Kubernetes CEL library `url`: `url('https://example.com')`
+//! creates a value of the opaque type `kubernetes.URL`, and `isURL` tests
+//! whether a string would be accepted by it.
+//!
+//! A URL has the methods `getScheme`, `getHost`, `getHostname`, `getPort`,
+//! `getEscapedPath` and `getQuery`, and URLs can be compared with `==` and
+//! `!=`.
+//!
+//! As in Kubernetes, which parses with Go's `url.ParseRequestURI`, a URL must
+//! be absolute or an absolute path, and has no fragment: a `#` is part of the
+//! path or query.
+
+use super::no_overload;
+use crate::checker::{Declarations, Overload, Type};
+use crate::interpreter::Functions;
+use crate::model::{Expression, Literal, Opaque, OpaqueValue};
+
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::fmt;
+
+type Result<T> = std::result::Result<T, String>;
+
+pub const URL_TYPE: &str = "kubernetes.URL";
+
+/// A URL, as created by `url`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Url {
+    /// Lowercased.
+    pub scheme: String,
+    /// What follows the scheme of a URL such as `mailto:user@example.com`,
+    /// whose path does not start with `/`.
+    pub opaque: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// The host and port, unescaped.
+    pub host: String,
+    /// The path, unescaped.
+    pub path: Vec<u8>,
+    /// The path as written, if escaping `path` would give something else.
+    pub raw_path: Option<String>,
+    /// Whether a URL with a scheme but no authority, such as `file:/x`, is
+    /// written without the empty one.
+    pub omit_host: bool,
+    /// The query, still escaped.
+    pub raw_query: String,
+    /// Whether the query is written even though it is empty.
+    pub force_query: bool,
+}
+
+impl Url {
+    /// Parses `s` as an absolute URL or an absolute path.
+    pub fn parse(s: &str) -> Result<Url> {
+        parse(s).map_err(|e| {
+            format!(
+                "URL parse error during conversion from string: parse {:?}: {}",
+                s, e
+            )
+        })
+    }
+
+    /// The host without the port, or the brackets around an IPv6 address.
+    pub fn hostname(&self) -> &str {
+        let (host, _) = split_host_port(&self.host);
+        host.strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host)
+    }
+
+    /// The port, without the `:`, or an empty string if there is none.
+    pub fn port(&self) -> &str {
+        split_host_port(&self.host).1
+    }
+
+    /// The path as written, if that is a valid escaping of it, and its
+    /// default escaping otherwise.
+    pub fn escaped_path(&self) -> String {
+        if let Some(raw) = &self.raw_path {
+            if valid_encoded(raw) {
+                return raw.clone();
+            }
+        }
+        if self.path == b"*" {
+            return String::from("*");
+        }
+        escape(&self.path, Mode::Path)
+    }
+
+    /// The values of each query parameter, in the order they appear.
+    /// Parameters that are not valid, such as those separated by `;`, are
+    /// left out.
+    pub fn query(&self) -> BTreeMap<String, Vec<String>> {
+        let mut params: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for param in self.raw_query.split('&') {
+            if param.is_empty() || param.contains(';') {
+                continue;
+            }
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let unescaped = |s| unescape(s, Mode::QueryComponent);
+            if let (Ok(key), Ok(value)) = (unescaped(key), unescaped(value)) {
+                let key = String::from_utf8_lossy(&key).into_owned();
+                let value = String::from_utf8_lossy(&value).into_owned();
+                params.entry(key).or_default().push(value);
+            }
+        }
+        params
+    }
+}
+
+/// Writes the URL as Go's `URL.String` does.
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut written = false;
+        if !self.scheme.is_empty() {
+            write!(f, "{}:", self.scheme)?;
+            written = true;
+        }
+        if !self.opaque.is_empty() {
+            write!(f, "{}", self.opaque)?;
+        } else {
+            let user = self.username.is_some();
+            let authority = !self.host.is_empty() || user;
+            if authority || !self.scheme.is_empty() && !self.omit_host {
+                if !self.host.is_empty() || !self.path.is_empty() || user {
+                    write!(f, "//")?;
+                    written = true;
+                }
+                if let Some(username) = &self.username {
+                    write!(f, "{}", escape(username.as_bytes(), Mode::UserPassword))?;
+                    if let Some(password) = &self.password {
+                        write!(f, ":{}", escape(password.as_bytes(), Mode::UserPassword))?;
+                    }
+                    write!(f, "@")?;
+                }
+                write!(f, "{}", escape(self.host.as_bytes(), Mode::Host))?;
+            }
+            let path = self.escaped_path();
+            if !path.is_empty() && !path.starts_with('/') && !self.host.is_empty() {
+                write!(f, "/")?;
+            }
+            let first_segment = path.split('/').next().unwrap_or("");
+            if !written && first_segment.contains(':') {
+                write!(f, "./")?;
+            }
+            write!(f, "{}", path)?;
+        }
+        if self.force_query || !self.raw_query.is_empty() {
+            write!(f, "?{}", self.raw_query)?;
+        }
+        Ok(())
+    }
+}
+
+impl OpaqueValue for Url {
+    fn type_name(&self) -> &'static str {
+        URL_TYPE
+    }
+
+    fn equals(&self, other: &dyn OpaqueValue) -> bool {
+        match other.as_any().downcast_ref::<Url>() {
+            Some(other) => self.to_string() == other.to_string(),
+            None => false,
+        }
+    }
+
+    fn constructor(&self) -> (&'static str, Vec<Literal>) {
+        ("url", vec![Literal::String(self.to_string())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+fn parse(s: &str) -> Result<Url> {
+    if s.bytes().any(|b| b < 0x20 || b == 0x7f) {
+        return Err(String::from("net/url: invalid control character in URL"));
+    }
+    if s.is_empty() {
+        return Err(String::from("empty url"));
+    }
+    let mut url = Url::default();
+    if s == "*" {
+        url.path = b"*".to_vec();
+        return Ok(url);
+    }
+    let (scheme, rest) = split_scheme(s)?;
+    url.scheme = scheme.to_ascii_lowercase();
+    let mut rest = rest;
+    if rest.ends_with('?') && rest.matches('?').count() == 1 {
+        url.force_query = true;
+        rest = &rest[..rest.len() - 1];
+    } else if let Some((before, query)) = rest.split_once('?') {
+        url.raw_query = String::from(query);
+        rest = before;
+    }
+    if !rest.starts_with('/') {
+        if !url.scheme.is_empty() {
+            url.opaque = String::from(rest);
+            return Ok(url);
+        }
+        return Err(String::from("invalid URI for request"));
+    }
+    if !url.scheme.is_empty() && rest.starts_with("//") {
+        let authority = &rest[2..];
+        let (authority, path) = match authority.find('/') {
+            Some(i) => (&authority[..i], &authority[i..]),
+            None => (authority, ""),
+        };
+        parse_authority(&mut url, authority)?;
+        rest = path;
+    } else if !url.scheme.is_empty() {
+        url.omit_host = true;
+    }
+    url.path = unescape(rest, Mode::Path)?;
+    if escape(&url.path, Mode::Path) != rest {
+        url.raw_path = Some(String::from(rest));
+    }
+    Ok(url)
+}
+
+/// Splits off the scheme, if `s` starts with one.
+fn split_scheme(s: &str) -> Result<(&str, &str)> {
+    for (i, c) in s.bytes().enumerate() {
+        match c {
+            b'a'..=b'z' | b'A'..=b'Z' => {}
+            b'0'..=b'9' | b'+' | b'-' | b'.' if i > 0 => {}
+            b':' if i == 0 => return Err(String::from("missing protocol scheme")),
+            b':' => return Ok((&s[..i], &s[i + 1..])),
+            _ => return Ok(("", s)),
+        }
+    }
+    Ok(("", s))
+}
+
+fn parse_authority(url: &mut Url, authority: &str) -> Result<()> {
+    let (userinfo, host) = match authority.rfind('@') {
+        Some(i) => (Some(&authority[..i]), &authority[i + 1..]),
+        None => (None, authority),
+    };
+    url.host = parse_host(host)?;
+    if let Some(userinfo) = userinfo {
+        let valid = userinfo
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-._:~!$&'()*+,;=%@".contains(&b));
+        if !valid {
+            return Err(String::from("net/url: invalid userinfo"));
+        }
+        let unescaped =
+            |s| unescape(s, Mode::UserPassword).map(|s| String::from_utf8_lossy(&s).into_owned());
+        match userinfo.split_once(':') {
+            Some((username, password)) => {
+                url.username = Some(unescaped(username)?);
+                url.password = Some(unescaped(password)?);
+            }
+            None => url.username = Some(unescaped(userinfo)?),
+        }
+    }
+    Ok(())
+}
+
+fn parse_host(host: &str) -> Result<String> {
+    if host.starts_with('[') {
+        let i = host
+            .rfind(']')
+            .ok_or_else(|| String::from("missing ']' in host"))?;
+        let port = &host[i + 1..];
+        if !valid_optional_port(port) {
+            return Err(format!("invalid port {:?} after host", port));
+        }
+    } else if let Some(i) = host.rfind(':') {
+        let port = &host[i..];
+        if !valid_optional_port(port) {
+            return Err(format!("invalid port {:?} after host", port));
+        }
+    }
+    unescape(host, Mode::Host).map(|host| String::from_utf8_lossy(&host).into_owned())
+}
+
+/// Whether `port` is empty or a `:` followed by digits.
+fn valid_optional_port(port: &str) -> bool {
+    match port.strip_prefix(':') {
+        Some(digits) => digits.bytes().all(|b| b.is_ascii_digit()),
+        None => port.is_empty(),
+    }
+}
+
+fn split_host_port(host: &str) -> (&str, &str) {
+    match host.rfind(':') {
+        Some(i) if valid_optional_port(&host[i..]) => (&host[..i], &host[i + 1..]),
+        _ => (host, ""),
+    }
+}
+
+/// The part of a URL a string is escaped for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Path,
+    Host,
+    UserPassword,
+    QueryComponent,
+}
+
+fn should_escape(c: u8, mode: Mode) -> bool {
+    if c.is_ascii_alphanumeric() {
+        return false;
+    }
+    if mode == Mode::Host && b"!$&'()*+,;=:[]<>\"".contains(&c) {
+        return false;
+    }
+    match c {
+        b'-' | b'_' | b'.' | b'~' => false,
+        b'$' | b'&' | b'+' | b',' | b'/' | b':' | b';' | b'=' | b'?' | b'@' => match mode {
+            Mode::Path => c == b'?',
+            Mode::UserPassword => matches!(c, b'@' | b'/' | b'?' | b':'),
+            Mode::QueryComponent | Mode::Host => true,
+        },
+        _ => true,
+    }
+}
+
+fn escape(s: &[u8], mode: Mode) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for &b in s {
+        if should_escape(b, mode) {
+            escaped.push_str(&format!("%{:02X}", b));
+        } else {
+            escaped.push(b as char);
+        }
+    }
+    escaped
+}
+
+/// Decodes `%XX` escapes, and `+` as a space in a query. A host may only
+/// escape bytes outside ASCII, and `%` itself within an IPv6 zone.
+fn unescape(s: &str, mode: Mode) -> Result<Vec<u8>> {
+    let bytes = s.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let digits = bytes.get(i + 1..i + 3).and_then(|digits| {
+                    let digits = std::str::from_utf8(digits).ok()?;
+                    u8::from_str_radix(digits, 16)
+                        .ok()
+                        .filter(|_| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+                });
+                let b = match digits {
+                    Some(b) => b,
+                    None => {
+                        let end = (i + 3).min(bytes.len());
+                        let escape = String::from_utf8_lossy(&bytes[i..end]);
+                        return Err(format!("invalid URL escape {:?}", escape));
+                    }
+                };
+                if mode == Mode::Host && b < 0x80 && &s[i..i + 3] != "%25" {
+                    return Err(format!("invalid URL escape {:?}", &s[i..i + 3]));
+                }
+                unescaped.push(b);
+                i += 3;
+            }
+            b'+' if mode == Mode::QueryComponent => {
+                unescaped.push(b' ');
+                i += 1;
+            }
+            b => {
+                if mode == Mode::Host && b < 0x80 && should_escape(b, mode) {
+                    return Err(format!(
+                        "invalid character {:?} in host name",
+                        (b as char).to_string()
+                    ));
+                }
+                unescaped.push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(unescaped)
+}
+
+/// Whether a path as written is a valid escaping of some path.
+fn valid_encoded(s: &str) -> bool {
+    s.bytes()
+        .all(|b| b"!$&'()*+,;=:@[]%".contains(&b) || !should_escape(b, Mode::Path))
+}
+
+pub fn register(functions: &mut Functions) {
+    functions.add_global("url", url);
+    functions.add_global("isURL", is_url);
+    functions.add_member("getScheme", URL_TYPE, get_scheme);
+    functions.add_member("getHost", URL_TYPE, get_host);
+    functions.add_member("getHostname", URL_TYPE, get_hostname);
+    functions.add_member("getPort", URL_TYPE, get_port);
+    functions.add_member("getEscapedPath", URL_TYPE, get_escaped_path);
+    functions.add_member("getQuery", URL_TYPE, get_query);
+}
+
+pub fn declare(decls: &mut Declarations) {
+    let url_type = Type::opaque(URL_TYPE);
+    decls.add_overload(
+        "url",
+        Overload::global("string_to_url", vec![Type::String], url_type.clone()),
+    );
+    decls.add_overload(
+        "isURL",
+        Overload::global("is_url_string", vec![Type::String], Type::Bool),
+    );
+    for (function, id) in [
+        ("getScheme", "url_get_scheme"),
+        ("getHost", "url_get_host"),
+        ("getHostname", "url_get_hostname"),
+        ("getPort", "url_get_port"),
+        ("getEscapedPath", "url_get_escaped_path"),
+    ] {
+        let overload = Overload::member(id, vec![url_type.clone()], Type::String);
+        decls.add_overload(function, overload);
+    }
+    let query = Type::map(Type::String, Type::list(Type::String));
+    decls.add_overload(
+        "getQuery",
+        Overload::member("url_get_query", vec![url_type.clone()], query),
+    );
+    for (function, id) in [("_==_", "equals_url"), ("_!=_", "not_equals_url")] {
+        let params = vec![url_type.clone(), url_type.clone()];
+        decls.add_overload(function, Overload::global(id, params, Type::Bool));
+    }
+}
+
+fn url(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Opaque(Opaque::new(Url::parse(s)?))),
+        _ => Err(no_overload("url", &args)),
+    }
+}
+
+fn is_url(args: Vec<Literal>) -> Result<Literal> {
+    match args.as_slice() {
+        [Literal::String(s)] => Ok(Literal::Bool(Url::parse(s).is_ok())),
+        _ => Err(no_overload("isURL", &args)),
+    }
+}
+
+/// Applies an accessor to the URL a method is called on.
+fn get<T>(function: &str, args: &[Literal], accessor: fn(&Url) -> T) -> Result<T> {
+    match args {
+        [Literal::Opaque(value)] => match value.downcast_ref::<Url>() {
+            Some(url) => Ok(accessor(url)),
+            None => Err(no_overload(function, args)),
+        },
+        _ => Err(no_overload(function, args)),
+    }
+}
+
+fn get_scheme(args: Vec<Literal>) -> Result<Literal> {
+    get("getScheme", &args, |url| {
+        Literal::String(url.scheme.clone())
+    })
+}
+
+fn get_host(args: Vec<Literal>) -> Result<Literal> {
+    get("getHost", &args, |url| Literal::String(url.host.clone()))
+}
+
+fn get_hostname(args: Vec<Literal>) -> Result<Literal> {
+    get("getHostname", &args, |url| {
+        Literal::String(String::from(url.hostname()))
+    })
+}
+
+fn get_port(args: Vec<Literal>) -> Result<Literal> {
+    get("getPort", &args, |url| {
+        Literal::String(String::from(url.port()))
+    })
+}
+
+fn get_escaped_path(args: Vec<Literal>) -> Result<Literal> {
+    get("getEscapedPath", &args, |url| {
+        Literal::String(url.escaped_path())
+    })
+}
+
+fn get_query(args: Vec<Literal>) -> Result<Literal> {
+    get("getQuery", &args, |url| {
+        let string = |s: String| Expression::from(Literal::String(s));
+        let entries = url
+            .query()
+            .into_iter()
+            .map(|(key, values)| {
+                let values = values.into_iter().map(string).collect();
+                (string(key), Expression::from(Literal::List(values)))
+            })
+            .collect();
+        Literal::Map(entries)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{declare, register};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with_functions, Activation, Functions};
+    use crate::model::{Expression, Literal};
+    use crate::parsers::parse;
+
+    fn eval(input: &str) -> Result<Literal, String> {
+        let mut functions = Functions::new();
+        register(&mut functions);
+        evaluate_with_functions(parse(input).unwrap(), &Activation::new(), &functions)
+    }
+
+    fn string(s: &str) -> Literal {
+        Literal::String(String::from(s))
+    }
+
+    fn get(url: &str, method: &str) -> Result<Literal, String> {
+        eval(&format!("url('{}').{}()", url, method))
+    }
+
+    #[test]
+    fn components() {
+        let url = "https://user:pw@example.com:8080/a%2Fb/c?k=v";
+        assert_eq!(get(url, "getScheme"), Ok(string("https")));
+        assert_eq!(get(url, "getHost"), Ok(string("example.com:8080")));
+        assert_eq!(get(url, "getHostname"), Ok(string("example.com")));
+        assert_eq!(get(url, "getPort"), Ok(string("8080")));
+        assert_eq!(get(url, "getEscapedPath"), Ok(string("/a%2Fb/c")));
+        assert_eq!(get("HTTPS://example.com", "getScheme"), Ok(string("https")));
+        assert_eq!(get("https://example.com", "getEscapedPath"), Ok(string("")));
+        assert_eq!(get("https://example.com/", "getPort"), Ok(string("")));
+        assert_eq!(get("https://[::1]:80/", "getHost"), Ok(string("[::1]:80")));
+        assert_eq!(get("https://[::1]:80/", "getHostname"), Ok(string("::1")));
+        assert_eq!(get("https://[::1]/", "getPort"), Ok(string("")));
+        assert_eq!(get("/path", "getScheme"), Ok(string("")));
+        assert_eq!(get("/path", "getHost"), Ok(string("")));
+        assert_eq!(
+            get("https://example.com/path with spaces/", "getEscapedPath"),
+            Ok(string("/path%20with%20spaces/"))
+        );
+        assert_eq!(
+            get("https://example.com/a#frag", "getEscapedPath"),
+            Ok(string("/a%23frag"))
+        );
+    }
+
+    #[test]
+    fn query() {
+        let expected = |entries: &[(&str, &[&str])]| {
+            let string = |s: &str| Expression::from(string(s));
+            Ok(Literal::Map(
+                entries
+                    .iter()
+                    .map(|(k, vs)| {
+                        let vs = vs.iter().map(|v| string(v)).collect();
+                        (string(k), Expression::from(Literal::List(vs)))
+                    })
+                    .collect(),
+            ))
+        };
+        assert_eq!(
+            get(
+                "https://example.com/?k=true&k=false&key=something",
+                "getQuery"
+            ),
+            expected(&[("k", &["true", "false"]), ("key", &["something"])])
+        );
+        assert_eq!(
+            get("https://example.com/?a+b=%41&c&d=1;e=2&%zz=3", "getQuery"),
+            expected(&[("a b", &["A"]), ("c", &[""])])
+        );
+        assert_eq!(get("https://example.com/", "getQuery"), expected(&[]));
+    }
+
+    #[test]
+    fn validation() {
+        for valid in [
+            "https://example.com",
+            "/absolute/path",
+            "*",
+            "mailto:someone@example.com",
+            "file:/etc/hosts",
+            "http://user@host/",
+        ] {
+            assert_eq!(
+                eval(&format!("isURL('{}')", valid)),
+                Ok(Literal::Bool(true)),
+                "{}",
+                valid
+            );
+        }
+        for invalid in [
+            "",
+            "relative/path",
+            "example.com",
+            ":missing",
+            "https://example.com:port/",
+            "https://[::1/",
+            "https://exa mple.com/",
+            "https://example.com/%zz",
+        ] {
+            assert_eq!(
+                eval(&format!("isURL('{}')", invalid)),
+                Ok(Literal::Bool(false)),
+                "{}",
+                invalid
+            );
+        }
+        assert_eq!(
+            eval("url('example.com')"),
+            Err(String::from(
+                "URL parse error during conversion from string: \
+                 parse \"example.com\": invalid URI for request"
+            ))
+        );
+        assert_eq!(
+            eval("url('https://example.com/%zz')"),
+            Err(String::from(
+                "URL parse error during conversion from string: \
+                 parse \"https://example.com/%zz\": invalid URL escape \"%zz\""
+            ))
+        );
+    }
+
+    #[test]
+    fn equality() {
+        assert_eq!(
+            eval("url('HTTPS://example.com/a b') == url('https://example.com/a%20b')"),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            eval("url('https://example.com/a') != url('https://example.com/b')"),
+            Ok(Literal::Bool(true))
+        );
+        for url in [
+            "https://user:pw@example.com:8080/a%2Fb?k=v",
+            "file:/etc/hosts",
+            "http:///path",
+            "mailto:someone@example.com",
+            "/path?",
+        ] {
+            assert_eq!(
+                super::Url::parse(url).unwrap().to_string(),
+                url,
+                "writing {}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn checked() {
+        let mut decls = Declarations::standard();
+        declare(&mut decls);
+        decls.add_variable("s", Type::String);
+        let expr = parse("isURL(s) && url(s).getQuery()['k'][0] == 'v'").unwrap();
+        assert!(check(expr, &decls).is_ok());
+        let expr = parse("url(s).getPort() == 80").unwrap();
+        assert!(check(expr, &decls).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+use crate::model::{walk_expression, ExprKind, Expression, Literal, SourceInfo, Visitor};
+use crate::parsers::{parse_with_source_info, ParserOptions};
+use crate::unparser::{
+    precedence, unparse, ADDITION, AND, CONDITIONAL, MEMBER, MULTIPLICATION, OR, UNARY,
+};
+
+use std::collections::HashMap;
+use std::mem::discriminant;
+
+const INDENT: usize = 2;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FormatOptions {
+    /// The column at which lines are wrapped, where the layout allows it.
+    pub width: usize,
+}
+
+impl Default for FormatOptions {
+    fn default() -> FormatOptions {
+        FormatOptions { width: 80 }
+    }
+}
+
+/// Lays out CEL source canonically.
+///
+/// Binary operators get one space either side and parentheses are reduced to
+/// those the grammar needs. `&&` and `||` chains, argument lists, list and map
+/// literals and conditionals that do not fit within `options.width` are
+/// broken across lines, outermost first. Comments are kept: a comment that
+/// followed code on its line stays at the end of a line, and any other is put
+/// on its own line before the operand, argument or element that followed it.
+pub fn format(source: &str, options: &FormatOptions) -> Result<String, String> {
+    let (expr, info) = parse_with_source_info(source, &ParserOptions::default())?;
+    let mut starts = Starts {
+        info: &info,
+        starts: HashMap::new(),
+        start: usize::MAX,
+    };
+    starts.visit_expression(&expr);
+    let mut builder = Builder {
+        comments: comments(source),
+        next: 0,
+        starts: starts.starts,
+    };
+    let (_, leading) = builder.comments_before(builder.start(&expr));
+    let mut docs = vec![leading, builder.doc(&expr, CONDITIONAL)];
+    for comment in &builder.comments[builder.next..] {
+        if !comment.trailing {
+            docs.push(Doc::HardLine);
+        } else {
+            docs.push(text(" "));
+        }
+        docs.push(Doc::Comment(comment.text.clone()));
+    }
+    let mut formatted = render(&Doc::Concat(docs), options.width);
+    formatted.push('\n');
+    Ok(formatted)
+}
+
+/// A line comment in the source.
+struct Comment {
+    offset: usize,
+    text: String,
+    /// Whether code precedes the comment on its line.
+    trailing: bool,
+}
+
+fn comments(source: &str) -> Vec<Comment> {
+    let mut comments = Vec::new();
+    let mut line_start = 0;
+    let mut quote = None;
+    let mut chars = source.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if let Some(q) = quote {
+            if c == '\\' {
+                chars.next();
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '\n' => line_start = i + 1,
+            '/' if chars.peek().map(|&(_, c)| c) == Some('/') => {
+                let end = source[i..].find('\n').map_or(source.len(), |n| i + n);
+                comments.push(Comment {
+                    offset: i,
+                    text: String::from(source[i..end].trim_end()),
+                    trailing: !source[line_start..i].trim().is_empty(),
+                });
+                while chars.next_if(|&(j, _)| j < end).is_some() {}
+            }
+            _ => {}
+        }
+    }
+    comments
+}
+
+/// Records the offset of the first token of every node, which for operators
+/// is that of their leftmost operand rather than their own location.
+struct Starts<'i> {
+    info: &'i SourceInfo,
+    starts: HashMap<u64, usize>,
+    /// The start of the subtree being visited, so far.
+    start: usize,
+}
+
+impl<'a, 'i> Visitor<'a> for Starts<'i> {
+    fn visit_expression(&mut self, expr: &'a Expression) {
+        let outer = self.start;
+        self.start = self.info.location(expr.id).map_or(usize::MAX, |l| l.offset);
+        walk_expression(self, expr);
+        self.starts.insert(expr.id, self.start);
+        self.start = outer.min(self.start);
+    }
+}
+
+/// A layout in the style of Wadler's "A prettier printer".
+enum Doc {
+    Text(String),
+    /// A space, or a line break if the enclosing group is broken.
+    Line,
+    /// Nothing, or a line break if the enclosing group is broken.
+    SoftLine,
+    HardLine,
+    /// A line comment. It must be followed by a line break, so it forces
+    /// every group around it to break.
+    Comment(String),
+    Concat(Vec<Doc>),
+    /// Laid out on one line if it fits, and otherwise with every line of its
+    /// own broken.
+    Group(Box<Doc>),
+    /// Indents the lines broken within it.
+    Nest(Box<Doc>),
+}
+
+fn text<S: Into<String>>(s: S) -> Doc {
+    Doc::Text(s.into())
+}
+
+fn group(docs: Vec<Doc>) -> Doc {
+    Doc::Group(Box::new(Doc::Concat(docs)))
+}
+
+fn nest(docs: Vec<Doc>) -> Doc {
+    Doc::Nest(Box::new(Doc::Concat(docs)))
+}
+
+struct Builder {
+    comments: Vec<Comment>,
+    /// The first comment not yet placed.
+    next: usize,
+    starts: HashMap<u64, usize>,
+}
+
+impl Builder {
+    fn start(&self, expr: &Expression) -> usize {
+        self.starts[&expr.id]
+    }
+
+    /// Takes the comments before `offset`, split into the one that belongs at
+    /// the end of the current line, if any, and those that belong on their
+    /// own lines after it.
+    fn comments_before(&mut self, offset: usize) -> (Doc, Doc) {
+        let mut trailing = Vec::new();
+        let mut leading = Vec::new();
+        while let Some(comment) = self.comments.get(self.next) {
+            if comment.offset >= offset {
+                break;
+            }
+            if comment.trailing && trailing.is_empty() && leading.is_empty() {
+                trailing.push(text(" "));
+                trailing.push(Doc::Comment(comment.text.clone()));
+            } else {
+                leading.push(Doc::Comment(comment.text.clone()));
+                leading.push(Doc::HardLine);
+            }
+            self.next += 1;
+        }
+        (Doc::Concat(trailing), Doc::Concat(leading))
+    }
+
+    /// Lays out `expr` where the grammar expects an expression that binds at
+    /// least as tightly as `min`.
+    fn doc(&mut self, expr: &Expression, min: u8) -> Doc {
+        if precedence(expr) < min {
+            return group(vec![
+                text("("),
+                nest(vec![Doc::SoftLine, self.doc(expr, CONDITIONAL)]),
+                Doc::SoftLine,
+                text(")"),
+            ]);
+        }
+        match &expr.kind {
+            ExprKind::Conditional(cond, a, b) => {
+                let cond = self.doc(cond, OR);
+                let (a_trailing, a_leading) = self.comments_before(self.start(a));
+                let a = self.doc(a, OR);
+                let (b_trailing, b_leading) = self.comments_before(self.start(b));
+                let b = self.doc(b, CONDITIONAL);
+                group(vec![
+                    cond,
+                    nest(vec![
+                        a_trailing,
+                        Doc::Line,
+                        a_leading,
+                        text("? "),
+                        a,
+                        b_trailing,
+                        Doc::Line,
+                        b_leading,
+                        text(": "),
+                        b,
+                    ]),
+                ])
+            }
+            ExprKind::Or(..) => self.chain(expr, "||", OR),
+            ExprKind::And(..) => self.chain(expr, "&&", AND),
+            ExprKind::Eq(a, b) => self.binary(a, "==", b, ADDITION, ADDITION),
+            ExprKind::Neq(a, b) => self.binary(a, "!=", b, ADDITION, ADDITION),
+            ExprKind::Lt(a, b) => self.binary(a, "<", b, ADDITION, ADDITION),
+            ExprKind::Lte(a, b) => self.binary(a, "<=", b, ADDITION, ADDITION),
+            ExprKind::Gte(a, b) => self.binary(a, ">=", b, ADDITION, ADDITION),
+            ExprKind::Gt(a, b) => self.binary(a, ">", b, ADDITION, ADDITION),
+            ExprKind::Add(a, b) => self.binary(a, "+", b, ADDITION, MULTIPLICATION),
+            ExprKind::Sub(a, b) => self.binary(a, "-", b, ADDITION, MULTIPLICATION),
+            ExprKind::Mul(a, b) => self.binary(a, "*", b, MULTIPLICATION, UNARY),
+            ExprKind::Div(a, b) => self.binary(a, "/", b, MULTIPLICATION, UNARY),
+            ExprKind::Mod(a, b) => self.binary(a, "%", b, MULTIPLICATION, UNARY),
+            ExprKind::Neg(a) => Doc::Concat(vec![text("-"), self.doc(a, UNARY)]),
+            ExprKind::Not(a) => Doc::Concat(vec![text("!"), self.doc(a, UNARY)]),
+            ExprKind::Method(e, name, args) => {
+                let e = self.doc(e, MEMBER);
+                let args: Vec<Vec<&Expression>> = args.iter().map(|arg| vec![arg]).collect();
+                let args = self.bracketed("(", args, CONDITIONAL, ")");
+                Doc::Concat(vec![e, text(format!(".{}", name)), args])
+            }
+            ExprKind::Call(function, args) => {
+                let args: Vec<Vec<&Expression>> = args.iter().map(|arg| vec![arg]).collect();
+                let args = self.bracketed("(", args, CONDITIONAL, ")");
+                Doc::Concat(vec![text(function.as_str()), args])
+            }
+            ExprKind::Select(e, field) => {
+                Doc::Concat(vec![self.doc(e, MEMBER), text(format!(".{}", field))])
+            }
+            ExprKind::OptionalSelect(e, field) => {
+                Doc::Concat(vec![self.doc(e, MEMBER), text(format!(".?{}", field))])
+            }
+            ExprKind::Index(e, i) => Doc::Concat(vec![
+                self.doc(e, MEMBER),
+                text("["),
+                self.doc(i, CONDITIONAL),
+                text("]"),
+            ]),
+            ExprKind::OptionalIndex(e, i) => Doc::Concat(vec![
+                self.doc(e, MEMBER),
+                text("[?"),
+                self.doc(i, CONDITIONAL),
+                text("]"),
+            ]),
+            ExprKind::OptionalElement(e) => Doc::Concat(vec![text("?"), self.doc(e, ADDITION)]),
+            ExprKind::Lit(Literal::List(xs)) => {
+                let elements = xs.iter().map(|x| vec![x]).collect();
+                self.bracketed("[", elements, ADDITION, "]")
+            }
+            ExprKind::Lit(Literal::Map(entries)) => {
+                let entries = entries.iter().map(|(k, v)| vec![k, v]).collect();
+                self.bracketed("{", entries, ADDITION, "}")
+            }
+            ExprKind::Lit(_) | ExprKind::Ident(_) => text(unparse(expr)),
+        }
+    }
+
+    fn binary(&mut self, a: &Expression, op: &str, b: &Expression, left: u8, right: u8) -> Doc {
+        let a = self.doc(a, left);
+        let b = self.doc(b, right);
+        Doc::Concat(vec![a, text(format!(" {} ", op)), b])
+    }
+
+    /// Lays out a chain of `&&` or `||` with one operand per line if it does
+    /// not fit on one.
+    fn chain(&mut self, expr: &Expression, op: &str, level: u8) -> Doc {
+        let mut operands = Vec::new();
+        let mut e = expr;
+        while discriminant(&e.kind) == discriminant(&expr.kind) {
+            match &e.kind {
+                ExprKind::Or(a, b) | ExprKind::And(a, b) => {
+                    operands.push(b.as_ref());
+                    e = a;
+                }
+                _ => unreachable!(),
+            }
+        }
+        operands.push(e);
+        operands.reverse();
+        let mut docs = vec![self.doc(operands[0], level)];
+        for operand in &operands[1..] {
+            let (trailing, leading) = self.comments_before(self.start(operand));
+            docs.push(text(format!(" {}", op)));
+            docs.push(trailing);
+            docs.push(Doc::Line);
+            docs.push(leading);
+            docs.push(self.doc(operand, level + 1));
+        }
+        group(docs)
+    }
+
+    /// Lays out comma-separated items between brackets, with one item per
+    /// line if they do not fit on one. Each item is a single expression or a
+    /// map entry.
+    fn bracketed(&mut self, open: &str, items: Vec<Vec<&Expression>>, min: u8, close: &str) -> Doc {
+        if items.is_empty() {
+            return text(format!("{}{}", open, close));
+        }
+        let mut docs = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                docs.push(text(","));
+            }
+            let (trailing, leading) = self.comments_before(self.start(item[0]));
+            docs.push(trailing);
+            docs.push(if i == 0 { Doc::SoftLine } else { Doc::Line });
+            docs.push(leading);
+            match item.as_slice() {
+                // An optional map entry is marked on its key, `?k: v`.
+                [k, v] => {
+                    let v: &Expression = match &v.kind {
+                        ExprKind::OptionalElement(v) => {
+                            docs.push(text("?"));
+                            v
+                        }
+                        _ => v,
+                    };
+                    docs.push(self.doc(k, min));
+                    docs.push(text(": "));
+                    docs.push(self.doc(v, min));
+                }
+                _ => {
+                    for e in item {
+                        docs.push(self.doc(e, min));
+                    }
+                }
+            }
+        }
+        group(vec![text(open), nest(docs), Doc::SoftLine, text(close)])
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+fn render(doc: &Doc, width: usize) -> String {
+    let mut out = String::new();
+    let mut column = 0;
+    let mut stack = vec![(0, Mode::Break, doc)];
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(s) | Doc::Comment(s) => {
+                out.push_str(s);
+                column += s.chars().count();
+            }
+            Doc::Line if mode == Mode::Flat => {
+                out.push(' ');
+                column += 1;
+            }
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                out.push('\n');
+                out.push_str(&" ".repeat(indent));
+                column = indent;
+            }
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (indent, mode, d))),
+            Doc::Nest(d) => stack.push((indent + INDENT, mode, d)),
+            Doc::Group(d) => {
+                let remaining = width as isize - column as isize;
+                let mode = if mode == Mode::Flat || fits(d, &stack, remaining) {
+                    Mode::Flat
+                } else {
+                    Mode::Break
+                };
+                stack.push((indent, mode, d));
+            }
+        }
+    }
+    out
+}
+
+/// Whether `doc` fits on one line in `width` columns, together with whatever
+/// follows it on the same line.
+fn fits(doc: &Doc, rest: &[(usize, Mode, &Doc)], mut width: isize) -> bool {
+    let mut stack = vec![(Mode::Flat, doc)];
+    let mut rest = rest.iter().rev();
+    loop {
+        let (mode, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(&(_, mode, doc)) => (mode, doc),
+                None => return true,
+            },
+        };
+        match doc {
+            Doc::Text(s) => width -= s.chars().count() as isize,
+            Doc::Line if mode == Mode::Flat => width -= 1,
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine => return true,
+            Doc::HardLine | Doc::Comment(_) => return mode == Mode::Break,
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|d| (mode, d))),
+            Doc::Nest(d) | Doc::Group(d) => stack.push((mode, d)),
+        }
+        if width < 0 {
+            return false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format, FormatOptions};
+    use crate::parsers::parse;
+
+    fn assert_formats_to(input: &str, width: usize, expected: &str) {
+        let options = FormatOptions { width };
+        let formatted = format(input, &options).unwrap();
+        assert_eq!(formatted, expected, "formatting {}", input);
+        assert_eq!(format(&formatted, &options).unwrap(), formatted);
+        assert_eq!(parse(&formatted), parse(input));
+    }
+
+    #[test]
+    fn spacing_and_parentheses() {
+        assert_formats_to("a&&(b)||!c", 80, "a && b || !c\n");
+        assert_formats_to("  ((1+2))*x.pow( 2 )\n", 80, "(1 + 2) * x.pow(2)\n");
+        assert_formats_to("{'a':[1,2],'b':{}}", 80, "{\"a\": [1, 2], \"b\": {}}\n");
+        assert_formats_to("x?1:2", 80, "x ? 1 : 2\n");
+    }
+
+    #[test]
+    fn wraps_logical_chains() {
+        assert_formats_to(
+            "request_size < 1024 && user == 'admin' && method != 'DELETE'",
+            40,
+            "request_size < 1024 &&\nuser == \"admin\" &&\nmethod != \"DELETE\"\n",
+        );
+        assert_formats_to(
+            "a && b || long_condition_name && another_condition_name",
+            50,
+            "a && b ||\nlong_condition_name && another_condition_name\n",
+        );
+        assert_formats_to(
+            "(first_condition || second_condition) && third",
+            30,
+            "(\n  first_condition ||\n  second_condition\n) &&\nthird\n",
+        );
+    }
+
+    #[test]
+    fn wraps_lists_and_arguments() {
+        assert_formats_to(
+            "['viewer', 'editor', 'admin'].contains(role)",
+            24,
+            "[\n  \"viewer\",\n  \"editor\",\n  \"admin\"\n].contains(role)\n",
+        );
+        assert_formats_to(
+            "x.pow(some_long_argument_name + another_one)",
+            30,
+            "x.pow(\n  some_long_argument_name + another_one\n)\n",
+        );
+        assert_formats_to(
+            "enabled ? quota_for_enabled_users : quota_for_others",
+            30,
+            "enabled\n  ? quota_for_enabled_users\n  : quota_for_others\n",
+        );
+        assert_formats_to(
+            "[?first.?name, {?'tier': plans[?tier]}]",
+            30,
+            "[\n  ?first.?name,\n  {?\"tier\": plans[?tier]}\n]\n",
+        );
+    }
+
+    #[test]
+    fn keeps_comments() {
+        assert_formats_to(
+            "// Only admins.\nuser == 'admin' // from the token\n  && size < 10",
+            80,
+            "// Only admins.\nuser == \"admin\" && // from the token\nsize < 10\n",
+        );
+        assert_formats_to(
+            "a &&\n// Second.\n// Really.\nb",
+            80,
+            "a &&\n// Second.\n// Really.\nb\n",
+        );
+        assert_formats_to("[1, // one\n 2]", 80, "[\n  1, // one\n  2\n]\n");
+        assert_formats_to(
+            "x + 1 // trailing\n// last\n",
+            80,
+            "x + 1 // trailing\n// last\n",
+        );
+        assert_formats_to("'// not a comment'", 80, "\"// not a comment\"\n");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(format("a &&", &FormatOptions::default()).is_err());
+    }
+}
@@ -1,199 +1,1405 @@
-use crate::model::{Expression, Literal, MethodName};
+use crate::analysis::{macro_arguments, qualified_name};
+use crate::cost::size;
+use crate::ext::{no_overload, type_name};
+use crate::model::{
+    walk_expression, ExprKind, Expression, Literal, MethodName, Opaque, OpaqueValue, SourceInfo,
+    Visitor,
+};
 
-type EvalResult = Result<Literal, String>;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
 
-pub fn evaluate(expr: Expression) -> EvalResult {
-    match expr {
-        Expression::Lit(literal) => Ok(literal),
-        Expression::Neg(e) => match evaluate(*e)? {
-            Literal::I64(x) => Ok(Literal::I64(-x)),
-            _ => Err(String::from("invalid types")),
-        },
-        Expression::Not(e) => match evaluate(*e)? {
-            Literal::Bool(x) => Ok(Literal::Bool(!x)),
-            _ => Err(String::from("invalid types")),
-        },
-        Expression::Eq(a, b) => {
-            let a = evaluate(*a)?;
-            let b = evaluate(*b)?;
-            match (a, b) {
-                (Literal::I64(a), Literal::I64(b)) => Ok(Literal::Bool(a == b)),
-                (Literal::String(a), Literal::String(b)) => Ok(Literal::Bool(a == b)),
-                (Literal::Bytes(a), Literal::Bytes(b)) => Ok(Literal::Bool(a == b)),
-                _ => Err(String::from("invalid types")),
-            }
+pub(crate) type EvalResult = Result<Literal, String>;
+
+/// Variable bindings available to an expression during evaluation.
+pub type Activation = HashMap<String, Literal>;
+
+/// The implementation of an extension function. A member function receives
+/// its receiver as the first argument.
+pub type Implementation = fn(Vec<Literal>) -> Result<Literal, String>;
+
+/// The implementation of an extension macro, called as
+/// `receiver.name(x, body)`. It receives the receiver and a function that
+/// evaluates `body` with `x` bound to a given value.
+pub type Macro = fn(Literal, &mut dyn FnMut(Literal) -> EvalResult) -> EvalResult;
+
+/// A rewrite of an expression that is run when it is compiled for evaluation
+/// with a set of `Functions`, e.g. to compile constant arguments ahead of
+/// time or to reject invalid ones. The compiled program keeps the result.
+pub type Prepare = fn(&mut Expression) -> Result<(), String>;
+
+/// The extension functions available to evaluation, beyond the operators and
+/// methods that are always available. Libraries such as `ext::strings` add
+/// theirs with `register`, so that an environment that wants only the core
+/// language can leave them off.
+#[derive(Debug, Clone, Default)]
+pub struct Functions {
+    /// Member functions by name and the type of their receiver.
+    members: BTreeMap<(String, String), Implementation>,
+    globals: BTreeMap<String, Implementation>,
+    macros: BTreeMap<String, Macro>,
+    preparations: Vec<Prepare>,
+}
+
+/// The functions available to the evaluation functions that do not take any.
+pub(crate) static NO_FUNCTIONS: Functions = Functions::new();
+
+impl Functions {
+    pub const fn new() -> Functions {
+        Functions {
+            members: BTreeMap::new(),
+            globals: BTreeMap::new(),
+            macros: BTreeMap::new(),
+            preparations: Vec::new(),
         }
-        Expression::Neq(a, b) => evaluate(Expression::Not(Box::new(Expression::Eq(a, b)))),
-        Expression::Lt(a, b) => {
-            let a = evaluate(*a)?;
-            let b = evaluate(*b)?;
-            match (a, b) {
-                (Literal::I64(a), Literal::I64(b)) => Ok(Literal::Bool(a < b)),
-                (Literal::String(a), Literal::String(b)) => Ok(Literal::Bool(a < b)),
-                (Literal::Bytes(a), Literal::Bytes(b)) => Ok(Literal::Bool(a < b)),
-                _ => Err(String::from("invalid types")),
-            }
+    }
+
+    /// Adds a member function, called as `receiver.name(args)` on a receiver
+    /// of the type called `receiver`, e.g. `string`. Functions of the same
+    /// name may be added for different receiver types.
+    pub fn add_member(&mut self, name: &str, receiver: &str, implementation: Implementation) {
+        self.members
+            .insert((String::from(name), String::from(receiver)), implementation);
+    }
+
+    /// Adds a global function. Its name may be qualified by a namespace, as
+    /// in `strings.quote`, in which case it is called as `strings.quote(s)`.
+    pub fn add_global(&mut self, name: &str, implementation: Implementation) {
+        self.globals.insert(String::from(name), implementation);
+    }
+
+    /// Adds a macro, called as `receiver.name(x, body)`.
+    pub fn add_macro(&mut self, name: &str, implementation: Macro) {
+        self.macros.insert(String::from(name), implementation);
+    }
+
+    /// Adds a rewrite to run on expressions compiled with these functions.
+    pub fn add_preparation(&mut self, prepare: Prepare) {
+        self.preparations.push(prepare);
+    }
+
+    /// Runs every preparation on `expr`, failing with the first error.
+    pub(crate) fn prepare(&self, expr: &mut Expression) -> Result<(), String> {
+        self.preparations
+            .iter()
+            .try_for_each(|prepare| prepare(expr))
+    }
+
+    /// The global function that the method call `receiver.name(...)` refers
+    /// to, if `receiver` is a variable or field path that qualifies `name`.
+    /// Such a function takes precedence over a variable of the same name.
+    pub(crate) fn qualified(
+        &self,
+        receiver: &Expression,
+        name: &MethodName,
+    ) -> Option<Implementation> {
+        self.global(&format!("{}.{}", qualified_name(receiver)?, name))
+    }
+
+    /// The global function `name`, either one that was added or one of the
+    /// functions that are always available, such as `optional.of`.
+    pub(crate) fn global(&self, name: &str) -> Option<Implementation> {
+        self.globals.get(name).copied().or_else(|| builtin(name))
+    }
+
+    /// The macro that the method call `receiver.name(args)` expands, with the
+    /// variable it binds and the body to evaluate, if there is one.
+    pub(crate) fn macro_call<'e>(
+        &self,
+        name: &MethodName,
+        args: &'e [Expression],
+    ) -> Option<(Macro, &'e str, &'e Expression)> {
+        let (var, body) = macro_arguments(name, args)?;
+        Some((self.macro_named(name)?, var, body))
+    }
+
+    pub(crate) fn macro_named(&self, name: &MethodName) -> Option<Macro> {
+        match name {
+            MethodName::Extension(name) => self.macros.get(name).copied(),
+            _ => None,
         }
-        Expression::Lte(a, b) => {
-            let a = evaluate(*a)?;
-            let b = evaluate(*b)?;
-            match (a, b) {
-                (Literal::I64(a), Literal::I64(b)) => Ok(Literal::Bool(a <= b)),
-                (Literal::String(a), Literal::String(b)) => Ok(Literal::Bool(a <= b)),
-                (Literal::Bytes(a), Literal::Bytes(b)) => Ok(Literal::Bool(a <= b)),
-                _ => Err(String::from("invalid types")),
+    }
+
+    /// Calls the member function `name` with `receiver` and `args`.
+    fn call_member(&self, name: &str, receiver: Literal, args: Vec<Literal>) -> EvalResult {
+        let key = (String::from(name), String::from(type_name(&receiver)));
+        let mut operands = vec![receiver];
+        operands.extend(args);
+        match self.members.get(&key) {
+            Some(implementation) => implementation(operands),
+            None if self.members.keys().any(|(member, _)| member == name) => {
+                Err(no_overload(name, &operands))
             }
+            None => Err(format!("undeclared reference to '{}'", name)),
         }
-        Expression::Gte(a, b) => evaluate(Expression::Not(Box::new(Expression::Lt(a, b)))),
-        Expression::Gt(a, b) => evaluate(Expression::Not(Box::new(Expression::Lte(a, b)))),
-        Expression::Add(a, b) => {
-            let a = evaluate(*a)?;
-            let b = evaluate(*b)?;
-            match (a, b) {
-                (Literal::I64(a), Literal::I64(b)) => Ok(Literal::I64(a + b)),
-                (Literal::String(a), Literal::String(b)) => {
-                    Ok(Literal::String(a.chars().chain(b.chars()).collect()))
-                }
-                _ => Err(String::from("invalid types")),
+    }
+}
+
+/// The global functions that are always available.
+fn builtin(name: &str) -> Option<Implementation> {
+    match name {
+        "optional.of" => Some(optional_of),
+        "optional.ofNonZeroValue" => Some(optional_of_non_zero_value),
+        "optional.none" => Some(optional_none),
+        _ => None,
+    }
+}
+
+fn optional_of(args: Vec<Literal>) -> EvalResult {
+    match <[Literal; 1]>::try_from(args) {
+        Ok([value]) => Ok(Literal::Optional(Some(Box::new(value)))),
+        Err(args) => Err(no_overload("optional.of", &args)),
+    }
+}
+
+/// `optional.of(value)`, unless `value` is the zero value of its type, such
+/// as `0`, `''` or `[]`, in which case `optional.none()`.
+fn optional_of_non_zero_value(args: Vec<Literal>) -> EvalResult {
+    let value = match <[Literal; 1]>::try_from(args) {
+        Ok([value]) => value,
+        Err(args) => return Err(no_overload("optional.ofNonZeroValue", &args)),
+    };
+    let zero = match &value {
+        Literal::I64(x) => *x == 0,
+        Literal::U64(x) => *x == 0,
+        Literal::F64(x) => *x == 0.0,
+        Literal::Bool(x) => !x,
+        Literal::String(s) => s.is_empty(),
+        Literal::Bytes(b) => b.is_empty(),
+        Literal::List(xs) => xs.is_empty(),
+        Literal::Map(entries) => entries.is_empty(),
+        Literal::Null => true,
+        Literal::Optional(_) | Literal::Opaque(_) => false,
+    };
+    Ok(Literal::Optional(if zero {
+        None
+    } else {
+        Some(Box::new(value))
+    }))
+}
+
+fn optional_none(args: Vec<Literal>) -> EvalResult {
+    if args.is_empty() {
+        Ok(Literal::Optional(None))
+    } else {
+        Err(no_overload("optional.none", &args))
+    }
+}
+
+/// Calls the global function `name`, which must be one of `functions`.
+pub(crate) fn call_global(name: &str, args: Vec<Literal>, functions: &Functions) -> EvalResult {
+    match functions.global(name) {
+        Some(function) => function(args),
+        None => Err(format!("undeclared reference to '{}'", name)),
+    }
+}
+
+/// Evaluates a macro's `body` with `var` bound to each value it is given, for
+/// evaluators other than the interpreter's own.
+pub(crate) fn bind_macro(
+    implementation: Macro,
+    receiver: Literal,
+    var: &str,
+    body: &Expression,
+    activation: &Activation,
+    functions: &Functions,
+) -> EvalResult {
+    let options = EvalOptions {
+        functions,
+        ..EvalOptions::default()
+    };
+    Evaluator::new(activation, &options).bind(0, implementation, receiver, var, body)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum EvalError {
+    /// Evaluation was aborted after spending more than the given budget.
+    CostLimitExceeded(u64),
+    /// The expression itself failed, e.g. by dividing by zero.
+    Failed(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::CostLimitExceeded(budget) => {
+                write!(f, "cost limit of {} exceeded", budget)
             }
+            EvalError::Failed(msg) => write!(f, "{}", msg),
         }
-        Expression::Sub(a, b) => {
-            let a = evaluate(*a)?;
-            let b = evaluate(*b)?;
-            match (a, b) {
-                (Literal::I64(a), Literal::I64(b)) => Ok(Literal::I64(a - b)),
-                _ => Err(String::from("invalid types")),
-            }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+/// Counts the cost of an evaluation. Every node costs 1, and every operator
+/// or method call additionally costs the size of its operands (see
+/// `cost::size`), so that e.g. repeated string concatenation is charged for
+/// the strings it copies.
+struct CostTracker {
+    used: u64,
+    budget: u64,
+}
+
+impl CostTracker {
+    fn charge(&mut self, cost: u64) -> Result<(), String> {
+        self.used = self.used.saturating_add(cost);
+        if self.used > self.budget {
+            // Once over budget every subsequent charge fails too, so this error
+            // cannot be absorbed by `&&` or `||`.
+            return Err(String::from("cost limit exceeded"));
         }
-        Expression::Mul(a, b) => {
-            let a = evaluate(*a)?;
-            let b = evaluate(*b)?;
-            match (a, b) {
-                (Literal::I64(a), Literal::I64(b)) => Ok(Literal::I64(a * b)),
-                (Literal::F64(a), Literal::F64(b)) => Ok(Literal::F64(a * b)),
-                _ => Err(String::from("invalid types")),
-            }
+        Ok(())
+    }
+}
+
+pub fn evaluate(expr: Expression) -> EvalResult {
+    evaluate_with(expr, &Activation::new())
+}
+
+pub fn evaluate_with(expr: Expression, activation: &Activation) -> EvalResult {
+    Evaluator::new(activation, &EvalOptions::default()).eval(expr)
+}
+
+/// Like `evaluate_with`, but with the extension functions in `functions`
+/// available to the expression. Their preparations run first, as in
+/// `compiler::compile_with_functions`, and fail evaluation outright.
+pub fn evaluate_with_functions(
+    mut expr: Expression,
+    activation: &Activation,
+    functions: &Functions,
+) -> EvalResult {
+    functions.prepare(&mut expr)?;
+    let options = EvalOptions {
+        functions,
+        ..EvalOptions::default()
+    };
+    Evaluator::new(activation, &options).eval(expr)
+}
+
+/// How to evaluate an expression. The defaults evaluate it like
+/// `evaluate_with` does: with no extension functions and no budget.
+#[derive(Debug)]
+pub struct EvalOptions<'a> {
+    /// The extension functions available to the expression.
+    pub functions: &'a Functions,
+    /// The cost at which evaluation is aborted.
+    pub budget: u64,
+    /// The locations of the nodes of the expression, to report the location
+    /// of the subexpression that failed, e.g. "divide by zero at 1:14".
+    pub source_info: Option<&'a SourceInfo>,
+    /// Where to record the result of every subexpression. Evaluation is then
+    /// exhaustive: both operands of `&&` and `||` and both branches of `?:`
+    /// are evaluated even when the result is already decided, so that every
+    /// node has a value to explain.
+    pub state: Option<&'a mut EvalState>,
+}
+
+impl Default for EvalOptions<'_> {
+    fn default() -> Self {
+        EvalOptions {
+            functions: &NO_FUNCTIONS,
+            budget: u64::MAX,
+            source_info: None,
+            state: None,
         }
-        Expression::Div(a, b) => {
-            let a = evaluate(*a)?;
-            let b = evaluate(*b)?;
-            match (a, b) {
-                (Literal::I64(a), Literal::I64(b)) => {
-                    if b != 0 {
-                        Ok(Literal::I64(a / b))
-                    } else {
-                        Err(String::from("divide by zero"))
+    }
+}
+
+/// Evaluates `expr` as `options` say, after running the preparations of
+/// `options.functions`, as `evaluate_with_functions` does.
+pub fn evaluate_with_options(
+    mut expr: Expression,
+    activation: &Activation,
+    mut options: EvalOptions,
+) -> Result<Literal, EvalError> {
+    options
+        .functions
+        .prepare(&mut expr)
+        .map_err(EvalError::Failed)?;
+    let mut evaluator = Evaluator::new(activation, &options);
+    evaluator.state = options.state.as_mut().map(|state| std::mem::take(*state));
+    let result = evaluator.eval(expr);
+    if let Some(state) = options.state {
+        *state = evaluator.state.take().unwrap_or_default();
+    }
+    evaluator.finish(result)
+}
+
+/// The value, or error, of each node of an expression by id, as recorded by
+/// evaluation with `EvalOptions::state`.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct EvalState {
+    values: HashMap<u64, EvalResult>,
+}
+
+impl EvalState {
+    /// The result of node `id`, or `None` if it was never evaluated.
+    pub fn value(&self, id: u64) -> Option<&Result<Literal, String>> {
+        self.values.get(&id)
+    }
+}
+
+/// Renders `expr` one subexpression per line, indented under its parent and
+/// annotated with its result in `state`, e.g.
+///
+/// ```text
+/// x > 1 && y == "a" => false
+///   x > 1 => true
+///     x => 3
+///   y == "a" => false
+///     y => "b"
+/// ```
+///
+/// Scalar literals are left out, since their values are plain to see.
+pub fn explain(expr: &Expression, state: &EvalState) -> String {
+    let mut explainer = Explainer {
+        state,
+        depth: 0,
+        out: String::new(),
+    };
+    explainer.visit_expression(expr);
+    explainer.out
+}
+
+struct Explainer<'s> {
+    state: &'s EvalState,
+    depth: usize,
+    out: String,
+}
+
+impl<'a, 's> Visitor<'a> for Explainer<'s> {
+    fn visit_expression(&mut self, expr: &'a Expression) {
+        match &expr.kind {
+            ExprKind::Lit(Literal::List(_)) | ExprKind::Lit(Literal::Map(_)) => {}
+            ExprKind::Lit(_) => return,
+            _ => {}
+        }
+        let value = match self.state.value(expr.id) {
+            Some(Ok(v)) => v.to_string(),
+            Some(Err(e)) => format!("error: {}", e),
+            None => String::from("not evaluated"),
+        };
+        let indent = "  ".repeat(self.depth);
+        self.out += &format!("{}{} => {}\n", indent, expr, value);
+        self.depth += 1;
+        walk_expression(self, expr);
+        self.depth -= 1;
+    }
+}
+
+/// A unit of work for the `Evaluator`.
+enum Step {
+    /// Evaluates a node, leaving its result on the result stack.
+    Eval(Expression),
+    /// Continues evaluating node `id` once the results `op` is waiting for
+    /// are on the result stack.
+    Apply(u64, Op),
+    /// Records the result on top of the stack as the value of node `id`.
+    Record(u64),
+    /// Drops the result on top of the stack.
+    Discard,
+    Push(EvalResult),
+}
+
+/// What a node does with the results of its operands.
+enum Op {
+    /// Chooses a branch once the condition has been evaluated.
+    Branch(Box<Expression>, Box<Expression>),
+    /// Evaluates the right operand of `||`, if `decisive` is true, or `&&`
+    /// unless the left one already decides the result.
+    LogicalRight(bool, Box<Expression>),
+    Logical(bool),
+    /// Evaluates the right operand of a binary operator unless the left one
+    /// failed.
+    Right(Binary, Box<Expression>),
+    Binary(Binary),
+    Unary(Unary),
+    /// Evaluates the arguments of a method call unless the receiver failed.
+    Args(MethodName, Vec<Expression>),
+    Method(MethodName, usize),
+    /// Calls a global function once its arguments have been evaluated.
+    Global(Implementation, usize),
+    /// Expands a macro once its receiver has been evaluated, binding the
+    /// variable to evaluate the body.
+    Macro(Macro, String, Expression),
+    /// Resolves the elements of a list one at a time, `current` being the
+    /// one just evaluated.
+    List {
+        remaining: std::vec::IntoIter<Expression>,
+        done: Vec<Expression>,
+        current: Option<Expression>,
+    },
+    Map {
+        remaining: std::vec::IntoIter<(Expression, Expression)>,
+        done: Vec<(Expression, Expression)>,
+        current: Option<Entry>,
+    },
+}
+
+enum Binary {
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gte,
+    Gt,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Index,
+    OptionalIndex,
+}
+
+enum Unary {
+    Neg,
+    Not,
+    Select(String),
+    OptionalSelect(String),
+}
+
+/// The map entry being evaluated: its key, given the id of the key and the
+/// value still to evaluate, or its value, given the evaluated key and the
+/// value as written.
+enum Entry {
+    Key(u64, Expression),
+    Value(Expression, Expression),
+}
+
+/// Evaluates an expression without recursing on the native stack: each node
+/// schedules its operands as `Step`s, followed by a step that combines their
+/// results, so that arbitrarily deep trees use only heap memory.
+struct Evaluator<'a> {
+    activation: &'a Activation,
+    /// The variables bound by the macros whose bodies are being evaluated,
+    /// innermost last, which shadow those of the activation.
+    scopes: Vec<(String, Literal)>,
+    functions: &'a Functions,
+    source_info: Option<&'a SourceInfo>,
+    tracker: CostTracker,
+    /// Where to record the result of every node, which also makes evaluation
+    /// exhaustive.
+    state: Option<EvalState>,
+    steps: Vec<Step>,
+    results: Vec<EvalResult>,
+}
+
+impl<'a> Evaluator<'a> {
+    /// An evaluator with the functions, budget and source info of `options`,
+    /// which leaves recording results in `options.state` to the caller.
+    fn new(activation: &'a Activation, options: &EvalOptions<'a>) -> Evaluator<'a> {
+        Evaluator {
+            activation,
+            scopes: Vec::new(),
+            functions: options.functions,
+            source_info: options.source_info,
+            tracker: CostTracker {
+                used: 0,
+                budget: options.budget,
+            },
+            state: None,
+            steps: Vec::new(),
+            results: Vec::new(),
+        }
+    }
+
+    /// Evaluates `expr`. A macro evaluates its body with a nested call, which
+    /// only runs the steps that it schedules itself.
+    fn eval(&mut self, expr: Expression) -> EvalResult {
+        let base = self.steps.len();
+        self.steps.push(Step::Eval(expr));
+        while self.steps.len() > base {
+            match self.steps.pop().expect("a step") {
+                Step::Eval(expr) => self.start(expr),
+                Step::Apply(id, op) => self.apply(id, op),
+                Step::Record(id) => {
+                    if let Some(state) = &mut self.state {
+                        let result = self.results.last().expect("a node's result");
+                        state.values.insert(id, result.clone());
                     }
                 }
-                (Literal::F64(a), Literal::F64(b)) => {
-                    if b != 0.0 {
-                        Ok(Literal::F64(a / b))
-                    } else {
-                        Err(String::from("divide by zero"))
-                    }
+                Step::Discard => {
+                    self.results.pop();
                 }
-                _ => Err(String::from("invalid types")),
+                Step::Push(result) => self.results.push(result),
             }
         }
-        Expression::Mod(a, b) => {
-            let a = evaluate(*a)?;
-            let b = evaluate(*b)?;
-            match (a, b) {
-                (Literal::I64(a), Literal::I64(b)) => Ok(Literal::I64(a % b)),
-                _ => Err(String::from("invalid types")),
+        self.results.pop().expect("the result")
+    }
+
+    /// Calls the macro of node `id`, evaluating its `body` with `var` bound to
+    /// each value the macro gives it.
+    fn bind(
+        &mut self,
+        id: u64,
+        implementation: Macro,
+        receiver: Literal,
+        var: &str,
+        body: &Expression,
+    ) -> EvalResult {
+        let mut body_error = None;
+        let result = implementation(receiver, &mut |value| {
+            self.scopes.push((String::from(var), value));
+            let result = self.eval(body.clone());
+            self.scopes.pop();
+            if let Err(e) = &result {
+                body_error = Some(e.clone());
             }
+            result
+        });
+        match result {
+            // An error of the body already has its own location.
+            Err(e) if Some(&e) == body_error.as_ref() => Err(e),
+            result => self.locate(id, result),
         }
-        Expression::Method(e, name, args) => {
-            let e = evaluate(*e)?;
-            match e {
-                Literal::String(a) => match name {
-                    MethodName::Len => {
-                        if !args.is_empty() {
-                            return Err(String::from("too may arguments to .len()"));
-                        }
-                        Ok(Literal::I64(a.chars().count() as i64))
+    }
+
+    /// Distinguishes running out of budget from the expression failing.
+    fn finish(&self, result: EvalResult) -> Result<Literal, EvalError> {
+        if self.tracker.used > self.tracker.budget {
+            return Err(EvalError::CostLimitExceeded(self.tracker.budget));
+        }
+        result.map_err(EvalError::Failed)
+    }
+
+    fn start(&mut self, expr: Expression) {
+        if self.state.is_some() {
+            self.steps.push(Step::Record(expr.id));
+        }
+        if let Err(e) = self.tracker.charge(1) {
+            self.results.push(Err(e));
+            return;
+        }
+        let id = expr.id;
+        match expr.into_kind() {
+            ExprKind::Lit(Literal::List(xs)) => {
+                let done = Vec::with_capacity(xs.len());
+                let remaining = xs.into_iter();
+                self.apply(
+                    id,
+                    Op::List {
+                        remaining,
+                        done,
+                        current: None,
+                    },
+                )
+            }
+            ExprKind::Lit(Literal::Map(entries)) => {
+                let done = Vec::with_capacity(entries.len());
+                let remaining = entries.into_iter();
+                self.apply(
+                    id,
+                    Op::Map {
+                        remaining,
+                        done,
+                        current: None,
+                    },
+                )
+            }
+            ExprKind::Lit(literal) => self.results.push(literal_value(literal)),
+            ExprKind::Ident(name) => {
+                let result = match self.scopes.iter().rev().find(|(var, _)| *var == name) {
+                    Some((_, value)) => Ok(value.clone()),
+                    None => self.locate(id, lookup(&name, self.activation)),
+                };
+                self.results.push(result)
+            }
+            ExprKind::Conditional(cond, a, b) => self.then(id, Op::Branch(a, b), *cond),
+            ExprKind::Or(a, b) => self.then(id, Op::LogicalRight(true, b), *a),
+            ExprKind::And(a, b) => self.then(id, Op::LogicalRight(false, b), *a),
+            ExprKind::Eq(a, b) => self.then(id, Op::Right(Binary::Eq, b), *a),
+            ExprKind::Neq(a, b) => self.then(id, Op::Right(Binary::Neq, b), *a),
+            ExprKind::Lt(a, b) => self.then(id, Op::Right(Binary::Lt, b), *a),
+            ExprKind::Lte(a, b) => self.then(id, Op::Right(Binary::Lte, b), *a),
+            ExprKind::Gte(a, b) => self.then(id, Op::Right(Binary::Gte, b), *a),
+            ExprKind::Gt(a, b) => self.then(id, Op::Right(Binary::Gt, b), *a),
+            ExprKind::Add(a, b) => self.then(id, Op::Right(Binary::Add, b), *a),
+            ExprKind::Sub(a, b) => self.then(id, Op::Right(Binary::Sub, b), *a),
+            ExprKind::Mul(a, b) => self.then(id, Op::Right(Binary::Mul, b), *a),
+            ExprKind::Div(a, b) => self.then(id, Op::Right(Binary::Div, b), *a),
+            ExprKind::Mod(a, b) => self.then(id, Op::Right(Binary::Mod, b), *a),
+            ExprKind::Index(e, i) => self.then(id, Op::Right(Binary::Index, i), *e),
+            ExprKind::OptionalIndex(e, i) => self.then(id, Op::Right(Binary::OptionalIndex, i), *e),
+            ExprKind::Neg(e) => self.then(id, Op::Unary(Unary::Neg), *e),
+            ExprKind::Not(e) => self.then(id, Op::Unary(Unary::Not), *e),
+            ExprKind::Select(e, field) => self.then(id, Op::Unary(Unary::Select(field)), *e),
+            ExprKind::OptionalSelect(e, field) => {
+                self.then(id, Op::Unary(Unary::OptionalSelect(field)), *e)
+            }
+            // On its own, an optional element is just the optional.
+            ExprKind::OptionalElement(e) => self.steps.push(Step::Eval(*e)),
+            ExprKind::Call(function, args) => match self.functions.global(&function) {
+                Some(function) => {
+                    self.steps
+                        .push(Step::Apply(id, Op::Global(function, args.len())));
+                    for arg in args.into_iter().rev() {
+                        self.steps.push(Step::Eval(arg));
                     }
-                    MethodName::Contains => Err(String::from("illegal type for .contains()")),
-                    MethodName::Pow => Err(String::from("illegal type for .pow()")),
-                },
-                Literal::Bytes(a) => match name {
-                    MethodName::Len => {
-                        if !args.is_empty() {
-                            return Err(String::from("too may arguments to .len()"));
-                        }
-                        Ok(Literal::I64(a.len() as i64))
+                }
+                None => {
+                    let e = format!("undeclared reference to '{}'", function);
+                    let result = self.locate(id, Err(e));
+                    self.results.push(result)
+                }
+            },
+            ExprKind::Method(e, name, args) => match self.functions.qualified(&e, &name) {
+                Some(function) => {
+                    self.steps
+                        .push(Step::Apply(id, Op::Global(function, args.len())));
+                    for arg in args.into_iter().rev() {
+                        self.steps.push(Step::Eval(arg));
                     }
-                    MethodName::Contains => Err(String::from("illegal type for .contains()")),
-                    MethodName::Pow => Err(String::from("illegal type for .pow()")),
-                },
-                Literal::I64(a) => match name {
-                    MethodName::Len => Err(String::from("illegal type for .len()")),
-                    MethodName::Contains => Err(String::from("illegal type for .contains()")),
-                    MethodName::Pow => {
-                        if args.len() != 1 {
-                            return Err(String::from("too may arguments to .pow()"));
-                        }
-                        match evaluate(args[0].clone())? {
-                            Literal::I64(b) => Ok(Literal::I64(i64::pow(a, b as u32))),
-                            _ => Err(String::from("illegal type for .pow()")),
-                        }
+                }
+                None => match self.functions.macro_call(&name, &args) {
+                    Some((function, var, body)) => {
+                        let op = Op::Macro(function, String::from(var), body.clone());
+                        self.then(id, op, *e)
                     }
+                    None => self.then(id, Op::Args(name, args), *e),
                 },
-                Literal::F64(a) => match name {
-                    MethodName::Len => Err(String::from("illegal type for .len()")),
-                    MethodName::Contains => Err(String::from("illegal type for .contains()")),
-                    MethodName::Pow => {
-                        if args.len() != 1 {
-                            return Err(String::from("too may arguments to .pow()"));
+            },
+        }
+    }
+
+    /// Evaluates `expr`, and then continues with `op`.
+    fn then(&mut self, id: u64, op: Op, expr: Expression) {
+        self.steps.push(Step::Apply(id, op));
+        self.steps.push(Step::Eval(expr));
+    }
+
+    fn apply(&mut self, id: u64, op: Op) {
+        match op {
+            Op::Branch(a, b) => {
+                let cond = self.pop();
+                if self.state.is_some() {
+                    // Evaluate the branch not taken too, for its value only.
+                    let (taken, other) = match cond {
+                        Ok(Literal::Bool(false)) => (b, a),
+                        _ => (a, b),
+                    };
+                    match cond {
+                        Ok(Literal::Bool(_)) => self.steps.push(Step::Eval(*taken)),
+                        Ok(_) => {
+                            let result = self.locate(id, Err(String::from("invalid types")));
+                            self.steps.push(Step::Push(result))
                         }
-                        match evaluate(args[0].clone())? {
-                            Literal::F64(b) => Ok(Literal::F64(f64::powf(a, b))),
-                            Literal::I64(b) => Ok(Literal::F64(f64::powf(a, b as f64))),
-                            _ => Err(String::from("illegal type for .pow()")),
+                        Err(e) => self.steps.push(Step::Push(Err(e))),
+                    }
+                    self.steps.push(Step::Discard);
+                    self.steps.push(Step::Eval(*other));
+                    return;
+                }
+                match cond {
+                    Ok(Literal::Bool(true)) => self.steps.push(Step::Eval(*a)),
+                    Ok(Literal::Bool(false)) => self.steps.push(Step::Eval(*b)),
+                    Ok(_) => {
+                        let result = self.locate(id, Err(String::from("invalid types")));
+                        self.results.push(result)
+                    }
+                    Err(e) => self.results.push(Err(e)),
+                }
+            }
+            Op::LogicalRight(decisive, b) => {
+                if let Some(Ok(Literal::Bool(a))) = self.results.last() {
+                    if *a == decisive {
+                        if self.state.is_some() {
+                            self.steps.push(Step::Discard);
+                            self.steps.push(Step::Eval(*b));
                         }
+                        return;
                     }
-                },
-                Literal::List(xs) => match name {
-                    MethodName::Pow => Err(String::from("illegal type for .pow()")),
-                    MethodName::Contains => {
-                        if args.len() != 1 {
-                            return Err(String::from("too may arguments to .contains()"));
+                }
+                self.then(id, Op::Logical(decisive), *b)
+            }
+            Op::Logical(decisive) => {
+                let b = self.pop();
+                let a = self.pop();
+                let failed = a.is_err() || b.is_err();
+                let result = if decisive {
+                    logical_or(a, b)
+                } else {
+                    logical_and(a, b)
+                };
+                let result = if failed {
+                    result
+                } else {
+                    self.locate(id, result)
+                };
+                self.results.push(result)
+            }
+            Op::Right(op, b) => {
+                // Exhaustive evaluation carries on past a failed operand.
+                if let Some(Err(_)) = self.results.last() {
+                    if self.state.is_none() {
+                        return;
+                    }
+                }
+                self.then(id, Op::Binary(op), *b)
+            }
+            Op::Binary(op) => {
+                let b = self.pop();
+                let a = self.pop();
+                let result = self.binary(id, op, a, b);
+                self.results.push(result)
+            }
+            Op::Unary(op) => {
+                let e = self.pop();
+                let result = self.unary(id, op, e);
+                self.results.push(result)
+            }
+            Op::Args(name, args) => {
+                if let Some(Err(_)) = self.results.last() {
+                    if self.state.is_none() {
+                        return;
+                    }
+                }
+                self.steps
+                    .push(Step::Apply(id, Op::Method(name, args.len())));
+                for arg in args.into_iter().rev() {
+                    self.steps.push(Step::Eval(arg));
+                }
+            }
+            Op::Method(name, count) => {
+                let args = self.results.split_off(self.results.len() - count);
+                let e = self.pop();
+                let result = self.method(id, name, e, args);
+                self.results.push(result)
+            }
+            Op::Global(function, count) => {
+                let args = self.results.split_off(self.results.len() - count);
+                let result = self.global(id, function, args);
+                self.results.push(result)
+            }
+            Op::Macro(function, var, body) => {
+                let result = self
+                    .pop()
+                    .and_then(|e| self.bind(id, function, e, &var, &body));
+                self.results.push(result)
+            }
+            Op::List {
+                mut remaining,
+                mut done,
+                current,
+            } => {
+                if let Some(x) = current {
+                    let result = self.pop();
+                    match resolve_element(x, result) {
+                        Ok(x) => done.extend(x),
+                        Err(e) => return self.results.push(Err(e)),
+                    }
+                }
+                match remaining.next() {
+                    Some(x) => {
+                        let current = Some(x.clone());
+                        let op = Op::List {
+                            remaining,
+                            done,
+                            current,
+                        };
+                        self.then(id, op, x)
+                    }
+                    None => self.results.push(Ok(Literal::List(done))),
+                }
+            }
+            Op::Map {
+                mut remaining,
+                mut done,
+                current,
+            } => {
+                match current {
+                    Some(Entry::Key(key_id, v)) => {
+                        let k = match self.pop() {
+                            Ok(k) => Expression {
+                                id: key_id,
+                                kind: ExprKind::Lit(k),
+                            },
+                            Err(e) => return self.results.push(Err(e)),
+                        };
+                        let current = Some(Entry::Value(k, v.clone()));
+                        let op = Op::Map {
+                            remaining,
+                            done,
+                            current,
+                        };
+                        return self.then(id, op, v);
+                    }
+                    Some(Entry::Value(k, v)) => {
+                        let result = self.pop();
+                        match resolve_element(v, result) {
+                            Ok(v) => done.extend(v.map(|v| (k, v))),
+                            Err(e) => return self.results.push(Err(e)),
                         }
-                        let needle = evaluate(args[0].clone())?;
-                        let mut err = None;
-                        for x in xs {
-                            match evaluate(x) {
-                                Ok(v) => {
-                                    if v == needle {
-                                        return Ok(Literal::Bool(true));
-                                    }
-                                }
-                                e @ Err(_) => {
-                                    err = Some(e);
-                                }
+                    }
+                    None => {}
+                }
+                match remaining.next() {
+                    Some((k, v)) => {
+                        let current = Some(Entry::Key(k.id, v));
+                        let op = Op::Map {
+                            remaining,
+                            done,
+                            current,
+                        };
+                        self.then(id, op, k)
+                    }
+                    None => self.results.push(Ok(Literal::Map(done))),
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self) -> EvalResult {
+        self.results.pop().expect("an operand's result")
+    }
+
+    /// Applies a binary operator, charging for the size of its operands.
+    fn binary(&mut self, id: u64, op: Binary, a: EvalResult, b: EvalResult) -> EvalResult {
+        let (a, b) = (a?, b?);
+        self.tracker.charge(size(&a).saturating_add(size(&b)))?;
+        let result = match op {
+            Binary::Eq => equals(a, b),
+            Binary::Neq => equals(a, b).and_then(not),
+            Binary::Lt => less(a, b),
+            Binary::Lte => less_equals(a, b),
+            Binary::Gte => less(a, b).and_then(not),
+            Binary::Gt => less_equals(a, b).and_then(not),
+            Binary::Add => add(a, b),
+            Binary::Sub => subtract(a, b),
+            Binary::Mul => multiply(a, b),
+            Binary::Div => divide(a, b),
+            Binary::Mod => modulo(a, b),
+            Binary::Index => index(a, b, self.activation),
+            Binary::OptionalIndex => optional_index(a, b, self.activation),
+        };
+        self.locate(id, result)
+    }
+
+    fn unary(&mut self, id: u64, op: Unary, e: EvalResult) -> EvalResult {
+        let e = e?;
+        self.tracker.charge(size(&e))?;
+        let result = match op {
+            Unary::Neg => negate(e),
+            Unary::Not => not(e),
+            Unary::Select(field) => select(e, &field, self.activation),
+            Unary::OptionalSelect(field) => optional_select(e, &field, self.activation),
+        };
+        self.locate(id, result)
+    }
+
+    fn method(
+        &mut self,
+        id: u64,
+        name: MethodName,
+        e: EvalResult,
+        args: Vec<EvalResult>,
+    ) -> EvalResult {
+        let e = e?;
+        let arg_sizes: u64 = args.iter().flatten().map(size).sum();
+        self.tracker.charge(size(&e).saturating_add(arg_sizes))?;
+        let failed = args.iter().any(Result::is_err);
+        let result = call_method(e, &name, args, self.activation, self.functions);
+        if failed {
+            return result;
+        }
+        self.locate(id, result)
+    }
+
+    fn global(&mut self, id: u64, function: Implementation, args: Vec<EvalResult>) -> EvalResult {
+        let args = args.into_iter().collect::<Result<Vec<_>, _>>()?;
+        self.tracker.charge(args.iter().map(size).sum())?;
+        self.locate(id, function(args))
+    }
+
+    /// Appends the location of node `id` to an error raised by that node
+    /// itself, rather than passed up from one of its operands.
+    fn locate(&self, id: u64, result: EvalResult) -> EvalResult {
+        let location = self.source_info.and_then(|info| info.location(id));
+        match (result, location) {
+            (Err(message), Some(location)) => Err(format!("{} at {}", message, location)),
+            (result, _) => result,
+        }
+    }
+}
+
+/// Replaces a collection element with its value so that it no longer depends
+/// on the activation, or with its error if it failed, so that the error only
+/// surfaces if the element is actually used.
+fn resolve(expr: Expression, result: EvalResult) -> Expression {
+    let value = match result {
+        Ok(v) => v,
+        Err(e) => Literal::Opaque(Opaque::new(Failure(e))),
+    };
+    Expression {
+        id: expr.id,
+        kind: ExprKind::Lit(value),
+    }
+}
+
+/// The error of a collection element that failed. Evaluating it raises the
+/// error again, wherever the element ends up, without needing the variables,
+/// functions or budget that it was first evaluated with.
+#[derive(Debug)]
+struct Failure(String);
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl OpaqueValue for Failure {
+    fn type_name(&self) -> &'static str {
+        "error"
+    }
+
+    fn equals(&self, other: &dyn OpaqueValue) -> bool {
+        other
+            .as_any()
+            .downcast_ref::<Failure>()
+            .is_some_and(|other| self.0 == other.0)
+    }
+
+    fn constructor(&self) -> (&'static str, Vec<Literal>) {
+        ("error", vec![Literal::String(self.0.clone())])
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// The value of a literal, which is the error it holds if it stands for a
+/// collection element that failed.
+pub(crate) fn literal_value(literal: Literal) -> EvalResult {
+    if let Literal::Opaque(value) = &literal {
+        if let Some(Failure(e)) = value.downcast_ref::<Failure>() {
+            return Err(e.clone());
+        }
+    }
+    Ok(literal)
+}
+
+/// Like `resolve`, for a list element or map value that may be optional,
+/// `?x`. An empty optional drops the element, and since that can't be known
+/// when the optional fails, the whole collection fails with it.
+pub(crate) fn resolve_element(
+    expr: Expression,
+    result: EvalResult,
+) -> Result<Option<Expression>, String> {
+    if let ExprKind::OptionalElement(_) = expr.kind {
+        return match result? {
+            Literal::Optional(None) => Ok(None),
+            Literal::Optional(Some(v)) => Ok(Some(Expression {
+                id: expr.id,
+                kind: ExprKind::Lit(*v),
+            })),
+            _ => Err(String::from("invalid types")),
+        };
+    }
+    Ok(Some(resolve(expr, result)))
+}
+
+pub(crate) fn lookup(name: &str, activation: &Activation) -> EvalResult {
+    activation
+        .get(name)
+        .cloned()
+        .ok_or_else(|| format!("undeclared reference to '{}'", name))
+}
+
+/// Combines the operands of `||`. Either side being `true` wins, even over an
+/// error on the other side.
+pub(crate) fn logical_or(a: EvalResult, b: EvalResult) -> EvalResult {
+    match (a, b) {
+        (Ok(Literal::Bool(true)), _) | (_, Ok(Literal::Bool(true))) => Ok(Literal::Bool(true)),
+        (Ok(Literal::Bool(false)), Ok(Literal::Bool(false))) => Ok(Literal::Bool(false)),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+/// Combines the operands of `&&`. Either side being `false` wins, even over an
+/// error on the other side.
+pub(crate) fn logical_and(a: EvalResult, b: EvalResult) -> EvalResult {
+    match (a, b) {
+        (Ok(Literal::Bool(false)), _) | (_, Ok(Literal::Bool(false))) => Ok(Literal::Bool(false)),
+        (Ok(Literal::Bool(true)), Ok(Literal::Bool(true))) => Ok(Literal::Bool(true)),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn index(e: Literal, i: Literal, activation: &Activation) -> EvalResult {
+    match (e, i) {
+        (Literal::List(xs), Literal::I64(i)) => {
+            if i < 0 || i as usize >= xs.len() {
+                return Err(format!("index out of range: {}", i));
+            }
+            evaluate_with(xs.into_iter().nth(i as usize).unwrap(), activation)
+        }
+        (Literal::Map(entries), key) => match lookup_key(entries, &key, activation)? {
+            Some(v) => Ok(v),
+            None => Err(format!("no such key: {:?}", key)),
+        },
+        // Indexing an optional is presence-tested, like `e[?i]`.
+        (e @ Literal::Optional(_), i) => optional_index(e, i, activation),
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+/// The value of `key` in a map, if it has one.
+fn lookup_key(
+    entries: Vec<(Expression, Expression)>,
+    key: &Literal,
+    activation: &Activation,
+) -> Result<Option<Literal>, String> {
+    for (k, v) in entries {
+        if evaluate_with(k, activation)? == *key {
+            return evaluate_with(v, activation).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// `e[?i]`: the element at `i` of a list or map as an optional, empty if
+/// there is none.
+pub(crate) fn optional_index(e: Literal, i: Literal, activation: &Activation) -> EvalResult {
+    let value = match (e, i) {
+        (Literal::Optional(None), _) => None,
+        (Literal::Optional(Some(e)), i) => return optional_index(*e, i, activation),
+        (Literal::List(xs), Literal::I64(i)) => {
+            if i < 0 || i as usize >= xs.len() {
+                None
+            } else {
+                Some(evaluate_with(
+                    xs.into_iter().nth(i as usize).unwrap(),
+                    activation,
+                )?)
+            }
+        }
+        (Literal::Map(entries), key) => lookup_key(entries, &key, activation)?,
+        _ => return Err(String::from("invalid types")),
+    };
+    Ok(Literal::Optional(value.map(Box::new)))
+}
+
+pub(crate) fn select(e: Literal, field: &str, activation: &Activation) -> EvalResult {
+    match e {
+        Literal::Map(_) => index(e, Literal::String(String::from(field)), activation),
+        Literal::Optional(_) => optional_select(e, field, activation),
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+/// `e.?field`: the field of a map as an optional, empty if it isn't set.
+pub(crate) fn optional_select(e: Literal, field: &str, activation: &Activation) -> EvalResult {
+    match e {
+        Literal::Map(_) | Literal::Optional(_) => {
+            optional_index(e, Literal::String(String::from(field)), activation)
+        }
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn negate(a: Literal) -> EvalResult {
+    match a {
+        Literal::I64(x) => x.checked_neg().map(Literal::I64).ok_or_else(overflow),
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn not(a: Literal) -> EvalResult {
+    match a {
+        Literal::Bool(x) => Ok(Literal::Bool(!x)),
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn equals(a: Literal, b: Literal) -> EvalResult {
+    match (a, b) {
+        (Literal::I64(a), Literal::I64(b)) => Ok(Literal::Bool(a == b)),
+        (Literal::U64(a), Literal::U64(b)) => Ok(Literal::Bool(a == b)),
+        (Literal::String(a), Literal::String(b)) => Ok(Literal::Bool(a == b)),
+        (Literal::Bytes(a), Literal::Bytes(b)) => Ok(Literal::Bool(a == b)),
+        (Literal::Opaque(a), Literal::Opaque(b)) => Ok(Literal::Bool(a == b)),
+        (Literal::Optional(a), Literal::Optional(b)) => match (a, b) {
+            (Some(a), Some(b)) => equals(*a, *b),
+            (a, b) => Ok(Literal::Bool(a.is_none() && b.is_none())),
+        },
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn less(a: Literal, b: Literal) -> EvalResult {
+    match (a, b) {
+        (Literal::I64(a), Literal::I64(b)) => Ok(Literal::Bool(a < b)),
+        (Literal::U64(a), Literal::U64(b)) => Ok(Literal::Bool(a < b)),
+        (Literal::String(a), Literal::String(b)) => Ok(Literal::Bool(a < b)),
+        (Literal::Bytes(a), Literal::Bytes(b)) => Ok(Literal::Bool(a < b)),
+        (Literal::Opaque(a), Literal::Opaque(b)) if a.partial_cmp(&b).is_some() => {
+            Ok(Literal::Bool(a < b))
+        }
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn less_equals(a: Literal, b: Literal) -> EvalResult {
+    match (a, b) {
+        (Literal::I64(a), Literal::I64(b)) => Ok(Literal::Bool(a <= b)),
+        (Literal::U64(a), Literal::U64(b)) => Ok(Literal::Bool(a <= b)),
+        (Literal::String(a), Literal::String(b)) => Ok(Literal::Bool(a <= b)),
+        (Literal::Bytes(a), Literal::Bytes(b)) => Ok(Literal::Bool(a <= b)),
+        (Literal::Opaque(a), Literal::Opaque(b)) if a.partial_cmp(&b).is_some() => {
+            Ok(Literal::Bool(a <= b))
+        }
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn add(a: Literal, b: Literal) -> EvalResult {
+    match (a, b) {
+        (Literal::I64(a), Literal::I64(b)) => {
+            a.checked_add(b).map(Literal::I64).ok_or_else(overflow)
+        }
+        (Literal::U64(a), Literal::U64(b)) => {
+            a.checked_add(b).map(Literal::U64).ok_or_else(overflow)
+        }
+        (Literal::String(a), Literal::String(b)) => {
+            Ok(Literal::String(a.chars().chain(b.chars()).collect()))
+        }
+        (Literal::List(mut a), Literal::List(b)) => {
+            a.extend(b);
+            Ok(Literal::List(a))
+        }
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn subtract(a: Literal, b: Literal) -> EvalResult {
+    match (a, b) {
+        (Literal::I64(a), Literal::I64(b)) => {
+            a.checked_sub(b).map(Literal::I64).ok_or_else(overflow)
+        }
+        (Literal::U64(a), Literal::U64(b)) => {
+            a.checked_sub(b).map(Literal::U64).ok_or_else(overflow)
+        }
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn multiply(a: Literal, b: Literal) -> EvalResult {
+    match (a, b) {
+        (Literal::I64(a), Literal::I64(b)) => {
+            a.checked_mul(b).map(Literal::I64).ok_or_else(overflow)
+        }
+        (Literal::U64(a), Literal::U64(b)) => {
+            a.checked_mul(b).map(Literal::U64).ok_or_else(overflow)
+        }
+        (Literal::F64(a), Literal::F64(b)) => Ok(Literal::F64(a * b)),
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn divide(a: Literal, b: Literal) -> EvalResult {
+    match (a, b) {
+        (Literal::I64(a), Literal::I64(b)) => {
+            if b != 0 {
+                a.checked_div(b).map(Literal::I64).ok_or_else(overflow)
+            } else {
+                Err(String::from("divide by zero"))
+            }
+        }
+        (Literal::U64(a), Literal::U64(b)) => match a.checked_div(b) {
+            Some(x) => Ok(Literal::U64(x)),
+            None => Err(String::from("divide by zero")),
+        },
+        (Literal::F64(a), Literal::F64(b)) => {
+            if b != 0.0 {
+                Ok(Literal::F64(a / b))
+            } else {
+                Err(String::from("divide by zero"))
+            }
+        }
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+pub(crate) fn modulo(a: Literal, b: Literal) -> EvalResult {
+    match (a, b) {
+        (Literal::I64(_), Literal::I64(0)) => Err(String::from("modulus by zero")),
+        (Literal::I64(a), Literal::I64(b)) => {
+            a.checked_rem(b).map(Literal::I64).ok_or_else(overflow)
+        }
+        (Literal::U64(_), Literal::U64(0)) => Err(String::from("modulus by zero")),
+        (Literal::U64(a), Literal::U64(b)) => Ok(Literal::U64(a % b)),
+        _ => Err(String::from("invalid types")),
+    }
+}
+
+/// The error for integer arithmetic whose result is out of range.
+fn overflow() -> String {
+    String::from("integer overflow")
+}
+
+/// The methods of optional values.
+const OPTIONAL_METHODS: [&str; 4] = ["hasValue", "value", "or", "orValue"];
+
+/// Calls one of `OPTIONAL_METHODS`. `or` and `orValue` only need their
+/// argument when the optional is empty, so an error in it doesn't matter
+/// otherwise.
+fn call_optional(value: &Option<Box<Literal>>, name: &str, args: Vec<EvalResult>) -> EvalResult {
+    match (name, value, <[EvalResult; 1]>::try_from(args)) {
+        ("hasValue", _, Err(args)) if args.is_empty() => Ok(Literal::Bool(value.is_some())),
+        ("value", Some(v), Err(args)) if args.is_empty() => Ok((**v).clone()),
+        ("value", None, Err(args)) if args.is_empty() => {
+            Err(String::from("optional.none() dereference"))
+        }
+        ("or", Some(_), Ok(_)) => Ok(Literal::Optional(value.clone())),
+        ("or", None, Ok([other])) => match other? {
+            other @ Literal::Optional(_) => Ok(other),
+            _ => Err(String::from("invalid types")),
+        },
+        ("orValue", Some(v), Ok(_)) => Ok((**v).clone()),
+        ("orValue", None, Ok([other])) => other,
+        (_, _, args) => {
+            let args = args.map(Vec::from).unwrap_or_else(|args| args);
+            let args = args.into_iter().collect::<Result<Vec<_>, _>>()?;
+            Err(no_overload(name, &args))
+        }
+    }
+}
+
+pub(crate) fn call_method(
+    e: Literal,
+    name: &MethodName,
+    args: Vec<EvalResult>,
+    activation: &Activation,
+    functions: &Functions,
+) -> EvalResult {
+    if let (Literal::Optional(value), MethodName::Extension(function)) = (&e, name) {
+        if OPTIONAL_METHODS.contains(&function.as_str()) {
+            return call_optional(value, function, args);
+        }
+    }
+    if let MethodName::Extension(function) = name {
+        let args = args.into_iter().collect::<Result<_, _>>()?;
+        return functions.call_member(function, e, args);
+    }
+    match e {
+        Literal::String(a) => match name {
+            MethodName::Extension(_) => unreachable!("extensions are dispatched above"),
+            MethodName::Len => {
+                if !args.is_empty() {
+                    return Err(String::from("too may arguments to .len()"));
+                }
+                Ok(Literal::I64(a.chars().count() as i64))
+            }
+            MethodName::Contains => Err(String::from("illegal type for .contains()")),
+            MethodName::Pow => Err(String::from("illegal type for .pow()")),
+        },
+        Literal::Bytes(a) => match name {
+            MethodName::Extension(_) => unreachable!("extensions are dispatched above"),
+            MethodName::Len => {
+                if !args.is_empty() {
+                    return Err(String::from("too may arguments to .len()"));
+                }
+                Ok(Literal::I64(a.len() as i64))
+            }
+            MethodName::Contains => Err(String::from("illegal type for .contains()")),
+            MethodName::Pow => Err(String::from("illegal type for .pow()")),
+        },
+        Literal::I64(a) => match name {
+            MethodName::Extension(_) => unreachable!("extensions are dispatched above"),
+            MethodName::Len => Err(String::from("illegal type for .len()")),
+            MethodName::Contains => Err(String::from("illegal type for .contains()")),
+            MethodName::Pow => {
+                if args.len() != 1 {
+                    return Err(String::from("too may arguments to .pow()"));
+                }
+                match args.into_iter().next().unwrap()? {
+                    Literal::I64(b) if b < 0 => Err(String::from("negative exponent to .pow()")),
+                    Literal::I64(b) => u32::try_from(b)
+                        .ok()
+                        .and_then(|b| a.checked_pow(b))
+                        .map(Literal::I64)
+                        .ok_or_else(overflow),
+                    _ => Err(String::from("illegal type for .pow()")),
+                }
+            }
+        },
+        Literal::F64(a) => match name {
+            MethodName::Extension(_) => unreachable!("extensions are dispatched above"),
+            MethodName::Len => Err(String::from("illegal type for .len()")),
+            MethodName::Contains => Err(String::from("illegal type for .contains()")),
+            MethodName::Pow => {
+                if args.len() != 1 {
+                    return Err(String::from("too may arguments to .pow()"));
+                }
+                match args.into_iter().next().unwrap()? {
+                    Literal::F64(b) => Ok(Literal::F64(f64::powf(a, b))),
+                    Literal::I64(b) => Ok(Literal::F64(f64::powf(a, b as f64))),
+                    _ => Err(String::from("illegal type for .pow()")),
+                }
+            }
+        },
+        Literal::List(xs) => match name {
+            MethodName::Extension(_) => unreachable!("extensions are dispatched above"),
+            MethodName::Pow => Err(String::from("illegal type for .pow()")),
+            MethodName::Contains => {
+                if args.len() != 1 {
+                    return Err(String::from("too may arguments to .contains()"));
+                }
+                let needle = args.into_iter().next().unwrap()?;
+                let mut err = None;
+                for x in xs {
+                    match evaluate_with(x, activation) {
+                        Ok(v) => {
+                            if v == needle {
+                                return Ok(Literal::Bool(true));
                             }
                         }
-                        err.unwrap_or(Ok(Literal::Bool(false)))
-                    }
-                    MethodName::Len => {
-                        if args.is_empty() {
-                            Ok(Literal::I64(xs.len() as i64))
-                        } else {
-                            Err(String::from("too may arguments to .len()"))
+                        e @ Err(_) => {
+                            err = Some(e);
                         }
                     }
-                },
-                other => Err(format!("illegal method call {:?}.{:?}", other, name)),
+                }
+                err.unwrap_or(Ok(Literal::Bool(false)))
             }
-        }
+            MethodName::Len => {
+                if args.is_empty() {
+                    Ok(Literal::I64(xs.len() as i64))
+                } else {
+                    Err(String::from("too may arguments to .len()"))
+                }
+            }
+        },
+        Literal::Map(entries) => match name {
+            MethodName::Extension(_) => unreachable!("extensions are dispatched above"),
+            MethodName::Len => {
+                if args.is_empty() {
+                    Ok(Literal::I64(entries.len() as i64))
+                } else {
+                    Err(String::from("too may arguments to .len()"))
+                }
+            }
+            MethodName::Contains => Err(String::from("illegal type for .contains()")),
+            MethodName::Pow => Err(String::from("illegal type for .pow()")),
+        },
+        other => Err(format!("illegal method call {:?}.{:?}", other, name)),
     }
 }
 
 #[cfg(test)]
 mod test {
-    use super::evaluate;
-    use crate::model::Literal;
-    use crate::parsers::parse;
+    use super::{
+        evaluate, evaluate_with, evaluate_with_options, explain, Activation, EvalError,
+        EvalOptions, EvalResult, EvalState, Functions,
+    };
+    use crate::model::{ExprKind, Expression, Literal};
+    use crate::parsers::{parse, parse_with_options, parse_with_source_info, ParserOptions};
 
     fn assert_eval_true(input: &str) {
         assert_eq!(
@@ -280,6 +1486,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn float_powf() {
         let input = r#" 3.1415926.pow(3.1415926) "#;
         assert_eq!(
@@ -289,6 +1496,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::approx_constant)]
     fn float_powi() {
         let input = r#" 3.1415926.pow(2) "#;
         assert_eq!(
@@ -365,4 +1573,423 @@ mod test {
             Err(String::from("divide by zero"))
         );
     }
+
+    #[test]
+    fn variables() {
+        let mut activation = Activation::new();
+        activation.insert(String::from("x"), Literal::I64(40));
+        let input = r#" x + 2 "#;
+        assert_eq!(
+            evaluate_with(parse(input).unwrap(), &activation),
+            Ok(Literal::I64(42)),
+        );
+    }
+
+    #[test]
+    fn undeclared_variable() {
+        let input = r#" x + 2 "#;
+        assert_eq!(
+            evaluate(parse(input).unwrap()),
+            Err(String::from("undeclared reference to 'x'")),
+        );
+    }
+
+    #[test]
+    fn undeclared_global() {
+        assert_eq!(
+            evaluate(parse("f(1, 2) + 1").unwrap()),
+            Err(String::from("undeclared reference to 'f'")),
+        );
+    }
+
+    #[test]
+    fn list_literal_captures_variables() {
+        let mut activation = Activation::new();
+        activation.insert(String::from("x"), Literal::I64(1));
+        let input = r#" [x, 1 / 0] "#;
+        let xs = match evaluate_with(parse(input).unwrap(), &activation) {
+            Ok(Literal::List(xs)) => xs,
+            result => panic!("not a list: {:?}", result),
+        };
+        assert_eq!(xs[0], Expression::from(Literal::I64(1)));
+        assert_eq!(evaluate(xs[1].clone()), Err(String::from("divide by zero")));
+    }
+
+    #[test]
+    fn list_index() {
+        assert_eval_true(r#" [1, 2, 3][1] == 2 "#);
+        assert_eq!(
+            evaluate(parse(r#" [1, 2, 3][3] "#).unwrap()),
+            Err(String::from("index out of range: 3")),
+        );
+        assert_eq!(
+            evaluate(parse(r#" [1, 1 / 0][1] "#).unwrap()),
+            Err(String::from("divide by zero")),
+        );
+    }
+
+    #[test]
+    fn map_index() {
+        assert_eval_true(r#" {"a": 1, "b": 2}["b"] == 2 "#);
+        assert_eval_true(r#" {1: "x", 2: 1 / 0}[1] == "x" "#);
+        assert_eq!(
+            evaluate(parse(r#" {"a": 1}["b"] "#).unwrap()),
+            Err(String::from(r#"no such key: String("b")"#)),
+        );
+    }
+
+    #[test]
+    fn field_selection() {
+        assert_eval_true(r#" {"a": {"b": 1}}.a.b == 1 "#);
+        assert_eq!(
+            evaluate(parse(r#" {"a": 1}.b "#).unwrap()),
+            Err(String::from(r#"no such key: String("b")"#)),
+        );
+        assert_eq!(
+            evaluate(parse(r#" [1].a "#).unwrap()),
+            Err(String::from("invalid types")),
+        );
+    }
+
+    #[test]
+    fn optional_selection_and_index() {
+        assert_eval_true(r#" {"a": 1}.?a == optional.of(1) "#);
+        assert_eval_true(r#" {"a": 1}.?b == optional.none() "#);
+        assert_eval_true(r#" {"a": {"b": 1}}.?a.b.value() == 1 "#);
+        assert_eval_true(r#" !{"a": {"b": 1}}.?x.b.hasValue() "#);
+        assert_eval_true(r#" [1, 2][?1].value() == 2 "#);
+        assert_eval_true(r#" ![1, 2][?2].hasValue() "#);
+        assert_eval_true(r#" {1: "x"}[?1].orValue("y") == "x" "#);
+        assert_eval_true(r#" optional.of([1])[0] == optional.of(1) "#);
+        assert_eq!(
+            evaluate(parse(r#" [1].?a "#).unwrap()),
+            Err(String::from("invalid types")),
+        );
+    }
+
+    #[test]
+    fn optional_values() {
+        assert_eval_true(r#" optional.none().orValue(2) == 2 "#);
+        assert_eval_true(r#" optional.of(1).orValue(1 / 0) == 1 "#);
+        assert_eval_true(r#" optional.none().or(optional.of(3)).value() == 3 "#);
+        assert_eval_true(r#" optional.of(1).or(optional.of(3)) == optional.of(1) "#);
+        assert_eval_true(r#" !optional.ofNonZeroValue("").hasValue() "#);
+        assert_eval_true(r#" optional.ofNonZeroValue([0]).hasValue() "#);
+        assert_eval_true(r#" optional.of(1) != optional.none() "#);
+        assert_eq!(
+            evaluate(parse(r#" optional.none().value() "#).unwrap()),
+            Err(String::from("optional.none() dereference")),
+        );
+        assert_eq!(
+            evaluate(parse(r#" optional.none().or(1) "#).unwrap()),
+            Err(String::from("invalid types")),
+        );
+    }
+
+    #[test]
+    fn optional_elements() {
+        assert_eval_true(r#" [?optional.none(), ?optional.of(1), 2][0] == 1 "#);
+        assert_eval_true(r#" [?{"a": 1}.?b].len() == 0 "#);
+        assert_eval_true(r#" {?"a": optional.none(), "b": 1}.len() == 1 "#);
+        assert_eval_true(r#" {?"a": {"x": 1}.?x}["a"] == 1 "#);
+        assert_eq!(
+            evaluate(parse(r#" [?1] "#).unwrap()),
+            Err(String::from("invalid types")),
+        );
+        assert_eq!(
+            evaluate(parse(r#" [1, ?optional.of(1 / 0)] "#).unwrap()),
+            Err(String::from("divide by zero")),
+        );
+    }
+
+    #[test]
+    fn map_len() {
+        let input = r#" {"a": 1, "b": 2}.len() "#;
+        assert_eq!(evaluate(parse(input).unwrap()), Ok(Literal::I64(2)));
+    }
+
+    #[test]
+    fn logical_and() {
+        assert_eval_true(r#" 1 < 2 && 2 < 3 "#);
+        assert_eval_true(r#" !(1 < 2 && 3 < 2) "#);
+        assert_eq!(
+            evaluate(parse(r#" 1 && true "#).unwrap()),
+            Err(String::from("invalid types")),
+        );
+    }
+
+    #[test]
+    fn logical_or() {
+        assert_eval_true(r#" 1 > 2 || 2 < 3 "#);
+        assert_eval_true(r#" !(1 > 2 || 3 < 2) "#);
+    }
+
+    #[test]
+    fn logical_operators_absorb_errors() {
+        assert_eval_true(r#" !(1 / 0 == 1 && false) "#);
+        assert_eval_true(r#" !(false && 1 / 0 == 1) "#);
+        assert_eval_true(r#" 1 / 0 == 1 || true "#);
+        assert_eval_true(r#" true || 1 / 0 == 1 "#);
+        assert_eq!(
+            evaluate(parse(r#" 1 / 0 == 1 && true "#).unwrap()),
+            Err(String::from("divide by zero")),
+        );
+        assert_eq!(
+            evaluate(parse(r#" false || 1 / 0 == 1 "#).unwrap()),
+            Err(String::from("divide by zero")),
+        );
+    }
+
+    #[test]
+    fn conditional() {
+        assert_eval_true(r#" (1 < 2 ? "a" : "b") == "a" "#);
+        assert_eval_true(r#" (1 > 2 ? 1 / 0 : 3) == 3 "#);
+        assert_eq!(
+            evaluate(parse(r#" 1 ? 2 : 3 "#).unwrap()),
+            Err(String::from("invalid types")),
+        );
+    }
+
+    fn evaluate_with_budget(
+        expr: Expression,
+        activation: &Activation,
+        budget: u64,
+    ) -> Result<Literal, EvalError> {
+        let options = EvalOptions {
+            budget,
+            ..EvalOptions::default()
+        };
+        evaluate_with_options(expr, activation, options)
+    }
+
+    fn evaluate_exhaustive(
+        expr: Expression,
+        activation: &Activation,
+        state: &mut EvalState,
+    ) -> EvalResult {
+        let options = EvalOptions {
+            state: Some(state),
+            ..EvalOptions::default()
+        };
+        evaluate_with_options(expr, activation, options).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn within_budget() {
+        let input = r#" "a" + "b" == "ab" "#;
+        assert_eq!(
+            evaluate_with_budget(parse(input).unwrap(), &Activation::new(), 100),
+            Ok(Literal::Bool(true)),
+        );
+    }
+
+    #[test]
+    fn cost_limit_exceeded() {
+        let mut activation = Activation::new();
+        activation.insert(String::from("s"), Literal::String("x".repeat(1000)));
+        let input = r#" s + s + s + s == "" "#;
+        assert_eq!(
+            evaluate_with_budget(parse(input).unwrap(), &activation, 5000),
+            Err(EvalError::CostLimitExceeded(5000)),
+        );
+        assert!(evaluate_with_budget(parse(input).unwrap(), &activation, 20000).is_ok());
+    }
+
+    #[test]
+    fn cost_limit_is_not_absorbed_by_logical_operators() {
+        let mut activation = Activation::new();
+        activation.insert(String::from("s"), Literal::String("x".repeat(1000)));
+        let input = r#" s + s == "" && false "#;
+        assert_eq!(
+            evaluate_with_budget(parse(input).unwrap(), &activation, 100),
+            Err(EvalError::CostLimitExceeded(100)),
+        );
+        let input = r#" s + s == "" || true "#;
+        assert_eq!(
+            evaluate_with_budget(parse(input).unwrap(), &activation, 100),
+            Err(EvalError::CostLimitExceeded(100)),
+        );
+    }
+
+    #[test]
+    fn errors_within_budget() {
+        assert_eq!(
+            evaluate_with_budget(parse("1 / 0").unwrap(), &Activation::new(), 100),
+            Err(EvalError::Failed(String::from("divide by zero"))),
+        );
+    }
+
+    #[test]
+    fn errors_report_their_location() {
+        let mut activation = Activation::new();
+        activation.insert(String::from("size"), Literal::I64(3));
+        let evaluate_located = |input: &str| {
+            let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+            let options = EvalOptions {
+                source_info: Some(&info),
+                ..EvalOptions::default()
+            };
+            evaluate_with_options(expr, &activation, options).map_err(|e| e.to_string())
+        };
+        assert_eq!(
+            evaluate_located("size * 2 + 1 / 0"),
+            Err(String::from("divide by zero at 1:14")),
+        );
+        assert_eq!(
+            evaluate_located("(size / 0) + 1"),
+            Err(String::from("divide by zero at 1:7")),
+        );
+        assert_eq!(
+            evaluate_located("size > 1 &&\n  count < 10"),
+            Err(String::from("undeclared reference to 'count' at 2:3")),
+        );
+        assert_eq!(
+            evaluate_located("'héllo' + 1"),
+            Err(String::from("invalid types at 1:9")),
+        );
+        assert_eq!(
+            evaluate_located("[1, 2][size]"),
+            Err(String::from("index out of range: 3 at 1:7")),
+        );
+        assert_eq!(
+            evaluate_located("size.pow(2) == 9 ? 'a'.pow(2) : 1"),
+            Err(String::from("illegal type for .pow() at 1:24")),
+        );
+        assert_eq!(
+            evaluate_located("1 / 0 == 1 || size == 3"),
+            Ok(Literal::Bool(true))
+        );
+    }
+
+    #[test]
+    fn options_combine() {
+        let mut functions = Functions::new();
+        crate::ext::strings::register(&mut functions);
+        let input = "'abc'.charAt(1) == 'b' && 'abc'.charAt(5) == ''";
+        let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+        let mut state = EvalState::default();
+        let options = EvalOptions {
+            functions: &functions,
+            budget: 100,
+            source_info: Some(&info),
+            state: Some(&mut state),
+        };
+        assert_eq!(
+            evaluate_with_options(expr.clone(), &Activation::new(), options),
+            Err(EvalError::Failed(String::from(
+                "index out of range: 5 at 1:33"
+            ))),
+        );
+        assert_eq!(
+            explain(&expr, &state).lines().nth(1),
+            Some("  \"abc\".charAt(1) == \"b\" => true")
+        );
+        let options = EvalOptions {
+            functions: &functions,
+            budget: 5,
+            ..EvalOptions::default()
+        };
+        assert_eq!(
+            evaluate_with_options(expr, &Activation::new(), options),
+            Err(EvalError::CostLimitExceeded(5)),
+        );
+    }
+
+    #[test]
+    fn exhaustive_evaluation_records_every_node() {
+        let mut activation = Activation::new();
+        activation.insert(String::from("x"), Literal::I64(3));
+        let expr = parse("x < 1 && x / 0 == 1 || (x > 2 ? 'a' : 'b') == 'a'").unwrap();
+        let mut state = EvalState::default();
+        assert_eq!(
+            evaluate_exhaustive(expr.clone(), &activation, &mut state),
+            Ok(Literal::Bool(true))
+        );
+        assert_eq!(
+            explain(&expr, &state),
+            [
+                "x < 1 && x / 0 == 1 || (x > 2 ? \"a\" : \"b\") == \"a\" => true",
+                "  x < 1 && x / 0 == 1 => false",
+                "    x < 1 => false",
+                "      x => 3",
+                "    x / 0 == 1 => error: divide by zero",
+                "      x / 0 => error: divide by zero",
+                "        x => 3",
+                "  (x > 2 ? \"a\" : \"b\") == \"a\" => true",
+                "    x > 2 ? \"a\" : \"b\" => \"a\"",
+                "      x > 2 => true",
+                "        x => 3",
+                "",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn exhaustive_evaluation_keeps_results() {
+        let expr = parse("1 > 2 ? 1 / 0 : [1].len()").unwrap();
+        let mut state = EvalState::default();
+        assert_eq!(
+            evaluate_exhaustive(expr.clone(), &Activation::new(), &mut state),
+            Ok(Literal::I64(1))
+        );
+        assert_eq!(
+            explain(&expr, &state),
+            [
+                "1 > 2 ? 1 / 0 : [1].len() => 1",
+                "  1 > 2 => false",
+                "  1 / 0 => error: divide by zero",
+                "  [1].len() => 1",
+                "    [1] => [1]",
+                "",
+            ]
+            .join("\n")
+        );
+        let expr = parse("'a' + 1 || true").unwrap();
+        let mut state = EvalState::default();
+        assert_eq!(
+            evaluate_exhaustive(expr, &Activation::new(), &mut state),
+            Ok(Literal::Bool(true))
+        );
+    }
+
+    #[test]
+    fn deep_trees_do_not_exhaust_the_stack() {
+        let terms = 100_000;
+        let options = ParserOptions {
+            max_recursion_depth: terms + 10,
+            max_code_points: 4 * terms + 20,
+            max_nodes: 2 * terms + 10,
+        };
+        let sum = vec!["1"; terms].join(" + ");
+        let expr = parse_with_options(&sum, &options).unwrap();
+        assert_eq!(evaluate(expr), Ok(Literal::I64(terms as i64)));
+        // Subtrees that are skipped, or abandoned after an error, are dropped
+        // without being evaluated.
+        for (input, expected) in [
+            (format!("false && ({} == 1)", sum), Ok(Literal::Bool(false))),
+            (format!("true ? 1 : ({})", sum), Ok(Literal::I64(1))),
+            (
+                format!("(1 / 0) + ({})", sum),
+                Err(String::from("divide by zero")),
+            ),
+            (format!("[{}][0]", sum), Ok(Literal::I64(terms as i64))),
+        ] {
+            let expr = parse_with_options(&input, &options).unwrap();
+            assert_eq!(evaluate(expr), expected, "{}", &input[..20]);
+        }
+
+        let mut expr = Expression::from(Literal::Bool(true));
+        for _ in 0..terms {
+            let not = ExprKind::Not(Box::new(expr));
+            let cond = Box::new(Expression::from(Literal::Bool(false)));
+            let other = Box::new(Expression::from(Literal::I64(0)));
+            expr = Expression::new(ExprKind::Conditional(
+                cond,
+                other,
+                Box::new(Expression::new(not)),
+            ));
+        }
+        assert_eq!(evaluate(expr), Ok(Literal::Bool(true)));
+    }
 }
@@ -1,6 +1,25 @@
+pub mod analysis;
+pub mod checker;
+pub mod compiler;
+pub mod cost;
+pub mod ext;
+pub mod formatter;
 pub mod interpreter;
 pub mod model;
+pub mod optimizer;
 pub mod parsers;
+pub mod partial;
+#[cfg(feature = "proto")]
+pub mod proto;
+#[cfg(feature = "serde")]
+pub mod serialization;
+pub mod unparser;
+pub mod vm;
 
+pub use crate::checker::check;
+pub use crate::compiler::compile;
+pub use crate::formatter::format;
 pub use crate::interpreter::evaluate;
+pub use crate::optimizer::optimize;
 pub use crate::parsers::parse;
+pub use crate::unparser::unparse;
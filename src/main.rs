@@ -1,13 +1,146 @@
 extern crate cel_rs;
 
+use cel_rs::ext;
+use cel_rs::formatter::FormatOptions;
+use cel_rs::interpreter::{self, Activation, EvalOptions, EvalState, Functions};
+use std::env;
+use std::fs;
 use std::io;
 use std::io::Read;
+use std::process;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 fn main() -> Result<()> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(String::as_str) == Some("fmt") {
+        return fmt(&args[1..]);
+    }
+    if args.first().map(String::as_str) == Some("--explain") {
+        return explain(&args[1..]);
+    }
     let mut buf = String::new();
     io::stdin().read_to_string(&mut buf)?;
     let parsed = cel_rs::parsers::parse(&buf)?;
     println!("{:?}", parsed);
     Ok(())
 }
+
+/// `cel-rs --explain [--ext LIBRARY...] [--var NAME=EXPR...]`
+///
+/// Evaluates the expression on stdin, with each variable bound to the value
+/// of its own expression and the functions of each extension library, e.g.
+/// `strings`, available to all of them, and prints every subexpression with
+/// its value.
+fn explain(args: &[String]) -> Result<()> {
+    let mut functions = Functions::new();
+    let mut vars = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ext" => {
+                let library = args.next().ok_or("--ext needs a value")?;
+                register(library, &mut functions)?;
+            }
+            "--var" => {
+                let binding = args.next().ok_or("--var needs a value")?;
+                vars.push(binding.split_once('=').ok_or("--var needs NAME=EXPR")?);
+            }
+            _ => return Err(format!("unknown argument '{}'", arg).into()),
+        }
+    }
+    let mut activation = Activation::new();
+    for (name, source) in vars {
+        let value = interpreter::evaluate_with_functions(
+            cel_rs::parse(source)?,
+            &Activation::new(),
+            &functions,
+        )
+        .map_err(|e| format!("{}: {}", name, e))?;
+        activation.insert(String::from(name), value);
+    }
+    let mut buf = String::new();
+    io::stdin().read_to_string(&mut buf)?;
+    let expr = cel_rs::parse(&buf)?;
+    let mut state = EvalState::default();
+    let options = EvalOptions {
+        functions: &functions,
+        state: Some(&mut state),
+        ..EvalOptions::default()
+    };
+    let _ = interpreter::evaluate_with_options(expr.clone(), &activation, options);
+    print!("{}", interpreter::explain(&expr, &state));
+    Ok(())
+}
+
+/// Adds the functions of the extension library called `name`.
+fn register(name: &str, functions: &mut Functions) -> Result<()> {
+    let register: fn(&mut Functions) = match name {
+        #[cfg(feature = "encoders")]
+        "encoders" => ext::encoders::register,
+        "lists" => ext::lists::register,
+        "math" => ext::math::register,
+        "network" => ext::network::register,
+        "quantity" => ext::quantity::register,
+        #[cfg(feature = "regex")]
+        "regex" => ext::regex::register,
+        "semver" => ext::semver::register,
+        "sets" => ext::sets::register,
+        "strings" => ext::strings::register,
+        "url" => ext::url::register,
+        _ => return Err(format!("unknown extension library '{}'", name).into()),
+    };
+    register(functions);
+    Ok(())
+}
+
+/// `cel-rs fmt [--check] [--width N] [PATH...]`
+///
+/// Formats each file in place, or stdin to stdout if no paths are given. With
+/// `--check` nothing is written; instead the exit status is 1 if any input is
+/// not already formatted, and the paths of unformatted files are printed.
+fn fmt(args: &[String]) -> Result<()> {
+    let mut check = false;
+    let mut options = FormatOptions::default();
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--check" => check = true,
+            "--width" => {
+                let width = args.next().ok_or("--width needs a value")?;
+                options.width = width.parse()?;
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown flag '{}'", arg).into()),
+            _ => paths.push(arg),
+        }
+    }
+    let mut unformatted = false;
+    if paths.is_empty() {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf)?;
+        let formatted = cel_rs::format(&buf, &options)?;
+        if check {
+            unformatted = formatted != buf;
+        } else {
+            print!("{}", formatted);
+        }
+    }
+    for path in paths {
+        let source = fs::read_to_string(path)?;
+        let formatted =
+            cel_rs::format(&source, &options).map_err(|e| format!("{}: {}", path, e))?;
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{}", path);
+            unformatted = true;
+        } else {
+            fs::write(path, formatted)?;
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+    Ok(())
+}
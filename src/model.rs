@@ -1,7 +1,191 @@
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
 use std::str::FromStr;
+use std::sync::Arc;
+
+/// A node of the syntax tree.
+///
+/// `id` identifies the node within the tree built by a single parse, and is
+/// the key into that parse's `SourceInfo`. Nodes built any other way, such as
+/// evaluated values wrapped back into literals, have id 0. Equality compares
+/// the structure of two trees and ignores their ids.
+#[derive(Debug)]
+pub struct Expression {
+    pub id: u64,
+    pub kind: ExprKind,
+}
+
+impl Expression {
+    pub fn new(kind: ExprKind) -> Expression {
+        Expression { id: 0, kind }
+    }
+
+    /// Takes the node apart, for matching on its kind by value.
+    pub fn into_kind(mut self) -> ExprKind {
+        std::mem::replace(&mut self.kind, ExprKind::Lit(Literal::Null))
+    }
+}
+
+/// Copies the tree with a stack of its own, like `drop`.
+impl Clone for Expression {
+    fn clone(&self) -> Expression {
+        enum Task<'e> {
+            Visit(&'e Expression),
+            Build(&'e Expression, usize),
+        }
+        let mut tasks = vec![Task::Visit(self)];
+        let mut built: Vec<Expression> = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Visit(expr) => {
+                    let mut children = Children(Vec::new());
+                    walk_expression(&mut children, expr);
+                    tasks.push(Task::Build(expr, children.0.len()));
+                    tasks.extend(children.0.into_iter().rev().map(Task::Visit));
+                }
+                Task::Build(expr, n) => {
+                    let mut children = built.drain(built.len() - n..);
+                    let mut next = || Box::new(children.next().unwrap());
+                    let kind = match &expr.kind {
+                        ExprKind::Conditional(_, _, _) => {
+                            ExprKind::Conditional(next(), next(), next())
+                        }
+                        ExprKind::Or(_, _) => ExprKind::Or(next(), next()),
+                        ExprKind::And(_, _) => ExprKind::And(next(), next()),
+                        ExprKind::Eq(_, _) => ExprKind::Eq(next(), next()),
+                        ExprKind::Neq(_, _) => ExprKind::Neq(next(), next()),
+                        ExprKind::Lt(_, _) => ExprKind::Lt(next(), next()),
+                        ExprKind::Lte(_, _) => ExprKind::Lte(next(), next()),
+                        ExprKind::Gte(_, _) => ExprKind::Gte(next(), next()),
+                        ExprKind::Gt(_, _) => ExprKind::Gt(next(), next()),
+                        ExprKind::Add(_, _) => ExprKind::Add(next(), next()),
+                        ExprKind::Sub(_, _) => ExprKind::Sub(next(), next()),
+                        ExprKind::Mul(_, _) => ExprKind::Mul(next(), next()),
+                        ExprKind::Div(_, _) => ExprKind::Div(next(), next()),
+                        ExprKind::Mod(_, _) => ExprKind::Mod(next(), next()),
+                        ExprKind::Index(_, _) => ExprKind::Index(next(), next()),
+                        ExprKind::OptionalIndex(_, _) => ExprKind::OptionalIndex(next(), next()),
+                        ExprKind::Neg(_) => ExprKind::Neg(next()),
+                        ExprKind::Not(_) => ExprKind::Not(next()),
+                        ExprKind::OptionalElement(_) => ExprKind::OptionalElement(next()),
+                        ExprKind::Select(_, field) => ExprKind::Select(next(), field.clone()),
+                        ExprKind::OptionalSelect(_, field) => {
+                            ExprKind::OptionalSelect(next(), field.clone())
+                        }
+                        ExprKind::Method(_, name, args) => {
+                            let e = next();
+                            let args = args.iter().map(|_| *next()).collect();
+                            ExprKind::Method(e, name.clone(), args)
+                        }
+                        ExprKind::Call(function, args) => {
+                            ExprKind::Call(function.clone(), args.iter().map(|_| *next()).collect())
+                        }
+                        ExprKind::Lit(Literal::List(xs)) => {
+                            ExprKind::Lit(Literal::List(xs.iter().map(|_| *next()).collect()))
+                        }
+                        ExprKind::Lit(Literal::Map(entries)) => ExprKind::Lit(Literal::Map(
+                            entries.iter().map(|_| (*next(), *next())).collect(),
+                        )),
+                        ExprKind::Lit(literal) => ExprKind::Lit(literal.clone()),
+                        ExprKind::Ident(name) => ExprKind::Ident(name.clone()),
+                    };
+                    drop(children);
+                    built.push(Expression { id: expr.id, kind });
+                }
+            }
+        }
+        built.pop().unwrap()
+    }
+}
+
+/// Collects the children of a node, without descending any further.
+struct Children<'e>(Vec<&'e Expression>);
+
+impl<'e> Visitor<'e> for Children<'e> {
+    fn visit_expression(&mut self, expr: &'e Expression) {
+        self.0.push(expr);
+    }
+}
+
+/// Trees may be far deeper than the native stack allows to recurse, e.g. a
+/// long chain of `+`, so they are taken apart with a stack of their own
+/// rather than by the recursive drop glue.
+impl Drop for Expression {
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        let mut kind = std::mem::replace(&mut self.kind, ExprKind::Lit(Literal::Null));
+        loop {
+            match kind {
+                ExprKind::Conditional(cond, a, b) => stack.extend([*cond, *a, *b]),
+                ExprKind::Or(a, b)
+                | ExprKind::And(a, b)
+                | ExprKind::Eq(a, b)
+                | ExprKind::Neq(a, b)
+                | ExprKind::Lt(a, b)
+                | ExprKind::Lte(a, b)
+                | ExprKind::Gte(a, b)
+                | ExprKind::Gt(a, b)
+                | ExprKind::Add(a, b)
+                | ExprKind::Sub(a, b)
+                | ExprKind::Mul(a, b)
+                | ExprKind::Div(a, b)
+                | ExprKind::Mod(a, b)
+                | ExprKind::Index(a, b)
+                | ExprKind::OptionalIndex(a, b) => stack.extend([*a, *b]),
+                ExprKind::Neg(a)
+                | ExprKind::Not(a)
+                | ExprKind::Select(a, _)
+                | ExprKind::OptionalSelect(a, _)
+                | ExprKind::OptionalElement(a) => stack.push(*a),
+                ExprKind::Method(e, _, args) => {
+                    stack.push(*e);
+                    stack.extend(args);
+                }
+                ExprKind::Call(_, args) => stack.extend(args),
+                ExprKind::Lit(literal) => {
+                    let mut literal = literal;
+                    while let Literal::Optional(Some(value)) = literal {
+                        literal = *value;
+                    }
+                    match literal {
+                        Literal::List(xs) => stack.extend(xs),
+                        Literal::Map(entries) => {
+                            for (k, v) in entries {
+                                stack.extend([k, v]);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                ExprKind::Ident(_) => {}
+            }
+            match stack.pop() {
+                Some(expr) => kind = expr.into_kind(),
+                None => break,
+            }
+        }
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Expression) -> bool {
+        self.kind == other.kind
+    }
+}
+
+impl From<Literal> for Expression {
+    fn from(literal: Literal) -> Expression {
+        Expression::new(ExprKind::Lit(literal))
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
-pub enum Expression {
+pub enum ExprKind {
+    Conditional(Box<Expression>, Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
     Eq(Box<Expression>, Box<Expression>),
     Neq(Box<Expression>, Box<Expression>),
     Lt(Box<Expression>, Box<Expression>),
@@ -16,18 +200,380 @@ pub enum Expression {
     Neg(Box<Expression>),
     Not(Box<Expression>),
     Method(Box<Expression>, MethodName, Vec<Expression>),
+    /// A call of a global function provided by an extension library, e.g.
+    /// `ip('10.0.0.1')`.
+    Call(String, Vec<Expression>),
+    /// Field selection, e.g. `request.auth`, which on a map looks up the
+    /// field name as a string key.
+    Select(Box<Expression>, String),
+    /// Optional field selection, e.g. `request.?auth`, which is
+    /// `optional.none()` rather than an error when the field is missing.
+    OptionalSelect(Box<Expression>, String),
+    Index(Box<Expression>, Box<Expression>),
+    /// Optional indexing, e.g. `headers[?'x-user']`.
+    OptionalIndex(Box<Expression>, Box<Expression>),
+    /// A list element or map value that is only there if the optional it
+    /// evaluates to has a value, as in `[?x]` and `{?k: v}`.
+    OptionalElement(Box<Expression>),
+    Ident(String),
     Lit(Literal),
 }
 
+/// Walks a tree by reference.
+///
+/// Every method defaults to visiting the children of its node, so an
+/// implementation only overrides the nodes it is interested in, and calls the
+/// matching `walk_*` function from its override to carry on into their
+/// children.
+pub trait Visitor<'a> {
+    fn visit_expression(&mut self, expr: &'a Expression) {
+        walk_expression(self, expr)
+    }
+
+    fn visit_ident(&mut self, _expr: &'a Expression, _name: &'a str) {}
+
+    fn visit_method(
+        &mut self,
+        _expr: &'a Expression,
+        receiver: &'a Expression,
+        _name: &'a MethodName,
+        args: &'a [Expression],
+    ) {
+        walk_method(self, receiver, args)
+    }
+
+    fn visit_call(&mut self, _expr: &'a Expression, _function: &'a str, args: &'a [Expression]) {
+        for arg in args {
+            self.visit_expression(arg);
+        }
+    }
+
+    /// Visits a field selection, `operand.field` or `operand.?field`.
+    fn visit_select(&mut self, _expr: &'a Expression, operand: &'a Expression, _field: &'a str) {
+        self.visit_expression(operand)
+    }
+
+    /// Visits a literal, including the elements of a list and the keys and
+    /// values of a map.
+    fn visit_literal(&mut self, _expr: &'a Expression, literal: &'a Literal) {
+        walk_literal(self, literal)
+    }
+}
+
+/// Visits the children of `expr`, in the order in which they appear in the
+/// source.
+pub fn walk_expression<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, expr: &'a Expression) {
+    match &expr.kind {
+        ExprKind::Conditional(cond, a, b) => {
+            visitor.visit_expression(cond);
+            visitor.visit_expression(a);
+            visitor.visit_expression(b);
+        }
+        ExprKind::Or(a, b)
+        | ExprKind::And(a, b)
+        | ExprKind::Eq(a, b)
+        | ExprKind::Neq(a, b)
+        | ExprKind::Lt(a, b)
+        | ExprKind::Lte(a, b)
+        | ExprKind::Gte(a, b)
+        | ExprKind::Gt(a, b)
+        | ExprKind::Add(a, b)
+        | ExprKind::Sub(a, b)
+        | ExprKind::Mul(a, b)
+        | ExprKind::Div(a, b)
+        | ExprKind::Mod(a, b)
+        | ExprKind::Index(a, b)
+        | ExprKind::OptionalIndex(a, b) => {
+            visitor.visit_expression(a);
+            visitor.visit_expression(b);
+        }
+        ExprKind::Neg(a) | ExprKind::Not(a) | ExprKind::OptionalElement(a) => {
+            visitor.visit_expression(a)
+        }
+        ExprKind::Method(e, name, args) => visitor.visit_method(expr, e, name, args),
+        ExprKind::Call(function, args) => visitor.visit_call(expr, function, args),
+        ExprKind::Select(e, field) | ExprKind::OptionalSelect(e, field) => {
+            visitor.visit_select(expr, e, field)
+        }
+        ExprKind::Ident(name) => visitor.visit_ident(expr, name),
+        ExprKind::Lit(literal) => visitor.visit_literal(expr, literal),
+    }
+}
+
+pub fn walk_method<'a, V: Visitor<'a> + ?Sized>(
+    visitor: &mut V,
+    receiver: &'a Expression,
+    args: &'a [Expression],
+) {
+    visitor.visit_expression(receiver);
+    for arg in args {
+        visitor.visit_expression(arg);
+    }
+}
+
+pub fn walk_literal<'a, V: Visitor<'a> + ?Sized>(visitor: &mut V, literal: &'a Literal) {
+    match literal {
+        Literal::List(xs) => {
+            for x in xs {
+                visitor.visit_expression(x);
+            }
+        }
+        Literal::Map(entries) => {
+            for (k, v) in entries {
+                visitor.visit_expression(k);
+                visitor.visit_expression(v);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Walks a tree by mutable reference, so that nodes can be changed in place.
+pub trait VisitorMut {
+    fn visit_expression_mut(&mut self, expr: &mut Expression) {
+        walk_expression_mut(self, expr)
+    }
+}
+
+/// Visits the children of `expr`, including list elements, map entries and
+/// method receivers and arguments, in source order.
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expression) {
+    match &mut expr.kind {
+        ExprKind::Conditional(cond, a, b) => {
+            visitor.visit_expression_mut(cond);
+            visitor.visit_expression_mut(a);
+            visitor.visit_expression_mut(b);
+        }
+        ExprKind::Or(a, b)
+        | ExprKind::And(a, b)
+        | ExprKind::Eq(a, b)
+        | ExprKind::Neq(a, b)
+        | ExprKind::Lt(a, b)
+        | ExprKind::Lte(a, b)
+        | ExprKind::Gte(a, b)
+        | ExprKind::Gt(a, b)
+        | ExprKind::Add(a, b)
+        | ExprKind::Sub(a, b)
+        | ExprKind::Mul(a, b)
+        | ExprKind::Div(a, b)
+        | ExprKind::Mod(a, b)
+        | ExprKind::Index(a, b)
+        | ExprKind::OptionalIndex(a, b) => {
+            visitor.visit_expression_mut(a);
+            visitor.visit_expression_mut(b);
+        }
+        ExprKind::Neg(a)
+        | ExprKind::Not(a)
+        | ExprKind::Select(a, _)
+        | ExprKind::OptionalSelect(a, _)
+        | ExprKind::OptionalElement(a) => visitor.visit_expression_mut(a),
+        ExprKind::Method(e, _, args) => {
+            visitor.visit_expression_mut(e);
+            for arg in args {
+                visitor.visit_expression_mut(arg);
+            }
+        }
+        ExprKind::Call(_, args) => {
+            for arg in args {
+                visitor.visit_expression_mut(arg);
+            }
+        }
+        ExprKind::Lit(Literal::List(xs)) => {
+            for x in xs {
+                visitor.visit_expression_mut(x);
+            }
+        }
+        ExprKind::Lit(Literal::Map(entries)) => {
+            for (k, v) in entries {
+                visitor.visit_expression_mut(k);
+                visitor.visit_expression_mut(v);
+            }
+        }
+        ExprKind::Lit(_) | ExprKind::Ident(_) => {}
+    }
+}
+
+/// Rebuilds a tree bottom-up, taking it by value. The default folds the
+/// children of each node and keeps the node itself, so an override typically
+/// calls `fold_children` first and then rewrites the result.
+pub trait Fold {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        fold_children(self, expr)
+    }
+}
+
+/// Folds the children of `expr`, keeping its id.
+pub fn fold_children<F: Fold + ?Sized>(folder: &mut F, expr: Expression) -> Expression {
+    let mut fold = |e: Box<Expression>| Box::new(folder.fold_expression(*e));
+    let id = expr.id;
+    let kind = match expr.into_kind() {
+        ExprKind::Conditional(cond, a, b) => ExprKind::Conditional(fold(cond), fold(a), fold(b)),
+        ExprKind::Or(a, b) => ExprKind::Or(fold(a), fold(b)),
+        ExprKind::And(a, b) => ExprKind::And(fold(a), fold(b)),
+        ExprKind::Eq(a, b) => ExprKind::Eq(fold(a), fold(b)),
+        ExprKind::Neq(a, b) => ExprKind::Neq(fold(a), fold(b)),
+        ExprKind::Lt(a, b) => ExprKind::Lt(fold(a), fold(b)),
+        ExprKind::Lte(a, b) => ExprKind::Lte(fold(a), fold(b)),
+        ExprKind::Gte(a, b) => ExprKind::Gte(fold(a), fold(b)),
+        ExprKind::Gt(a, b) => ExprKind::Gt(fold(a), fold(b)),
+        ExprKind::Add(a, b) => ExprKind::Add(fold(a), fold(b)),
+        ExprKind::Sub(a, b) => ExprKind::Sub(fold(a), fold(b)),
+        ExprKind::Mul(a, b) => ExprKind::Mul(fold(a), fold(b)),
+        ExprKind::Div(a, b) => ExprKind::Div(fold(a), fold(b)),
+        ExprKind::Mod(a, b) => ExprKind::Mod(fold(a), fold(b)),
+        ExprKind::Index(a, b) => ExprKind::Index(fold(a), fold(b)),
+        ExprKind::OptionalIndex(a, b) => ExprKind::OptionalIndex(fold(a), fold(b)),
+        ExprKind::Neg(a) => ExprKind::Neg(fold(a)),
+        ExprKind::Not(a) => ExprKind::Not(fold(a)),
+        ExprKind::OptionalElement(a) => ExprKind::OptionalElement(fold(a)),
+        ExprKind::Select(e, field) => ExprKind::Select(fold(e), field),
+        ExprKind::OptionalSelect(e, field) => ExprKind::OptionalSelect(fold(e), field),
+        ExprKind::Method(e, name, args) => {
+            let e = fold(e);
+            let args = args
+                .into_iter()
+                .map(|arg| folder.fold_expression(arg))
+                .collect();
+            ExprKind::Method(e, name, args)
+        }
+        ExprKind::Call(function, args) => ExprKind::Call(
+            function,
+            args.into_iter()
+                .map(|arg| folder.fold_expression(arg))
+                .collect(),
+        ),
+        ExprKind::Lit(Literal::List(xs)) => ExprKind::Lit(Literal::List(
+            xs.into_iter().map(|x| folder.fold_expression(x)).collect(),
+        )),
+        ExprKind::Lit(Literal::Map(entries)) => ExprKind::Lit(Literal::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (folder.fold_expression(k), folder.fold_expression(v)))
+                .collect(),
+        )),
+        kind @ ExprKind::Lit(_) | kind @ ExprKind::Ident(_) => kind,
+    };
+    Expression { id, kind }
+}
+
+/// Where a node starts in the source. For operators this is the operator
+/// itself, e.g. the `/` of `a / b`, the `[` of `a[b]` or the `?` of `a ? b : c`,
+/// and for method calls it is the method name.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Location {
+    /// The byte offset into the source.
+    pub offset: usize,
+    /// The line, counting from 1.
+    pub line: usize,
+    /// The column within the line in Unicode code points, counting from 1.
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// The location of every node of a parsed expression, by id.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SourceInfo {
+    pub(crate) locations: HashMap<u64, Location>,
+    /// For each line, the offset in code points at which the next line would
+    /// start, as in cel-go's `SourceInfo.line_offsets`.
+    pub(crate) line_offsets: Vec<usize>,
+}
+
+impl SourceInfo {
+    pub fn location(&self, id: u64) -> Option<Location> {
+        self.locations.get(&id).copied()
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum Literal {
     I64(i64),
+    /// An unsigned int, written with a `u` suffix, e.g. `3u`.
+    U64(u64),
     F64(f64),
     Bool(bool),
     String(String),
     Bytes(Vec<u8>),
     List(Vec<Expression>),
+    Map(Vec<(Expression, Expression)>),
     Null,
+    /// A value that may be absent, as created by `optional.of(x)` or
+    /// `optional.none()`.
+    Optional(Option<Box<Literal>>),
+    /// A value of a type defined outside of CEL, e.g. by an extension
+    /// library.
+    Opaque(Opaque),
+}
+
+/// A value of a type that the host or an extension library defines, such as
+/// an IP address. Such values are only created by functions, never written
+/// as literals.
+pub trait OpaqueValue: fmt::Debug + fmt::Display + Send + Sync {
+    /// The CEL name of the type, e.g. `net.IP`.
+    fn type_name(&self) -> &'static str;
+
+    /// Whether the value equals `other`, which may be of any opaque type.
+    fn equals(&self, other: &dyn OpaqueValue) -> bool;
+
+    /// The global function and arguments that create the value, e.g. `ip`
+    /// and `'10.0.0.1'`, for writing it back out as an expression. Equal
+    /// values have equal constructors.
+    fn constructor(&self) -> (&'static str, Vec<Literal>);
+
+    /// How the value is ordered with respect to `other`, for `<` and the
+    /// other relational operators. Values are unordered unless their type
+    /// says otherwise.
+    fn compare(&self, _other: &dyn OpaqueValue) -> Option<Ordering> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// A shared `OpaqueValue`.
+#[derive(Debug, Clone)]
+pub struct Opaque(Arc<dyn OpaqueValue>);
+
+impl Opaque {
+    pub fn new<T: OpaqueValue + 'static>(value: T) -> Opaque {
+        Opaque(Arc::new(value))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.0.type_name()
+    }
+
+    pub fn constructor(&self) -> (&'static str, Vec<Literal>) {
+        self.0.constructor()
+    }
+
+    /// The value, if it is a `T`.
+    pub fn downcast_ref<T: 'static>(&self) -> Option<&T> {
+        self.0.as_any().downcast_ref()
+    }
+}
+
+impl PartialEq for Opaque {
+    fn eq(&self, other: &Opaque) -> bool {
+        self.0.equals(other.0.as_ref())
+    }
+}
+
+impl PartialOrd for Opaque {
+    fn partial_cmp(&self, other: &Opaque) -> Option<Ordering> {
+        self.0.compare(other.0.as_ref())
+    }
+}
+
+impl fmt::Display for Opaque {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -35,6 +581,9 @@ pub enum MethodName {
     Len,
     Pow,
     Contains,
+    /// A function provided by an extension library, such as `split`, which is
+    /// only available to evaluation once the library is registered.
+    Extension(String),
 }
 
 impl FromStr for MethodName {
@@ -44,7 +593,100 @@ impl FromStr for MethodName {
             "len" => Ok(MethodName::Len),
             "pow" => Ok(MethodName::Pow),
             "contains" => Ok(MethodName::Contains),
-            _ => Err(format!("unknown method '{}'", s)),
+            _ if is_identifier(s) => Ok(MethodName::Extension(String::from(s))),
+            _ => Err(format!("invalid method name '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for MethodName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MethodName::Len => write!(f, "len"),
+            MethodName::Pow => write!(f, "pow"),
+            MethodName::Contains => write!(f, "contains"),
+            MethodName::Extension(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        fold_children, walk_expression, walk_expression_mut, ExprKind, Expression, Fold, Literal,
+        Visitor, VisitorMut,
+    };
+    use crate::parsers::parse;
+
+    #[derive(Default)]
+    struct Names<'a> {
+        idents: Vec<&'a str>,
+        nodes: usize,
+    }
+
+    impl<'a> Visitor<'a> for Names<'a> {
+        fn visit_expression(&mut self, expr: &'a Expression) {
+            self.nodes += 1;
+            walk_expression(self, expr);
+        }
+
+        fn visit_ident(&mut self, _expr: &'a Expression, name: &'a str) {
+            self.idents.push(name);
+        }
+    }
+
+    #[test]
+    fn visitor() {
+        let expr = parse("a ? b.pow(c) : [d, {e: f}][g]").unwrap();
+        let mut names = Names::default();
+        names.visit_expression(&expr);
+        assert_eq!(names.idents, vec!["a", "b", "c", "d", "e", "f", "g"]);
+        assert_eq!(names.nodes, 12);
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expression_mut(&mut self, expr: &mut Expression) {
+            if let ExprKind::Ident(name) = &mut expr.kind {
+                name.push('_');
+            }
+            walk_expression_mut(self, expr);
         }
     }
+
+    #[test]
+    fn visitor_mut() {
+        let mut expr = parse("x.contains([y, {z: 1}][0])").unwrap();
+        Rename.visit_expression_mut(&mut expr);
+        assert_eq!(expr, parse("x_.contains([y_, {z_: 1}][0])").unwrap());
+    }
+
+    struct Substitute;
+
+    impl Fold for Substitute {
+        fn fold_expression(&mut self, expr: Expression) -> Expression {
+            match expr.kind {
+                ExprKind::Ident(_) => Expression {
+                    id: expr.id,
+                    kind: ExprKind::Lit(Literal::I64(0)),
+                },
+                _ => fold_children(self, expr),
+            }
+        }
+    }
+
+    #[test]
+    fn fold() {
+        let expr = parse("-x + [y][z].len()").unwrap();
+        let folded = Substitute.fold_expression(expr.clone());
+        assert_eq!(folded, parse("-0 + [0][0].len()").unwrap());
+        assert_eq!(folded.id, expr.id);
+    }
 }
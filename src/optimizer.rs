@@ -0,0 +1,203 @@
+use crate::interpreter::evaluate;
+use crate::model::{fold_children, ExprKind, Expression, Fold, Literal};
+
+/// Folds constant subexpressions and prunes branches of `&&`, `||` and `?:`
+/// whose outcome is already decided.
+///
+/// The simplifications `!!x => x`, `x && true => x` and `x || false => x`
+/// assume that `x` is a `bool`, so this should only be run on expressions
+/// that have passed the checker. Any fold that would produce an error keeps
+/// the original expression so that the error is still raised at evaluation.
+pub fn optimize(expr: Expression) -> Expression {
+    Optimizer.fold_expression(expr)
+}
+
+struct Optimizer;
+
+impl Fold for Optimizer {
+    fn fold_expression(&mut self, expr: Expression) -> Expression {
+        let folded = fold_children(self, expr);
+        let id = folded.id;
+        let kind = match folded.into_kind() {
+            ExprKind::Conditional(cond, a, _) if is_bool(&cond, true) => return *a,
+            ExprKind::Conditional(cond, _, b) if is_bool(&cond, false) => return *b,
+            ExprKind::Or(a, _) if is_bool(&a, true) => return *a,
+            ExprKind::Or(_, b) if is_bool(&b, true) => return *b,
+            ExprKind::Or(a, b) if is_bool(&a, false) => return *b,
+            ExprKind::Or(a, b) if is_bool(&b, false) => return *a,
+            ExprKind::And(a, _) if is_bool(&a, false) => return *a,
+            ExprKind::And(_, b) if is_bool(&b, false) => return *b,
+            ExprKind::And(a, b) if is_bool(&a, true) => return *b,
+            ExprKind::And(a, b) if is_bool(&b, true) => return *a,
+            ExprKind::Not(a) => {
+                let a_id = a.id;
+                match a.into_kind() {
+                    ExprKind::Not(x) => return *x,
+                    kind => ExprKind::Not(Box::new(Expression { id: a_id, kind })),
+                }
+            }
+            kind @ ExprKind::Lit(_) | kind @ ExprKind::Ident(_) => return Expression { id, kind },
+            kind => kind,
+        };
+        fold(Expression { id, kind })
+    }
+}
+
+fn is_bool(expr: &Expression, value: bool) -> bool {
+    expr.kind == ExprKind::Lit(Literal::Bool(value))
+}
+
+/// Replaces an operator or method application whose operands are all
+/// constant with its value, unless evaluating it fails.
+fn fold(expr: Expression) -> Expression {
+    let foldable = match &expr.kind {
+        ExprKind::Conditional(cond, a, b) => is_constant(cond) && is_constant(a) && is_constant(b),
+        ExprKind::Or(a, b)
+        | ExprKind::And(a, b)
+        | ExprKind::Eq(a, b)
+        | ExprKind::Neq(a, b)
+        | ExprKind::Lt(a, b)
+        | ExprKind::Lte(a, b)
+        | ExprKind::Gte(a, b)
+        | ExprKind::Gt(a, b)
+        | ExprKind::Add(a, b)
+        | ExprKind::Sub(a, b)
+        | ExprKind::Mul(a, b)
+        | ExprKind::Div(a, b)
+        | ExprKind::Mod(a, b)
+        | ExprKind::Index(a, b)
+        | ExprKind::OptionalIndex(a, b) => is_constant(a) && is_constant(b),
+        ExprKind::Neg(a)
+        | ExprKind::Not(a)
+        | ExprKind::Select(a, _)
+        | ExprKind::OptionalSelect(a, _) => is_constant(a),
+        ExprKind::Method(e, _, args) => is_constant(e) && args.iter().all(is_constant),
+        // Whether an optional element is kept is up to its list or map.
+        ExprKind::OptionalElement(_)
+        | ExprKind::Call(..)
+        | ExprKind::Lit(_)
+        | ExprKind::Ident(_) => false,
+    };
+    if !foldable {
+        return expr;
+    }
+    match evaluate(expr.clone()) {
+        Ok(v) => Expression {
+            id: expr.id,
+            kind: ExprKind::Lit(v),
+        },
+        Err(_) => expr,
+    }
+}
+
+pub(crate) fn is_constant(expr: &Expression) -> bool {
+    match &expr.kind {
+        ExprKind::Lit(Literal::List(xs)) => xs.iter().all(is_constant),
+        ExprKind::Lit(Literal::Map(entries)) => entries
+            .iter()
+            .all(|(k, v)| is_constant(k) && is_constant(v)),
+        ExprKind::Lit(_) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::optimize;
+    use crate::model::{ExprKind, Expression, Literal, MethodName};
+    use crate::parsers::parse;
+
+    fn assert_optimizes_to(input: &str, expected: &str) {
+        assert_eq!(
+            optimize(parse(input).unwrap()),
+            parse(expected).unwrap(),
+            "optimizing {}",
+            input
+        );
+    }
+
+    #[test]
+    fn folds_arithmetic() {
+        assert_optimizes_to("60 * 60 * 24", "86400");
+        assert_eq!(
+            optimize(parse("-(1 + 2)").unwrap()),
+            Expression::from(Literal::I64(-3))
+        );
+        assert_optimizes_to("2.0 * 1.5", "3.0");
+        assert_optimizes_to("x + (1 + 2)", "x + 3");
+        assert_optimizes_to("x + 1 + 2", "x + 1 + 2");
+    }
+
+    #[test]
+    fn folds_strings_and_methods() {
+        assert_optimizes_to(r#" "prefix" + "/" "#, r#" "prefix/" "#);
+        assert_optimizes_to("[1, 2, 3].len()", "3");
+        assert_optimizes_to("[1, 2, 3].contains(2)", "true");
+        assert_optimizes_to("{'a': 1 + 1}['a']", "2");
+        assert_optimizes_to("[x, 1 + 1].len()", "[x, 2].len()");
+        assert_optimizes_to("x == 1 + 1", "x == 2");
+    }
+
+    #[test]
+    fn keeps_expressions_that_fail() {
+        assert_optimizes_to("1 / 0", "1 / 0");
+        assert_optimizes_to("(1 / 0) + (2 + 2)", "(1 / 0) + 4");
+        assert_optimizes_to("'a' + 1", "'a' + 1");
+        assert_optimizes_to("[1][3]", "[1][3]");
+    }
+
+    #[test]
+    fn keeps_arithmetic_that_overflows() {
+        for input in [
+            "1 % 0",
+            "9223372036854775807 + 1",
+            "9223372036854775807 * 2",
+            "2.pow(100)",
+        ]
+        .iter()
+        {
+            assert_optimizes_to(input, input);
+        }
+        // The operands still fold, to values that have no literal syntax.
+        let int = |x| Box::new(Expression::from(Literal::I64(x)));
+        assert_eq!(
+            optimize(parse("(-9223372036854775807 - 1) / -1").unwrap()),
+            Expression::new(ExprKind::Div(int(i64::MIN), int(-1)))
+        );
+        assert_eq!(
+            optimize(parse("-(-9223372036854775807 - 1)").unwrap()),
+            Expression::new(ExprKind::Neg(int(i64::MIN)))
+        );
+        assert_eq!(
+            optimize(parse("2.pow(-1)").unwrap()),
+            Expression::new(ExprKind::Method(int(2), MethodName::Pow, vec![*int(-1)]))
+        );
+    }
+
+    #[test]
+    fn simplifies_negation() {
+        assert_optimizes_to("!!x", "x");
+        assert_optimizes_to("!!!x", "!x");
+        assert_optimizes_to("!(1 < 2)", "false");
+    }
+
+    #[test]
+    fn prunes_logical_operators() {
+        assert_optimizes_to("x && true", "x");
+        assert_optimizes_to("true && x", "x");
+        assert_optimizes_to("x && 1 > 2", "false");
+        assert_optimizes_to("x || 1 < 2", "true");
+        assert_optimizes_to("false || x", "x");
+        assert_optimizes_to("x && y", "x && y");
+        assert_optimizes_to("1 / 0 == 1 && false", "false");
+        assert_optimizes_to("1 / 0 == 1 && true", "1 / 0 == 1");
+    }
+
+    #[test]
+    fn prunes_conditionals() {
+        assert_optimizes_to("1 < 2 ? x : y", "x");
+        assert_optimizes_to("1 > 2 ? x : y", "y");
+        assert_optimizes_to("x ? 1 + 1 : 2 + 2", "x ? 2 : 4");
+        assert_optimizes_to("1 ? x : y", "1 ? x : y");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::model::{Expression, Literal, MethodName};
+use crate::model::{ExprKind, Expression, Literal, Location, MethodName, SourceInfo};
 
 use pest::iterators::Pair;
 use pest::Parser;
@@ -10,126 +10,502 @@ use std::convert::TryFrom;
 #[grammar = "cel.pest"]
 struct CelParser;
 
+/// Limits on the input accepted by `parse_with_options`. They bound how deep
+/// the parser recurses and how large the resulting tree is, so that every
+/// recursive pass over it is safe to run on untrusted input.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParserOptions {
+    /// The maximum nesting of brackets, unary operators and conditionals in
+    /// the source, and the maximum depth of the resulting tree.
+    pub max_recursion_depth: usize,
+    /// The maximum length of the source, in Unicode code points.
+    pub max_code_points: usize,
+    /// The maximum number of nodes in the resulting tree.
+    pub max_nodes: usize,
+}
+
+impl Default for ParserOptions {
+    fn default() -> ParserOptions {
+        ParserOptions {
+            max_recursion_depth: 100,
+            max_code_points: 100_000,
+            max_nodes: 10_000,
+        }
+    }
+}
+
 pub fn parse(input: &str) -> Result<Expression, String> {
+    parse_with_options(input, &ParserOptions::default())
+}
+
+pub fn parse_with_options(input: &str, options: &ParserOptions) -> Result<Expression, String> {
+    parse_with_source_info(input, options).map(|(expr, _)| expr)
+}
+
+/// Parses `input`, returning the location in `input` of every node of the
+/// resulting tree alongside it.
+pub fn parse_with_source_info(
+    input: &str,
+    options: &ParserOptions,
+) -> Result<(Expression, SourceInfo), String> {
+    let code_points = input.chars().count();
+    if code_points > options.max_code_points {
+        return Err(format!(
+            "expression has {} code points, exceeding the limit of {}",
+            code_points, options.max_code_points
+        ));
+    }
+    let depth_exceeded = || {
+        format!(
+            "expression exceeds the maximum recursion depth of {}",
+            options.max_recursion_depth
+        )
+    };
+    if nesting(input) > options.max_recursion_depth {
+        return Err(depth_exceeded());
+    }
     let mut parsed =
         CelParser::parse(Rule::Expression, input).map_err(|err| format!("{:?}", err))?;
-    Ok(extract_expression(parsed.next().unwrap()))
+    let pair = parsed.next().unwrap();
+    let size = measure(pair.clone());
+    if size.depth > options.max_recursion_depth {
+        return Err(depth_exceeded());
+    }
+    if size.nodes > options.max_nodes {
+        return Err(format!(
+            "expression has {} nodes, exceeding the limit of {}",
+            size.nodes, options.max_nodes
+        ));
+    }
+    let mut nodes = Nodes::new(input);
+    let expr = extract_expression(pair, &mut nodes);
+    Ok((expr, nodes.info))
+}
+
+/// An upper bound on how deeply the parser recurses on `input`: the nesting
+/// of brackets, plus the conditionals and runs of unary operators within them.
+fn nesting(input: &str) -> usize {
+    let mut max = 0;
+    // The nesting contributed by each open bracket and the conditionals in it.
+    let mut levels = vec![0];
+    let mut depth = 0;
+    let mut unary = 0;
+    let mut quote = None;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            if c == '\\' {
+                chars.next();
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '/' if chars.peek() == Some(&'/') => {
+                chars.find(|&c| c == '\n');
+                continue;
+            }
+            '(' | '[' | '{' => {
+                levels.push(1);
+                depth += 1;
+            }
+            ')' | ']' | '}' if levels.len() > 1 => depth -= levels.pop().unwrap(),
+            '?' => {
+                *levels.last_mut().unwrap() += 1;
+                depth += 1;
+            }
+            '-' | '!' => {
+                unary += 1;
+                max = max.max(depth + unary);
+                continue;
+            }
+            ' ' | '\t' | '\r' | '\n' => continue,
+            _ => {}
+        }
+        unary = 0;
+        max = max.max(depth);
+    }
+    max
+}
+
+struct TreeSize {
+    depth: usize,
+    nodes: usize,
+}
+
+/// Computes the depth and node count of the tree that `extract_*` would build
+/// from `pair`, without building it.
+fn measure(pair: Pair<Rule>) -> TreeSize {
+    match pair.as_rule() {
+        Rule::Expression | Rule::Literal | Rule::Index | Rule::OptionalIndex => {
+            measure(pair.into_inner().next().unwrap())
+        }
+        Rule::OptionalElement => wrapped(measure(pair.into_inner().next().unwrap())),
+        Rule::Conditional | Rule::ListLiteral | Rule::MapLiteral => {
+            let rule = pair.as_rule();
+            let children: Vec<TreeSize> = pair
+                .into_inner()
+                .flat_map(|p| match p.as_rule() {
+                    Rule::MapEntry => measure_entry(p),
+                    _ => vec![measure(p)],
+                })
+                .collect();
+            if rule == Rule::Conditional && children.len() == 1 {
+                return children.into_iter().next().unwrap();
+            }
+            TreeSize {
+                depth: 1 + children.iter().map(|c| c.depth).max().unwrap_or(0),
+                nodes: 1 + children.iter().map(|c| c.nodes).sum::<usize>(),
+            }
+        }
+        Rule::Or | Rule::And | Rule::Relation | Rule::Addition | Rule::Multiplication => {
+            // Chains of binary operators build left-deep trees.
+            let operands: Vec<TreeSize> = pair
+                .into_inner()
+                .filter(|p| !matches!(p.as_rule(), Rule::RelOp | Rule::AddOp | Rule::MulOp))
+                .map(measure)
+                .collect();
+            let k = operands.len() - 1;
+            TreeSize {
+                depth: operands
+                    .iter()
+                    .enumerate()
+                    .map(|(i, o)| o.depth + k - i.saturating_sub(1))
+                    .max()
+                    .unwrap(),
+                nodes: k + operands.iter().map(|o| o.nodes).sum::<usize>(),
+            }
+        }
+        Rule::Unary => {
+            let mut pairs = pair.into_inner();
+            let a = pairs.next().unwrap();
+            match a.as_rule() {
+                Rule::Member => measure(a),
+                _ => {
+                    let inner = measure(pairs.next().unwrap());
+                    TreeSize {
+                        depth: inner.depth + 1,
+                        nodes: inner.nodes + 1,
+                    }
+                }
+            }
+        }
+        Rule::Member => {
+            let mut pairs = pair.into_inner();
+            let mut size = measure(pairs.next().unwrap());
+            while let Some(p) = pairs.next() {
+                let operands: Vec<TreeSize> = match p.as_rule() {
+                    Rule::Identifier => pairs.next().unwrap().into_inner().map(measure).collect(),
+                    Rule::Field | Rule::OptionalField => vec![],
+                    _ => vec![measure(p)],
+                };
+                size = TreeSize {
+                    depth: 1 + operands
+                        .iter()
+                        .map(|o| o.depth)
+                        .fold(size.depth, usize::max),
+                    nodes: 1 + size.nodes + operands.iter().map(|o| o.nodes).sum::<usize>(),
+                };
+            }
+            size
+        }
+        Rule::Call => {
+            let mut pairs = pair.into_inner();
+            pairs.next();
+            let operands: Vec<TreeSize> = pairs.next().unwrap().into_inner().map(measure).collect();
+            TreeSize {
+                depth: 1 + operands.iter().map(|o| o.depth).max().unwrap_or(0),
+                nodes: 1 + operands.iter().map(|o| o.nodes).sum::<usize>(),
+            }
+        }
+        _ => TreeSize { depth: 1, nodes: 1 },
+    }
+}
+
+/// The sizes of the key and value of a map entry, the value of an optional
+/// entry being wrapped in an `OptionalElement`.
+fn measure_entry(pair: Pair<Rule>) -> Vec<TreeSize> {
+    let mut pairs = pair.into_inner().peekable();
+    let optional = pairs
+        .next_if(|p| p.as_rule() == Rule::OptionalKey)
+        .is_some();
+    let k = measure(pairs.next().unwrap());
+    let v = measure(pairs.next().unwrap());
+    vec![k, if optional { wrapped(v) } else { v }]
+}
+
+/// The size of a node with a single operand of size `operand`.
+fn wrapped(operand: TreeSize) -> TreeSize {
+    TreeSize {
+        depth: operand.depth + 1,
+        nodes: operand.nodes + 1,
+    }
+}
+
+/// Assigns ids to nodes as they are built, and records where each came from.
+struct Nodes<'i> {
+    input: &'i str,
+    /// The byte offset at which each line starts.
+    line_starts: Vec<usize>,
+    info: SourceInfo,
+}
+
+impl<'i> Nodes<'i> {
+    fn new(input: &'i str) -> Nodes<'i> {
+        let mut line_starts = vec![0];
+        line_starts.extend(input.match_indices('\n').map(|(i, _)| i + 1));
+        let mut info = SourceInfo::default();
+        let mut code_points = 0;
+        for c in input.chars() {
+            code_points += 1;
+            if c == '\n' {
+                info.line_offsets.push(code_points);
+            }
+        }
+        info.line_offsets.push(code_points + 1);
+        Nodes {
+            input,
+            line_starts,
+            info,
+        }
+    }
+
+    fn node(&mut self, offset: usize, kind: ExprKind) -> Expression {
+        let id = self.info.locations.len() as u64 + 1;
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let line_start = self.line_starts[line];
+        let location = Location {
+            offset,
+            line: line + 1,
+            column: self.input[line_start..offset].chars().count() + 1,
+        };
+        self.info.locations.insert(id, location);
+        Expression { id, kind }
+    }
+
+    /// The offset of the operator that follows an operand ending at `end`.
+    fn operator(&self, end: usize) -> usize {
+        let mut rest = &self.input[end..];
+        loop {
+            rest = rest.trim_start_matches(&[' ', '\t', '\r', '\n'][..]);
+            if !rest.starts_with("//") {
+                return self.input.len() - rest.len();
+            }
+            rest = rest.find('\n').map_or("", |i| &rest[i..]);
+        }
+    }
 }
 
-fn extract_expression(pair: Pair<Rule>) -> Expression {
+fn extract_expression(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
     assert_eq!(pair.as_rule(), Rule::Expression);
-    extract_relation(pair.into_inner().next().unwrap())
+    extract_conditional(pair.into_inner().next().unwrap(), nodes)
 }
 
-fn extract_relation(pair: Pair<Rule>) -> Expression {
+fn extract_conditional(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
+    assert_eq!(pair.as_rule(), Rule::Conditional);
+    let mut pairs = pair.into_inner();
+    let cond = pairs.next().unwrap();
+    let offset = nodes.operator(cond.as_span().end());
+    let cond = extract_or(cond, nodes);
+    match pairs.next() {
+        None => cond,
+        Some(a) => {
+            let a = extract_or(a, nodes);
+            let b = extract_conditional(pairs.next().unwrap(), nodes);
+            let kind = ExprKind::Conditional(Box::new(cond), Box::new(a), Box::new(b));
+            nodes.node(offset, kind)
+        }
+    }
+}
+
+fn extract_or(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
+    assert_eq!(pair.as_rule(), Rule::Or);
+    let mut pairs = pair.into_inner();
+    let first = pairs.next().unwrap();
+    let mut end = first.as_span().end();
+    let mut a = extract_and(first, nodes);
+    for p in pairs {
+        let offset = nodes.operator(end);
+        end = p.as_span().end();
+        let kind = ExprKind::Or(Box::new(a), Box::new(extract_and(p, nodes)));
+        a = nodes.node(offset, kind);
+    }
+    a
+}
+
+fn extract_and(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
+    assert_eq!(pair.as_rule(), Rule::And);
+    let mut pairs = pair.into_inner();
+    let first = pairs.next().unwrap();
+    let mut end = first.as_span().end();
+    let mut a = extract_relation(first, nodes);
+    for p in pairs {
+        let offset = nodes.operator(end);
+        end = p.as_span().end();
+        let kind = ExprKind::And(Box::new(a), Box::new(extract_relation(p, nodes)));
+        a = nodes.node(offset, kind);
+    }
+    a
+}
+
+fn extract_relation(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
     assert_eq!(pair.as_rule(), Rule::Relation);
     let mut pairs = pair.into_inner();
-    let a = extract_addition(pairs.next().unwrap());
+    let a = extract_addition(pairs.next().unwrap(), nodes);
     match pairs.next() {
         None => a,
         Some(op) => {
             assert_eq!(op.as_rule(), Rule::RelOp);
-            let b = extract_addition(pairs.next().unwrap());
-            match op.as_str() {
-                "==" => Expression::Eq(Box::new(a), Box::new(b)),
-                "!=" => Expression::Neq(Box::new(a), Box::new(b)),
-                "<" => Expression::Lt(Box::new(a), Box::new(b)),
-                "<=" => Expression::Lte(Box::new(a), Box::new(b)),
-                ">=" => Expression::Gte(Box::new(a), Box::new(b)),
-                ">" => Expression::Gt(Box::new(a), Box::new(b)),
+            let b = extract_addition(pairs.next().unwrap(), nodes);
+            let kind = match op.as_str() {
+                "==" => ExprKind::Eq(Box::new(a), Box::new(b)),
+                "!=" => ExprKind::Neq(Box::new(a), Box::new(b)),
+                "<" => ExprKind::Lt(Box::new(a), Box::new(b)),
+                "<=" => ExprKind::Lte(Box::new(a), Box::new(b)),
+                ">=" => ExprKind::Gte(Box::new(a), Box::new(b)),
+                ">" => ExprKind::Gt(Box::new(a), Box::new(b)),
                 _ => unreachable!(),
-            }
+            };
+            nodes.node(op.as_span().start(), kind)
         }
     }
 }
 
-fn extract_addition(pair: Pair<Rule>) -> Expression {
+fn extract_addition(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
     assert_eq!(pair.as_rule(), Rule::Addition);
     let mut pairs = pair.into_inner();
-    let mut a = extract_multiplication(pairs.next().unwrap());
+    let mut a = extract_multiplication(pairs.next().unwrap(), nodes);
     while let Some(op) = pairs.next() {
         assert_eq!(op.as_rule(), Rule::AddOp);
-        let b = extract_multiplication(pairs.next().unwrap());
-        a = match op.as_str() {
-            "+" => Expression::Add(Box::new(a), Box::new(b)),
-            "-" => Expression::Sub(Box::new(a), Box::new(b)),
+        let b = extract_multiplication(pairs.next().unwrap(), nodes);
+        let kind = match op.as_str() {
+            "+" => ExprKind::Add(Box::new(a), Box::new(b)),
+            "-" => ExprKind::Sub(Box::new(a), Box::new(b)),
             _ => unreachable!(),
-        }
+        };
+        a = nodes.node(op.as_span().start(), kind);
     }
     a
 }
 
-fn extract_multiplication(pair: Pair<Rule>) -> Expression {
+fn extract_multiplication(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
     assert_eq!(pair.as_rule(), Rule::Multiplication);
     let mut pairs = pair.into_inner();
-    let mut a = extract_unary(pairs.next().unwrap());
+    let mut a = extract_unary(pairs.next().unwrap(), nodes);
     while let Some(op) = pairs.next() {
         assert_eq!(op.as_rule(), Rule::MulOp);
-        let b = extract_unary(pairs.next().unwrap());
-        a = match op.as_str() {
-            "*" => Expression::Mul(Box::new(a), Box::new(b)),
-            "/" => Expression::Div(Box::new(a), Box::new(b)),
-            "%" => Expression::Mod(Box::new(a), Box::new(b)),
+        let b = extract_unary(pairs.next().unwrap(), nodes);
+        let kind = match op.as_str() {
+            "*" => ExprKind::Mul(Box::new(a), Box::new(b)),
+            "/" => ExprKind::Div(Box::new(a), Box::new(b)),
+            "%" => ExprKind::Mod(Box::new(a), Box::new(b)),
             _ => unreachable!(),
-        }
+        };
+        a = nodes.node(op.as_span().start(), kind);
     }
     a
 }
 
-fn extract_unary(pair: Pair<Rule>) -> Expression {
+fn extract_unary(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
     assert_eq!(pair.as_rule(), Rule::Unary);
     let mut pairs = pair.into_inner();
     let a = pairs.next().unwrap();
     match a.as_rule() {
-        Rule::Member => extract_member(a),
+        Rule::Member => extract_member(a, nodes),
         Rule::UnaryOp => {
             assert_eq!(a.as_rule(), Rule::UnaryOp);
-            match a.as_str() {
-                "-" => Expression::Neg(Box::new(extract_unary(pairs.next().unwrap()))),
-                "!" => Expression::Not(Box::new(extract_unary(pairs.next().unwrap()))),
+            let e = Box::new(extract_unary(pairs.next().unwrap(), nodes));
+            let kind = match a.as_str() {
+                "-" => ExprKind::Neg(e),
+                "!" => ExprKind::Not(e),
                 _ => unreachable!(),
-            }
+            };
+            nodes.node(a.as_span().start(), kind)
         }
         _ => unreachable!(),
     }
 }
 
-fn extract_member(pair: Pair<Rule>) -> Expression {
+fn extract_member(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
     assert_eq!(pair.as_rule(), Rule::Member);
     let mut pairs = pair.into_inner();
     let a = pairs.next().unwrap();
+    let offset = a.as_span().start();
     let mut a = match a.as_rule() {
-        Rule::Literal => Expression::Lit(extract_literal(a)),
-        Rule::Relation => extract_relation(a),
+        Rule::Literal => {
+            let kind = ExprKind::Lit(extract_literal(a, nodes));
+            nodes.node(offset, kind)
+        }
+        Rule::Call => {
+            let mut pairs = a.into_inner();
+            let function = String::from(pairs.next().unwrap().as_str());
+            let args = extract_args(pairs.next().unwrap(), nodes);
+            nodes.node(offset, ExprKind::Call(function, args))
+        }
+        Rule::Identifier => nodes.node(offset, ExprKind::Ident(String::from(a.as_str()))),
+        Rule::Conditional => extract_conditional(a, nodes),
         _ => unreachable!(),
     };
-    while let Some(id) = pairs.next() {
-        let name = extract_method_name(&id);
-        let args = extract_args(pairs.next().unwrap());
-        a = Expression::Method(Box::new(a), name, args);
+    while let Some(p) = pairs.next() {
+        let offset = p.as_span().start();
+        let kind = match p.as_rule() {
+            Rule::Identifier => {
+                let name = extract_method_name(&p);
+                let args = extract_args(pairs.next().unwrap(), nodes);
+                ExprKind::Method(Box::new(a), name, args)
+            }
+            Rule::Field => ExprKind::Select(Box::new(a), String::from(p.as_str())),
+            Rule::OptionalField => {
+                let field = p.into_inner().next().unwrap().as_str();
+                ExprKind::OptionalSelect(Box::new(a), String::from(field))
+            }
+            Rule::Index => ExprKind::Index(Box::new(a), Box::new(extract_index(p, nodes))),
+            Rule::OptionalIndex => {
+                ExprKind::OptionalIndex(Box::new(a), Box::new(extract_index(p, nodes)))
+            }
+            _ => unreachable!(),
+        };
+        a = nodes.node(offset, kind);
     }
     a
 }
 
+fn extract_index(pair: Pair<Rule>, nodes: &mut Nodes) -> Expression {
+    assert!(matches!(pair.as_rule(), Rule::Index | Rule::OptionalIndex));
+    extract_conditional(pair.into_inner().next().unwrap(), nodes)
+}
+
 fn extract_method_name(pair: &Pair<Rule>) -> MethodName {
     assert_eq!(pair.as_rule(), Rule::Identifier);
     pair.as_str().parse::<MethodName>().unwrap()
 }
 
-fn extract_args(pair: Pair<Rule>) -> Vec<Expression> {
+fn extract_args(pair: Pair<Rule>, nodes: &mut Nodes) -> Vec<Expression> {
     assert_eq!(pair.as_rule(), Rule::Args);
-    pair.into_inner().map(extract_relation).collect()
+    pair.into_inner()
+        .map(|p| extract_conditional(p, nodes))
+        .collect()
 }
 
-fn extract_literal(pair: Pair<Rule>) -> Literal {
+fn extract_literal(pair: Pair<Rule>, nodes: &mut Nodes) -> Literal {
     assert_eq!(pair.as_rule(), Rule::Literal);
     let pair = pair.into_inner().next().unwrap();
     match pair.as_rule() {
         Rule::StringLiteral => Literal::String(extract_string(pair)),
         Rule::BytesLiteral => Literal::Bytes(extract_bytes(pair)),
         Rule::FloatLiteral => Literal::F64(pair.as_str().parse().unwrap()),
+        Rule::UintLiteral => {
+            let digits = pair.as_str();
+            Literal::U64(digits[..digits.len() - 1].parse().unwrap())
+        }
         Rule::IntLiteral => Literal::I64(pair.as_str().parse().unwrap()),
-        Rule::ListLiteral => extract_list(pair),
+        Rule::ListLiteral => extract_list(pair, nodes),
+        Rule::MapLiteral => extract_map(pair, nodes),
         Rule::BoolLiteral => Literal::Bool(pair.as_str().parse().unwrap()),
         _ => unreachable!(),
     }
@@ -169,11 +545,16 @@ fn unescape_sequence(pair: &Pair<Rule>) -> Unescaped {
         Rule::Escape => {
             let s = &pair.as_str()[1..];
             match &s[..1] {
-                "t" => Unescaped::Byte(b'\t'),
+                "a" => Unescaped::Byte(0x07),
+                "b" => Unescaped::Byte(0x08),
+                "f" => Unescaped::Byte(0x0C),
                 "n" => Unescaped::Byte(b'\n'),
-                "\"" => Unescaped::Byte(b'"'),
-                "x" => Unescaped::Byte(u8::from_str_radix(&s[1..], 16).unwrap()),
-                "u" => Unescaped::Unicode(
+                "r" => Unescaped::Byte(b'\r'),
+                "t" => Unescaped::Byte(b'\t'),
+                "v" => Unescaped::Byte(0x0B),
+                "\\" | "?" | "\"" | "'" | "`" => Unescaped::Byte(s.as_bytes()[0]),
+                "x" | "X" => Unescaped::Byte(u8::from_str_radix(&s[1..], 16).unwrap()),
+                "u" | "U" => Unescaped::Unicode(
                     char::try_from(u32::from_str_radix(&s[1..], 16).unwrap()).unwrap(),
                 ),
                 "0" | "1" | "2" | "3" => Unescaped::Byte(u8::from_str_radix(s, 8).unwrap()),
@@ -184,15 +565,41 @@ fn unescape_sequence(pair: &Pair<Rule>) -> Unescaped {
     }
 }
 
-fn extract_list(pair: Pair<Rule>) -> Literal {
+fn extract_list(pair: Pair<Rule>, nodes: &mut Nodes) -> Literal {
     assert_eq!(pair.as_rule(), Rule::ListLiteral);
     let mut vs = Vec::new();
     for p in pair.into_inner() {
-        vs.push(extract_addition(p));
+        let v = match p.as_rule() {
+            Rule::OptionalElement => {
+                let offset = p.as_span().start();
+                let e = extract_addition(p.into_inner().next().unwrap(), nodes);
+                nodes.node(offset, ExprKind::OptionalElement(Box::new(e)))
+            }
+            _ => extract_addition(p, nodes),
+        };
+        vs.push(v);
     }
     Literal::List(vs)
 }
 
+fn extract_map(pair: Pair<Rule>, nodes: &mut Nodes) -> Literal {
+    assert_eq!(pair.as_rule(), Rule::MapLiteral);
+    let mut entries = Vec::new();
+    for p in pair.into_inner() {
+        assert_eq!(p.as_rule(), Rule::MapEntry);
+        let mut kv = p.into_inner().peekable();
+        let optional = kv.next_if(|p| p.as_rule() == Rule::OptionalKey);
+        let k = extract_addition(kv.next().unwrap(), nodes);
+        let mut v = extract_addition(kv.next().unwrap(), nodes);
+        if let Some(marker) = optional {
+            let kind = ExprKind::OptionalElement(Box::new(v));
+            v = nodes.node(marker.as_span().start(), kind);
+        }
+        entries.push((k, v));
+    }
+    Literal::Map(entries)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -210,10 +617,10 @@ mod test {
 
     fn literal(x: &dyn Any) -> Expression {
         if let Some(&s) = x.downcast_ref::<&str>() {
-            return Expression::Lit(Literal::String(String::from(s)));
+            return Expression::from(Literal::String(String::from(s)));
         }
         if let Some(&b) = x.downcast_ref::<&[u8]>() {
-            return Expression::Lit(Literal::Bytes(b.to_vec()));
+            return Expression::from(Literal::Bytes(b.to_vec()));
         }
         unimplemented!("literal of type {:?}", x.type_id())
     }
@@ -232,18 +639,40 @@ mod test {
         assert_invalid("3.");
     }
 
+    #[test]
+    fn relations() {
+        let one = || Box::new(Expression::from(Literal::I64(1)));
+        assert_eq!(
+            parse("1 < 1"),
+            Ok(Expression::new(ExprKind::Lt(one(), one())))
+        );
+        assert_eq!(
+            parse("1 <= 1"),
+            Ok(Expression::new(ExprKind::Lte(one(), one())))
+        );
+        assert_eq!(
+            parse("1 > 1"),
+            Ok(Expression::new(ExprKind::Gt(one(), one())))
+        );
+        assert_eq!(
+            parse("1 >= 1"),
+            Ok(Expression::new(ExprKind::Gte(one(), one())))
+        );
+        assert_invalid("1 < 1 < 1");
+    }
+
     #[test]
     fn cel_smoke() {
         let input = "22 * (4 + 15)";
         assert_eq!(
             parse(input),
-            Ok(Expression::Mul(
-                Box::new(Expression::Lit(Literal::I64(22))),
-                Box::new(Expression::Add(
-                    Box::new(Expression::Lit(Literal::I64(4))),
-                    Box::new(Expression::Lit(Literal::I64(15))),
-                ))
-            ))
+            Ok(Expression::new(ExprKind::Mul(
+                Box::new(Expression::from(Literal::I64(22))),
+                Box::new(Expression::new(ExprKind::Add(
+                    Box::new(Expression::from(Literal::I64(4))),
+                    Box::new(Expression::from(Literal::I64(15))),
+                )))
+            )))
         );
     }
 
@@ -252,13 +681,13 @@ mod test {
         let input = "[0, '1', 2 + '3']";
         assert_eq!(
             parse(input),
-            Ok(Expression::Lit(Literal::List(vec![
-                Expression::Lit(Literal::I64(0)),
-                Expression::Lit(Literal::String(String::from("1"))),
-                Expression::Add(
-                    Box::new(Expression::Lit(Literal::I64(2))),
-                    Box::new(Expression::Lit(Literal::String(String::from("3")))),
-                )
+            Ok(Expression::from(Literal::List(vec![
+                Expression::from(Literal::I64(0)),
+                Expression::from(Literal::String(String::from("1"))),
+                Expression::new(ExprKind::Add(
+                    Box::new(Expression::from(Literal::I64(2))),
+                    Box::new(Expression::from(Literal::String(String::from("3")))),
+                ))
             ])))
         );
     }
@@ -268,7 +697,7 @@ mod test {
         let input = r#""asdf""#;
         assert_eq!(
             parse(input),
-            Ok(Expression::Lit(Literal::String(String::from("asdf"))))
+            Ok(Expression::from(Literal::String(String::from("asdf"))))
         );
     }
 
@@ -284,7 +713,7 @@ mod test {
         let input = r#""as\"df""#;
         assert_eq!(
             parse(input),
-            Ok(Expression::Lit(Literal::String(String::from("as\"df"))))
+            Ok(Expression::from(Literal::String(String::from("as\"df"))))
         );
     }
 
@@ -310,6 +739,7 @@ mod test {
     fn valid_hex_escapes() {
         assert_eq!(parse(r#" "\x00" "#).unwrap(), literal(&"\u{0000}"));
         assert_eq!(parse(r#" "\xFF" "#).unwrap(), literal(&"\u{00FF}"));
+        assert_eq!(parse(r#" "\XfF" "#).unwrap(), literal(&"\u{00FF}"));
     }
 
     #[test]
@@ -318,6 +748,33 @@ mod test {
         assert_eq!(parse(r#" "\u00FF" "#).unwrap(), literal(&"\u{00FF}"));
         assert_eq!(parse(r#" "\uFF00" "#).unwrap(), literal(&"\u{FF00}"));
         assert_eq!(parse(r#" "\uFFFF" "#).unwrap(), literal(&"\u{FFFF}"));
+        assert_eq!(parse(r#" "\U0001F600" "#).unwrap(), literal(&"\u{1F600}"));
+        assert_eq!(parse(r#" "\U0010FFFF" "#).unwrap(), literal(&"\u{10FFFF}"));
+        assert_invalid(r#" "\U00110000" "#);
+    }
+
+    #[test]
+    fn valid_simple_escapes() {
+        assert_eq!(
+            parse(r#" "\\ \? \" \' \` " "#).unwrap(),
+            literal(&"\\ ? \" ' ` ")
+        );
+        assert_eq!(
+            parse(r#" '\a\b\f\n\r\t\v' "#).unwrap(),
+            literal(&"\u{7}\u{8}\u{C}\n\r\t\u{B}")
+        );
+        assert_eq!(parse(r#" b'\\\'' "#).unwrap(), literal(&"\\'".as_bytes()));
+        assert_invalid(r#" "\d" "#);
+    }
+
+    #[test]
+    fn uint_literals() {
+        assert_eq!(parse("0u"), Ok(Expression::from(Literal::U64(0))));
+        assert_eq!(
+            parse("18446744073709551615U"),
+            Ok(Expression::from(Literal::U64(u64::MAX)))
+        );
+        assert_invalid("1.5u");
     }
 
     #[test]
@@ -332,4 +789,275 @@ mod test {
         assert_valid(r#" ([1] + [2]).len() "#);
         assert_valid(r#" ([1] + [2]).len().pow(2) "#);
     }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(
+            parse("x"),
+            Ok(Expression::new(ExprKind::Ident(String::from("x"))))
+        );
+        assert_eq!(
+            parse("request_size"),
+            Ok(Expression::new(ExprKind::Ident(String::from(
+                "request_size"
+            ))))
+        );
+        assert_eq!(
+            parse("trueish"),
+            Ok(Expression::new(ExprKind::Ident(String::from("trueish"))))
+        );
+        assert_eq!(parse("true"), Ok(Expression::from(Literal::Bool(true))));
+        assert_invalid("1x");
+    }
+
+    #[test]
+    fn cel_map() {
+        let input = "{'a': 1, 'b': x}";
+        assert_eq!(
+            parse(input),
+            Ok(Expression::from(Literal::Map(vec![
+                (literal(&"a"), Expression::from(Literal::I64(1))),
+                (
+                    literal(&"b"),
+                    Expression::new(ExprKind::Ident(String::from("x")))
+                ),
+            ])))
+        );
+        assert_eq!(parse("{}"), Ok(Expression::from(Literal::Map(vec![]))));
+    }
+
+    #[test]
+    fn index() {
+        assert_eq!(
+            parse("xs[0]"),
+            Ok(Expression::new(ExprKind::Index(
+                Box::new(Expression::new(ExprKind::Ident(String::from("xs")))),
+                Box::new(Expression::from(Literal::I64(0))),
+            )))
+        );
+        assert_valid(r#" {"a": [1, 2]}["a"][1].pow(2) "#);
+        assert_invalid("xs[]");
+    }
+
+    #[test]
+    fn select() {
+        let request = Box::new(Expression::new(ExprKind::Ident(String::from("request"))));
+        assert_eq!(
+            parse("request.auth.len()"),
+            Ok(Expression::new(ExprKind::Method(
+                Box::new(Expression::new(ExprKind::Select(
+                    request,
+                    String::from("auth")
+                ))),
+                MethodName::Len,
+                vec![],
+            )))
+        );
+        assert_valid(r#" {"a": {"b": 1}}.a.b "#);
+        assert_valid("xs[0].size");
+        assert_invalid("x.");
+        assert_invalid("x.1");
+
+        let (expr, info) = parse_with_source_info("a.b\n  .c", &ParserOptions::default()).unwrap();
+        let location = info.location(expr.id).unwrap();
+        assert_eq!((location.line, location.column), (2, 4));
+    }
+
+    #[test]
+    fn optional_syntax() {
+        let ident = |name: &str| Box::new(Expression::new(ExprKind::Ident(String::from(name))));
+        let optional = |e: Expression| Expression::new(ExprKind::OptionalElement(Box::new(e)));
+        assert_eq!(
+            parse("m.?a[?0]"),
+            Ok(Expression::new(ExprKind::OptionalIndex(
+                Box::new(Expression::new(ExprKind::OptionalSelect(
+                    ident("m"),
+                    String::from("a")
+                ))),
+                Box::new(Expression::from(Literal::I64(0))),
+            )))
+        );
+        assert_eq!(
+            parse("[?x, 1]"),
+            Ok(Expression::from(Literal::List(vec![
+                optional(*ident("x")),
+                Expression::from(Literal::I64(1)),
+            ])))
+        );
+        assert_eq!(
+            parse("{?'a': x, 'b': 1}"),
+            Ok(Expression::from(Literal::Map(vec![
+                (literal(&"a"), optional(*ident("x"))),
+                (literal(&"b"), Expression::from(Literal::I64(1))),
+            ])))
+        );
+        assert_invalid("m.?");
+        assert_invalid("m[?]");
+        assert_invalid("?x");
+        assert_invalid("{'a': ?x}");
+
+        let (expr, info) = parse_with_source_info("[1, ?x]", &ParserOptions::default()).unwrap();
+        let element = match &expr.kind {
+            ExprKind::Lit(Literal::List(xs)) => &xs[1],
+            _ => unreachable!(),
+        };
+        assert_eq!(info.location(element.id).unwrap().column, 5);
+    }
+
+    #[test]
+    fn logical_operators() {
+        let x = || Box::new(Expression::new(ExprKind::Ident(String::from("x"))));
+        let y = || Box::new(Expression::new(ExprKind::Ident(String::from("y"))));
+        let z = || Box::new(Expression::new(ExprKind::Ident(String::from("z"))));
+        assert_eq!(
+            parse("x || y && z"),
+            Ok(Expression::new(ExprKind::Or(
+                x(),
+                Box::new(Expression::new(ExprKind::And(y(), z())))
+            )))
+        );
+        assert_eq!(
+            parse("x && y && z"),
+            Ok(Expression::new(ExprKind::And(
+                Box::new(Expression::new(ExprKind::And(x(), y()))),
+                z()
+            )))
+        );
+        assert_valid("1 < 2 && 'a' == 'b' || !false");
+        assert_invalid("x &&");
+        assert_invalid("x & y");
+    }
+
+    #[test]
+    fn conditional() {
+        let x = || Box::new(Expression::new(ExprKind::Ident(String::from("x"))));
+        let y = || Box::new(Expression::new(ExprKind::Ident(String::from("y"))));
+        let z = || Box::new(Expression::new(ExprKind::Ident(String::from("z"))));
+        assert_eq!(
+            parse("x ? y : z"),
+            Ok(Expression::new(ExprKind::Conditional(x(), y(), z())))
+        );
+        assert_eq!(
+            parse("x ? y : x ? y : z"),
+            Ok(Expression::new(ExprKind::Conditional(
+                x(),
+                y(),
+                Box::new(Expression::new(ExprKind::Conditional(x(), y(), z())))
+            )))
+        );
+        assert_valid("(x ? [1] : [2])[0].pow(x ? 1 : 2)");
+        assert_invalid("x ? y");
+    }
+
+    #[test]
+    fn limits() {
+        let options = |max_recursion_depth, max_code_points, max_nodes| ParserOptions {
+            max_recursion_depth,
+            max_code_points,
+            max_nodes,
+        };
+        let depth_exceeded = |max| {
+            Err(format!(
+                "expression exceeds the maximum recursion depth of {}",
+                max
+            ))
+        };
+        let input = "1 + 2 + x.pow([3, {4: 5}][1][4])";
+        assert!(parse_with_options(input, &options(7, 32, 15)).is_ok());
+        assert_eq!(
+            parse_with_options(input, &options(6, 32, 15)),
+            depth_exceeded(6)
+        );
+        assert_eq!(
+            parse_with_options(input, &options(7, 31, 15)),
+            Err(String::from(
+                "expression has 32 code points, exceeding the limit of 31"
+            ))
+        );
+        assert_eq!(
+            parse_with_options(input, &options(7, 32, 14)),
+            Err(String::from(
+                "expression has 15 nodes, exceeding the limit of 14"
+            ))
+        );
+        assert_eq!(
+            parse_with_options("(((x)))", &options(2, 32, 12)),
+            depth_exceeded(2)
+        );
+        assert_eq!(
+            parse_with_options("--!x", &options(2, 32, 12)),
+            depth_exceeded(2)
+        );
+        assert!(parse_with_options("'(((' + '[[['", &options(2, 32, 12)).is_ok());
+    }
+
+    #[test]
+    fn untrusted_input() {
+        let depth_exceeded = Err(String::from(
+            "expression exceeds the maximum recursion depth of 100",
+        ));
+        let nested = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(parse(&nested).unwrap_err().contains("code points"));
+        let nested = format!("{}1{}", "[".repeat(10_000), "]".repeat(10_000));
+        assert_eq!(parse(&nested), depth_exceeded);
+        assert_eq!(parse(&"-".repeat(10_000)), depth_exceeded);
+        assert_eq!(parse(&"x ? y : ".repeat(1_000)), depth_exceeded);
+        assert_eq!(parse(&vec!["x"; 1_000].join(" + ")), depth_exceeded);
+        assert_eq!(
+            parse(&format!("x{}", ".len()".repeat(1_000))),
+            depth_exceeded
+        );
+        let wide = format!("[{}]", vec!["x"; 20_000].join(", "));
+        assert_eq!(
+            parse(&wide),
+            Err(String::from(
+                "expression has 20001 nodes, exceeding the limit of 10000"
+            ))
+        );
+    }
+
+    #[test]
+    fn source_info() {
+        let input = "xs[0] +\n  'é'.len() * -1";
+        let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+        let location = |e: &Expression| {
+            let location = info.location(e.id).unwrap();
+            (location.offset, location.line, location.column)
+        };
+        assert_eq!(location(&expr), (6, 1, 7));
+        let (index, product) = match &expr.kind {
+            ExprKind::Add(a, b) => (a, b),
+            _ => unreachable!(),
+        };
+        assert_eq!(location(index), (2, 1, 3));
+        assert_eq!(location(product), (21, 2, 13));
+        let (len, neg) = match &product.kind {
+            ExprKind::Mul(a, b) => (a, b),
+            _ => unreachable!(),
+        };
+        assert_eq!(location(len), (15, 2, 7));
+        assert_eq!(location(neg), (23, 2, 15));
+        let (receiver, ids) = match &len.kind {
+            ExprKind::Method(e, _, _) => (e, [expr.id, index.id, product.id, len.id, e.id]),
+            _ => unreachable!(),
+        };
+        assert_eq!(location(receiver), (10, 2, 3));
+        let mut unique = ids.to_vec();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), ids.len());
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            parse("// leading\n1 +\t// 'quoted' too\r\n2 // trailing"),
+            parse("1 + 2")
+        );
+        assert_eq!(parse("'// kept'"), parse("\"// kept\""));
+        assert!(parse("// only a comment").is_err());
+        let input = "a // x\n&& b";
+        let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+        assert_eq!(info.location(expr.id).unwrap().offset, 7);
+    }
 }
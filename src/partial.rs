@@ -0,0 +1,712 @@
+//! Partial evaluation: evaluates as much of an expression as the attributes
+//! available so far allow, and produces the residual expression that is left
+//! to evaluate once the rest are known.
+//!
+//! An attribute is a variable or a chain of field selections from one, such
+//! as `request.auth.claims`. Attributes that are not yet available are marked
+//! unknown by pattern, and any value computed from one is itself unknown.
+
+use crate::analysis::path;
+use crate::interpreter::{
+    add, call_method, divide, equals, index, less, less_equals, logical_and, logical_or, lookup,
+    modulo, multiply, negate, not, optional_index, optional_select, select, subtract, Activation,
+    EvalResult, Functions, Macro,
+};
+use crate::model::{ExprKind, Expression, Literal};
+
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+/// A pattern matching attributes, e.g. `request.auth` or `request.headers.*`,
+/// in which a `*` field matches any field.
+///
+/// A pattern matches the attributes it is a prefix of, so `request.auth` also
+/// covers `request.auth.claims`, and the attributes that are a prefix of it,
+/// since the value of `request` is incomplete without `request.auth`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AttributePattern {
+    variable: String,
+    /// The fields selected in turn, with `None` for a wildcard.
+    fields: Vec<Option<String>>,
+}
+
+impl AttributePattern {
+    fn matches(&self, variable: &str, fields: &[&str]) -> bool {
+        self.variable == variable
+            && self
+                .fields
+                .iter()
+                .zip(fields)
+                .all(|(pattern, field)| pattern.as_deref().is_none_or(|p| p == *field))
+    }
+}
+
+impl FromStr for AttributePattern {
+    type Err = String;
+    fn from_str(s: &str) -> Result<AttributePattern, String> {
+        let mut parts = s.split('.');
+        let variable = parts.next().unwrap_or_default();
+        if variable.is_empty() || variable == "*" {
+            return Err(format!("invalid attribute pattern '{}'", s));
+        }
+        let mut fields = Vec::new();
+        for part in parts {
+            match part {
+                "" => return Err(format!("invalid attribute pattern '{}'", s)),
+                "*" => fields.push(None),
+                _ => fields.push(Some(String::from(part))),
+            }
+        }
+        Ok(AttributePattern {
+            variable: String::from(variable),
+            fields,
+        })
+    }
+}
+
+/// Variable bindings together with the attributes that are not yet known.
+#[derive(Debug, Clone, Default)]
+pub struct PartialActivation {
+    pub values: Activation,
+    pub unknowns: Vec<AttributePattern>,
+    /// The extension functions available to the expression.
+    pub functions: Functions,
+}
+
+impl PartialActivation {
+    pub fn new(values: Activation) -> PartialActivation {
+        PartialActivation {
+            values,
+            unknowns: Vec::new(),
+            functions: Functions::new(),
+        }
+    }
+
+    /// Marks the attributes matching `pattern` as unknown.
+    pub fn add_unknown(&mut self, pattern: &str) -> Result<(), String> {
+        self.unknowns.push(pattern.parse()?);
+        Ok(())
+    }
+
+    fn is_unknown(&self, variable: &str, fields: &[&str]) -> bool {
+        self.unknowns.iter().any(|p| p.matches(variable, fields))
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum PartialValue {
+    Known(Literal),
+    Unknown(Unknown),
+}
+
+/// A value that depends on attributes that are not yet known.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Unknown {
+    /// The unknown attributes the value was computed from, e.g.
+    /// `request.auth.claims`.
+    pub attributes: BTreeSet<String>,
+    /// What is left to evaluate once those attributes are available, with
+    /// every known subexpression already replaced by its value.
+    pub residual: Expression,
+}
+
+type PartialResult = Result<PartialValue, String>;
+
+/// Evaluates `expr` as far as the known attributes allow.
+///
+/// `&&` and `||` still short-circuit on a known operand, so `x && false` is
+/// `false` however `x` turns out, and `x && true` leaves just `x`, which like
+/// the optimizer assumes that `x` is a `bool`. An operand that fails next to
+/// an unknown one is kept as it was, so that its error is raised, or absorbed,
+/// when the residual is evaluated.
+pub fn evaluate_partial(expr: Expression, activation: &PartialActivation) -> PartialResult {
+    PartialEvaluator {
+        activation,
+        scopes: Vec::new(),
+    }
+    .eval(expr)
+}
+
+/// The expression left to evaluate once the unknown attributes are available,
+/// which is just a literal if the value did not depend on any of them.
+pub fn residual(expr: Expression, activation: &PartialActivation) -> Result<Expression, String> {
+    let id = expr.id;
+    match evaluate_partial(expr, activation)? {
+        PartialValue::Known(v) => Ok(literal(id, v)),
+        PartialValue::Unknown(unknown) => Ok(unknown.residual),
+    }
+}
+
+/// The operands of an operator: either all known, or the residuals of each
+/// once one of them turned out to be unknown.
+enum Operands {
+    Known(Vec<Literal>),
+    Unknown(Vec<Expression>, BTreeSet<String>),
+}
+
+struct PartialEvaluator<'a> {
+    activation: &'a PartialActivation,
+    /// The variables bound by the macros whose bodies are being evaluated,
+    /// innermost last, which shadow those of the activation and are always
+    /// known.
+    scopes: Vec<(String, Literal)>,
+}
+
+impl<'a> PartialEvaluator<'a> {
+    fn eval(&mut self, expr: Expression) -> PartialResult {
+        if let Some((variable, mut fields)) = path(&expr) {
+            fields.reverse();
+            let bound = self.scopes.iter().rev().find(|(var, _)| var == variable);
+            let bound = bound.map(|(_, value)| value.clone());
+            if bound.is_none() && self.activation.is_unknown(variable, &fields) {
+                let mut attribute = String::from(variable);
+                for field in fields {
+                    attribute.push('.');
+                    attribute.push_str(field);
+                }
+                return Ok(PartialValue::Unknown(Unknown {
+                    attributes: std::iter::once(attribute).collect(),
+                    residual: expr,
+                }));
+            }
+            // Resolve the whole path here, since its prefixes may match a
+            // pattern that the path itself does not.
+            let values = &self.activation.values;
+            let mut value = match bound {
+                Some(value) => value,
+                None => lookup(variable, values)?,
+            };
+            for field in fields {
+                value = select(value, field, values)?;
+            }
+            return Ok(PartialValue::Known(value));
+        }
+        let id = expr.id;
+        match expr.into_kind() {
+            ExprKind::Lit(Literal::List(xs)) => {
+                let mut attributes = BTreeSet::new();
+                let mut reduced = Vec::with_capacity(xs.len());
+                for x in xs {
+                    reduced.extend(self.element(x, &mut attributes)?);
+                }
+                Ok(collection(id, Literal::List(reduced), attributes))
+            }
+            ExprKind::Lit(Literal::Map(entries)) => {
+                let mut attributes = BTreeSet::new();
+                let mut reduced = Vec::with_capacity(entries.len());
+                for (k, v) in entries {
+                    let key_id = k.id;
+                    let k = match self.eval(k)? {
+                        PartialValue::Known(key) => literal(key_id, key),
+                        PartialValue::Unknown(unknown) => {
+                            attributes.extend(unknown.attributes);
+                            unknown.residual
+                        }
+                    };
+                    if let Some(v) = self.element(v, &mut attributes)? {
+                        reduced.push((k, v));
+                    }
+                }
+                Ok(collection(id, Literal::Map(reduced), attributes))
+            }
+            ExprKind::Lit(literal) => Ok(PartialValue::Known(literal)),
+            ExprKind::Ident(_) => unreachable!("identifiers are attributes"),
+            ExprKind::Conditional(cond, a, b) => match self.eval(*cond)? {
+                PartialValue::Known(Literal::Bool(true)) => self.eval(*a),
+                PartialValue::Known(Literal::Bool(false)) => self.eval(*b),
+                PartialValue::Known(_) => Err(String::from("invalid types")),
+                PartialValue::Unknown(unknown) => {
+                    let mut attributes = unknown.attributes;
+                    let a = self.reduce(*a, &mut attributes);
+                    let b = self.reduce(*b, &mut attributes);
+                    let kind =
+                        ExprKind::Conditional(Box::new(unknown.residual), Box::new(a), Box::new(b));
+                    Ok(partial(id, kind, attributes))
+                }
+            },
+            ExprKind::Or(a, b) => self.logical(id, *a, *b, true),
+            ExprKind::And(a, b) => self.logical(id, *a, *b, false),
+            ExprKind::Eq(a, b) => self.binary(id, *a, *b, equals, ExprKind::Eq),
+            ExprKind::Neq(a, b) => {
+                self.binary(id, *a, *b, |a, b| equals(a, b).and_then(not), ExprKind::Neq)
+            }
+            ExprKind::Lt(a, b) => self.binary(id, *a, *b, less, ExprKind::Lt),
+            ExprKind::Lte(a, b) => self.binary(id, *a, *b, less_equals, ExprKind::Lte),
+            ExprKind::Gte(a, b) => {
+                self.binary(id, *a, *b, |a, b| less(a, b).and_then(not), ExprKind::Gte)
+            }
+            ExprKind::Gt(a, b) => {
+                let gt = |a, b| less_equals(a, b).and_then(not);
+                self.binary(id, *a, *b, gt, ExprKind::Gt)
+            }
+            ExprKind::Add(a, b) => self.binary(id, *a, *b, add, ExprKind::Add),
+            ExprKind::Sub(a, b) => self.binary(id, *a, *b, subtract, ExprKind::Sub),
+            ExprKind::Mul(a, b) => self.binary(id, *a, *b, multiply, ExprKind::Mul),
+            ExprKind::Div(a, b) => self.binary(id, *a, *b, divide, ExprKind::Div),
+            ExprKind::Mod(a, b) => self.binary(id, *a, *b, modulo, ExprKind::Mod),
+            ExprKind::Index(a, b) => {
+                let values = &self.activation.values;
+                self.binary(id, *a, *b, |e, i| index(e, i, values), ExprKind::Index)
+            }
+            ExprKind::OptionalIndex(a, b) => {
+                let values = &self.activation.values;
+                let apply = |e, i| optional_index(e, i, values);
+                self.binary(id, *a, *b, apply, ExprKind::OptionalIndex)
+            }
+            ExprKind::OptionalElement(e) => self.unary(id, *e, Ok, ExprKind::OptionalElement),
+            ExprKind::Neg(e) => self.unary(id, *e, negate, ExprKind::Neg),
+            ExprKind::Not(e) => self.unary(id, *e, not, ExprKind::Not),
+            ExprKind::Select(e, field) => {
+                let values = &self.activation.values;
+                let apply = |e| select(e, &field, values);
+                self.unary(id, *e, apply, |e| ExprKind::Select(e, field.clone()))
+            }
+            ExprKind::OptionalSelect(e, field) => {
+                let values = &self.activation.values;
+                let apply = |e| optional_select(e, &field, values);
+                self.unary(id, *e, apply, |e| {
+                    ExprKind::OptionalSelect(e, field.clone())
+                })
+            }
+            ExprKind::Method(e, name, args) => {
+                if let Some(function) = self.activation.functions.qualified(&e, &name) {
+                    return match self.operands(args)? {
+                        Operands::Known(values) => function(values).map(PartialValue::Known),
+                        Operands::Unknown(residuals, attributes) => {
+                            let kind = ExprKind::Method(e, name, residuals);
+                            Ok(partial(id, kind, attributes))
+                        }
+                    };
+                }
+                if let Some((function, var, body)) =
+                    self.activation.functions.macro_call(&name, &args)
+                {
+                    let (var, body) = (String::from(var), body.clone());
+                    let receiver_id = e.id;
+                    return match self.operands(vec![*e])? {
+                        Operands::Known(mut values) => {
+                            let e = values.pop().unwrap();
+                            let receiver = Box::new(literal(receiver_id, e.clone()));
+                            match self.bind(function, e, &var, &body) {
+                                (result, None) => result.map(PartialValue::Known),
+                                // The body depends on an unknown attribute for
+                                // some element, so the whole call is left to
+                                // evaluate, on the known receiver.
+                                (_, Some(attributes)) => Ok(partial(
+                                    id,
+                                    ExprKind::Method(receiver, name, args),
+                                    attributes,
+                                )),
+                            }
+                        }
+                        Operands::Unknown(mut residuals, attributes) => {
+                            let e = Box::new(residuals.pop().unwrap());
+                            Ok(partial(id, ExprKind::Method(e, name, args), attributes))
+                        }
+                    };
+                }
+                let operands = std::iter::once(*e).chain(args).collect();
+                match self.operands(operands)? {
+                    Operands::Known(values) => {
+                        let mut values = values.into_iter();
+                        let e = values.next().unwrap();
+                        let args = values.map(Ok).collect();
+                        let functions = &self.activation.functions;
+                        call_method(e, &name, args, &self.activation.values, functions)
+                            .map(PartialValue::Known)
+                    }
+                    Operands::Unknown(residuals, attributes) => {
+                        let mut residuals = residuals.into_iter();
+                        let e = Box::new(residuals.next().unwrap());
+                        let kind = ExprKind::Method(e, name, residuals.collect());
+                        Ok(partial(id, kind, attributes))
+                    }
+                }
+            }
+            ExprKind::Call(function, args) => {
+                let implementation = match self.activation.functions.global(&function) {
+                    Some(implementation) => implementation,
+                    None => return Err(format!("undeclared reference to '{}'", function)),
+                };
+                match self.operands(args)? {
+                    Operands::Known(values) => implementation(values).map(PartialValue::Known),
+                    Operands::Unknown(residuals, attributes) => {
+                        Ok(partial(id, ExprKind::Call(function, residuals), attributes))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Calls a macro, evaluating its `body` with `var` bound to each value the
+    /// macro gives it, and also returns the unknown attributes the body
+    /// depended on for any of them, in which case the result is meaningless.
+    fn bind(
+        &mut self,
+        implementation: Macro,
+        receiver: Literal,
+        var: &str,
+        body: &Expression,
+    ) -> (EvalResult, Option<BTreeSet<String>>) {
+        let mut unknown = None;
+        let result = implementation(receiver, &mut |value| {
+            self.scopes.push((String::from(var), value));
+            let result = self.eval(body.clone());
+            self.scopes.pop();
+            match result? {
+                PartialValue::Known(value) => Ok(value),
+                PartialValue::Unknown(body) => {
+                    unknown
+                        .get_or_insert_with(BTreeSet::new)
+                        .extend(body.attributes);
+                    Err(String::from("unknown"))
+                }
+            }
+        });
+        (result, unknown)
+    }
+
+    /// Evaluates `expr` as far as possible for use within a residual, adding
+    /// the unknown attributes it depends on to `attributes`. An expression
+    /// that fails is kept as it was.
+    fn reduce(&mut self, expr: Expression, attributes: &mut BTreeSet<String>) -> Expression {
+        let id = expr.id;
+        match self.eval(expr.clone()) {
+            Ok(PartialValue::Known(v)) => literal(id, v),
+            Ok(PartialValue::Unknown(unknown)) => {
+                attributes.extend(unknown.attributes);
+                unknown.residual
+            }
+            Err(_) => expr,
+        }
+    }
+
+    /// Reduces a list element or map value like `reduce`, unwrapping it if it
+    /// is optional, `?x`: `None` if it is known to be empty, and an error for
+    /// the whole collection if it fails.
+    fn element(
+        &mut self,
+        x: Expression,
+        attributes: &mut BTreeSet<String>,
+    ) -> Result<Option<Expression>, String> {
+        let id = x.id;
+        let e = match x.into_kind() {
+            ExprKind::OptionalElement(e) => e,
+            kind => return Ok(Some(self.reduce(Expression { id, kind }, attributes))),
+        };
+        match self.eval(*e)? {
+            PartialValue::Known(Literal::Optional(v)) => Ok(v.map(|v| literal(id, *v))),
+            PartialValue::Known(_) => Err(String::from("invalid types")),
+            PartialValue::Unknown(unknown) => {
+                attributes.extend(unknown.attributes);
+                let kind = ExprKind::OptionalElement(Box::new(unknown.residual));
+                Ok(Some(Expression { id, kind }))
+            }
+        }
+    }
+
+    /// Evaluates operands from left to right. A failure before any unknown
+    /// operand is an error, and one after is kept in the residual.
+    fn operands(&mut self, exprs: Vec<Expression>) -> Result<Operands, String> {
+        let mut known = Vec::with_capacity(exprs.len());
+        let mut unknown: Option<(Vec<Expression>, BTreeSet<String>)> = None;
+        for expr in exprs {
+            if let Some((residuals, attributes)) = &mut unknown {
+                residuals.push(self.reduce(expr, attributes));
+                continue;
+            }
+            let id = expr.id;
+            match self.eval(expr)? {
+                PartialValue::Known(v) => known.push((id, v)),
+                PartialValue::Unknown(u) => {
+                    let mut residuals: Vec<Expression> =
+                        known.drain(..).map(|(id, v)| literal(id, v)).collect();
+                    residuals.push(u.residual);
+                    unknown = Some((residuals, u.attributes));
+                }
+            }
+        }
+        Ok(match unknown {
+            Some((residuals, attributes)) => Operands::Unknown(residuals, attributes),
+            None => Operands::Known(known.into_iter().map(|(_, v)| v).collect()),
+        })
+    }
+
+    fn unary(
+        &mut self,
+        id: u64,
+        e: Expression,
+        apply: impl FnOnce(Literal) -> EvalResult,
+        rebuild: impl FnOnce(Box<Expression>) -> ExprKind,
+    ) -> PartialResult {
+        match self.operands(vec![e])? {
+            Operands::Known(mut values) => apply(values.remove(0)).map(PartialValue::Known),
+            Operands::Unknown(mut residuals, attributes) => {
+                let kind = rebuild(Box::new(residuals.remove(0)));
+                Ok(partial(id, kind, attributes))
+            }
+        }
+    }
+
+    fn binary(
+        &mut self,
+        id: u64,
+        a: Expression,
+        b: Expression,
+        apply: impl FnOnce(Literal, Literal) -> EvalResult,
+        rebuild: impl FnOnce(Box<Expression>, Box<Expression>) -> ExprKind,
+    ) -> PartialResult {
+        match self.operands(vec![a, b])? {
+            Operands::Known(values) => {
+                let mut values = values.into_iter();
+                let (a, b) = (values.next().unwrap(), values.next().unwrap());
+                apply(a, b).map(PartialValue::Known)
+            }
+            Operands::Unknown(residuals, attributes) => {
+                let mut residuals = residuals.into_iter();
+                let a = Box::new(residuals.next().unwrap());
+                let b = Box::new(residuals.next().unwrap());
+                Ok(partial(id, rebuild(a, b), attributes))
+            }
+        }
+    }
+
+    /// Evaluates `||` if `decisive` is true and `&&` otherwise: the operator
+    /// for which an operand equal to `decisive` decides the result.
+    fn logical(&mut self, id: u64, a: Expression, b: Expression, decisive: bool) -> PartialResult {
+        let (a_source, b_source) = (a.clone(), b.clone());
+        let a = self.eval(a);
+        if let Ok(PartialValue::Known(Literal::Bool(v))) = a {
+            if v == decisive {
+                return a;
+            }
+        }
+        let b = self.eval(b);
+        if let Ok(PartialValue::Known(Literal::Bool(v))) = b {
+            if v == decisive {
+                return b;
+            }
+        }
+        let is_unknown = |r: &PartialResult| matches!(r, Ok(PartialValue::Unknown(_)));
+        if !is_unknown(&a) && !is_unknown(&b) {
+            let (a, b) = (known(a), known(b));
+            let result = if decisive {
+                logical_or(a, b)
+            } else {
+                logical_and(a, b)
+            };
+            return result.map(PartialValue::Known);
+        }
+        let mut attributes = BTreeSet::new();
+        let a = remaining(a, a_source, decisive, &mut attributes);
+        let b = remaining(b, b_source, decisive, &mut attributes);
+        let kind = match (a, b) {
+            (Some(a), Some(b)) if decisive => ExprKind::Or(Box::new(a), Box::new(b)),
+            (Some(a), Some(b)) => ExprKind::And(Box::new(a), Box::new(b)),
+            (Some(x), None) | (None, Some(x)) => {
+                return Ok(PartialValue::Unknown(Unknown {
+                    attributes,
+                    residual: x,
+                }))
+            }
+            (None, None) => unreachable!("one operand is unknown"),
+        };
+        Ok(partial(id, kind, attributes))
+    }
+}
+
+/// What an operand of `&&` or `||` contributes to the residual: nothing if it
+/// is the operator's identity, and its source if it failed.
+fn remaining(
+    result: PartialResult,
+    source: Expression,
+    decisive: bool,
+    attributes: &mut BTreeSet<String>,
+) -> Option<Expression> {
+    match result {
+        Ok(PartialValue::Known(Literal::Bool(v))) if v != decisive => None,
+        Ok(PartialValue::Known(v)) => Some(literal(source.id, v)),
+        Ok(PartialValue::Unknown(unknown)) => {
+            attributes.extend(unknown.attributes);
+            Some(unknown.residual)
+        }
+        Err(_) => Some(source),
+    }
+}
+
+fn known(result: PartialResult) -> EvalResult {
+    match result? {
+        PartialValue::Known(v) => Ok(v),
+        PartialValue::Unknown(_) => unreachable!("operand is known"),
+    }
+}
+
+fn literal(id: u64, v: Literal) -> Expression {
+    Expression {
+        id,
+        kind: ExprKind::Lit(v),
+    }
+}
+
+fn partial(id: u64, kind: ExprKind, attributes: BTreeSet<String>) -> PartialValue {
+    PartialValue::Unknown(Unknown {
+        attributes,
+        residual: Expression { id, kind },
+    })
+}
+
+/// A list or map is unknown if any of its elements are.
+fn collection(id: u64, value: Literal, attributes: BTreeSet<String>) -> PartialValue {
+    if attributes.is_empty() {
+        PartialValue::Known(value)
+    } else {
+        partial(id, ExprKind::Lit(value), attributes)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{evaluate_partial, residual, PartialActivation, PartialValue, Unknown};
+    use crate::interpreter::{evaluate, Activation};
+    use crate::model::Literal;
+    use crate::parsers::parse;
+
+    fn activation(unknowns: &[&str]) -> PartialActivation {
+        let mut values = Activation::new();
+        values.insert(String::from("one"), Literal::I64(1));
+        let request = evaluate(parse("{'path': '/admin', 'user': {'id': 7}}").unwrap()).unwrap();
+        values.insert(String::from("request"), request);
+        let mut activation = PartialActivation::new(values);
+        for pattern in unknowns {
+            activation.add_unknown(pattern).unwrap();
+        }
+        activation
+    }
+
+    fn assert_residual(input: &str, unknowns: &[&str], expected: &str) {
+        assert_eq!(
+            residual(parse(input).unwrap(), &activation(unknowns)),
+            Ok(parse(expected).unwrap()),
+            "residual of {}",
+            input
+        );
+    }
+
+    #[test]
+    fn known_values_evaluate_fully() {
+        assert_residual("one + 1", &["x"], "2");
+        assert_eq!(
+            evaluate_partial(parse("request.user.id").unwrap(), &activation(&[])),
+            Ok(PartialValue::Known(Literal::I64(7)))
+        );
+    }
+
+    #[test]
+    fn unknowns_propagate() {
+        assert_residual("x + (one + 2)", &["x"], "x + 3");
+        assert_residual("-x.pow(one + 1) * 2", &["x"], "-x.pow(2) * 2");
+        assert_residual("[x, one + 1].len()", &["x"], "[x, 2].len()");
+        assert_residual("{'a': one, x: 2}['a']", &["x"], "{'a': 1, x: 2}['a']");
+        assert_residual("one + 1 == x.y.z", &["x"], "2 == x.y.z");
+        assert_residual(
+            "[?request.?path, ?x.?a, ?request.?none]",
+            &["x"],
+            "['/admin', ?x.?a]",
+        );
+        assert_residual("{?'a': x[?one]}", &["x"], "{?'a': x[?1]}");
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_residual("x && one > 2", &["x"], "false");
+        assert_residual("one > 2 && x", &["x"], "false");
+        assert_residual("x || one == 1", &["x"], "true");
+        assert_residual("x && one == 1", &["x"], "x");
+        assert_residual("one > 2 || x", &["x"], "x");
+        assert_residual("x && y", &["x", "y"], "x && y");
+        assert_residual("x || y && one == 1", &["x", "y"], "x || y");
+    }
+
+    #[test]
+    fn errors() {
+        let failed = residual(parse("1 / 0 + x").unwrap(), &activation(&["x"]));
+        assert_eq!(failed, Err(String::from("divide by zero")));
+        assert_residual("x + 1 / 0", &["x"], "x + 1 / 0");
+        assert_residual("x || 1 / 0 == 1", &["x"], "x || 1 / 0 == 1");
+        assert_residual("1 / 0 == 1 && x", &["x"], "1 / 0 == 1 && x");
+        assert_residual("1 / 0 == 1 && x && false", &["x"], "false");
+    }
+
+    #[test]
+    fn global_functions() {
+        let mut activation = activation(&["x"]);
+        activation
+            .functions
+            .add_global("twice", |args| match args.as_slice() {
+                [Literal::I64(n)] => Ok(Literal::I64(n * 2)),
+                _ => Err(String::from("invalid types")),
+            });
+        let reduce = |input| residual(parse(input).unwrap(), &activation);
+        assert_eq!(reduce("twice(one) + x"), Ok(parse("2 + x").unwrap()));
+        assert_eq!(reduce("twice(x + one)"), Ok(parse("twice(x + 1)").unwrap()));
+        assert_eq!(
+            reduce("thrice(x)"),
+            Err(String::from("undeclared reference to 'thrice'"))
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_residual("x ? one + 1 : 2 * 3", &["x"], "x ? 2 : 6");
+        assert_residual("one == 1 ? x : 1 / 0", &["x"], "x");
+        assert_residual("x ? 1 / 0 : y", &["x", "y"], "x ? 1 / 0 : y");
+    }
+
+    #[test]
+    fn attribute_patterns() {
+        let input = "request.auth.claims.email == 'a' && request.path == '/admin'";
+        assert_residual(input, &["request.auth"], "request.auth.claims.email == 'a'");
+        assert_residual(
+            "request.user.id + 1",
+            &["request.*.id"],
+            "request.user.id + 1",
+        );
+        assert_residual("request.user.id + 1", &["request.*.name"], "8");
+        assert_residual("request.path", &["request.user"], "'/admin'");
+        assert_residual("request['path']", &["request.user"], "request['path']");
+        assert_eq!(
+            evaluate_partial(
+                parse("request.auth.claims.email == x").unwrap(),
+                &activation(&["request.auth", "x"])
+            ),
+            Ok(PartialValue::Unknown(Unknown {
+                attributes: ["request.auth.claims.email", "x"]
+                    .iter()
+                    .map(|s| String::from(*s))
+                    .collect(),
+                residual: parse("request.auth.claims.email == x").unwrap(),
+            }))
+        );
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let mut activation = PartialActivation::default();
+        assert!(activation.add_unknown("").is_err());
+        assert!(activation.add_unknown("*.a").is_err());
+        assert!(activation.add_unknown("a..b").is_err());
+        assert!(activation.add_unknown("a.*.b").is_ok());
+    }
+
+    #[test]
+    fn ids_are_kept() {
+        let expr = parse("x + (1 + 2)").unwrap();
+        let reduced = residual(expr.clone(), &activation(&["x"])).unwrap();
+        assert_eq!(reduced.id, expr.id);
+        assert_eq!(reduced, parse("x + 3").unwrap());
+        assert_eq!(
+            residual(expr, &activation(&[])),
+            Err(String::from("undeclared reference to 'x'"))
+        );
+    }
+}
@@ -0,0 +1,705 @@
+//! Conversion to and from cel-go's `google.api.expr.v1alpha1` messages,
+//! behind the `proto` feature.
+//!
+//! Operators become calls to their CEL functions, e.g. `a + b` is a call to
+//! `_+_`, and maps become `CreateStruct` messages without a message name.
+//! As in cel-go, `a.?b` is a call to `_?._` with the field name as a string
+//! and `a[?b]` one to `_[?_]`. An optional list element has no id of its own
+//! in a message, so one read back has id 0.
+//! Reading a message fails on anything this crate cannot represent:
+//! presence tests, comprehensions, message construction, and calls to
+//! functions other than the operators and supported methods.
+//!
+//! cel-go records positions as code point offsets, whereas `Location::offset`
+//! counts bytes. Locations read from a message therefore have an `offset` in
+//! code points, which is only the byte offset for ASCII sources; their `line`
+//! and `column` are exact.
+
+pub mod v1alpha1;
+
+use crate::checker::{CheckedExpression, Type};
+use crate::model::{
+    walk_expression, ExprKind, Expression, Literal, Location, MethodName, SourceInfo, Visitor,
+};
+use v1alpha1::constant::ConstantKind;
+use v1alpha1::expr::create_struct::{entry::KeyKind, Entry};
+use v1alpha1::expr::{Call, CreateList, CreateStruct, Ident, Select};
+use v1alpha1::r#type::{AbstractType, ListType, MapType, PrimitiveType, TypeKind};
+use v1alpha1::{CheckedExpr, Constant, Empty, Expr, ParsedExpr, Reference};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+pub fn to_parsed_expr(expr: &Expression, info: &SourceInfo) -> ParsedExpr {
+    ParsedExpr {
+        expr: Some(to_expr(expr)),
+        source_info: Some(to_source_info(info)),
+    }
+}
+
+pub fn from_parsed_expr(parsed: &ParsedExpr) -> Result<(Expression, SourceInfo), String> {
+    let expr = parsed.expr.as_ref().ok_or("ParsedExpr has no expr")?;
+    let info = parsed.source_info.as_ref().map(from_source_info);
+    Ok((from_expr(expr)?, info.unwrap_or_default()))
+}
+
+/// Records the type of every node whose type is not `dyn`, a reference for
+/// every identifier, and a reference to the overloads of every call.
+pub fn to_checked_expr(checked: &CheckedExpression, info: &SourceInfo) -> CheckedExpr {
+    let mut nodes = PreOrder(Vec::new());
+    nodes.visit_expression(&checked.expr);
+    let mut type_map = HashMap::new();
+    let mut reference_map = HashMap::new();
+    for node in nodes.0 {
+        match checked.type_of(node.id) {
+            Some(Type::Dyn) | None => {}
+            Some(t) => {
+                type_map.insert(node.id as i64, to_type(t));
+            }
+        }
+        let overloads = checked.overloads_of(node.id);
+        if let ExprKind::Ident(name) = &node.kind {
+            let reference = Reference {
+                name: name.clone(),
+                ..Reference::default()
+            };
+            reference_map.insert(node.id as i64, reference);
+        } else if !overloads.is_empty() {
+            let reference = Reference {
+                overload_id: overloads.to_vec(),
+                ..Reference::default()
+            };
+            reference_map.insert(node.id as i64, reference);
+        }
+    }
+    CheckedExpr {
+        reference_map,
+        type_map,
+        source_info: Some(to_source_info(info)),
+        expr_version: String::new(),
+        expr: Some(to_expr(&checked.expr)),
+    }
+}
+
+/// Nodes missing from the `type_map` are taken to be `dyn`, and the overloads
+/// of each node are read from its reference, if any.
+pub fn from_checked_expr(checked: &CheckedExpr) -> Result<(CheckedExpression, SourceInfo), String> {
+    let expr = checked.expr.as_ref().ok_or("CheckedExpr has no expr")?;
+    let expr = from_expr(expr)?;
+    let mut nodes = PreOrder(Vec::new());
+    nodes.visit_expression(&expr);
+    let mut types = HashMap::new();
+    let mut overloads = HashMap::new();
+    for node in nodes.0 {
+        let t = match checked.type_map.get(&(node.id as i64)) {
+            Some(t) => from_type(t)?,
+            None => Type::Dyn,
+        };
+        types.insert(node.id, t);
+        match checked.reference_map.get(&(node.id as i64)) {
+            Some(reference) if !reference.overload_id.is_empty() => {
+                overloads.insert(node.id, reference.overload_id.clone());
+            }
+            _ => {}
+        }
+    }
+    let checked_expression = CheckedExpression {
+        expr,
+        types,
+        overloads,
+    };
+    let info = checked.source_info.as_ref().map(from_source_info);
+    Ok((checked_expression, info.unwrap_or_default()))
+}
+
+/// The nodes of `expr`, in pre-order.
+struct PreOrder<'a>(Vec<&'a Expression>);
+
+impl<'a> Visitor<'a> for PreOrder<'a> {
+    fn visit_expression(&mut self, expr: &'a Expression) {
+        self.0.push(expr);
+        walk_expression(self, expr);
+    }
+}
+
+pub fn to_expr(expr: &Expression) -> Expr {
+    use v1alpha1::expr::ExprKind as Kind;
+    let call = |function: &str, operands: &[&Expression]| Call {
+        target: None,
+        function: String::from(function),
+        args: operands.iter().map(|e| to_expr(e)).collect(),
+    };
+    let kind = match &expr.kind {
+        ExprKind::Conditional(cond, a, b) => Kind::CallExpr(Box::new(call("_?_:_", &[cond, a, b]))),
+        ExprKind::Or(a, b) => Kind::CallExpr(Box::new(call("_||_", &[a, b]))),
+        ExprKind::And(a, b) => Kind::CallExpr(Box::new(call("_&&_", &[a, b]))),
+        ExprKind::Eq(a, b) => Kind::CallExpr(Box::new(call("_==_", &[a, b]))),
+        ExprKind::Neq(a, b) => Kind::CallExpr(Box::new(call("_!=_", &[a, b]))),
+        ExprKind::Lt(a, b) => Kind::CallExpr(Box::new(call("_<_", &[a, b]))),
+        ExprKind::Lte(a, b) => Kind::CallExpr(Box::new(call("_<=_", &[a, b]))),
+        ExprKind::Gte(a, b) => Kind::CallExpr(Box::new(call("_>=_", &[a, b]))),
+        ExprKind::Gt(a, b) => Kind::CallExpr(Box::new(call("_>_", &[a, b]))),
+        ExprKind::Add(a, b) => Kind::CallExpr(Box::new(call("_+_", &[a, b]))),
+        ExprKind::Sub(a, b) => Kind::CallExpr(Box::new(call("_-_", &[a, b]))),
+        ExprKind::Mul(a, b) => Kind::CallExpr(Box::new(call("_*_", &[a, b]))),
+        ExprKind::Div(a, b) => Kind::CallExpr(Box::new(call("_/_", &[a, b]))),
+        ExprKind::Mod(a, b) => Kind::CallExpr(Box::new(call("_%_", &[a, b]))),
+        ExprKind::Neg(a) => Kind::CallExpr(Box::new(call("-_", &[a]))),
+        ExprKind::Not(a) => Kind::CallExpr(Box::new(call("!_", &[a]))),
+        ExprKind::Index(a, b) => Kind::CallExpr(Box::new(call("_[_]", &[a, b]))),
+        ExprKind::OptionalIndex(a, b) => Kind::CallExpr(Box::new(call("_[?_]", &[a, b]))),
+        ExprKind::OptionalSelect(e, field) => {
+            let field = Expression::from(Literal::String(field.clone()));
+            Kind::CallExpr(Box::new(call("_?._", &[e, &field])))
+        }
+        // Lists and maps mark their optional elements instead, so this is
+        // only reached for one outside of them.
+        ExprKind::OptionalElement(e) => return to_expr(e),
+        ExprKind::Method(e, name, args) => Kind::CallExpr(Box::new(Call {
+            target: Some(Box::new(to_expr(e))),
+            function: name.to_string(),
+            args: args.iter().map(to_expr).collect(),
+        })),
+        ExprKind::Call(function, args) => Kind::CallExpr(Box::new(Call {
+            target: None,
+            function: function.clone(),
+            args: args.iter().map(to_expr).collect(),
+        })),
+        ExprKind::Select(e, field) => Kind::SelectExpr(Box::new(Select {
+            operand: Some(Box::new(to_expr(e))),
+            field: field.clone(),
+            test_only: false,
+        })),
+        ExprKind::Ident(name) => Kind::IdentExpr(Ident { name: name.clone() }),
+        ExprKind::Lit(Literal::List(xs)) => Kind::ListExpr(CreateList {
+            elements: xs.iter().map(to_expr).collect(),
+            optional_indices: xs
+                .iter()
+                .enumerate()
+                .filter(|(_, x)| matches!(x.kind, ExprKind::OptionalElement(_)))
+                .map(|(i, _)| i as i32)
+                .collect(),
+        }),
+        ExprKind::Lit(Literal::Map(entries)) => Kind::StructExpr(CreateStruct {
+            message_name: String::new(),
+            entries: entries
+                .iter()
+                .map(|(k, v)| {
+                    // The entry carries the id of an optional value's marker.
+                    let optional = matches!(v.kind, ExprKind::OptionalElement(_));
+                    Entry {
+                        id: if optional { v.id as i64 } else { 0 },
+                        key_kind: Some(KeyKind::MapKey(to_expr(k))),
+                        value: Some(to_expr(v)),
+                        optional_entry: optional,
+                    }
+                })
+                .collect(),
+        }),
+        // The arguments have no ids of their own.
+        ExprKind::Lit(Literal::Optional(value)) => {
+            let (function, args) = match value {
+                Some(value) => ("of", vec![to_expr(&Expression::from((**value).clone()))]),
+                None => ("none", Vec::new()),
+            };
+            let optional = Expression::new(ExprKind::Ident(String::from("optional")));
+            Kind::CallExpr(Box::new(Call {
+                target: Some(Box::new(to_expr(&optional))),
+                function: String::from(function),
+                args,
+            }))
+        }
+        // The arguments have no ids of their own.
+        ExprKind::Lit(Literal::Opaque(value)) => {
+            let (function, args) = value.constructor();
+            Kind::CallExpr(Box::new(Call {
+                target: None,
+                function: String::from(function),
+                args: args
+                    .into_iter()
+                    .map(|arg| to_expr(&Expression::new(ExprKind::Lit(arg))))
+                    .collect(),
+            }))
+        }
+        ExprKind::Lit(literal) => Kind::ConstExpr(Constant {
+            constant_kind: Some(match literal {
+                Literal::Null => ConstantKind::NullValue(0),
+                Literal::Bool(b) => ConstantKind::BoolValue(*b),
+                Literal::I64(n) => ConstantKind::Int64Value(*n),
+                Literal::U64(n) => ConstantKind::Uint64Value(*n),
+                Literal::F64(n) => ConstantKind::DoubleValue(*n),
+                Literal::String(s) => ConstantKind::StringValue(s.clone()),
+                Literal::Bytes(b) => ConstantKind::BytesValue(b.clone()),
+                Literal::List(_) | Literal::Map(_) | Literal::Opaque(_) | Literal::Optional(_) => {
+                    unreachable!()
+                }
+            }),
+        }),
+    };
+    Expr {
+        id: expr.id as i64,
+        expr_kind: Some(kind),
+    }
+}
+
+pub fn from_expr(expr: &Expr) -> Result<Expression, String> {
+    use v1alpha1::expr::ExprKind as Kind;
+    let kind = match &expr.expr_kind {
+        Some(Kind::ConstExpr(constant)) => ExprKind::Lit(from_constant(constant)?),
+        Some(Kind::IdentExpr(ident)) => ExprKind::Ident(ident.name.clone()),
+        Some(Kind::CallExpr(call)) => from_call(call)?,
+        Some(Kind::ListExpr(list)) => {
+            let mut xs = Vec::with_capacity(list.elements.len());
+            for (i, x) in list.elements.iter().enumerate() {
+                let x = from_expr(x)?;
+                xs.push(if list.optional_indices.contains(&(i as i32)) {
+                    Expression::new(ExprKind::OptionalElement(Box::new(x)))
+                } else {
+                    x
+                });
+            }
+            ExprKind::Lit(Literal::List(xs))
+        }
+        Some(Kind::StructExpr(create)) => {
+            if !create.message_name.is_empty() {
+                return Err(format!(
+                    "creating messages of type '{}' is not supported",
+                    create.message_name
+                ));
+            }
+            let mut entries = Vec::new();
+            for entry in &create.entries {
+                let key = match &entry.key_kind {
+                    Some(KeyKind::MapKey(key)) => from_expr(key)?,
+                    _ => return Err(String::from("map entry has no key")),
+                };
+                let value = entry.value.as_ref().ok_or("map entry has no value")?;
+                let mut value = from_expr(value)?;
+                if entry.optional_entry {
+                    value = Expression {
+                        id: entry.id as u64,
+                        kind: ExprKind::OptionalElement(Box::new(value)),
+                    };
+                }
+                entries.push((key, value));
+            }
+            ExprKind::Lit(Literal::Map(entries))
+        }
+        Some(Kind::SelectExpr(select)) => {
+            if select.test_only {
+                return Err(format!(
+                    "testing for field '{}' is not supported",
+                    select.field
+                ));
+            }
+            let operand = select.operand.as_ref().ok_or("select has no operand")?;
+            ExprKind::Select(Box::new(from_expr(operand)?), select.field.clone())
+        }
+        Some(Kind::ComprehensionExpr(_)) => {
+            return Err(String::from("comprehensions are not supported"))
+        }
+        None => return Err(format!("expression {} has no kind", expr.id)),
+    };
+    Ok(Expression {
+        id: expr.id as u64,
+        kind,
+    })
+}
+
+fn from_call(call: &Call) -> Result<ExprKind, String> {
+    let mut args = call
+        .args
+        .iter()
+        .map(|arg| from_expr(arg).map(Box::new))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(target) = &call.target {
+        let name: MethodName = call.function.parse()?;
+        let args = args.into_iter().map(|arg| *arg).collect();
+        return Ok(ExprKind::Method(Box::new(from_expr(target)?), name, args));
+    }
+    let kind = match (call.function.as_str(), args.len()) {
+        ("_?_:_", 3) => {
+            let b = args.pop().unwrap();
+            let a = args.pop().unwrap();
+            ExprKind::Conditional(args.pop().unwrap(), a, b)
+        }
+        ("-_", 1) => ExprKind::Neg(args.pop().unwrap()),
+        ("!_", 1) => ExprKind::Not(args.pop().unwrap()),
+        ("_?._", 2) => {
+            let field = match args.pop().unwrap().into_kind() {
+                ExprKind::Lit(Literal::String(field)) => field,
+                _ => return Err(String::from("'_?._' takes a field name as a string")),
+            };
+            ExprKind::OptionalSelect(args.pop().unwrap(), field)
+        }
+        (function, 2) => {
+            let kind = match function {
+                "_||_" => ExprKind::Or,
+                "_&&_" => ExprKind::And,
+                "_==_" => ExprKind::Eq,
+                "_!=_" => ExprKind::Neq,
+                "_<_" => ExprKind::Lt,
+                "_<=_" => ExprKind::Lte,
+                "_>=_" => ExprKind::Gte,
+                "_>_" => ExprKind::Gt,
+                "_+_" => ExprKind::Add,
+                "_-_" => ExprKind::Sub,
+                "_*_" => ExprKind::Mul,
+                "_/_" => ExprKind::Div,
+                "_%_" => ExprKind::Mod,
+                "_[_]" => ExprKind::Index,
+                "_[?_]" => ExprKind::OptionalIndex,
+                _ if !is_operator(function) => return Ok(global(function, args)),
+                _ => return Err(format!("unsupported function '{}'", function)),
+            };
+            let b = args.pop().unwrap();
+            kind(args.pop().unwrap(), b)
+        }
+        (function, _) if is_operator(function) => {
+            return Err(format!("unsupported function '{}'", function))
+        }
+        (function, _) => global(function, args),
+    };
+    Ok(kind)
+}
+
+/// Whether `function` is named like an operator, such as `_in_`, rather
+/// than a global function.
+fn is_operator(function: &str) -> bool {
+    function.starts_with('_') || function.ends_with('_')
+}
+
+fn global(function: &str, args: impl IntoIterator<Item = Box<Expression>>) -> ExprKind {
+    ExprKind::Call(
+        String::from(function),
+        args.into_iter().map(|arg| *arg).collect(),
+    )
+}
+
+fn from_constant(constant: &Constant) -> Result<Literal, String> {
+    match &constant.constant_kind {
+        Some(ConstantKind::NullValue(_)) => Ok(Literal::Null),
+        Some(ConstantKind::BoolValue(b)) => Ok(Literal::Bool(*b)),
+        Some(ConstantKind::Int64Value(n)) => Ok(Literal::I64(*n)),
+        Some(ConstantKind::Uint64Value(n)) => Ok(Literal::U64(*n)),
+        Some(ConstantKind::DoubleValue(n)) => Ok(Literal::F64(*n)),
+        Some(ConstantKind::StringValue(s)) => Ok(Literal::String(s.clone())),
+        Some(ConstantKind::BytesValue(b)) => Ok(Literal::Bytes(b.clone())),
+        None => Err(String::from("constant has no value")),
+    }
+}
+
+fn to_source_info(info: &SourceInfo) -> v1alpha1::SourceInfo {
+    let positions = info
+        .locations
+        .iter()
+        .map(|(&id, location)| {
+            let line_start = match location.line {
+                1 => 0,
+                line => info.line_offsets.get(line - 2).copied().unwrap_or(0),
+            };
+            (id as i64, (line_start + location.column - 1) as i32)
+        })
+        .collect();
+    v1alpha1::SourceInfo {
+        line_offsets: info.line_offsets.iter().map(|&n| n as i32).collect(),
+        positions,
+        ..v1alpha1::SourceInfo::default()
+    }
+}
+
+fn from_source_info(info: &v1alpha1::SourceInfo) -> SourceInfo {
+    let line_offsets: Vec<usize> = info.line_offsets.iter().map(|&n| n as usize).collect();
+    let locations = info
+        .positions
+        .iter()
+        .map(|(&id, &position)| {
+            let position = position as usize;
+            let line = line_offsets.iter().take_while(|&&n| n <= position).count();
+            let line_start = if line == 0 { 0 } else { line_offsets[line - 1] };
+            let location = Location {
+                offset: position,
+                line: line + 1,
+                column: position - line_start + 1,
+            };
+            (id as u64, location)
+        })
+        .collect();
+    SourceInfo {
+        locations,
+        line_offsets,
+    }
+}
+
+fn to_type(t: &Type) -> v1alpha1::Type {
+    let primitive = |p: PrimitiveType| TypeKind::Primitive(p as i32);
+    let kind = match t {
+        Type::Null => TypeKind::Null(0),
+        Type::Bool => primitive(PrimitiveType::Bool),
+        Type::Int => primitive(PrimitiveType::Int64),
+        Type::Uint => primitive(PrimitiveType::Uint64),
+        Type::Double => primitive(PrimitiveType::Double),
+        Type::String => primitive(PrimitiveType::String),
+        Type::Bytes => primitive(PrimitiveType::Bytes),
+        Type::List(elem) => TypeKind::ListType(Box::new(ListType {
+            elem_type: Some(Box::new(to_type(elem))),
+        })),
+        Type::Map(key, value) => TypeKind::MapType(Box::new(MapType {
+            key_type: Some(Box::new(to_type(key))),
+            value_type: Some(Box::new(to_type(value))),
+        })),
+        Type::Dyn => TypeKind::Dyn(Empty {}),
+        Type::Param(name) => TypeKind::TypeParam(name.clone()),
+        Type::Error => TypeKind::Error(Empty {}),
+        Type::Opaque(name) => TypeKind::AbstractType(AbstractType {
+            name: name.clone(),
+            parameter_types: Vec::new(),
+        }),
+        Type::Optional(value) => TypeKind::AbstractType(AbstractType {
+            name: String::from("optional_type"),
+            parameter_types: vec![to_type(value)],
+        }),
+    };
+    v1alpha1::Type {
+        type_kind: Some(kind),
+    }
+}
+
+fn from_type(t: &v1alpha1::Type) -> Result<Type, String> {
+    let nested = |t: &Option<Box<v1alpha1::Type>>| match t {
+        Some(t) => from_type(t),
+        None => Ok(Type::Dyn),
+    };
+    match &t.type_kind {
+        Some(TypeKind::Null(_)) => Ok(Type::Null),
+        Some(TypeKind::Primitive(p)) => match PrimitiveType::try_from(*p) {
+            Ok(PrimitiveType::Bool) => Ok(Type::Bool),
+            Ok(PrimitiveType::Int64) => Ok(Type::Int),
+            Ok(PrimitiveType::Uint64) => Ok(Type::Uint),
+            Ok(PrimitiveType::Double) => Ok(Type::Double),
+            Ok(PrimitiveType::String) => Ok(Type::String),
+            Ok(PrimitiveType::Bytes) => Ok(Type::Bytes),
+            _ => Err(format!("unsupported primitive type {}", p)),
+        },
+        Some(TypeKind::ListType(list)) => Ok(Type::list(nested(&list.elem_type)?)),
+        Some(TypeKind::MapType(map)) => {
+            Ok(Type::map(nested(&map.key_type)?, nested(&map.value_type)?))
+        }
+        Some(TypeKind::Dyn(_)) | None => Ok(Type::Dyn),
+        Some(TypeKind::TypeParam(name)) => Ok(Type::param(name)),
+        Some(TypeKind::Error(_)) => Ok(Type::Error),
+        Some(TypeKind::AbstractType(t)) if t.parameter_types.is_empty() => {
+            Ok(Type::opaque(&t.name))
+        }
+        Some(TypeKind::AbstractType(t))
+            if t.name == "optional_type" && t.parameter_types.len() == 1 =>
+        {
+            Ok(Type::optional(from_type(&t.parameter_types[0])?))
+        }
+        Some(TypeKind::Wrapper(_))
+        | Some(TypeKind::WellKnown(_))
+        | Some(TypeKind::Function(_))
+        | Some(TypeKind::MessageType(_))
+        | Some(TypeKind::Type(_))
+        | Some(TypeKind::AbstractType(_)) => Err(format!("unsupported type {:?}", t)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::v1alpha1::{CheckedExpr, ParsedExpr};
+    use super::{from_checked_expr, from_parsed_expr, to_checked_expr, to_parsed_expr};
+    use crate::checker::{check, Declarations, Type};
+    use crate::interpreter::{evaluate_with, Activation};
+    use crate::model::Literal;
+    use crate::parsers::{parse_with_source_info, ParserOptions};
+    use prost::Message;
+    use serde_json::json;
+
+    #[test]
+    fn parsed_expr() {
+        let input = "x + 1\n  < 'é'.len()";
+        let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+        let parsed = to_parsed_expr(&expr, &info);
+        assert_eq!(
+            serde_json::to_value(&parsed).unwrap(),
+            json!({
+                "expr": {"id": "6", "callExpr": {"function": "_<_", "args": [
+                    {"id": "3", "callExpr": {"function": "_+_", "args": [
+                        {"id": "1", "identExpr": {"name": "x"}},
+                        {"id": "2", "constExpr": {"int64Value": "1"}},
+                    ]}},
+                    {"id": "5", "callExpr": {
+                        "target": {"id": "4", "constExpr": {"stringValue": "é"}},
+                        "function": "len",
+                    }},
+                ]}},
+                "sourceInfo": {
+                    "lineOffsets": [6, 20],
+                    "positions": {"1": 0, "2": 4, "3": 2, "4": 10, "5": 14, "6": 8},
+                },
+            })
+        );
+
+        let decoded = ParsedExpr::decode(&parsed.encode_to_vec()[..]).unwrap();
+        assert_eq!(decoded, parsed);
+        let json = serde_json::to_string(&parsed).unwrap();
+        assert_eq!(serde_json::from_str::<ParsedExpr>(&json).unwrap(), parsed);
+
+        let (read, read_info) = from_parsed_expr(&decoded).unwrap();
+        assert_eq!(read, expr);
+        assert_eq!(read.id, expr.id);
+        for id in 1..=6 {
+            let (a, b) = (info.location(id).unwrap(), read_info.location(id).unwrap());
+            assert_eq!((a.line, a.column), (b.line, b.column));
+        }
+        assert_eq!(read_info.location(5).unwrap().offset, 14);
+    }
+
+    #[test]
+    fn global_calls() {
+        let (expr, info) = parse_with_source_info("ip(x)", &ParserOptions::default()).unwrap();
+        let parsed = to_parsed_expr(&expr, &info);
+        assert_eq!(
+            serde_json::to_value(&parsed.expr).unwrap(),
+            json!({"id": "2", "callExpr": {"function": "ip", "args": [
+                {"id": "1", "identExpr": {"name": "x"}},
+            ]}})
+        );
+        assert_eq!(from_parsed_expr(&parsed).unwrap().0, expr);
+    }
+
+    #[test]
+    fn uints() {
+        let (expr, info) = parse_with_source_info("3u", &ParserOptions::default()).unwrap();
+        let parsed = to_parsed_expr(&expr, &info);
+        assert_eq!(
+            serde_json::to_value(&parsed.expr).unwrap(),
+            json!({"id": "1", "constExpr": {"uint64Value": "3"}})
+        );
+        assert_eq!(from_parsed_expr(&parsed).unwrap().0, expr);
+    }
+
+    #[test]
+    fn optionals() {
+        let input = "[?m.?a, {?'k': m[?0]}]";
+        let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+        let parsed = to_parsed_expr(&expr, &info);
+        assert_eq!(
+            serde_json::to_value(&parsed.expr).unwrap(),
+            json!({"id": "10", "listExpr": {
+                "elements": [
+                    {"id": "2", "callExpr": {"function": "_?._", "args": [
+                        {"id": "1", "identExpr": {"name": "m"}},
+                        {"constExpr": {"stringValue": "a"}},
+                    ]}},
+                    {"id": "9", "structExpr": {"entries": [{
+                        "id": "8",
+                        "mapKey": {"id": "4", "constExpr": {"stringValue": "k"}},
+                        "value": {"id": "7", "callExpr": {"function": "_[?_]", "args": [
+                            {"id": "5", "identExpr": {"name": "m"}},
+                            {"id": "6", "constExpr": {"int64Value": "0"}},
+                        ]}},
+                        "optionalEntry": true,
+                    }]}},
+                ],
+                "optionalIndices": [0],
+            }})
+        );
+        assert_eq!(from_parsed_expr(&parsed).unwrap().0, expr);
+
+        let mut decls = Declarations::standard();
+        decls.add_variable("m", Type::map(Type::String, Type::Int));
+        let (expr, info) = parse_with_source_info("m.?a", &ParserOptions::default()).unwrap();
+        let checked = check(expr, &decls).unwrap();
+        let proto = to_checked_expr(&checked, &info);
+        assert_eq!(
+            serde_json::to_value(&proto.type_map[&2]).unwrap(),
+            json!({"abstractType": {"name": "optional_type", "parameterTypes": [
+                {"primitive": "INT64"},
+            ]}})
+        );
+        assert_eq!(from_checked_expr(&proto).unwrap().0, checked);
+    }
+
+    #[test]
+    fn checked_expr() {
+        let input = "xs[0] == {'a': 1}['a'] || xs.len() > 2";
+        let (expr, info) = parse_with_source_info(input, &ParserOptions::default()).unwrap();
+        let mut decls = Declarations::standard();
+        decls.add_variable("xs", Type::list(Type::Int));
+        let checked = check(expr, &decls).unwrap();
+        let proto = to_checked_expr(&checked, &info);
+        assert_eq!(
+            serde_json::to_value(&proto.type_map[&1]).unwrap(),
+            json!({"listType": {"elemType": {"primitive": "INT64"}}})
+        );
+        assert_eq!(proto.reference_map[&1].name, "xs");
+
+        let decoded = CheckedExpr::decode(&proto.encode_to_vec()[..]).unwrap();
+        let (read, _) = from_checked_expr(&decoded).unwrap();
+        assert_eq!(read, checked);
+    }
+
+    #[test]
+    fn reads_cel_go_output() {
+        // `a && !false`, as checked by cel-go.
+        let checked: CheckedExpr = serde_json::from_value(json!({
+            "referenceMap": {
+                "1": {"name": "a"},
+                "3": {"overloadId": ["logical_not"]},
+                "4": {"overloadId": ["logical_and"]},
+            },
+            "typeMap": {
+                "1": {"primitive": "BOOL"},
+                "2": {"primitive": "BOOL"},
+                "3": {"primitive": "BOOL"},
+                "4": {"primitive": "BOOL"},
+            },
+            "sourceInfo": {
+                "location": "<input>",
+                "lineOffsets": [12],
+                "positions": {"1": 0, "2": 6, "3": 5, "4": 2},
+            },
+            "expr": {"id": "4", "callExpr": {"function": "_&&_", "args": [
+                {"id": "1", "identExpr": {"name": "a"}},
+                {"id": "3", "callExpr": {"function": "!_", "args": [
+                    {"id": "2", "constExpr": {"boolValue": false}},
+                ]}},
+            ]}},
+        }))
+        .unwrap();
+        let (checked, info) = from_checked_expr(&checked).unwrap();
+        assert_eq!(checked.result_type(), &Type::Bool);
+        assert_eq!(checked.overloads_of(4), &[String::from("logical_and")]);
+        assert_eq!(info.location(3).unwrap().column, 6);
+        let mut activation = Activation::new();
+        activation.insert(String::from("a"), Literal::Bool(true));
+        assert_eq!(
+            evaluate_with(checked.expr, &activation),
+            Ok(Literal::Bool(true))
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        let read = |expr| {
+            let parsed: ParsedExpr = serde_json::from_value(json!({ "expr": expr })).unwrap();
+            from_parsed_expr(&parsed).unwrap_err()
+        };
+        assert_eq!(
+            read(
+                json!({"id": "2", "selectExpr": {"operand": {"identExpr": {"name": "a"}}, "field": "b", "testOnly": true}})
+            ),
+            "testing for field 'b' is not supported"
+        );
+        assert_eq!(
+            read(json!({"callExpr": {"function": "_in_", "args": [
+                {"identExpr": {"name": "a"}},
+                {"identExpr": {"name": "b"}},
+            ]}})),
+            "unsupported function '_in_'"
+        );
+        assert_eq!(read(json!({"id": "7"})), "expression 7 has no kind");
+    }
+}
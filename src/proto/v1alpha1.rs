@@ -0,0 +1,569 @@
+//! The messages of `google.api.expr.v1alpha1`, written out by hand from the
+//! definitions vendored under `proto/` so that the build needs no `protoc`.
+//! Every message encodes to the binary wire format through `prost::Message`
+//! and to protojson through serde. `Decl`, and the deprecated duration and
+//! timestamp constants, are left out.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// An expression together with source information as returned by the parser.
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ParsedExpr {
+    #[prost(message, optional, tag = "2")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expr: Option<Expr>,
+    #[prost(message, optional, tag = "3")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_info: Option<SourceInfo>,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Expr {
+    #[prost(int64, tag = "2")]
+    #[serde(with = "int64", skip_serializing_if = "is_default")]
+    pub id: i64,
+    #[prost(oneof = "expr::ExprKind", tags = "3, 4, 5, 6, 7, 8, 9")]
+    #[serde(flatten)]
+    pub expr_kind: Option<expr::ExprKind>,
+}
+
+pub mod expr {
+    use super::is_default;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct Ident {
+        #[prost(string, tag = "1")]
+        #[serde(skip_serializing_if = "String::is_empty")]
+        pub name: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct Select {
+        #[prost(message, optional, boxed, tag = "1")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub operand: Option<Box<super::Expr>>,
+        #[prost(string, tag = "2")]
+        #[serde(skip_serializing_if = "String::is_empty")]
+        pub field: String,
+        #[prost(bool, tag = "3")]
+        #[serde(skip_serializing_if = "is_default")]
+        pub test_only: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct Call {
+        #[prost(message, optional, boxed, tag = "1")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub target: Option<Box<super::Expr>>,
+        #[prost(string, tag = "2")]
+        #[serde(skip_serializing_if = "String::is_empty")]
+        pub function: String,
+        #[prost(message, repeated, tag = "3")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub args: Vec<super::Expr>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct CreateList {
+        #[prost(message, repeated, tag = "1")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub elements: Vec<super::Expr>,
+        #[prost(int32, repeated, tag = "2")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub optional_indices: Vec<i32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct CreateStruct {
+        #[prost(string, tag = "1")]
+        #[serde(skip_serializing_if = "String::is_empty")]
+        pub message_name: String,
+        #[prost(message, repeated, tag = "2")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub entries: Vec<create_struct::Entry>,
+    }
+
+    pub mod create_struct {
+        use super::super::{int64, is_default};
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+        #[serde(rename_all = "camelCase", default)]
+        pub struct Entry {
+            #[prost(int64, tag = "1")]
+            #[serde(with = "int64", skip_serializing_if = "is_default")]
+            pub id: i64,
+            #[prost(oneof = "entry::KeyKind", tags = "2, 3")]
+            #[serde(flatten)]
+            pub key_kind: Option<entry::KeyKind>,
+            #[prost(message, optional, tag = "4")]
+            #[serde(skip_serializing_if = "Option::is_none")]
+            pub value: Option<super::super::Expr>,
+            #[prost(bool, tag = "5")]
+            #[serde(skip_serializing_if = "is_default")]
+            pub optional_entry: bool,
+        }
+
+        pub mod entry {
+            use serde::{Deserialize, Serialize};
+
+            #[derive(Clone, PartialEq, prost::Oneof, Serialize, Deserialize)]
+            #[serde(rename_all = "camelCase")]
+            pub enum KeyKind {
+                #[prost(string, tag = "2")]
+                FieldKey(String),
+                #[prost(message, tag = "3")]
+                MapKey(super::super::super::Expr),
+            }
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct Comprehension {
+        #[prost(string, tag = "1")]
+        #[serde(skip_serializing_if = "String::is_empty")]
+        pub iter_var: String,
+        #[prost(message, optional, boxed, tag = "2")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub iter_range: Option<Box<super::Expr>>,
+        #[prost(string, tag = "3")]
+        #[serde(skip_serializing_if = "String::is_empty")]
+        pub accu_var: String,
+        #[prost(message, optional, boxed, tag = "4")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub accu_init: Option<Box<super::Expr>>,
+        #[prost(message, optional, boxed, tag = "5")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub loop_condition: Option<Box<super::Expr>>,
+        #[prost(message, optional, boxed, tag = "6")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub loop_step: Option<Box<super::Expr>>,
+        #[prost(message, optional, boxed, tag = "7")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub result: Option<Box<super::Expr>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum ExprKind {
+        #[prost(message, tag = "3")]
+        ConstExpr(super::Constant),
+        #[prost(message, tag = "4")]
+        IdentExpr(Ident),
+        #[prost(message, tag = "5")]
+        SelectExpr(Box<Select>),
+        #[prost(message, tag = "6")]
+        CallExpr(Box<Call>),
+        #[prost(message, tag = "7")]
+        ListExpr(CreateList),
+        #[prost(message, tag = "8")]
+        StructExpr(CreateStruct),
+        #[prost(message, tag = "9")]
+        ComprehensionExpr(Box<Comprehension>),
+    }
+}
+
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Constant {
+    #[prost(oneof = "constant::ConstantKind", tags = "1, 2, 3, 4, 5, 6, 7")]
+    #[serde(flatten)]
+    pub constant_kind: Option<constant::ConstantKind>,
+}
+
+pub mod constant {
+    use super::{base64, int64, null_value, uint64, NullValue};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, PartialEq, prost::Oneof, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum ConstantKind {
+        #[prost(enumeration = "NullValue", tag = "1")]
+        #[serde(with = "null_value")]
+        NullValue(i32),
+        #[prost(bool, tag = "2")]
+        BoolValue(bool),
+        #[prost(int64, tag = "3")]
+        #[serde(with = "int64")]
+        Int64Value(i64),
+        #[prost(uint64, tag = "4")]
+        #[serde(with = "uint64")]
+        Uint64Value(u64),
+        #[prost(double, tag = "5")]
+        DoubleValue(f64),
+        #[prost(string, tag = "6")]
+        StringValue(String),
+        #[prost(bytes = "vec", tag = "7")]
+        #[serde(with = "base64")]
+        BytesValue(Vec<u8>),
+    }
+}
+
+/// Source information collected at parse time. Positions and line offsets
+/// count Unicode code points.
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SourceInfo {
+    #[prost(string, tag = "1")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub syntax_version: String,
+    #[prost(string, tag = "2")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub location: String,
+    #[prost(int32, repeated, tag = "3")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub line_offsets: Vec<i32>,
+    #[prost(map = "int64, int32", tag = "4")]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub positions: HashMap<i64, i32>,
+    #[prost(map = "int64, message", tag = "5")]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub macro_calls: HashMap<i64, Expr>,
+}
+
+/// A CEL expression which has been successfully type checked.
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CheckedExpr {
+    #[prost(map = "int64, message", tag = "2")]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub reference_map: HashMap<i64, Reference>,
+    #[prost(map = "int64, message", tag = "3")]
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub type_map: HashMap<i64, Type>,
+    #[prost(message, optional, tag = "5")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_info: Option<SourceInfo>,
+    #[prost(string, tag = "6")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub expr_version: String,
+    #[prost(message, optional, tag = "4")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expr: Option<Expr>,
+}
+
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Type {
+    #[prost(
+        oneof = "r#type::TypeKind",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 14"
+    )]
+    #[serde(flatten)]
+    pub type_kind: Option<r#type::TypeKind>,
+}
+
+pub mod r#type {
+    use super::{null_value, primitive_type, well_known_type, Empty, NullValue};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct ListType {
+        #[prost(message, optional, boxed, tag = "1")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub elem_type: Option<Box<super::Type>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct MapType {
+        #[prost(message, optional, boxed, tag = "1")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub key_type: Option<Box<super::Type>>,
+        #[prost(message, optional, boxed, tag = "2")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value_type: Option<Box<super::Type>>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct FunctionType {
+        #[prost(message, optional, boxed, tag = "1")]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub result_type: Option<Box<super::Type>>,
+        #[prost(message, repeated, tag = "2")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub arg_types: Vec<super::Type>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase", default)]
+    pub struct AbstractType {
+        #[prost(string, tag = "1")]
+        #[serde(skip_serializing_if = "String::is_empty")]
+        pub name: String,
+        #[prost(message, repeated, tag = "2")]
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub parameter_types: Vec<super::Type>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum PrimitiveType {
+        Unspecified = 0,
+        Bool = 1,
+        Int64 = 2,
+        Uint64 = 3,
+        Double = 4,
+        String = 5,
+        Bytes = 6,
+    }
+
+    impl PrimitiveType {
+        /// The name of the value in the protobuf definition.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                PrimitiveType::Unspecified => "PRIMITIVE_TYPE_UNSPECIFIED",
+                PrimitiveType::Bool => "BOOL",
+                PrimitiveType::Int64 => "INT64",
+                PrimitiveType::Uint64 => "UINT64",
+                PrimitiveType::Double => "DOUBLE",
+                PrimitiveType::String => "STRING",
+                PrimitiveType::Bytes => "BYTES",
+            }
+        }
+
+        pub fn from_str_name(name: &str) -> Option<PrimitiveType> {
+            match name {
+                "PRIMITIVE_TYPE_UNSPECIFIED" => Some(PrimitiveType::Unspecified),
+                "BOOL" => Some(PrimitiveType::Bool),
+                "INT64" => Some(PrimitiveType::Int64),
+                "UINT64" => Some(PrimitiveType::Uint64),
+                "DOUBLE" => Some(PrimitiveType::Double),
+                "STRING" => Some(PrimitiveType::String),
+                "BYTES" => Some(PrimitiveType::Bytes),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum WellKnownType {
+        Unspecified = 0,
+        Any = 1,
+        Timestamp = 2,
+        Duration = 3,
+    }
+
+    impl WellKnownType {
+        /// The name of the value in the protobuf definition.
+        pub fn as_str_name(&self) -> &'static str {
+            match self {
+                WellKnownType::Unspecified => "WELL_KNOWN_TYPE_UNSPECIFIED",
+                WellKnownType::Any => "ANY",
+                WellKnownType::Timestamp => "TIMESTAMP",
+                WellKnownType::Duration => "DURATION",
+            }
+        }
+
+        pub fn from_str_name(name: &str) -> Option<WellKnownType> {
+            match name {
+                "WELL_KNOWN_TYPE_UNSPECIFIED" => Some(WellKnownType::Unspecified),
+                "ANY" => Some(WellKnownType::Any),
+                "TIMESTAMP" => Some(WellKnownType::Timestamp),
+                "DURATION" => Some(WellKnownType::Duration),
+                _ => None,
+            }
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Oneof, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum TypeKind {
+        #[prost(message, tag = "1")]
+        Dyn(Empty),
+        #[prost(enumeration = "NullValue", tag = "2")]
+        #[serde(with = "null_value")]
+        Null(i32),
+        #[prost(enumeration = "PrimitiveType", tag = "3")]
+        #[serde(with = "primitive_type")]
+        Primitive(i32),
+        #[prost(enumeration = "PrimitiveType", tag = "4")]
+        #[serde(with = "primitive_type")]
+        Wrapper(i32),
+        #[prost(enumeration = "WellKnownType", tag = "5")]
+        #[serde(with = "well_known_type")]
+        WellKnown(i32),
+        #[prost(message, tag = "6")]
+        ListType(Box<ListType>),
+        #[prost(message, tag = "7")]
+        MapType(Box<MapType>),
+        #[prost(message, tag = "8")]
+        Function(Box<FunctionType>),
+        #[prost(string, tag = "9")]
+        MessageType(String),
+        #[prost(string, tag = "10")]
+        TypeParam(String),
+        #[prost(message, tag = "11")]
+        Type(Box<super::Type>),
+        #[prost(message, tag = "12")]
+        Error(Empty),
+        #[prost(message, tag = "14")]
+        AbstractType(AbstractType),
+    }
+}
+
+/// Describes a resolved reference to a declaration.
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Reference {
+    #[prost(string, tag = "1")]
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[prost(string, repeated, tag = "3")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overload_id: Vec<String>,
+    #[prost(message, optional, tag = "4")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<Constant>,
+}
+
+/// `google.protobuf.Empty`.
+#[derive(Clone, PartialEq, prost::Message, Serialize, Deserialize)]
+pub struct Empty {}
+
+/// `google.protobuf.NullValue`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum NullValue {
+    NullValue = 0,
+}
+
+fn is_default<T: Default + PartialEq>(t: &T) -> bool {
+    *t == T::default()
+}
+
+/// 64-bit integers, which protojson writes as strings and reads from either
+/// strings or numbers.
+mod int64 {
+    use serde::de::{self, Deserializer};
+    use serde::{Deserialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Number(i64),
+        String(String),
+    }
+
+    pub fn serialize<S: Serializer>(n: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(n)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i64, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(n) => Ok(n),
+            Repr::String(s) => s
+                .parse()
+                .map_err(|_| de::Error::custom(format!("invalid int64 '{}'", s))),
+        }
+    }
+}
+
+mod uint64 {
+    use serde::de::{self, Deserializer};
+    use serde::{Deserialize, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Number(u64),
+        String(String),
+    }
+
+    pub fn serialize<S: Serializer>(n: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(n)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Number(n) => Ok(n),
+            Repr::String(s) => s
+                .parse()
+                .map_err(|_| de::Error::custom(format!("invalid uint64 '{}'", s))),
+        }
+    }
+}
+
+mod base64 {
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use serde::de::{self, Deserializer};
+    use serde::{Deserialize, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        STANDARD
+            .decode(&s)
+            .map_err(|_| de::Error::custom(format!("invalid base64 '{}'", s)))
+    }
+}
+
+/// `google.protobuf.NullValue`, which protojson writes as `null`.
+mod null_value {
+    use serde::de::{Deserializer, IgnoredAny};
+    use serde::{Deserialize, Serializer};
+
+    pub fn serialize<S: Serializer>(_: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_unit()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<i32, D::Error> {
+        IgnoredAny::deserialize(deserializer).map(|_| 0)
+    }
+}
+
+/// Enums, which protojson writes by name and reads by name or number.
+macro_rules! enumeration {
+    ($module:ident, $enum:ty) => {
+        mod $module {
+            use serde::de::{self, Deserializer};
+            use serde::{Deserialize, Serializer};
+            use std::convert::TryFrom;
+
+            #[derive(Deserialize)]
+            #[serde(untagged)]
+            enum Repr {
+                Number(i32),
+                Name(String),
+            }
+
+            pub fn serialize<S: Serializer>(n: &i32, serializer: S) -> Result<S::Ok, S::Error> {
+                match <$enum>::try_from(*n) {
+                    Ok(value) => serializer.serialize_str(value.as_str_name()),
+                    Err(_) => serializer.serialize_i32(*n),
+                }
+            }
+
+            pub fn deserialize<'de, D: Deserializer<'de>>(
+                deserializer: D,
+            ) -> Result<i32, D::Error> {
+                match Repr::deserialize(deserializer)? {
+                    Repr::Number(n) => Ok(n),
+                    Repr::Name(name) => <$enum>::from_str_name(&name)
+                        .map(|value| value as i32)
+                        .ok_or_else(|| de::Error::custom(format!("unknown enum value '{}'", name))),
+                }
+            }
+        }
+    };
+}
+
+enumeration!(primitive_type, super::r#type::PrimitiveType);
+enumeration!(well_known_type, super::r#type::WellKnownType);
@@ -0,0 +1,676 @@
+//! Serde support, behind the `serde` feature.
+//!
+//! An `Expression` is an object holding its `id` and exactly one key naming
+//! its kind. Operators hold their operands in an array, or directly if they
+//! have only one:
+//!
+//! ```json
+//! {"id": 2, "add": [{"id": 1, "ident": "x"}, {"id": 3, "int": 1}]}
+//! ```
+//!
+//! The kinds are `conditional` (`[cond, then, else]`), `or`, `and`, `eq`,
+//! `neq`, `lt`, `lte`, `gte`, `gt`, `add`, `sub`, `mul`, `div`, `mod` (all
+//! `[left, right]`), `neg` and `not` (the operand), `index` and
+//! `optionalIndex` (`[operand, index]`), `method` (`{"target": ..., "name":
+//! "len", "args": [...]}`), `call` (`{"function": "size", "args": [...]}`),
+//! `select` and `optionalSelect` (`{"operand": ..., "field": "name"}`),
+//! `optionalElement` (the operand, for `?x` in a list or map), `ident` (the
+//! name) and the literals `null` (`null`), `bool`, `int`, `uint`, `double`, `string`,
+//! `bytes`, `list` (an array of expressions) and `map` (an array of
+//! `[key, value]` pairs of expressions). A missing `id` is read as 0. A value
+//! of an opaque type is written as the `call` that creates it, and an
+//! optional value as the `method` call `optional.of(x)` or `optional.none()`.
+//!
+//! A `Literal` on its own is a value, and maps to the data model as directly
+//! as it can: lists to sequences, maps to maps, and strings, doubles, bools
+//! and null to themselves. Values of opaque and optional types have no such
+//! mapping. In human-readable formats such as JSON, bytes are
+//! base64 strings and ints whose magnitude exceeds 2^53 are decimal strings,
+//! so that they survive readers that parse every number as a double. Reading
+//! a value back cannot tell those strings apart from strings, so only the AST
+//! shape round-trips exactly.
+
+use crate::model::{ExprKind, Expression, Literal, MethodName};
+use crate::unparser::unparse;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{self, SerializeMap, SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+
+const KINDS: &[&str] = &[
+    "conditional",
+    "or",
+    "and",
+    "eq",
+    "neq",
+    "lt",
+    "lte",
+    "gte",
+    "gt",
+    "add",
+    "sub",
+    "mul",
+    "div",
+    "mod",
+    "neg",
+    "not",
+    "method",
+    "call",
+    "select",
+    "optionalSelect",
+    "index",
+    "optionalIndex",
+    "optionalElement",
+    "ident",
+    "null",
+    "bool",
+    "int",
+    "uint",
+    "double",
+    "string",
+    "bytes",
+    "list",
+    "map",
+];
+
+/// The largest magnitude up to which every integer is exactly representable
+/// as a double.
+const MAX_SAFE_INT: i64 = 1 << 53;
+
+#[derive(Serialize)]
+struct MethodRef<'a> {
+    target: &'a Expression,
+    name: String,
+    args: &'a [Expression],
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Method {
+    target: Expression,
+    name: String,
+    args: Vec<Expression>,
+}
+
+#[derive(Serialize)]
+struct CallRef<'a> {
+    function: &'a str,
+    args: &'a [Expression],
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Call {
+    function: String,
+    args: Vec<Expression>,
+}
+
+#[derive(Serialize)]
+struct SelectRef<'a> {
+    operand: &'a Expression,
+    field: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Select {
+    operand: Expression,
+    field: String,
+}
+
+impl Serialize for Expression {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(2))?;
+        map.serialize_entry("id", &self.id)?;
+        match &self.kind {
+            ExprKind::Conditional(cond, a, b) => {
+                map.serialize_entry("conditional", &(cond, a, b))?
+            }
+            ExprKind::Or(a, b) => map.serialize_entry("or", &(a, b))?,
+            ExprKind::And(a, b) => map.serialize_entry("and", &(a, b))?,
+            ExprKind::Eq(a, b) => map.serialize_entry("eq", &(a, b))?,
+            ExprKind::Neq(a, b) => map.serialize_entry("neq", &(a, b))?,
+            ExprKind::Lt(a, b) => map.serialize_entry("lt", &(a, b))?,
+            ExprKind::Lte(a, b) => map.serialize_entry("lte", &(a, b))?,
+            ExprKind::Gte(a, b) => map.serialize_entry("gte", &(a, b))?,
+            ExprKind::Gt(a, b) => map.serialize_entry("gt", &(a, b))?,
+            ExprKind::Add(a, b) => map.serialize_entry("add", &(a, b))?,
+            ExprKind::Sub(a, b) => map.serialize_entry("sub", &(a, b))?,
+            ExprKind::Mul(a, b) => map.serialize_entry("mul", &(a, b))?,
+            ExprKind::Div(a, b) => map.serialize_entry("div", &(a, b))?,
+            ExprKind::Mod(a, b) => map.serialize_entry("mod", &(a, b))?,
+            ExprKind::Neg(a) => map.serialize_entry("neg", a)?,
+            ExprKind::Not(a) => map.serialize_entry("not", a)?,
+            ExprKind::Method(e, name, args) => {
+                let method = MethodRef {
+                    target: e,
+                    name: name.to_string(),
+                    args,
+                };
+                map.serialize_entry("method", &method)?
+            }
+            ExprKind::Call(function, args) => {
+                map.serialize_entry("call", &CallRef { function, args })?
+            }
+            ExprKind::Select(e, field) => {
+                let select = SelectRef { operand: e, field };
+                map.serialize_entry("select", &select)?
+            }
+            ExprKind::OptionalSelect(e, field) => {
+                let select = SelectRef { operand: e, field };
+                map.serialize_entry("optionalSelect", &select)?
+            }
+            ExprKind::Index(e, i) => map.serialize_entry("index", &(e, i))?,
+            ExprKind::OptionalIndex(e, i) => map.serialize_entry("optionalIndex", &(e, i))?,
+            ExprKind::OptionalElement(e) => map.serialize_entry("optionalElement", e)?,
+            ExprKind::Ident(name) => map.serialize_entry("ident", name)?,
+            ExprKind::Lit(Literal::Null) => map.serialize_entry("null", &())?,
+            ExprKind::Lit(Literal::Bool(b)) => map.serialize_entry("bool", b)?,
+            ExprKind::Lit(Literal::I64(n)) => map.serialize_entry("int", &Int(*n))?,
+            ExprKind::Lit(Literal::U64(n)) => map.serialize_entry("uint", &Uint(*n))?,
+            ExprKind::Lit(Literal::F64(n)) => map.serialize_entry("double", n)?,
+            ExprKind::Lit(Literal::String(s)) => map.serialize_entry("string", s)?,
+            ExprKind::Lit(Literal::Bytes(b)) => map.serialize_entry("bytes", &Bytes(b.clone()))?,
+            ExprKind::Lit(Literal::List(xs)) => map.serialize_entry("list", xs)?,
+            ExprKind::Lit(Literal::Map(entries)) => map.serialize_entry("map", entries)?,
+            ExprKind::Lit(Literal::Opaque(value)) => {
+                let (function, args) = value.constructor();
+                let args: Vec<Expression> = args
+                    .into_iter()
+                    .map(|arg| Expression::new(ExprKind::Lit(arg)))
+                    .collect();
+                map.serialize_entry(
+                    "call",
+                    &CallRef {
+                        function,
+                        args: &args,
+                    },
+                )?
+            }
+            ExprKind::Lit(Literal::Optional(value)) => {
+                let (name, args) = match value {
+                    Some(value) => ("of", vec![Expression::from((**value).clone())]),
+                    None => ("none", Vec::new()),
+                };
+                let method = MethodRef {
+                    target: &Expression::new(ExprKind::Ident(String::from("optional"))),
+                    name: String::from(name),
+                    args: &args,
+                };
+                map.serialize_entry("method", &method)?
+            }
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Expression {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Expression, D::Error> {
+        deserializer.deserialize_map(ExpressionVisitor)
+    }
+}
+
+struct ExpressionVisitor;
+
+impl<'de> Visitor<'de> for ExpressionVisitor {
+    type Value = Expression;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an expression")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Expression, A::Error> {
+        let mut id = 0;
+        let mut kind = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "id" {
+                id = map.next_value()?;
+                continue;
+            }
+            if kind.is_some() {
+                return Err(de::Error::custom("expression has more than one kind"));
+            }
+            kind = Some(match key.as_str() {
+                "conditional" => {
+                    let (cond, a, b): (Expression, Expression, Expression) = map.next_value()?;
+                    ExprKind::Conditional(Box::new(cond), Box::new(a), Box::new(b))
+                }
+                "or" => binary(&mut map, ExprKind::Or)?,
+                "and" => binary(&mut map, ExprKind::And)?,
+                "eq" => binary(&mut map, ExprKind::Eq)?,
+                "neq" => binary(&mut map, ExprKind::Neq)?,
+                "lt" => binary(&mut map, ExprKind::Lt)?,
+                "lte" => binary(&mut map, ExprKind::Lte)?,
+                "gte" => binary(&mut map, ExprKind::Gte)?,
+                "gt" => binary(&mut map, ExprKind::Gt)?,
+                "add" => binary(&mut map, ExprKind::Add)?,
+                "sub" => binary(&mut map, ExprKind::Sub)?,
+                "mul" => binary(&mut map, ExprKind::Mul)?,
+                "div" => binary(&mut map, ExprKind::Div)?,
+                "mod" => binary(&mut map, ExprKind::Mod)?,
+                "index" => binary(&mut map, ExprKind::Index)?,
+                "optionalIndex" => binary(&mut map, ExprKind::OptionalIndex)?,
+                "optionalElement" => ExprKind::OptionalElement(Box::new(map.next_value()?)),
+                "neg" => ExprKind::Neg(Box::new(map.next_value()?)),
+                "not" => ExprKind::Not(Box::new(map.next_value()?)),
+                "method" => {
+                    let method: Method = map.next_value()?;
+                    let name: MethodName = method.name.parse().map_err(de::Error::custom)?;
+                    ExprKind::Method(Box::new(method.target), name, method.args)
+                }
+                "call" => {
+                    let call: Call = map.next_value()?;
+                    ExprKind::Call(call.function, call.args)
+                }
+                "select" => {
+                    let select: Select = map.next_value()?;
+                    ExprKind::Select(Box::new(select.operand), select.field)
+                }
+                "optionalSelect" => {
+                    let select: Select = map.next_value()?;
+                    ExprKind::OptionalSelect(Box::new(select.operand), select.field)
+                }
+                "ident" => ExprKind::Ident(map.next_value()?),
+                "null" => {
+                    map.next_value::<()>()?;
+                    ExprKind::Lit(Literal::Null)
+                }
+                "bool" => ExprKind::Lit(Literal::Bool(map.next_value()?)),
+                "int" => ExprKind::Lit(Literal::I64(map.next_value::<Int>()?.0)),
+                "uint" => ExprKind::Lit(Literal::U64(map.next_value::<Uint>()?.0)),
+                "double" => ExprKind::Lit(Literal::F64(map.next_value()?)),
+                "string" => ExprKind::Lit(Literal::String(map.next_value()?)),
+                "bytes" => ExprKind::Lit(Literal::Bytes(map.next_value::<Bytes>()?.0)),
+                "list" => ExprKind::Lit(Literal::List(map.next_value()?)),
+                "map" => ExprKind::Lit(Literal::Map(map.next_value()?)),
+                _ => return Err(de::Error::unknown_field(&key, KINDS)),
+            });
+        }
+        match kind {
+            Some(kind) => Ok(Expression { id, kind }),
+            None => Err(de::Error::custom("expression has no kind")),
+        }
+    }
+}
+
+fn binary<'de, A: MapAccess<'de>>(
+    map: &mut A,
+    kind: fn(Box<Expression>, Box<Expression>) -> ExprKind,
+) -> Result<ExprKind, A::Error> {
+    let (a, b): (Expression, Expression) = map.next_value()?;
+    Ok(kind(Box::new(a), Box::new(b)))
+}
+
+impl Serialize for Literal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Literal::Null => serializer.serialize_unit(),
+            Literal::Bool(b) => serializer.serialize_bool(*b),
+            Literal::I64(n) => Int(*n).serialize(serializer),
+            Literal::U64(n) => Uint(*n).serialize(serializer),
+            Literal::F64(n) => serializer.serialize_f64(*n),
+            Literal::String(s) => serializer.serialize_str(s),
+            Literal::Bytes(b) => Bytes(b.clone()).serialize(serializer),
+            Literal::List(xs) => {
+                let mut seq = serializer.serialize_seq(Some(xs.len()))?;
+                for x in xs {
+                    seq.serialize_element(value(x)?)?;
+                }
+                seq.end()
+            }
+            Literal::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (k, v) in entries {
+                    map.serialize_entry(value(k)?, value(v)?)?;
+                }
+                map.end()
+            }
+            Literal::Opaque(value) => Err(ser::Error::custom(format!(
+                "{} values cannot be serialized",
+                value.type_name()
+            ))),
+            Literal::Optional(_) => Err(ser::Error::custom(
+                "optional_type values cannot be serialized",
+            )),
+        }
+    }
+}
+
+/// The value held by an element of a list or map, which is only a value once
+/// it has been evaluated.
+fn value<E: ser::Error>(expr: &Expression) -> Result<&Literal, E> {
+    match &expr.kind {
+        ExprKind::Lit(literal) => Ok(literal),
+        _ => Err(E::custom(format!("'{}' is not a value", unparse(expr)))),
+    }
+}
+
+impl<'de> Deserialize<'de> for Literal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Literal, D::Error> {
+        deserializer.deserialize_any(LiteralVisitor)
+    }
+}
+
+struct LiteralVisitor;
+
+impl<'de> Visitor<'de> for LiteralVisitor {
+    type Value = Literal;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a value")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Literal, E> {
+        Ok(Literal::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Literal, E> {
+        Ok(Literal::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Literal, D::Error> {
+        Literal::deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, b: bool) -> Result<Literal, E> {
+        Ok(Literal::Bool(b))
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Literal, E> {
+        Ok(Literal::I64(n))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Literal, E> {
+        IntVisitor.visit_u64(n).map(|n| Literal::I64(n.0))
+    }
+
+    fn visit_f64<E: de::Error>(self, n: f64) -> Result<Literal, E> {
+        Ok(Literal::F64(n))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Literal, E> {
+        Ok(Literal::String(String::from(s)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, b: &[u8]) -> Result<Literal, E> {
+        Ok(Literal::Bytes(b.to_vec()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Literal, A::Error> {
+        let mut xs = Vec::new();
+        while let Some(x) = seq.next_element::<Literal>()? {
+            xs.push(Expression::from(x));
+        }
+        Ok(Literal::List(xs))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Literal, A::Error> {
+        let mut entries = Vec::new();
+        while let Some((k, v)) = map.next_entry::<Literal, Literal>()? {
+            entries.push((Expression::from(k), Expression::from(v)));
+        }
+        Ok(Literal::Map(entries))
+    }
+}
+
+/// An int, written as a string in human-readable formats if a double could
+/// not hold it exactly.
+struct Int(i64);
+
+impl Serialize for Int {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() && !(-MAX_SAFE_INT..=MAX_SAFE_INT).contains(&self.0) {
+            serializer.collect_str(&self.0)
+        } else {
+            serializer.serialize_i64(self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Int {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Int, D::Error> {
+        deserializer.deserialize_any(IntVisitor)
+    }
+}
+
+struct IntVisitor;
+
+impl<'de> Visitor<'de> for IntVisitor {
+    type Value = Int;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a 64-bit signed integer, or one written as a string")
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Int, E> {
+        Ok(Int(n))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Int, E> {
+        if n > i64::MAX as u64 {
+            return Err(E::custom(format!("integer overflow: {}", n)));
+        }
+        Ok(Int(n as i64))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Int, E> {
+        s.parse()
+            .map(Int)
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(s), &self))
+    }
+}
+
+/// A uint, written as a string in human-readable formats if a double could
+/// not hold it exactly.
+struct Uint(u64);
+
+impl Serialize for Uint {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() && self.0 > MAX_SAFE_INT as u64 {
+            serializer.collect_str(&self.0)
+        } else {
+            serializer.serialize_u64(self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Uint {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Uint, D::Error> {
+        deserializer.deserialize_any(UintVisitor)
+    }
+}
+
+struct UintVisitor;
+
+impl<'de> Visitor<'de> for UintVisitor {
+    type Value = Uint;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a 64-bit unsigned integer, or one written as a string")
+    }
+
+    fn visit_i64<E: de::Error>(self, n: i64) -> Result<Uint, E> {
+        u64::try_from(n)
+            .map(Uint)
+            .map_err(|_| E::invalid_value(de::Unexpected::Signed(n), &self))
+    }
+
+    fn visit_u64<E: de::Error>(self, n: u64) -> Result<Uint, E> {
+        Ok(Uint(n))
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Uint, E> {
+        s.parse()
+            .map(Uint)
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(s), &self))
+    }
+}
+
+/// Bytes, written as a base64 string in human-readable formats.
+struct Bytes(Vec<u8>);
+
+impl Serialize for Bytes {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&STANDARD.encode(&self.0))
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Bytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Bytes, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(BytesVisitor)
+        } else {
+            deserializer.deserialize_bytes(BytesVisitor)
+        }
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bytes, or a base64 string")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Bytes, E> {
+        STANDARD
+            .decode(s)
+            .map(Bytes)
+            .map_err(|_| E::invalid_value(de::Unexpected::Str(s), &self))
+    }
+
+    fn visit_bytes<E: de::Error>(self, b: &[u8]) -> Result<Bytes, E> {
+        Ok(Bytes(b.to_vec()))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Bytes, A::Error> {
+        let mut bytes = Vec::new();
+        while let Some(b) = seq.next_element()? {
+            bytes.push(b);
+        }
+        Ok(Bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::model::{ExprKind, Expression, Literal};
+    use crate::parsers::parse;
+    use serde_json::json;
+
+    fn cbor_round_trip<T: serde::Serialize + serde::de::DeserializeOwned>(t: &T) -> T {
+        let mut buf = Vec::new();
+        ciborium::ser::into_writer(t, &mut buf).unwrap();
+        ciborium::de::from_reader(&buf[..]).unwrap()
+    }
+
+    #[test]
+    fn expression_shape() {
+        let expr = parse("x.pow(2) + -1 > 0 ? [b'hi', true] : {'k': 9007199254740993}").unwrap();
+        assert_eq!(
+            serde_json::to_value(&expr).unwrap(),
+            json!({"id": 15, "conditional": [
+                {"id": 8, "gt": [
+                    {"id": 6, "add": [
+                        {"id": 3, "method": {
+                            "target": {"id": 1, "ident": "x"},
+                            "name": "pow",
+                            "args": [{"id": 2, "int": 2}],
+                        }},
+                        {"id": 5, "neg": {"id": 4, "int": 1}},
+                    ]},
+                    {"id": 7, "int": 0},
+                ]},
+                {"id": 11, "list": [{"id": 9, "bytes": "aGk="}, {"id": 10, "bool": true}]},
+                {"id": 14, "map": [[{"id": 12, "string": "k"}, {"id": 13, "int": "9007199254740993"}]]},
+            ]})
+        );
+    }
+
+    #[test]
+    fn expressions_round_trip() {
+        for input in &[
+            "a || b && !c",
+            "1 == 1.5 && 1 != 2 && 2 < 3 && 4 <= 5 && 4 >= 5 && 5 > 6",
+            "1 + 2 - 3 * 4 / -5",
+            "[1, 'a', b'\\x00', 2.5, true]",
+            "{1: {}, x: xs[0].len()}",
+            "f(x, 1) + g()",
+            "x.contains('\\u00e9') ? -9223372036854775807 - 1 : 9223372036854775807",
+            "3u + 18446744073709551615u",
+            "[?m.?a, {?'k': m[?0]}]",
+        ] {
+            let expr = parse(input).unwrap();
+            let json = serde_json::to_string(&expr).unwrap();
+            let read: Expression = serde_json::from_str(&json).unwrap();
+            assert_eq!(read, expr, "{}", json);
+            assert_eq!(serde_json::to_string(&read).unwrap(), json);
+            assert_eq!(cbor_round_trip(&expr), expr);
+        }
+    }
+
+    #[test]
+    fn reading_expressions() {
+        let read = |value| serde_json::from_value::<Expression>(value);
+        assert_eq!(
+            read(json!({"neg": {"int": "12"}})).unwrap(),
+            Expression::new(ExprKind::Neg(Box::new(Expression::from(Literal::I64(12)))))
+        );
+        assert_eq!(read(json!({"id": 7, "ident": "x"})).unwrap().id, 7);
+        assert!(read(json!({"id": 7})).is_err());
+        assert!(read(json!({"ident": "x", "int": 1})).is_err());
+        assert!(read(json!({"call": []})).is_err());
+        assert!(read(json!({"int": 9223372036854775808u64})).is_err());
+        assert!(
+            read(json!({"method": {"target": {"ident": "x"}, "name": "no pe", "args": []}}))
+                .is_err()
+        );
+        assert_eq!(
+            read(json!({"method": {"target": {"ident": "x"}, "name": "trim", "args": []}}))
+                .unwrap(),
+            crate::parsers::parse("x.trim()").unwrap()
+        );
+        assert!(read(json!({"bytes": "not base64!"})).is_err());
+    }
+
+    #[test]
+    fn values() {
+        let value = crate::evaluate(
+            parse("[1, 9007199254740992 + 1, 1.5, 'a', b'\\xff', {1: true}]").unwrap(),
+        )
+        .unwrap();
+        let json = serde_json::to_value(&value).unwrap();
+        assert_eq!(
+            json,
+            json!([1, "9007199254740993", 1.5, "a", "/w==", {"1": true}])
+        );
+        assert_eq!(
+            serde_json::from_value::<Literal>(json).unwrap(),
+            Literal::List(vec![
+                Expression::from(Literal::I64(1)),
+                Expression::from(Literal::String(String::from("9007199254740993"))),
+                Expression::from(Literal::F64(1.5)),
+                Expression::from(Literal::String(String::from("a"))),
+                Expression::from(Literal::String(String::from("/w=="))),
+                Expression::from(Literal::Map(vec![(
+                    Expression::from(Literal::String(String::from("1"))),
+                    Expression::from(Literal::Bool(true)),
+                )])),
+            ])
+        );
+        assert_eq!(cbor_round_trip(&value), value);
+        assert_eq!(serde_json::to_value(&Literal::Null).unwrap(), json!(null));
+        let unevaluated = Literal::List(vec![parse("x + 1").unwrap()]);
+        assert!(serde_json::to_string(&unevaluated).is_err());
+    }
+}
@@ -0,0 +1,374 @@
+use crate::model::{ExprKind, Expression, Literal};
+
+use std::fmt;
+
+/// Renders an expression as CEL source, with only the parentheses needed to
+/// preserve its structure, so that `parse(&unparse(e)) == Ok(e)` for every
+/// parsed `e`.
+///
+/// Trees that did not come from the parser may have no exact source form:
+/// negative and non-finite numbers, `null` and empty lists are rendered the
+/// way CEL writes them even though this grammar does not accept them back.
+pub fn unparse(expr: &Expression) -> String {
+    expr.to_string()
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expression(f, self, CONDITIONAL)
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_literal(f, self)
+    }
+}
+
+// Binding strength of each level of the grammar, loosest first.
+pub(crate) const CONDITIONAL: u8 = 1;
+pub(crate) const OR: u8 = 2;
+pub(crate) const AND: u8 = 3;
+pub(crate) const RELATION: u8 = 4;
+pub(crate) const ADDITION: u8 = 5;
+pub(crate) const MULTIPLICATION: u8 = 6;
+pub(crate) const UNARY: u8 = 7;
+pub(crate) const MEMBER: u8 = 8;
+
+pub(crate) fn precedence(expr: &Expression) -> u8 {
+    match &expr.kind {
+        ExprKind::Conditional(..) => CONDITIONAL,
+        ExprKind::Or(..) => OR,
+        ExprKind::And(..) => AND,
+        ExprKind::Eq(..)
+        | ExprKind::Neq(..)
+        | ExprKind::Lt(..)
+        | ExprKind::Lte(..)
+        | ExprKind::Gte(..)
+        | ExprKind::Gt(..) => RELATION,
+        ExprKind::Add(..) | ExprKind::Sub(..) => ADDITION,
+        ExprKind::Mul(..) | ExprKind::Div(..) | ExprKind::Mod(..) => MULTIPLICATION,
+        // A negative number is written with a leading `-`, which binds like
+        // the unary operator.
+        ExprKind::Neg(..) | ExprKind::Not(..) => UNARY,
+        ExprKind::Lit(Literal::I64(x)) if *x < 0 => UNARY,
+        ExprKind::Lit(Literal::F64(x)) if x.is_sign_negative() => UNARY,
+        ExprKind::Method(..)
+        | ExprKind::Call(..)
+        | ExprKind::Select(..)
+        | ExprKind::OptionalSelect(..)
+        | ExprKind::Index(..)
+        | ExprKind::OptionalIndex(..)
+        | ExprKind::OptionalElement(_)
+        | ExprKind::Ident(_)
+        | ExprKind::Lit(_) => MEMBER,
+    }
+}
+
+/// Writes `expr` where the grammar expects an expression that binds at least
+/// as tightly as `min`, parenthesizing it if it does not.
+fn write_expression(f: &mut fmt::Formatter, expr: &Expression, min: u8) -> fmt::Result {
+    if precedence(expr) < min {
+        write!(f, "(")?;
+        write_expression(f, expr, CONDITIONAL)?;
+        return write!(f, ")");
+    }
+    match &expr.kind {
+        ExprKind::Conditional(cond, a, b) => {
+            write_expression(f, cond, OR)?;
+            write!(f, " ? ")?;
+            write_expression(f, a, OR)?;
+            write!(f, " : ")?;
+            write_expression(f, b, CONDITIONAL)
+        }
+        ExprKind::Or(a, b) => write_binary(f, a, "||", b, OR),
+        ExprKind::And(a, b) => write_binary(f, a, "&&", b, AND),
+        // Relations do not chain, so neither side may be another relation.
+        ExprKind::Eq(a, b) => write_relation(f, a, "==", b),
+        ExprKind::Neq(a, b) => write_relation(f, a, "!=", b),
+        ExprKind::Lt(a, b) => write_relation(f, a, "<", b),
+        ExprKind::Lte(a, b) => write_relation(f, a, "<=", b),
+        ExprKind::Gte(a, b) => write_relation(f, a, ">=", b),
+        ExprKind::Gt(a, b) => write_relation(f, a, ">", b),
+        ExprKind::Add(a, b) => write_binary(f, a, "+", b, ADDITION),
+        ExprKind::Sub(a, b) => write_binary(f, a, "-", b, ADDITION),
+        ExprKind::Mul(a, b) => write_binary(f, a, "*", b, MULTIPLICATION),
+        ExprKind::Div(a, b) => write_binary(f, a, "/", b, MULTIPLICATION),
+        ExprKind::Mod(a, b) => write_binary(f, a, "%", b, MULTIPLICATION),
+        ExprKind::Neg(a) => {
+            write!(f, "-")?;
+            write_expression(f, a, UNARY)
+        }
+        ExprKind::Not(a) => {
+            write!(f, "!")?;
+            write_expression(f, a, UNARY)
+        }
+        ExprKind::Method(e, name, args) => {
+            write_expression(f, e, MEMBER)?;
+            write!(f, ".{}(", name)?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_expression(f, arg, CONDITIONAL)?;
+            }
+            write!(f, ")")
+        }
+        ExprKind::Call(function, args) => {
+            write!(f, "{}(", function)?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_expression(f, arg, CONDITIONAL)?;
+            }
+            write!(f, ")")
+        }
+        ExprKind::Select(e, field) => {
+            write_expression(f, e, MEMBER)?;
+            write!(f, ".{}", field)
+        }
+        ExprKind::OptionalSelect(e, field) => {
+            write_expression(f, e, MEMBER)?;
+            write!(f, ".?{}", field)
+        }
+        ExprKind::Index(e, i) => {
+            write_expression(f, e, MEMBER)?;
+            write!(f, "[")?;
+            write_expression(f, i, CONDITIONAL)?;
+            write!(f, "]")
+        }
+        ExprKind::OptionalIndex(e, i) => {
+            write_expression(f, e, MEMBER)?;
+            write!(f, "[?")?;
+            write_expression(f, i, CONDITIONAL)?;
+            write!(f, "]")
+        }
+        ExprKind::OptionalElement(e) => {
+            write!(f, "?")?;
+            write_expression(f, e, ADDITION)
+        }
+        ExprKind::Ident(name) => write!(f, "{}", name),
+        ExprKind::Lit(literal) => write_literal(f, literal),
+    }
+}
+
+/// Writes a left-associative binary operator at precedence `level`: the left
+/// operand may be at the same level, the right operand must bind tighter.
+fn write_binary(
+    f: &mut fmt::Formatter,
+    a: &Expression,
+    op: &str,
+    b: &Expression,
+    level: u8,
+) -> fmt::Result {
+    write_expression(f, a, level)?;
+    write!(f, " {} ", op)?;
+    write_expression(f, b, level + 1)
+}
+
+fn write_relation(f: &mut fmt::Formatter, a: &Expression, op: &str, b: &Expression) -> fmt::Result {
+    write_expression(f, a, ADDITION)?;
+    write!(f, " {} ", op)?;
+    write_expression(f, b, ADDITION)
+}
+
+fn write_literal(f: &mut fmt::Formatter, literal: &Literal) -> fmt::Result {
+    match literal {
+        Literal::I64(x) => write!(f, "{}", x),
+        Literal::U64(x) => write!(f, "{}u", x),
+        Literal::F64(x) => {
+            let s = x.to_string();
+            if x.is_finite() && !s.contains('.') {
+                write!(f, "{}.0", s)
+            } else {
+                write!(f, "{}", s)
+            }
+        }
+        Literal::Bool(x) => write!(f, "{}", x),
+        Literal::String(s) => {
+            write!(f, "\"")?;
+            for c in s.chars() {
+                match c {
+                    '"' => write!(f, "\\\"")?,
+                    '\n' => write!(f, "\\n")?,
+                    '\t' => write!(f, "\\t")?,
+                    // A bare quote of either kind ends the literal.
+                    '\'' => write!(f, "\\'")?,
+                    '\\' => write!(f, "\\\\")?,
+                    c if c.is_control() => write!(f, "\\u{:04X}", c as u32)?,
+                    c => write!(f, "{}", c)?,
+                }
+            }
+            write!(f, "\"")
+        }
+        Literal::Bytes(bytes) => {
+            write!(f, "b\"")?;
+            for &b in bytes {
+                match b {
+                    b'"' => write!(f, "\\\"")?,
+                    b'\n' => write!(f, "\\n")?,
+                    b'\t' => write!(f, "\\t")?,
+                    b'\'' => write!(f, "\\'")?,
+                    b'\\' => write!(f, "\\\\")?,
+                    b' '..=b'~' => write!(f, "{}", b as char)?,
+                    b => write!(f, "\\x{:02X}", b)?,
+                }
+            }
+            write!(f, "\"")
+        }
+        Literal::List(xs) => {
+            write!(f, "[")?;
+            for (i, x) in xs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_expression(f, x, ADDITION)?;
+            }
+            write!(f, "]")
+        }
+        Literal::Map(entries) => {
+            write!(f, "{{")?;
+            for (i, (k, v)) in entries.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                // An optional entry is marked on its key, `?k: v`.
+                let v = match &v.kind {
+                    ExprKind::OptionalElement(v) => {
+                        write!(f, "?")?;
+                        v
+                    }
+                    _ => v,
+                };
+                write_expression(f, k, ADDITION)?;
+                write!(f, ": ")?;
+                write_expression(f, v, ADDITION)?;
+            }
+            write!(f, "}}")
+        }
+        Literal::Null => write!(f, "null"),
+        Literal::Optional(None) => write!(f, "optional.none()"),
+        Literal::Optional(Some(value)) => {
+            write!(f, "optional.of(")?;
+            write_literal(f, value)?;
+            write!(f, ")")
+        }
+        Literal::Opaque(value) => {
+            let (function, args) = value.constructor();
+            write!(f, "{}(", function)?;
+            for (i, arg) in args.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_literal(f, arg)?;
+            }
+            write!(f, ")")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::unparse;
+    use crate::model::{ExprKind, Expression, Literal};
+    use crate::parsers::parse;
+
+    fn assert_unparses_to(input: &str, expected: &str) {
+        let expr = parse(input).unwrap();
+        let unparsed = unparse(&expr);
+        assert_eq!(unparsed, expected, "unparsing {}", input);
+        assert_eq!(parse(&unparsed), Ok(expr), "reparsing {}", unparsed);
+    }
+
+    #[test]
+    fn minimal_parentheses() {
+        assert_unparses_to("1+2*3", "1 + 2 * 3");
+        assert_unparses_to("(1 + 2) * 3", "(1 + 2) * 3");
+        assert_unparses_to("((1 - 2)) - 3", "1 - 2 - 3");
+        assert_unparses_to("1 - (2 - 3)", "1 - (2 - 3)");
+        assert_unparses_to("(a || b) || c", "a || b || c");
+        assert_unparses_to("a || (b || c)", "a || (b || c)");
+        assert_unparses_to("(a || b) && c", "(a || b) && c");
+        assert_unparses_to("a && b || c && d", "a && b || c && d");
+        assert_unparses_to("(1 < 2) == (2 < 3)", "(1 < 2) == (2 < 3)");
+        assert_unparses_to("-(-x)", "--x");
+        assert_unparses_to("!(x && y)", "!(x && y)");
+        assert_unparses_to("(-x).pow(2)", "(-x).pow(2)");
+        assert_unparses_to("(x + 1).len()", "(x + 1).len()");
+        assert_unparses_to("(xs[0])[1].len()", "xs[0][1].len()");
+        assert_unparses_to("(a.b).c", "a.b.c");
+        assert_unparses_to("(a + b).c", "(a + b).c");
+        assert_unparses_to("f( (a || b) ,g()).c", "f(a || b, g()).c");
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_unparses_to("a ? b : c ? d : e", "a ? b : c ? d : e");
+        assert_unparses_to("a ? (b ? c : d) : e", "a ? (b ? c : d) : e");
+        assert_unparses_to("(a ? b : c) ? d : e", "(a ? b : c) ? d : e");
+        assert_unparses_to(
+            "xs[a ? 0 : 1].pow(a ? 1 : 2)",
+            "xs[a ? 0 : 1].pow(a ? 1 : 2)",
+        );
+    }
+
+    #[test]
+    fn collections() {
+        assert_unparses_to("[1,2 + 3, [x]]", "[1, 2 + 3, [x]]");
+        assert_unparses_to("[(a && b), (a ? 1 : 2)]", "[(a && b), (a ? 1 : 2)]");
+        assert_unparses_to("{'a': 1, (x || y): [2]}", r#"{"a": 1, (x || y): [2]}"#);
+        assert_unparses_to("{}", "{}");
+        assert_unparses_to("[?x, 1, ?(a || b)]", "[?x, 1, ?(a || b)]");
+        assert_unparses_to("{?'a': m.?b, 'c': xs[?0]}", r#"{?"a": m.?b, "c": xs[?0]}"#);
+    }
+
+    #[test]
+    fn literals() {
+        assert_unparses_to("3.0", "3.0");
+        assert_unparses_to("0.000001", "0.000001");
+        assert_unparses_to(r#" 'it\'s \"quoted\"' "#, r#""it\'s \"quoted\"""#);
+        assert_unparses_to(
+            r#" "tab\tnewline\n\\\u0001¢" "#,
+            r#""tab\tnewline\n\\\u0001¢""#,
+        );
+        assert_unparses_to(r#" b'\\\'' "#, r#"b"\\\'""#);
+        assert_unparses_to(r#" b'\xFF\000a\"¢' "#, r#"b"\xFF\x00a\"\xC2\xA2""#);
+        assert_unparses_to("true != false", "true != false");
+    }
+
+    #[test]
+    fn trees_without_source() {
+        let neg = || Box::new(Expression::from(Literal::I64(-3)));
+        assert_eq!(
+            unparse(&Expression::new(ExprKind::Index(
+                neg(),
+                Box::new(Expression::new(ExprKind::Sub(
+                    neg(),
+                    Box::new(Expression::from(Literal::F64(-0.5)))
+                )))
+            ))),
+            "(-3)[-3 - -0.5]"
+        );
+        assert_eq!(Literal::List(vec![]).to_string(), "[]");
+        assert_eq!(
+            Literal::Optional(Some(Box::new(Literal::I64(1)))).to_string(),
+            "optional.of(1)"
+        );
+        assert_eq!(Literal::Optional(None).to_string(), "optional.none()");
+    }
+
+    #[test]
+    fn round_trip() {
+        for input in &[
+            r#"method == "GET" && ["admin", "editor"].contains(role) && size < 1024 * 1024"#,
+            r#"headers["x-tenant"] == "acme" && (retries < 3 ? priority > 2 : priority > 5) || path.len() > 64"#,
+            r#"used + requested <= limits[tier] && (tier == "free" ? requested <= 10 : requested <= 1000)"#,
+            "!(1 / 0 == 1 && !false) || -x.pow(2) >= -(x - 1)",
+            "request.auth.claims[key].len() > 0 && !request.auth.anonymous",
+        ] {
+            let expr = parse(input).unwrap();
+            assert_eq!(parse(&unparse(&expr)), Ok(expr), "round-tripping {}", input);
+        }
+    }
+}
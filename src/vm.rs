@@ -0,0 +1,315 @@
+use crate::compiler::{Function, Instruction, Program};
+use crate::interpreter::{
+    add, bind_macro, call_global, call_method, divide, equals, index, less, less_equals,
+    literal_value, logical_and, logical_or, lookup, modulo, multiply, negate, not, optional_index,
+    optional_select, resolve_element, select, subtract, Activation, EvalResult, Functions,
+    NO_FUNCTIONS,
+};
+use crate::model::{Expression, Literal};
+
+impl Program {
+    /// Runs the program against `activation`. This produces exactly the same
+    /// result as `interpreter::evaluate_with` on the compiled expression.
+    pub fn evaluate(&self, activation: &Activation) -> EvalResult {
+        self.evaluate_with_functions(activation, &NO_FUNCTIONS)
+    }
+
+    /// Like `evaluate`, with the extension functions in `functions` available.
+    pub fn evaluate_with_functions(
+        &self,
+        activation: &Activation,
+        functions: &Functions,
+    ) -> EvalResult {
+        let mut stack: Vec<EvalResult> = Vec::new();
+        let mut pc = 0;
+        while pc < self.instructions.len() {
+            match &self.instructions[pc] {
+                Instruction::Const(i) => stack.push(literal_value(self.constants[*i].clone())),
+                Instruction::Load(i) => stack.push(lookup(&self.names[*i], activation)),
+                Instruction::Call(function) => {
+                    let result = call(function, &mut stack, activation, functions);
+                    stack.push(result);
+                }
+                Instruction::List(i) => {
+                    let originals = &self.collections[*i];
+                    let values = stack.split_off(stack.len() - originals.len());
+                    let xs = values
+                        .into_iter()
+                        .zip(originals.iter())
+                        .map(|(v, original)| resolve_element(original.clone(), v))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(|xs| Literal::List(xs.into_iter().flatten().collect()));
+                    stack.push(xs);
+                }
+                Instruction::Map(i) => {
+                    let originals = &self.collections[*i];
+                    let values = stack.split_off(stack.len() - 2 * originals.len());
+                    stack.push(make_map(values, originals));
+                }
+                Instruction::JumpIfFalse(target) => {
+                    if let Some(Ok(Literal::Bool(false))) = stack.last() {
+                        pc = *target;
+                        continue;
+                    }
+                }
+                Instruction::JumpIfTrue(target) => {
+                    if let Some(Ok(Literal::Bool(true))) = stack.last() {
+                        pc = *target;
+                        continue;
+                    }
+                }
+                Instruction::Branch { otherwise, end } => match stack.pop().unwrap() {
+                    Ok(Literal::Bool(true)) => {}
+                    Ok(Literal::Bool(false)) => {
+                        pc = *otherwise;
+                        continue;
+                    }
+                    other => {
+                        stack.push(other.and(Err(String::from("invalid types"))));
+                        pc = *end;
+                        continue;
+                    }
+                },
+                Instruction::Jump(target) => {
+                    pc = *target;
+                    continue;
+                }
+            }
+            pc += 1;
+        }
+        stack.pop().unwrap()
+    }
+}
+
+fn make_map(values: Vec<EvalResult>, originals: &[Expression]) -> EvalResult {
+    let mut entries = Vec::with_capacity(originals.len());
+    let mut values = values.into_iter();
+    for original in originals {
+        let k = values.next().unwrap()?;
+        if let Some(v) = resolve_element(original.clone(), values.next().unwrap())? {
+            entries.push((Expression::from(k), v));
+        }
+    }
+    Ok(Literal::Map(entries))
+}
+
+fn call(
+    function: &Function,
+    stack: &mut Vec<EvalResult>,
+    activation: &Activation,
+    functions: &Functions,
+) -> EvalResult {
+    match function {
+        Function::Qualified(qualified, method) => {
+            let global = match functions.global(qualified) {
+                Some(global) => global,
+                None => return call(method, stack, activation, functions),
+            };
+            let argc = match method.as_ref() {
+                Function::Method(_, argc) => *argc,
+                Function::Macro(..) => 2,
+                _ => unreachable!("only methods are qualified"),
+            };
+            let args = stack.split_off(stack.len() - argc);
+            // The receiver is only the namespace of the function.
+            stack.pop();
+            return global(args.into_iter().collect::<Result<_, _>>()?);
+        }
+        Function::Global(name, argc) => {
+            let args = stack.split_off(stack.len() - argc);
+            let args = args.into_iter().collect::<Result<_, _>>()?;
+            return call_global(name, args, functions);
+        }
+        Function::Method(name, argc) => {
+            let args = stack.split_off(stack.len() - argc);
+            let e = stack.pop().unwrap()?;
+            return call_method(e, name, args, activation, functions);
+        }
+        Function::Macro(name, var, body) => {
+            let args = stack.split_off(stack.len() - 2);
+            let e = stack.pop().unwrap()?;
+            return match functions.macro_named(name) {
+                Some(function) => bind_macro(function, e, var, body, activation, functions),
+                None => call_method(e, name, args, activation, functions),
+            };
+        }
+        _ => {}
+    }
+    if let Function::Neg | Function::Not | Function::Select(_) | Function::OptionalSelect(_) =
+        function
+    {
+        let a = stack.pop().unwrap()?;
+        return match function {
+            Function::Neg => negate(a),
+            Function::Select(field) => select(a, field, activation),
+            Function::OptionalSelect(field) => optional_select(a, field, activation),
+            _ => not(a),
+        };
+    }
+    let b = stack.pop().unwrap();
+    let a = stack.pop().unwrap();
+    match function {
+        Function::And => logical_and(a, b),
+        Function::Or => logical_or(a, b),
+        Function::Eq => equals(a?, b?),
+        Function::Neq => not(equals(a?, b?)?),
+        Function::Lt => less(a?, b?),
+        Function::Lte => less_equals(a?, b?),
+        Function::Gte => not(less(a?, b?)?),
+        Function::Gt => not(less_equals(a?, b?)?),
+        Function::Add => add(a?, b?),
+        Function::Sub => subtract(a?, b?),
+        Function::Mul => multiply(a?, b?),
+        Function::Div => divide(a?, b?),
+        Function::Mod => modulo(a?, b?),
+        Function::Index => index(a?, b?, activation),
+        Function::OptionalIndex => optional_index(a?, b?, activation),
+        Function::Neg
+        | Function::Not
+        | Function::Select(_)
+        | Function::OptionalSelect(_)
+        | Function::Method(..)
+        | Function::Macro(..)
+        | Function::Global(..)
+        | Function::Qualified(..) => {
+            unreachable!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compiler::compile;
+    use crate::interpreter::{evaluate_with, Activation};
+    use crate::model::{Expression, Literal};
+    use crate::parsers::parse;
+
+    fn activation() -> Activation {
+        let mut activation = Activation::new();
+        activation.insert(String::from("x"), Literal::I64(3));
+        activation.insert(String::from("s"), Literal::String(String::from("asdf")));
+        activation.insert(String::from("t"), Literal::Bool(true));
+        activation.insert(
+            String::from("xs"),
+            Literal::List(vec![
+                Expression::from(Literal::I64(1)),
+                Expression::from(Literal::I64(2)),
+            ]),
+        );
+        activation
+    }
+
+    fn assert_same(input: &str) {
+        let expr = parse(input).unwrap();
+        let activation = activation();
+        assert_eq!(
+            compile(&expr).evaluate(&activation),
+            evaluate_with(expr, &activation),
+            "evaluating {}",
+            input
+        );
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_same("1 + 2 * 3 - 4 / 2");
+        assert_same("-x + x.pow(2)");
+        assert_same("2.0.pow(x) / 0.5");
+        assert_same("(7u - 1u) * 2u / 3u % 3u == 1u && 1u < 2u");
+        assert_same("s + s == 'asdfasdf'");
+        assert_same("x != 3 || x >= 3 && x > 2 && x <= 3 && x < 4");
+    }
+
+    #[test]
+    fn errors() {
+        assert_same("1 / 0");
+        assert_same("'a' + 1");
+        assert_same("y + 1");
+        assert_same("(1 / 0) + y");
+        assert_same("s.pow(1 / 0)");
+        assert_same("s.len(1)");
+        assert_same("!1");
+        assert_same("f(x)");
+    }
+
+    #[test]
+    fn integer_errors() {
+        let cases = [
+            ("1 % 0", "modulus by zero"),
+            ("x / 0", "divide by zero"),
+            ("9223372036854775807 + 1", "integer overflow"),
+            ("(-9223372036854775807 - 1) - x", "integer overflow"),
+            ("9223372036854775807 * 2", "integer overflow"),
+            ("(-9223372036854775807 - 1) / -1", "integer overflow"),
+            ("(-9223372036854775807 - 1) % -1", "integer overflow"),
+            ("-(-9223372036854775807 - 1)", "integer overflow"),
+            ("2.pow(100)", "integer overflow"),
+            ("2.pow(-1)", "negative exponent to .pow()"),
+            ("18446744073709551615u + 1u", "integer overflow"),
+            ("0u - 1u", "integer overflow"),
+            ("4294967296u * 4294967296u", "integer overflow"),
+            ("1u / 0u", "divide by zero"),
+            ("1u % 0u", "modulus by zero"),
+        ];
+        let activation = activation();
+        for &(input, error) in cases.iter() {
+            assert_same(input);
+            let expr = parse(input).unwrap();
+            assert_eq!(
+                compile(&expr).evaluate(&activation),
+                Err(String::from(error)),
+                "evaluating {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn logical_operators() {
+        assert_same("t && 1 / 0 == 1");
+        assert_same("1 / 0 == 1 && !t");
+        assert_same("!t && 1 / 0 == 1");
+        assert_same("t || 1 / 0 == 1");
+        assert_same("1 / 0 == 1 || t");
+        assert_same("!t || 1 / 0 == 1");
+        assert_same("1 && t");
+        assert_same("x || t");
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_same("t ? 1 : 1 / 0");
+        assert_same("!t ? 1 / 0 : s");
+        assert_same("x ? 1 : 2");
+        assert_same("y ? 1 : 2");
+        assert_same("(t ? xs : [3])[1] + (x > 1 ? (x > 2 ? 10 : 20) : 30)");
+    }
+
+    #[test]
+    fn collections() {
+        assert_same("[x, 1 / 0, s]");
+        assert_same("[x, 1 / 0].contains(3)");
+        assert_same("[x, 1 / 0].contains(4)");
+        assert_same("[x, [1 / 0]][1]");
+        assert_same("xs.contains(x - 1)");
+        assert_same("{s: x, 'b': 1 / 0}");
+        assert_same("{s: x, 'b': 1 / 0}['asdf']");
+        assert_same("{s: x, 'b': 1 / 0}['b']");
+        assert_same("{1 / 0: x}");
+        assert_same("{'a': 1}['b']");
+        assert_same("xs[2]");
+        assert_same("{'a': {s: x}}.a.asdf");
+        assert_same("{'a': 1}.b");
+        assert_same("xs.a");
+    }
+
+    #[test]
+    fn optionals() {
+        assert_same("{s: x}.?asdf.orValue(0) + xs[?5].orValue(1)");
+        assert_same("[?xs[?0], ?xs[?9], x]");
+        assert_same("{?s: {'a': 1}.?b, 'c': 2}");
+        assert_same("[1, ?optional.of(1 / 0)]");
+        assert_same("[?x]");
+        assert_same("optional.none().value()");
+    }
+}